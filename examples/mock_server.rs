@@ -0,0 +1,74 @@
+//! Serves the responses in `fixtures` in place of the AEMET observation and
+//! forecast and warning, METAR and regional network services for end-to-end
+//! tests:
+//!
+//! ```sh
+//! cargo run --example mock_server -- 8765
+//! ```
+//!
+//! `GET /<path>` answers `fixtures/<path>`, ignoring the query, with
+//! `{{base}}` replaced by the URL of the server. Tar archives are served as
+//! they are. Daily Euskalmet readings
+//! (`/euskalmet/readings/<station>/<date>/readingsData.json`) answer
+//! `fixtures/euskalmet/readings/<station>.json` for any date.
+
+use std::{env, fs, path::PathBuf, str::FromStr};
+
+use tide::{http::Mime, Request, Response};
+
+const FIXTURES_DIR: &str = "fixtures";
+
+fn fixture_path(path: &str) -> Option<PathBuf> {
+    if path.split('/').any(|part| part == "..") {
+        return None;
+    }
+    let parts: Vec<&str> = path.split('/').collect();
+    let path = match parts[..] {
+        ["euskalmet", "readings", station, ..] if parts.len() > 3 => format!("euskalmet/readings/{}.json", station),
+        _ => path.to_string(),
+    };
+    Some(PathBuf::from(FIXTURES_DIR).join(path))
+}
+
+async fn serve_fixture(req: Request<String>) -> tide::Result {
+    let path = req.param("path").unwrap_or_default();
+    if path.ends_with(".tar") {
+        return match fixture_path(path).map(fs::read) {
+            Some(Ok(content)) => {
+                println!("GET /{} 200", path);
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/x-tar").unwrap());
+                response.set_body(content);
+                Ok(response)
+            }
+            _ => {
+                println!("GET /{} 404", path);
+                Ok(Response::new(404))
+            }
+        };
+    }
+    let content = match fixture_path(path).map(fs::read_to_string) {
+        Some(Ok(content)) => content.replace("{{base}}", req.state()),
+        _ => {
+            println!("GET /{} 404", path);
+            return Ok(Response::new(404));
+        }
+    };
+    println!("GET /{} 200", path);
+    let mut response = Response::new(200);
+    let content_type = if path.ends_with(".txt") { "text/plain" } else { "application/json" };
+    response.set_content_type(Mime::from_str(content_type).unwrap());
+    response.set_body(content);
+    Ok(response)
+}
+
+#[async_std::main]
+async fn main() -> tide::Result<()> {
+    let port = env::args().nth(1).unwrap_or("8765".to_string());
+    let base = format!("http://127.0.0.1:{}", port);
+    let mut app = tide::with_state(base.clone());
+    app.at("/*path").get(serve_fixture);
+    println!("Serving {} at {}", FIXTURES_DIR, base);
+    app.listen(format!("127.0.0.1:{}", port)).await?;
+    Ok(())
+}
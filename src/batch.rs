@@ -0,0 +1,161 @@
+use std::io::{Error, ErrorKind};
+
+use serde_json::Value;
+
+use crate::{
+    boundaries::MunicipalityBoundaries,
+    connectors::store::Store,
+    met::{Location, WheatrBatchItem},
+    snapshot::ObservationSnapshot,
+};
+
+/// Most points accepted in a single batch request.
+pub const MAX_BATCH_POINTS: usize = 1000;
+
+/// Optional id of a requested point with its location, or why it is invalid.
+type BatchPoint = (Option<String>, Result<Location, String>);
+
+fn read_id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+fn read_coordinate(value: &Value, name: &str) -> Result<f32, String> {
+    value
+        .as_f64()
+        .filter(|v| v.is_finite())
+        .map(|v| v as f32)
+        .ok_or_else(|| format!("{} is not a number", name))
+}
+
+fn read_point_geometry(geometry: &Value) -> Result<Location, String> {
+    if geometry["type"] != "Point" {
+        return Err("geometry is not a Point".to_string());
+    }
+    Ok(Location {
+        lon: read_coordinate(&geometry["coordinates"][0], "longitude")?,
+        lat: read_coordinate(&geometry["coordinates"][1], "latitude")?,
+    })
+}
+
+/**
+ * Reads a point given as `{"id", "lat", "lon"}`, a GeoJSON Point or a
+ * GeoJSON Feature with a Point geometry. Invalid points are returned with
+ * their error, so they don't fail the whole batch.
+ */
+fn read_point(value: &Value) -> BatchPoint {
+    match value["type"].as_str() {
+        Some("Feature") => {
+            let id = read_id(&value["id"]).or_else(|| read_id(&value["properties"]["id"]));
+            (id, read_point_geometry(&value["geometry"]))
+        }
+        Some("Point") => (None, read_point_geometry(value)),
+        _ => {
+            let location = read_coordinate(&value["lat"], "lat")
+                .and_then(|lat| Ok(Location { lat, lon: read_coordinate(&value["lon"], "lon")? }));
+            (read_id(&value["id"]), location)
+        }
+    }
+}
+
+/**
+ * Reads the points of a batch request: a JSON array of points, or a
+ * GeoJSON FeatureCollection or MultiPoint.
+ */
+pub fn read_batch_points(body: &Value) -> Result<Vec<BatchPoint>, Error> {
+    let points: Vec<BatchPoint> = match (body, body["type"].as_str()) {
+        (Value::Array(points), _) => points.iter().map(read_point).collect(),
+        (_, Some("FeatureCollection")) => match body["features"].as_array() {
+            Some(features) => features.iter().map(read_point).collect(),
+            None => return Err(Error::new(ErrorKind::InvalidData, "Bad Request: features are missing")),
+        },
+        (_, Some("MultiPoint")) => match body["coordinates"].as_array() {
+            Some(coordinates) => coordinates
+                .iter()
+                .map(|c| (None, read_point_geometry(&serde_json::json!({ "type": "Point", "coordinates": c }))))
+                .collect(),
+            None => return Err(Error::new(ErrorKind::InvalidData, "Bad Request: coordinates are missing")),
+        },
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Bad Request: body must be an array of points, a FeatureCollection or a MultiPoint",
+            ))
+        }
+    };
+    if points.len() > MAX_BATCH_POINTS {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Bad Request: more than {} points", MAX_BATCH_POINTS)));
+    }
+    Ok(points)
+}
+
+/**
+ * Local data for every point of a batch request from a single snapshot of
+ * the latest observations, in the order of the request, with the
+ * municipality of every point.
+ */
+pub fn get_batch_local_data(store: &dyn Store, boundaries: &MunicipalityBoundaries, body: &Value) -> Result<Vec<WheatrBatchItem>, Error> {
+    let points = read_batch_points(body)?;
+    let snapshot = ObservationSnapshot::load(store)?;
+    Ok(points
+        .into_iter()
+        .map(|(id, location)| {
+            let data = location.and_then(|loc| {
+                let mut data = snapshot.get_local_data(&loc).map_err(|e| e.to_string())?;
+                data.place = boundaries.locate(&loc).cloned();
+                Ok(data)
+            });
+            match data {
+                Ok(data) => WheatrBatchItem { id, data: Some(data), error: None },
+                Err(error) => WheatrBatchItem { id, data: None, error: Some(error) },
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{testing::{observation, station}, MeteoData},
+    };
+
+    #[test]
+    fn batch_with_invalid_points() {
+        let store = MemoryStore::default();
+        let meteo_data = MeteoData {
+            stations: vec![
+                station("6155A", 36.66612, -4.482307),
+                station("6156X", 36.717785, -4.48167),
+                station("6172O", 36.716663, -4.41972),
+                station("5402", 37.844166, -4.846111),
+            ],
+            observations: vec![observation("6155A", "2023-08-10T10:00:00", 30.0), observation("6156X", "2023-08-10T10:00:00", 30.0), observation("6172O", "2023-08-10T10:00:00", 30.0)],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+
+        let body = json!([
+            { "id": "depot", "lat": 36.69528, "lon": -4.45386 },
+            { "id": 2, "lat": "north" },
+            { "type": "Feature", "id": "shop", "geometry": { "type": "Point", "coordinates": [-4.45, 36.7] } },
+        ]);
+        let items = get_batch_local_data(&store, &MunicipalityBoundaries::empty(), &body).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].id.as_deref(), Some("depot"));
+        assert!((items[0].data.as_ref().unwrap().local_air_temperature - 30.0).abs() < 0.001);
+        assert_eq!(items[1].id.as_deref(), Some("2"));
+        assert_eq!(items[1].error.as_deref(), Some("lat is not a number"));
+        assert_eq!(items[2].data.as_ref().unwrap().used_stations.len(), 3);
+
+        let multi_point = json!({ "type": "MultiPoint", "coordinates": [[-4.45, 36.7], [-4.46, 36.69]] });
+        assert_eq!(read_batch_points(&multi_point).unwrap().len(), 2);
+        assert!(read_batch_points(&json!({ "lat": 36.7 })).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use rstar::{primitives::GeomWithData, primitives::Rectangle, RTree};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    config::get_env_var_or,
+    met::{Location, Place},
+    territory::{read_geometry, Polygon},
+};
+
+pub mod shapefile;
+
+const ENV_PATH: &str = "WHEATR_MUNICIPALITIES_PATH";
+const ENV_CODE_FIELD: &str = "WHEATR_MUNICIPALITIES_CODE_FIELD";
+const ENV_NAME_FIELD: &str = "WHEATR_MUNICIPALITIES_NAME_FIELD";
+// fields of the municipal boundaries of the IGN
+const DEFAULT_CODE_FIELD: &str = "NATCODE";
+const DEFAULT_NAME_FIELD: &str = "NAMEUNIT";
+const PROVINCES_CSV: &str = include_str!("../../data/provinces.csv");
+
+/// Bounding box of a polygon with the index of the polygon.
+type IndexedBox = GeomWithData<Rectangle<[f32; 2]>, usize>;
+
+#[derive(Deserialize)]
+struct ProvinceRecord {
+    code: String,
+    province: String,
+    autonomous_community: String,
+}
+
+/// Province and autonomous community names by province code.
+fn read_provinces(content: &str) -> Result<HashMap<String, ProvinceRecord>, Error> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).comment(Some(b'#')).from_reader(content.as_bytes());
+    let mut provinces = HashMap::new();
+    for record in reader.deserialize() {
+        let record: ProvinceRecord = record.map_err(|err| Error::new(ErrorKind::InvalidData, format!("Province table parsing failed: {}", err)))?;
+        provinces.insert(record.code.clone(), record);
+    }
+    Ok(provinces)
+}
+
+/**
+ * INE code of a municipality, the last five digits of the code of a
+ * boundary: `34012929067` (the NATCODE of the IGN) and `29067` are both
+ * Málaga. Leading zeros lost to numeric fields are restored.
+ */
+fn ine_code(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code = format!("{:0>5}", value);
+    Some(code[code.len() - 5..].to_string())
+}
+
+/**
+ * Municipal boundaries, to tell the municipality of a location. Polygons
+ * are found by their bounding boxes in an R-tree, then tested exactly.
+ */
+pub struct MunicipalityBoundaries {
+    places: Vec<Place>,
+    /// Polygons with the index of their place, municipalities may have several.
+    polygons: Vec<(Polygon, usize)>,
+    index: RTree<IndexedBox>,
+}
+
+impl MunicipalityBoundaries {
+    fn new(areas: Vec<(String, String, Vec<Polygon>)>) -> Result<MunicipalityBoundaries, Error> {
+        let provinces = read_provinces(PROVINCES_CSV)?;
+        let mut places = vec![];
+        let mut polygons = vec![];
+        for (code, name, area_polygons) in areas {
+            let province = match ine_code(&code).and_then(|code| provinces.get(&code[..2]).map(|province| (code, province))) {
+                Some(p) => p,
+                None => {
+                    println!("Municipality {} has an invalid code: {}", name, code);
+                    continue;
+                }
+            };
+            let (code, province) = province;
+            polygons.extend(area_polygons.into_iter().map(|polygon| (polygon, places.len())));
+            places.push(Place {
+                label: format!("{} ({})", name, code),
+                code,
+                municipality: name,
+                province: province.province.clone(),
+                autonomous_community: province.autonomous_community.clone(),
+            });
+        }
+        let boxes = polygons
+            .iter()
+            .enumerate()
+            .map(|(i, (polygon, _))| {
+                let b = &polygon.bbox;
+                GeomWithData::new(Rectangle::from_corners([b.min_lon, b.min_lat], [b.max_lon, b.max_lat]), i)
+            })
+            .collect();
+        Ok(MunicipalityBoundaries { places, polygons, index: RTree::bulk_load(boxes) })
+    }
+
+    /// Without boundaries, no location has a municipality.
+    pub fn empty() -> MunicipalityBoundaries {
+        MunicipalityBoundaries { places: vec![], polygons: vec![], index: RTree::new() }
+    }
+
+    /**
+     * Reads the Polygon and MultiPolygon features of a GeoJSON
+     * FeatureCollection, with the municipality code and name in the
+     * properties `code_field` and `name_field`.
+     */
+    pub fn from_geojson(content: &str, code_field: &str, name_field: &str) -> Result<MunicipalityBoundaries, Error> {
+        let geojson: Value = serde_json::from_str(content).map_err(|err| Error::new(ErrorKind::InvalidData, format!("Boundaries parsing failed: {}", err)))?;
+        let features = geojson["features"].as_array().ok_or_else(|| Error::new(ErrorKind::InvalidData, "Boundaries parsing failed: no features"))?;
+        let mut areas = vec![];
+        for feature in features {
+            let property = |field: &str| match &feature["properties"][field] {
+                Value::String(value) => value.clone(),
+                Value::Number(value) => value.to_string(),
+                _ => String::new(),
+            };
+            let mut polygons = vec![];
+            read_geometry(&feature["geometry"], &mut polygons).ok_or_else(|| Error::new(ErrorKind::InvalidData, "Boundaries parsing failed: invalid geometry"))?;
+            areas.push((property(code_field), property(name_field), polygons));
+        }
+        MunicipalityBoundaries::new(areas)
+    }
+
+    /**
+     * Reads the polygons of a shapefile, `.shp` and `.dbf` contents, with
+     * the municipality code and name in the attributes `code_field` and
+     * `name_field`.
+     */
+    pub fn from_shapefile(shp: &[u8], dbf: &[u8], code_field: &str, name_field: &str) -> Result<MunicipalityBoundaries, Error> {
+        let shapes = shapefile::read_shapes(shp)?;
+        let records = shapefile::read_records(dbf)?;
+        let areas = shapes
+            .into_iter()
+            .zip(records)
+            .filter_map(|(shape, record)| {
+                let value = |field: &str| record.get(field).cloned().unwrap_or_default();
+                Some((value(code_field), value(name_field), vec![shape?]))
+            })
+            .collect();
+        MunicipalityBoundaries::new(areas)
+    }
+
+    /**
+     * Loads the GeoJSON or shapefile (`.shp`, with its `.dbf` beside it) at
+     * `WHEATR_MUNICIPALITIES_PATH`, in longitude and latitude. Without it,
+     * or if it cannot be read, the boundaries are empty.
+     */
+    pub fn from_env() -> MunicipalityBoundaries {
+        let path: String = get_env_var_or(ENV_PATH, String::new());
+        if path.is_empty() {
+            return MunicipalityBoundaries::empty();
+        }
+        let code_field: String = get_env_var_or(ENV_CODE_FIELD, DEFAULT_CODE_FIELD.to_string());
+        let name_field: String = get_env_var_or(ENV_NAME_FIELD, DEFAULT_NAME_FIELD.to_string());
+        let path = Path::new(&path);
+        let boundaries = if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("shp")) {
+            fs::read(path)
+                .and_then(|shp| Ok((shp, fs::read(path.with_extension("dbf"))?)))
+                .and_then(|(shp, dbf)| MunicipalityBoundaries::from_shapefile(&shp, &dbf, &code_field, &name_field))
+        } else {
+            fs::read_to_string(path).and_then(|content| MunicipalityBoundaries::from_geojson(&content, &code_field, &name_field))
+        };
+        match boundaries {
+            Ok(boundaries) => {
+                println!("Loaded boundaries of {} municipalities from {}", boundaries.places.len(), path.display());
+                boundaries
+            }
+            Err(e) => {
+                println!("Municipality boundaries {} are not loaded. {}", path.display(), e);
+                MunicipalityBoundaries::empty()
+            }
+        }
+    }
+
+    /**
+     * Municipalities with a (lon, lat) point inside them, in their largest
+     * polygon if they have several.
+     */
+    pub fn places(&self) -> Vec<(&Place, (f32, f32))> {
+        let mut largest: Vec<Option<&Polygon>> = vec![None; self.places.len()];
+        let area = |p: &Polygon| (p.bbox.max_lat - p.bbox.min_lat) * (p.bbox.max_lon - p.bbox.min_lon);
+        for (polygon, place) in &self.polygons {
+            if largest[*place].is_none_or(|l| area(polygon) > area(l)) {
+                largest[*place] = Some(polygon);
+            }
+        }
+        self.places
+            .iter()
+            .zip(largest)
+            .filter_map(|(place, polygon)| Some((place, polygon?.interior_point()?)))
+            .collect()
+    }
+
+    /// Municipality containing `loc`, if any.
+    pub fn locate(&self, loc: &Location) -> Option<&Place> {
+        self.index
+            .locate_all_at_point(&[loc.lon, loc.lat])
+            .map(|indexed| &self.polygons[indexed.data])
+            .find(|(polygon, _)| polygon.contains(loc))
+            .map(|(_, place)| &self.places[*place])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_municipalities_of_geojson_and_shapefile() {
+        let boundaries = MunicipalityBoundaries::from_geojson(include_str!("../../fixtures/boundaries/municipalities.geojson"), "NATCODE", "NAMEUNIT").unwrap();
+        let malaga = boundaries.locate(&Location { lat: 36.72, lon: -4.42 }).unwrap();
+        assert_eq!(malaga.label, "Málaga (29067)");
+        assert_eq!((malaga.province.as_str(), malaga.autonomous_community.as_str()), ("Málaga", "Andalucía"));
+        // Torremolinos is a MultiPolygon, its code a number
+        assert_eq!(boundaries.locate(&Location { lat: 36.62, lon: -4.50 }).unwrap().code, "29901");
+        // the hole of Málaga
+        assert!(boundaries.locate(&Location { lat: 36.75, lon: -4.35 }).is_none());
+        assert!(boundaries.locate(&Location { lat: 40.42, lon: -3.70 }).is_none());
+
+        let boundaries = MunicipalityBoundaries::from_shapefile(
+            include_bytes!("../../fixtures/boundaries/municipalities.shp"),
+            include_bytes!("../../fixtures/boundaries/municipalities.dbf"),
+            "NATCODE",
+            "NAMEUNIT",
+        )
+        .unwrap();
+        let alcala = boundaries.locate(&Location { lat: 40.48, lon: -3.36 }).unwrap();
+        assert_eq!((alcala.label.as_str(), alcala.autonomous_community.as_str()), ("Alcalá de Henares (28005)", "Comunidad de Madrid"));
+        assert_eq!(boundaries.locate(&Location { lat: 40.42, lon: -3.70 }).unwrap().municipality, "Madrid");
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+};
+
+use encoding_rs::ISO_8859_15;
+
+use crate::territory::Polygon;
+
+const SHP_FILE_CODE: i32 = 9994;
+const SHP_HEADER_LENGTH: usize = 100;
+const DBF_HEADER_TERMINATOR: u8 = 0x0d;
+const DBF_DELETED: u8 = b'*';
+
+/// Polygon shape types: Polygon, PolygonZ and PolygonM, whose X and Y parts are alike.
+const POLYGON_TYPES: [i32; 3] = [5, 15, 25];
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Shapefile parsing failed: {}", message))
+}
+
+fn read_i32_be(content: &[u8], offset: usize) -> Option<i32> {
+    Some(i32::from_be_bytes(content.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_i32_le(content: &[u8], offset: usize) -> Option<i32> {
+    Some(i32::from_le_bytes(content.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_f64_le(content: &[u8], offset: usize) -> Option<f64> {
+    Some(f64::from_le_bytes(content.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// Rings of a polygon record, `None` for other shapes.
+fn read_polygon(record: &[u8]) -> Option<Polygon> {
+    if !POLYGON_TYPES.contains(&read_i32_le(record, 0)?) {
+        return None;
+    }
+    // shape type and bounding box come first
+    let parts = read_i32_le(record, 36)? as usize;
+    let points = read_i32_le(record, 40)? as usize;
+    let points_offset = 44 + parts * 4;
+    let mut starts: Vec<usize> = (0..parts).map(|i| read_i32_le(record, 44 + i * 4).map(|s| s as usize)).collect::<Option<_>>()?;
+    starts.push(points);
+    let mut rings = vec![];
+    for window in starts.windows(2) {
+        let ring: Option<Vec<(f32, f32)>> = (window[0]..window[1])
+            .map(|i| {
+                let offset = points_offset + i * 16;
+                Some((read_f64_le(record, offset)? as f32, read_f64_le(record, offset + 8)? as f32))
+            })
+            .collect();
+        rings.push(ring?);
+    }
+    Some(Polygon::new(rings))
+}
+
+/**
+ * Reads the shapes of a `.shp` file in record order, `None` for null and
+ * non-polygon shapes. Coordinates are taken as they are, so the file has
+ * to be in longitude and latitude.
+ */
+pub fn read_shapes(content: &[u8]) -> Result<Vec<Option<Polygon>>, Error> {
+    if read_i32_be(content, 0) != Some(SHP_FILE_CODE) {
+        return Err(invalid_data("not a shapefile"));
+    }
+    let mut shapes = vec![];
+    let mut offset = SHP_HEADER_LENGTH;
+    // record headers are big endian, lengths in 16 bit words
+    while offset + 8 <= content.len() {
+        let length = read_i32_be(content, offset + 4).ok_or_else(|| invalid_data("truncated record header"))? as usize * 2;
+        let record = content.get(offset + 8..offset + 8 + length).ok_or_else(|| invalid_data("truncated record"))?;
+        shapes.push(read_polygon(record));
+        offset += 8 + length;
+    }
+    Ok(shapes)
+}
+
+/// Text of a field, which is UTF-8 or else taken as ISO-8859-15.
+fn decode_field(bytes: &[u8]) -> String {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => ISO_8859_15.decode_without_bom_handling(bytes).0.to_string(),
+    };
+    text.trim().to_string()
+}
+
+/**
+ * Reads the attribute records of a `.dbf` file in record order, as field
+ * name and trimmed text value. Deleted records are kept empty, so records
+ * stay in line with the shapes.
+ */
+pub fn read_records(content: &[u8]) -> Result<Vec<HashMap<String, String>>, Error> {
+    let header = content.get(..32).ok_or_else(|| invalid_data("truncated attribute header"))?;
+    let records = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    let header_length = u16::from_le_bytes(header[8..10].try_into().unwrap()) as usize;
+    let record_length = u16::from_le_bytes(header[10..12].try_into().unwrap()) as usize;
+
+    let mut fields = vec![];
+    let mut offset = 32;
+    while let Some(descriptor) = content.get(offset..offset + 32) {
+        if descriptor[0] == DBF_HEADER_TERMINATOR {
+            break;
+        }
+        let name_length = descriptor[..11].iter().position(|b| *b == 0).unwrap_or(11);
+        fields.push((decode_field(&descriptor[..name_length]), descriptor[16] as usize));
+        offset += 32;
+    }
+
+    // the header is not trusted for the allocation, a corrupt count fails on the first missing record
+    let mut result = Vec::with_capacity(records.min(content.len() / record_length.max(1)));
+    for i in 0..records {
+        let start = header_length + i * record_length;
+        let record = content.get(start..start + record_length).filter(|r| !r.is_empty()).ok_or_else(|| invalid_data("truncated attributes"))?;
+        let mut values = HashMap::new();
+        if record[0] != DBF_DELETED {
+            // the deletion flag comes first
+            let mut offset = 1;
+            for (name, length) in &fields {
+                let value = record.get(offset..offset + length).ok_or_else(|| invalid_data("truncated field"))?;
+                values.insert(name.clone(), decode_field(value));
+                offset += length;
+            }
+        }
+        result.push(values);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupt_record_counts_fail_without_allocating() {
+        let mut dbf = include_bytes!("../../fixtures/boundaries/municipalities.dbf").to_vec();
+        assert_eq!(read_records(&dbf).unwrap().len(), 2);
+        dbf[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_records(&dbf).unwrap_err().kind(), ErrorKind::InvalidData);
+        dbf[10..12].copy_from_slice(&0u16.to_le_bytes());
+        assert!(read_records(&dbf).is_err());
+    }
+}
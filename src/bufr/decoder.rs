@@ -0,0 +1,240 @@
+use std::io::{Error, ErrorKind};
+
+use super::tables::{split_descriptor, Descriptor, Tables};
+
+/// Nesting of sequences and replications beyond which a message is rejected.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Missing,
+    Number(f64),
+    Text(String),
+}
+
+/// A value of a subset with the element descriptor (or `205YYY` operator) it was decoded by.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataValue {
+    pub descriptor: Descriptor,
+    pub value: Value,
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, width: u32) -> Result<u64, Error> {
+        if width > 64 || self.position + width as usize > self.data.len() * 8 {
+            return Err(invalid(format!("Reading {} bits at bit {} of the data section failed", width, self.position)));
+        }
+        let mut value = 0u64;
+        for _ in 0..width {
+            let bit = (self.data[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>, Error> {
+        (0..count).map(|_| self.read(8).map(|b| b as u8)).collect()
+    }
+}
+
+fn is_all_ones(raw: u64, width: u32) -> bool {
+    width > 0 && raw == u64::MAX >> (64 - width)
+}
+
+fn to_text(bytes: Vec<u8>) -> Value {
+    if bytes.iter().all(|b| *b == 0xff) {
+        return Value::Missing;
+    }
+    Value::Text(String::from_utf8_lossy(&bytes).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string())
+}
+
+/**
+ * Decoding state of the data section. When compressed, every element holds
+ * the values of all subsets, otherwise one subset is decoded at a time.
+ */
+struct Decoder<'a, 'b> {
+    tables: &'a Tables,
+    reader: &'b mut BitReader<'a>,
+    compressed: bool,
+    subsets: usize,
+    width_change: i32,
+    scale_change: i32,
+    increased_precision: i32,
+    character_width: Option<u32>,
+    local_width: Option<u32>,
+    values: Vec<(Descriptor, Vec<Value>)>,
+}
+
+impl<'a, 'b> Decoder<'a, 'b> {
+    fn new(tables: &'a Tables, reader: &'b mut BitReader<'a>, compressed: bool, subsets: usize) -> Decoder<'a, 'b> {
+        Decoder {
+            tables,
+            reader,
+            compressed,
+            subsets,
+            width_change: 0,
+            scale_change: 0,
+            increased_precision: 0,
+            character_width: None,
+            local_width: None,
+            values: vec![],
+        }
+    }
+
+    fn read_numbers(&mut self, width: u32, scale: i32, reference: i64, can_be_missing: bool) -> Result<Vec<Value>, Error> {
+        let to_value = |raw: u64, missing: bool| if missing { Value::Missing } else { Value::Number((raw as i64 + reference) as f64 / 10f64.powi(scale)) };
+        let base = self.reader.read(width)?;
+        if !self.compressed {
+            return Ok(vec![to_value(base, can_be_missing && is_all_ones(base, width))]);
+        }
+        let increment_width = self.reader.read(6)? as u32;
+        if increment_width == 0 {
+            return Ok(vec![to_value(base, can_be_missing && is_all_ones(base, width)); self.subsets]);
+        }
+        (0..self.subsets)
+            .map(|_| self.reader.read(increment_width).map(|increment| to_value(base + increment, can_be_missing && is_all_ones(increment, increment_width))))
+            .collect()
+    }
+
+    fn read_texts(&mut self, length: usize) -> Result<Vec<Value>, Error> {
+        let base = self.reader.read_bytes(length)?;
+        if !self.compressed {
+            return Ok(vec![to_text(base)]);
+        }
+        let increment_length = self.reader.read(6)? as usize;
+        if increment_length == 0 {
+            return Ok(vec![to_text(base); self.subsets]);
+        }
+        (0..self.subsets).map(|_| self.reader.read_bytes(increment_length).map(to_text)).collect()
+    }
+
+    fn decode_element(&mut self, descriptor: Descriptor) -> Result<(), Error> {
+        let local_width = self.local_width.take();
+        let (_, class, _) = split_descriptor(descriptor);
+        let tables = self.tables;
+        let values = match (tables.elements.get(&descriptor), local_width) {
+            (Some(element), _) if element.is_text() => self.read_texts(self.character_width.unwrap_or(element.width) as usize / 8)?,
+            (Some(element), _) if element.is_code() || class == 31 => self.read_numbers(element.width, element.scale, element.reference, class != 31)?,
+            (Some(element), _) => {
+                let precision = self.increased_precision;
+                let width = element.width as i32 + self.width_change + (10 * precision + 2) / 3;
+                if !(1..=64).contains(&width) {
+                    return Err(invalid(format!("Width of {:06} is {} bits", descriptor, width)));
+                }
+                self.read_numbers(width as u32, element.scale + self.scale_change + precision, element.reference * 10i64.pow(precision as u32), true)?
+            }
+            // a local descriptor announced by 206YYY can be skipped without its definition
+            (None, Some(width)) => self.read_numbers(width, 0, 0, true)?,
+            (None, None) => {
+                return Err(invalid(format!(
+                    "Descriptor {:06} is not in the loaded tables, local tables can be set by WHEATR_BUFR_TABLE_B_PATH and WHEATR_BUFR_TABLE_D_PATH",
+                    descriptor
+                )))
+            }
+        };
+        self.values.push((descriptor, values));
+        Ok(())
+    }
+
+    fn apply_operator(&mut self, descriptor: Descriptor) -> Result<(), Error> {
+        let (_, x, y) = split_descriptor(descriptor);
+        let change = if y == 0 { 0 } else { y as i32 - 128 };
+        match (x, y) {
+            (1, _) => self.width_change = change,
+            (2, _) => self.scale_change = change,
+            (5, _) => {
+                let values = self.read_texts(y as usize)?;
+                self.values.push((descriptor, values));
+            }
+            (6, _) => self.local_width = Some(y),
+            (7, _) => self.increased_precision = y as i32,
+            (8, _) => self.character_width = if y == 0 { None } else { Some(y * 8) },
+            // markers of quality information and bitmaps, which hold no data themselves
+            (22 | 23 | 24 | 25 | 32 | 35 | 36, 0) | (37, 0 | 255) => (),
+            _ => return Err(invalid(format!("Operator {:06} is not supported", descriptor))),
+        }
+        Ok(())
+    }
+
+    fn replication_factor(&self) -> Result<usize, Error> {
+        match self.values.last().and_then(|(_, values)| values.first()) {
+            Some(Value::Number(factor)) => Ok(*factor as usize),
+            _ => Err(invalid("Delayed replication factor is missing".to_string())),
+        }
+    }
+
+    fn decode(&mut self, descriptors: &[Descriptor], depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid(format!("Descriptors are nested deeper than {} levels", MAX_DEPTH)));
+        }
+        let mut i = 0;
+        while i < descriptors.len() {
+            let descriptor = descriptors[i];
+            match split_descriptor(descriptor) {
+                (0, _, _) => self.decode_element(descriptor)?,
+                (1, count, times) => {
+                    let (times, start) = if times == 0 {
+                        let factor = descriptors.get(i + 1).copied().filter(|d| matches!(d, 31000..=31002));
+                        self.decode_element(factor.ok_or_else(|| invalid(format!("{:06} is not followed by a replication factor", descriptor)))?)?;
+                        (self.replication_factor()?, i + 2)
+                    } else {
+                        (times as usize, i + 1)
+                    };
+                    let group = descriptors
+                        .get(start..start + count as usize)
+                        .ok_or_else(|| invalid(format!("{:06} replicates more descriptors than follow", descriptor)))?;
+                    for _ in 0..times {
+                        self.decode(group, depth + 1)?;
+                    }
+                    i = start + count as usize;
+                    continue;
+                }
+                (2, _, _) => self.apply_operator(descriptor)?,
+                _ => {
+                    let tables = self.tables;
+                    let sequence = tables.sequences.get(&descriptor).ok_or_else(|| {
+                        invalid(format!("Sequence {:06} is not in the loaded tables, local tables can be set by WHEATR_BUFR_TABLE_D_PATH", descriptor))
+                    })?;
+                    self.decode(sequence, depth + 1)?;
+                }
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Decodes the data section of `subsets` subsets described by the
+ * descriptors of section 3, returning the values of every subset in order.
+ * Replication, the operators changing width, scale, precision and character
+ * width, character data and local descriptors are supported.
+ */
+pub fn decode_data(tables: &Tables, descriptors: &[Descriptor], data: &[u8], subsets: usize, compressed: bool) -> Result<Vec<Vec<DataValue>>, Error> {
+    let mut reader = BitReader { data, position: 0 };
+    let subset_values = |values: &[(Descriptor, Vec<Value>)], subset: usize| -> Vec<DataValue> {
+        values.iter().map(|(descriptor, values)| DataValue { descriptor: *descriptor, value: values[subset].clone() }).collect()
+    };
+    if compressed {
+        let mut decoder = Decoder::new(tables, &mut reader, compressed, subsets);
+        decoder.decode(descriptors, 0)?;
+        return Ok((0..subsets).map(|subset| subset_values(&decoder.values, subset)).collect());
+    }
+    let mut decoded = vec![];
+    for _ in 0..subsets {
+        let mut decoder = Decoder::new(tables, &mut reader, compressed, 1);
+        decoder.decode(descriptors, 0)?;
+        decoded.push(subset_values(&decoder.values, 0));
+    }
+    Ok(decoded)
+}
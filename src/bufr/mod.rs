@@ -0,0 +1,186 @@
+use std::io::{Error, ErrorKind};
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use self::tables::{from_bits, Descriptor, Tables};
+
+pub use self::decoder::{DataValue, Value};
+
+mod decoder;
+pub mod tables;
+#[cfg(test)]
+pub mod testing;
+
+const EDITION: u8 = 4;
+/// Master table of meteorology, the one the tables are of.
+const MASTER_TABLE: u8 = 0;
+
+/// A decoded BUFR edition 4 message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub originating_centre: u16,
+    /// International data category of BUFR Table A, 0 for surface land data.
+    pub data_category: u8,
+    pub local_table_version: u8,
+    /// Typical time of the data in section 1.
+    pub reference_time: Option<DateTime<Utc>>,
+    pub subsets: Vec<Vec<DataValue>>,
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u24(bytes: &[u8], offset: usize) -> usize {
+    u32::from_be_bytes([0, bytes[offset], bytes[offset + 1], bytes[offset + 2]]) as usize
+}
+
+/// Section starting at `offset`, its length given by its first three octets.
+fn section<'a>(message: &'a [u8], offset: usize, name: &str) -> Result<&'a [u8], Error> {
+    let length = message.get(offset..offset + 3).map(|_| read_u24(message, offset)).unwrap_or_default();
+    message.get(offset..offset + length).filter(|s| s.len() >= 4).ok_or_else(|| invalid(format!("BUFR {} is truncated", name)))
+}
+
+/**
+ * Decodes a message of edition 4 of master table 0. Subsets are decoded by
+ * the element and sequence descriptors of `tables`.
+ */
+pub fn decode_message(message: &[u8], tables: &Tables) -> Result<Message, Error> {
+    if message.len() < 12 || !message.starts_with(b"BUFR") || !message.ends_with(b"7777") {
+        return Err(invalid("Not a complete BUFR message".to_string()));
+    }
+    if message[7] != EDITION {
+        return Err(invalid(format!("BUFR edition {} is not supported", message[7])));
+    }
+    let identification = section(message, 8, "section 1")?;
+    if identification.len() < 22 {
+        return Err(invalid("BUFR section 1 is truncated".to_string()));
+    }
+    if identification[3] != MASTER_TABLE {
+        return Err(invalid(format!("BUFR master table {} is not supported", identification[3])));
+    }
+    let reference_time = NaiveDate::from_ymd_opt(read_u16(identification, 15) as i32, identification[17] as u32, identification[18] as u32)
+        .and_then(|date| date.and_hms_opt(identification[19] as u32, identification[20] as u32, identification[21] as u32))
+        .map(|time| time.and_utc());
+
+    let mut offset = 8 + identification.len();
+    if identification[9] & 0x80 != 0 {
+        offset += section(message, offset, "section 2")?.len();
+    }
+    let description = section(message, offset, "section 3")?;
+    if description.len() < 7 {
+        return Err(invalid("BUFR section 3 is truncated".to_string()));
+    }
+    let subsets = read_u16(description, 4) as usize;
+    let compressed = description[6] & 0x40 != 0;
+    // the section may be padded to an even length
+    let descriptors: Vec<Descriptor> = description[7..].chunks_exact(2).map(|pair| from_bits(read_u16(pair, 0))).filter(|d| *d != 0).collect();
+    offset += description.len();
+    let data = section(message, offset, "section 4")?;
+
+    Ok(Message {
+        originating_centre: read_u16(identification, 4),
+        data_category: identification[10],
+        local_table_version: identification[14],
+        reference_time,
+        subsets: decoder::decode_data(tables, &descriptors, &data[4..], subsets, compressed)?,
+    })
+}
+
+/**
+ * Messages of a file or bulletin, which may hold several with headings or
+ * other bytes in between, by the total length in section 0.
+ */
+pub fn split_messages(content: &[u8]) -> Vec<&[u8]> {
+    let mut messages = vec![];
+    let mut offset = 0;
+    while let Some(start) = content[offset..].windows(4).position(|w| w == b"BUFR").map(|p| offset + p) {
+        let length = content.get(start + 4..start + 7).map(|_| read_u24(content, start + 4)).unwrap_or_default();
+        match content.get(start..start + length).filter(|m| m.ends_with(b"7777")) {
+            Some(message) => {
+                messages.push(message);
+                offset = start + length;
+            }
+            None => offset = start + 4,
+        }
+    }
+    messages
+}
+
+/// First value of an element in a subset, `None` if absent or missing.
+pub fn number(subset: &[DataValue], descriptor: Descriptor) -> Option<f64> {
+    match subset.iter().find(|v| v.descriptor == descriptor).map(|v| &v.value) {
+        Some(Value::Number(number)) => Some(*number),
+        _ => None,
+    }
+}
+
+/// First text of an element in a subset, `None` if absent, missing or empty.
+pub fn text(subset: &[DataValue], descriptor: Descriptor) -> Option<&str> {
+    match subset.iter().find(|v| v.descriptor == descriptor).map(|v| &v.value) {
+        Some(Value::Text(text)) if !text.is_empty() => Some(text),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{testing::MessageBuilder, *};
+
+    #[test]
+    fn decodes_uncompressed_subsets() {
+        let descriptors = [301090, 302032, 201132, 11002, 201000, 102000, 31001, 11001, 11041];
+        let mut builder = MessageBuilder::new(&descriptors, 2, false);
+        for (name, temperature, winds) in [("MADRID BARAJAS", Some(301.65), vec![(240.0, 12.5), (250.0, 15.0)]), ("NAVACERRADA", None, vec![])] {
+            builder.number(8, 7).number(221, 10).text(name, 20).number(1, 2);
+            builder.number(2024, 12).number(7, 4).number(14, 6).number(12, 5).number(0, 6);
+            builder.value(Some(40.4667), 25, 5, -9000000).value(Some(-3.5556), 26, 5, -18000000).value(Some(609.0), 17, 1, -4000).missing(17);
+            builder.value(Some(1.5), 16, 2, 0).value(temperature, 16, 2, 0).value(Some(283.35), 16, 2, 0).missing(7);
+            builder.value(Some(3.4), 16, 1, 0).number(winds.len() as u64, 8);
+            winds.iter().for_each(|(direction, gust)| {
+                builder.value(Some(*direction), 9, 0, 0).value(Some(*gust), 12, 1, 0);
+            });
+        }
+        let message = decode_message(&builder.build(), &Tables::bundled()).unwrap();
+
+        assert_eq!(message.reference_time, Some(NaiveDate::from_ymd_opt(2024, 7, 14).unwrap().and_hms_opt(12, 0, 0).unwrap().and_utc()));
+        assert_eq!(message.subsets.len(), 2);
+        let barajas = &message.subsets[0];
+        assert_eq!((number(barajas, 1001), number(barajas, 1002)), (Some(8.0), Some(221.0)));
+        assert_eq!(text(barajas, 1015), Some("MADRID BARAJAS"));
+        assert_eq!((number(barajas, 5001), number(barajas, 6001), number(barajas, 7030)), (Some(40.4667), Some(-3.5556), Some(609.0)));
+        assert_eq!((number(barajas, 12101), number(barajas, 13003)), (Some(301.65), None));
+        assert_eq!(number(barajas, 11002), Some(3.4));
+        let gusts: Vec<f64> = barajas.iter().filter(|v| v.descriptor == 11041).filter_map(|v| if let Value::Number(n) = v.value { Some(n) } else { None }).collect();
+        assert_eq!(gusts, vec![12.5, 15.0]);
+        let navacerrada = &message.subsets[1];
+        assert_eq!((number(navacerrada, 12101), number(navacerrada, 12103)), (None, Some(283.35)));
+        assert!(!navacerrada.iter().any(|v| v.descriptor == 11001));
+    }
+
+    #[test]
+    fn decodes_compressed_subsets() {
+        let descriptors = [301001, 301011, 301012, 301023, 12101, 13003];
+        let mut builder = MessageBuilder::new(&descriptors, 3, true);
+        builder.compressed(&[Some(8.0); 3], 7, 0, 0).compressed(&[Some(221.0), Some(181.0), Some(284.0)], 10, 0, 0);
+        builder.compressed(&[Some(2024.0); 3], 12, 0, 0).compressed(&[Some(7.0); 3], 4, 0, 0).compressed(&[Some(14.0); 3], 6, 0, 0);
+        builder.compressed(&[Some(12.0); 3], 5, 0, 0).compressed(&[Some(0.0); 3], 6, 0, 0);
+        builder.compressed(&[Some(40.47), Some(41.29), Some(39.49)], 15, 2, -9000).compressed(&[Some(-3.56), Some(2.07), Some(-0.48)], 16, 2, -18000);
+        builder.compressed(&[Some(301.65), Some(299.15), None], 16, 2, 0).compressed(&[None; 3], 7, 0, 0);
+
+        let content = [b"IUSN01 LEMM 141200\r\r\n".as_slice(), &builder.build(), b"\r\r\n"].concat();
+        let messages = split_messages(&content);
+        assert_eq!(messages.len(), 1);
+        let message = decode_message(messages[0], &Tables::bundled()).unwrap();
+        let stations: Vec<Option<f64>> = message.subsets.iter().map(|s| number(s, 1002)).collect();
+        assert_eq!(stations, vec![Some(221.0), Some(181.0), Some(284.0)]);
+        let temperatures: Vec<Option<f64>> = message.subsets.iter().map(|s| number(s, 12101)).collect();
+        assert_eq!(temperatures, vec![Some(301.65), Some(299.15), None]);
+        assert_eq!(number(&message.subsets[1], 6002), Some(2.07));
+        assert!(message.subsets.iter().all(|s| number(s, 13003).is_none()));
+    }
+}
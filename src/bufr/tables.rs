@@ -0,0 +1,157 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+};
+
+use serde::Deserialize;
+
+use crate::config::get_env_var_or;
+
+const ENV_TABLE_B_PATH: &str = "WHEATR_BUFR_TABLE_B_PATH";
+const ENV_TABLE_D_PATH: &str = "WHEATR_BUFR_TABLE_D_PATH";
+const TABLE_B_CSV: &str = include_str!("../../data/bufr_table_b.csv");
+const TABLE_D_CSV: &str = include_str!("../../data/bufr_table_d.csv");
+
+/// Descriptor FXXYYY as written in the tables, e.g. 301090.
+pub type Descriptor = u32;
+
+/// F, X and Y of a descriptor.
+pub fn split_descriptor(descriptor: Descriptor) -> (u32, u32, u32) {
+    (descriptor / 100000, descriptor / 1000 % 100, descriptor % 1000)
+}
+
+/// Descriptor of its 16 bit encoding in section 3 (F 2 bits, X 6 bits, Y 8 bits).
+pub fn from_bits(bits: u16) -> Descriptor {
+    (bits >> 14) as u32 * 100000 + ((bits >> 8) & 0x3f) as u32 * 1000 + (bits & 0xff) as u32
+}
+
+fn parse_descriptor(value: &str) -> Result<Descriptor, Error> {
+    let descriptor = value.trim().parse::<Descriptor>().ok().filter(|_| value.trim().len() == 6);
+    match descriptor.map(|d| (d, split_descriptor(d))) {
+        Some((d, (f, x, y))) if f <= 3 && x < 64 && y < 256 => Ok(d),
+        _ => Err(Error::new(ErrorKind::InvalidData, format!("{} is not a FXXYYY descriptor", value))),
+    }
+}
+
+#[derive(Deserialize)]
+struct ElementRecord {
+    descriptor: String,
+    name: String,
+    unit: String,
+    scale: i32,
+    reference: i64,
+    width: u32,
+}
+
+#[derive(Deserialize)]
+struct SequenceRecord {
+    descriptor: String,
+    members: String,
+}
+
+/// Table B element: the value is `(encoded + reference) / 10^scale` in `unit`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub unit: String,
+    pub scale: i32,
+    pub reference: i64,
+    pub width: u32,
+}
+
+impl Element {
+    pub fn is_text(&self) -> bool {
+        self.unit == "CCITT IA5"
+    }
+
+    /// Code and flag table values, which operators changing width and scale leave alone.
+    pub fn is_code(&self) -> bool {
+        self.unit.starts_with("Code table") || self.unit.starts_with("Flag table")
+    }
+}
+
+#[derive(Default)]
+pub struct Tables {
+    pub elements: HashMap<Descriptor, Element>,
+    pub sequences: HashMap<Descriptor, Vec<Descriptor>>,
+}
+
+fn csv_reader(content: &str) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new().trim(csv::Trim::All).comment(Some(b'#')).from_reader(content.as_bytes())
+}
+
+/// Path and content of the local table set by `env_path`, if any.
+fn read_local_table(env_path: &str) -> Option<(String, String)> {
+    let path: String = get_env_var_or(env_path, String::new());
+    if path.is_empty() {
+        return None;
+    }
+    match fs::read_to_string(&path) {
+        Ok(content) => Some((path, content)),
+        Err(e) => {
+            println!("BUFR table {} is not loaded. {}", path, e);
+            None
+        }
+    }
+}
+
+impl Tables {
+    /**
+     * Adds the elements of a Table B CSV with the columns `descriptor`,
+     * `name`, `unit`, `scale`, `reference` and `width`, replacing known ones.
+     */
+    pub fn read_table_b(&mut self, content: &str) -> Result<usize, Error> {
+        let mut count = 0;
+        for record in csv_reader(content).deserialize() {
+            let record: ElementRecord = record.map_err(|err| Error::new(ErrorKind::InvalidData, format!("Table B parsing failed: {}", err)))?;
+            let element = Element { name: record.name, unit: record.unit, scale: record.scale, reference: record.reference, width: record.width };
+            self.elements.insert(parse_descriptor(&record.descriptor)?, element);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /**
+     * Adds the sequences of a Table D CSV with the columns `descriptor` and
+     * `members`, the latter separated by spaces, replacing known ones.
+     */
+    pub fn read_table_d(&mut self, content: &str) -> Result<usize, Error> {
+        let mut count = 0;
+        for record in csv_reader(content).deserialize() {
+            let record: SequenceRecord = record.map_err(|err| Error::new(ErrorKind::InvalidData, format!("Table D parsing failed: {}", err)))?;
+            let members = record.members.split_whitespace().map(parse_descriptor).collect::<Result<Vec<Descriptor>, Error>>()?;
+            self.sequences.insert(parse_descriptor(&record.descriptor)?, members);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// The bundled tables, covering surface land observations.
+    pub fn bundled() -> Tables {
+        let mut tables = Tables::default();
+        tables.read_table_b(TABLE_B_CSV).expect("bundled table B is valid");
+        tables.read_table_d(TABLE_D_CSV).expect("bundled table D is valid");
+        tables
+    }
+
+    /**
+     * The bundled tables extended by the local ones at the paths set by
+     * `WHEATR_BUFR_TABLE_B_PATH` and `WHEATR_BUFR_TABLE_D_PATH`, whose
+     * entries take precedence.
+     */
+    pub fn load() -> Tables {
+        let mut tables = Tables::bundled();
+        if let Some((path, content)) = read_local_table(ENV_TABLE_B_PATH) {
+            if let Err(e) = tables.read_table_b(&content) {
+                println!("BUFR table {} is not loaded. {}", path, e);
+            }
+        }
+        if let Some((path, content)) = read_local_table(ENV_TABLE_D_PATH) {
+            if let Err(e) = tables.read_table_d(&content) {
+                println!("BUFR table {} is not loaded. {}", path, e);
+            }
+        }
+        tables
+    }
+}
@@ -0,0 +1,103 @@
+use super::tables::{split_descriptor, Descriptor};
+
+/// Encodes BUFR edition 4 messages of surface land data to test decoding with.
+pub struct MessageBuilder {
+    descriptors: Vec<Descriptor>,
+    subsets: u16,
+    compressed: bool,
+    data: Vec<u8>,
+    bits: usize,
+}
+
+fn all_ones(width: u32) -> u64 {
+    u64::MAX >> (64 - width)
+}
+
+fn encode(value: f64, scale: i32, reference: i64) -> u64 {
+    ((value * 10f64.powi(scale)).round() as i64 - reference) as u64
+}
+
+impl MessageBuilder {
+    pub fn new(descriptors: &[Descriptor], subsets: u16, compressed: bool) -> MessageBuilder {
+        MessageBuilder { descriptors: descriptors.to_vec(), subsets, compressed, data: vec![], bits: 0 }
+    }
+
+    fn push(&mut self, value: u64, width: u32) -> &mut MessageBuilder {
+        for bit in (0..width).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            let last = self.data.len() - 1;
+            self.data[last] |= (((value >> bit) & 1) as u8) << (7 - self.bits % 8);
+            self.bits += 1;
+        }
+        self
+    }
+
+    pub fn number(&mut self, value: u64, width: u32) -> &mut MessageBuilder {
+        self.push(value, width)
+    }
+
+    pub fn value(&mut self, value: Option<f64>, width: u32, scale: i32, reference: i64) -> &mut MessageBuilder {
+        self.push(value.map_or(all_ones(width), |v| encode(v, scale, reference)), width)
+    }
+
+    pub fn missing(&mut self, width: u32) -> &mut MessageBuilder {
+        self.push(all_ones(width), width)
+    }
+
+    /// Text padded with spaces to `length` characters.
+    pub fn text(&mut self, value: &str, length: usize) -> &mut MessageBuilder {
+        format!("{:<1$}", value, length).bytes().for_each(|b| {
+            self.push(b as u64, 8);
+        });
+        self
+    }
+
+    /// Values of all subsets as the minimum and increments, missing ones all ones.
+    pub fn compressed(&mut self, values: &[Option<f64>], width: u32, scale: i32, reference: i64) -> &mut MessageBuilder {
+        let raw: Vec<Option<u64>> = values.iter().map(|v| v.map(|v| encode(v, scale, reference))).collect();
+        if raw.iter().all(|r| *r == raw[0]) {
+            return self.push(raw[0].unwrap_or(all_ones(width)), width).push(0, 6);
+        }
+        let base = raw.iter().flatten().min().copied().unwrap_or_default();
+        let largest = raw.iter().flatten().map(|r| r - base).max().unwrap_or_default();
+        // the largest increment must not be all ones, which is missing
+        let increment_width = 64 - (largest + 1).leading_zeros();
+        self.push(base, width).push(increment_width as u64, 6);
+        for r in raw {
+            self.push(r.map_or(all_ones(increment_width), |r| r - base), increment_width);
+        }
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut identification = vec![0, 0, 22, 0, 0, 214, 0, 0, 0, 0, 0, 0, 0, 38, 0];
+        identification.extend_from_slice(&2024u16.to_be_bytes());
+        identification.extend_from_slice(&[7, 14, 12, 0, 0]);
+
+        let mut description = vec![0, 0, 0, 0];
+        description.extend_from_slice(&self.subsets.to_be_bytes());
+        description.push(if self.compressed { 0xc0 } else { 0x80 });
+        for descriptor in &self.descriptors {
+            let (f, x, y) = split_descriptor(*descriptor);
+            description.extend_from_slice(&((f << 14 | x << 8 | y) as u16).to_be_bytes());
+        }
+        let mut data = vec![0, 0, 0, 0];
+        data.extend_from_slice(&self.data);
+        for section in [&mut description, &mut data] {
+            if section.len() % 2 == 1 {
+                section.push(0);
+            }
+            let length = (section.len() as u32).to_be_bytes();
+            section[0..3].copy_from_slice(&length[1..4]);
+        }
+
+        let length = 8 + identification.len() + description.len() + data.len() + 4;
+        let mut message = b"BUFR".to_vec();
+        message.extend_from_slice(&(length as u32).to_be_bytes()[1..4]);
+        message.push(4);
+        [identification, description, data, b"7777".to_vec()].iter().for_each(|s| message.extend_from_slice(s));
+        message
+    }
+}
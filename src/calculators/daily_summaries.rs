@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use super::location_data_calculations::calculate_heat_index;
+use crate::met::{Aggregate, DailySummary, Observation};
+
+fn aggregate(values: &[f32]) -> Aggregate {
+    Aggregate {
+        min: values.iter().copied().fold(f32::INFINITY, f32::min),
+        max: values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        mean: values.iter().sum::<f32>() / values.len() as f32,
+    }
+}
+
+/// Start of the UTC day after the one of `time`, where the roll-up of its day ends.
+pub fn next_day_start(time: DateTime<Utc>) -> DateTime<Utc> {
+    (time.date_naive() + Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+/**
+ * Rolls observations up into one summary per station and UTC day, with
+ * minimum, maximum and mean of temperature, humidity and heat index.
+ */
+pub fn summarize_daily(observations: &[Observation]) -> Vec<DailySummary> {
+    let mut groups: BTreeMap<(&str, NaiveDate), Vec<&Observation>> = BTreeMap::new();
+    for observation in observations {
+        groups
+            .entry((&observation.station_id, observation.observation_time.date_naive()))
+            .or_default()
+            .push(observation);
+    }
+    groups
+        .into_iter()
+        .map(|((station_id, day), group)| {
+            let temperatures: Vec<f32> = group.iter().map(|o| o.aerial_temperature).collect();
+            let humidities: Vec<f32> = group.iter().map(|o| o.relative_humidity).collect();
+            let heat_indexes: Vec<f32> = group
+                .iter()
+                .map(|o| calculate_heat_index(o.aerial_temperature, o.relative_humidity))
+                .collect();
+            DailySummary {
+                station_id: station_id.to_string(),
+                day,
+                samples: group.len() as u32,
+                aerial_temperature: aggregate(&temperatures),
+                relative_humidity: aggregate(&humidities),
+                heat_index: aggregate(&heat_indexes),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::met::parse_observation_time;
+
+    fn observation(station_id: &str, time: &str, aerial_temperature: f32, relative_humidity: f32) -> Observation {
+        Observation {
+            station_id: station_id.to_string(),
+            observation_time: parse_observation_time(time).unwrap(),
+            aerial_temperature,
+            relative_humidity,
+        }
+    }
+
+    #[test]
+    fn summarize_per_station_and_day() {
+        let summaries = summarize_daily(&[
+            observation("6155A", "2023-08-10T10:00:00", 29.0, 40.0),
+            observation("6155A", "2023-08-10T14:00:00", 35.0, 60.0),
+            observation("6155A", "2023-08-10T23:00:00", 17.0, 80.0),
+            observation("6155A", "2023-08-11T00:00:00", 16.0, 85.0),
+            observation("5402", "2023-08-10T10:00:00", 30.0, 30.0),
+        ]);
+
+        assert_eq!(summaries.len(), 3);
+        let malaga = &summaries[1];
+        assert_eq!(malaga.station_id, "6155A");
+        assert_eq!(malaga.day, NaiveDate::from_ymd_opt(2023, 8, 10).unwrap());
+        assert_eq!(malaga.samples, 3);
+        assert_eq!(malaga.aerial_temperature, Aggregate { min: 17.0, max: 35.0, mean: 27.0 });
+        assert_eq!(malaga.heat_index.min, 17.0);
+        assert_eq!(malaga.heat_index.max, 45.050167);
+    }
+}
@@ -1,12 +1,12 @@
-use crate::met::{Observation, Station, Location};
+use crate::met::{Observation, Station, Location, WheatrApiResponseData};
 
 // Heat index calculation constants
-const C1: f32 = -8.78469475556;
-const C2: f32 = 1.61139411;
-const C3: f32 = 2.33854883889;
+const C1: f32 = -8.784695;
+const C2: f32 = 1.6113942;
+const C3: f32 = 2.338549;
 const C4: f32 = -0.14611605;
 const C5: f32 = -1.2308094e-2;
-const C6: f32 = -1.64248277778e-2;
+const C6: f32 = -1.6424827e-2;
 const C7: f32 = 2.211732e-3;
 const C8: f32 = 7.2546e-4;
 const C9: f32 = -3.582e-6;
@@ -19,12 +19,12 @@ pub struct LocatedValue {
     val: f32,
 }
 
-pub fn get_located_values(stations: &[Station; 3], observations: &[Observation; 3], get_value: &dyn Fn(&Observation) -> f32) -> [LocatedValue; 3] {
+/// Values of the stations at their positions, `None` if a station has no observation.
+pub fn get_located_values(stations: &[Station; 3], observations: &[Observation; 3], get_value: &dyn Fn(&Observation) -> f32) -> Option<[LocatedValue; 3]> {
     let mut located_values: [LocatedValue; 3] = Default::default();
     for i in 0..stations.len() {
         let station = &stations[i];
-        let rel_observation_pos = observations.iter().position(|o| o.station_id == station.id).unwrap();
-        let observation = &observations[rel_observation_pos];
+        let observation = observations.iter().find(|o| o.station_id == station.id)?;
         let l_value = LocatedValue {
             lat: station.lat,
             lon: station.lon,
@@ -32,7 +32,7 @@ pub fn get_located_values(stations: &[Station; 3], observations: &[Observation;
         };
         located_values[i] = l_value;
     }
-    located_values
+    Some(located_values)
 }
 
 pub fn calculate_local_data(location: &Location, known_points: &[LocatedValue; 3]) -> f32 {
@@ -45,11 +45,10 @@ pub fn calculate_local_data(location: &Location, known_points: &[LocatedValue; 3
         lon: ((pb.lat - pa.lat) * (pc.val - pa.val)) - ((pb.val - pa.val) * (pc.lat - pa.lat)),
         val: ((pb.lat - pa.lat) * (pc.lon - pa.lon)) - ((pb.lon - pa.lon) * (pc.lat - pa.lat)),
     };
-    let pd_val = ((nv.lat * pa.lat) - (nv.lon * pa.lon) + (nv.val * pa.val)
+    ((nv.lat * pa.lat) - (nv.lon * pa.lon) + (nv.val * pa.val)
         - (nv.lat * pd.lat)
         - (-nv.lon * pd.lon))
-        / nv.val;
-    pd_val
+        / nv.val
 }
 
 /**
@@ -64,7 +63,7 @@ pub fn calculate_heat_index(temperature: f32, humidity: f32) -> f32 {
     }
     let t_pow2 = temperature.powi(2);
     let h_pow2 = humidity.powi(2);
-    let hi = C1
+    C1
         + C2 * temperature
         + C3 * humidity
         + C4 * temperature * humidity
@@ -72,8 +71,53 @@ pub fn calculate_heat_index(temperature: f32, humidity: f32) -> f32 {
         + C6 * h_pow2
         + C7 * t_pow2 * humidity
         + C8 * temperature * h_pow2
-        + C9 * t_pow2 * h_pow2;
-    hi
+        + C9 * t_pow2 * h_pow2
+}
+
+/**
+ * Relative humidity (%) from temperature and dew point (°C) by the Magnus
+ * formula with the coefficients of Alduchov and Eskridge 1996.
+ */
+pub fn calculate_relative_humidity(temperature: f32, dew_point: f32) -> f32 {
+    let saturation = |t: f32| (17.625 * t / (243.04 + t)).exp();
+    (100.0 * saturation(dew_point) / saturation(temperature)).min(100.0)
+}
+
+/**
+ * Interpolates temperature and humidity at the location from the
+ * observations of the three stations, returning them with the heat index.
+ * `None` if a station has no observation among them.
+ */
+pub fn calculate_local_values(location: &Location, stations: &[Station; 3], observations: &[Observation; 3]) -> Option<(f32, f32, f32)> {
+    let located_temperature_values = get_located_values(stations, observations, &|o| { o.aerial_temperature })?;
+    let located_humidity_values = get_located_values(stations, observations, &|o| { o.relative_humidity })?;
+    let temperature = calculate_local_data(location, &located_temperature_values);
+    let humidity = calculate_local_data(location, &located_humidity_values);
+    Some((temperature, humidity, calculate_heat_index(temperature, humidity)))
+}
+
+/**
+ * API response of the location from the observations of the three
+ * stations, timed by the latest of them. `None` if a station has no
+ * observation among them.
+ */
+pub fn calculate_response_data(location: &Location, stations: &[Station; 3], observations: &[Observation; 3]) -> Option<WheatrApiResponseData> {
+    let (local_air_temperature, local_rel_humidity, local_hi) = calculate_local_values(location, stations, observations)?;
+    let observation_time = observations.iter().map(|o| o.observation_time).max().unwrap_or_default();
+    let local_timezone = location.timezone();
+    Some(WheatrApiResponseData {
+        used_stations: stations.to_vec(),
+        local_air_temperature,
+        local_hi,
+        local_lat: location.lat,
+        local_lon: location.lon,
+        local_rel_humidity,
+        observation_time,
+        observation_local_time: observation_time.with_timezone(&local_timezone).to_rfc3339(),
+        local_timezone: local_timezone.name().to_string(),
+        warnings: vec![],
+        place: None,
+    })
 }
 
 #[cfg(test)]
@@ -83,7 +127,7 @@ mod tests {
     #[test]
     fn calculate_temperature() {
         let location = Location {
-            lat: 36.6952842,
+            lat: 36.695286,
             lon: -4.4538607,
         };
         let point_1 = LocatedValue {
@@ -112,4 +156,10 @@ mod tests {
         assert_eq!(calculate_heat_index(29.0, 40.0), 28.606316);
         assert_eq!(calculate_heat_index(35.0, 60.0), 45.050167);
     }
+    #[test]
+    fn calculate_rh_from_dew_point() {
+        assert_eq!((calculate_relative_humidity(25.0, 12.0) * 10.0).round(), 443.0);
+        assert_eq!((calculate_relative_humidity(-2.0, -5.0) * 10.0).round(), 799.0);
+        assert_eq!(calculate_relative_humidity(20.0, 20.0), 100.0);
+    }
 }
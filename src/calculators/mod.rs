@@ -1 +1,2 @@
+pub mod daily_summaries;
 pub mod location_data_calculations;
@@ -0,0 +1,46 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+/**
+ * Reads an optional environment variable, falling back to `default` if it
+ * is unset or invalid.
+ */
+pub fn get_env_var_or<T: FromStr + Display>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(val) => match val.parse() {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                println!("{} is invalid: {}, using {}", key, val, default);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+pub fn invalid_input(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/**
+ * Reads `--name value` command line arguments. Flags without a value, like
+ * `--join`, are `true`.
+ */
+pub fn parse_args(args: &[String]) -> Result<HashMap<String, String>, Error> {
+    let mut values = HashMap::new();
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let name = arg.strip_prefix("--").ok_or_else(|| invalid_input(format!("Unexpected argument: {}", arg)))?;
+        let value = match args.peek() {
+            Some(value) if !value.starts_with("--") => args.next().unwrap().clone(),
+            _ => "true".to_string(),
+        };
+        values.insert(name.to_string(), value);
+    }
+    Ok(values)
+}
@@ -4,15 +4,18 @@ use std::fmt::{Display, Formatter};
 use std::io::{Cursor, Read, Result};
 use std::{env, process};
 
-use crate::met::{MeteoData, Observation, Station};
+use crate::met::{parse_observation_time, MeteoData, Observation, Station};
 
 use super::downloader;
 
-const ENV_API_KEY: &str = "AEMET_API_KEY";
+pub const PROVIDER: &str = "aemet";
+
+pub const ENV_API_KEY: &str = "AEMET_API_KEY";
 const ENV_URL: &str = "AEMET_URL";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AemetData {
+    pub alt: Option<f32>, // Station altitude
     pub fint: String,    // Observation time
     pub idema: String,   // Station ID
     pub hr: Option<f32>, // Relative humidity
@@ -23,14 +26,8 @@ pub struct AemetData {
 }
 impl Display for AemetData {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        let hr = match self.hr {
-            Some(hr) => hr,
-            None => -1.0,
-        };
-        let ta = match self.ta {
-            Some(ta) => ta,
-            None => -1.0,
-        };
+        let hr = self.hr.unwrap_or(-1.0);
+        let ta = self.ta.unwrap_or(-1.0);
         write!(
             f,
             "{} {} {} {} {} {} {}",
@@ -39,23 +36,22 @@ impl Display for AemetData {
     }
 }
 
+/// First response of the AEMET OpenData API, linking to the data.
 #[derive(Deserialize)]
 pub struct AemetFirstResponse {
-    pub estado: i32,
     pub datos: String,
-    pub metadatos: String,
 }
 
 fn get_env_var(key: &str) -> String {
     match env::var(key) {
         Ok(val) => {
-            return val.to_string();
+            val.to_string()
         },
         Err(e) => {
             println!("{} is undefined: {}", key, e);
             process::exit(0x001)
         }
-    };
+    }
 }
 
 fn vec_to_string(content: Vec<u8>) -> Result<String> {
@@ -87,40 +83,66 @@ fn read_data_set(content: &str) -> Result<Vec<AemetData>> {
 fn convert_to_data_objects(data_set: &Vec<AemetData>) -> MeteoData {
     let mut stations: Vec<Station> = vec![];
     let mut observations: Vec<Observation> = vec![];
+    let mut skipped_observations = 0;
     for data_entry in data_set {
         let station = Station {
             id: data_entry.idema.clone(),
             name: data_entry.ubi.clone(),
             lat: data_entry.lat,
             lon: data_entry.lon,
+            altitude: data_entry.alt,
+            provider: PROVIDER.to_string(),
         };
         stations.push(station);
-        if data_entry.ta.is_some() && data_entry.hr.is_some() {
+        if let (Some(ta), Some(hr)) = (data_entry.ta, data_entry.hr) {
+            let observation_time = match parse_observation_time(&data_entry.fint) {
+                Ok(ot) => ot,
+                Err(e) => {
+                    println!("Invalid observation time {} of {}: {}", data_entry.fint, data_entry.idema, e);
+                    skipped_observations += 1;
+                    continue;
+                }
+            };
             let observation = Observation {
                 station_id: data_entry.idema.clone(),
-                aerial_temperature: data_entry.ta.unwrap(),
-                observation_time: data_entry.fint.clone(),
-                relative_humidity: data_entry.hr.unwrap(),
+                aerial_temperature: ta,
+                observation_time,
+                relative_humidity: hr,
             };
             observations.push(observation);
+        } else {
+            skipped_observations += 1;
         }
     }
     MeteoData {
-        stations: stations,
-        observations: observations,
+        stations,
+        observations,
+        skipped_observations,
     }
 }
 
+/**
+ * Downloads the raw data of AEMET OpenData, whose requests are answered by
+ * the URL of the data to download next.
+ */
+pub fn download_data(url: &str, api_key: &str) -> Result<Vec<u8>> {
+    let main_download_reader = downloader::download_content(url, api_key)?;
+    let main_download_content = vec_to_string(main_download_reader)?;
+    let main_download_url = read_main_download_json(&main_download_content)?;
+    downloader::download_content(&main_download_url, api_key)
+}
+
+/// Downloads a data set of AEMET OpenData as text.
+pub fn download_data_set(url: &str, api_key: &str) -> Result<String> {
+    vec_to_string(download_data(url, api_key)?)
+}
+
 pub fn load_data() -> Result<MeteoData> {
     let api_key = get_env_var(ENV_API_KEY);
     let url = get_env_var(ENV_URL);
 
-    let main_download_reader = downloader::download_content(&url, &api_key)?;
-    let main_download_content = vec_to_string(main_download_reader)?;
-    let main_download_url = read_main_download_json(&main_download_content)?;
-    let data_reader = downloader::download_content(&main_download_url, &api_key)?;
-    let data_content = vec_to_string(data_reader)?;
-    let data_set = read_data_set(&data_content.as_str())?;
+    let data_content = download_data_set(&url, &api_key)?;
+    let data_set = read_data_set(data_content.as_str())?;
     let meteo_data = convert_to_data_objects(&data_set);
     Ok(meteo_data)
 }
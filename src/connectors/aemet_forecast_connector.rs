@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+use crate::{
+    config::get_env_var_or,
+    met::{Forecast, ForecastData, Location, Municipality},
+};
+
+use super::aemet_connector::{download_data_set, ENV_API_KEY};
+
+pub const PROVIDER: &str = "aemet-forecast";
+
+const ENV_URL: &str = "WHEATR_AEMET_FORECAST_URL";
+const ENV_MUNICIPALITIES: &str = "WHEATR_FORECAST_MUNICIPALITIES";
+const DEFAULT_URL: &str = "https://opendata.aemet.es/opendata/api";
+const MUNICIPALITIES_PATH: &str = "maestro/municipios";
+const FORECAST_PATH: &str = "prediccion/especifica/municipio/horaria";
+
+/// Municipality of the AEMET master table, numbers are strings.
+#[derive(Debug, Deserialize)]
+pub struct AemetMunicipality {
+    /// INE code prefixed by `id`, e.g. `id29067`.
+    pub id: String,
+    pub nombre: String,
+    pub latitud_dec: String,
+    pub longitud_dec: String,
+    pub altitud: Option<String>,
+}
+
+/// Value of an hour (`periodo`, local time).
+#[derive(Debug, Deserialize)]
+pub struct AemetHourlyValue {
+    pub value: String,
+    pub periodo: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AemetForecastDay {
+    pub fecha: String,
+    pub temperatura: Vec<AemetHourlyValue>,
+    pub humedad_relativa: Vec<AemetHourlyValue>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AemetPrediction {
+    pub dia: Vec<AemetForecastDay>,
+}
+
+/// Hourly forecast of a municipality, times are local.
+#[derive(Debug, Deserialize)]
+pub struct AemetMunicipalForecast {
+    pub elaborado: String,
+    pub provincia: Option<String>,
+    pub prediccion: AemetPrediction,
+}
+
+/// Whether municipalities to forecast are set.
+pub fn is_configured() -> bool {
+    !municipality_codes().is_empty()
+}
+
+fn municipality_codes() -> Vec<String> {
+    get_env_var_or(ENV_MUNICIPALITIES, String::new())
+        .split(',')
+        .map(|code| code.trim().to_string())
+        .filter(|code| !code.is_empty())
+        .collect()
+}
+
+fn to_municipality(municipality: &AemetMunicipality, province: Option<String>) -> Option<Municipality> {
+    Some(Municipality {
+        id: municipality.id.trim_start_matches("id").to_string(),
+        name: municipality.nombre.clone(),
+        province,
+        lat: municipality.latitud_dec.parse().ok()?,
+        lon: municipality.longitud_dec.parse().ok()?,
+        altitude: municipality.altitud.as_ref().and_then(|a| a.parse().ok()),
+    })
+}
+
+fn to_utc(timezone: &Tz, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+    timezone.from_local_datetime(&time).earliest().map(|time| time.with_timezone(&Utc))
+}
+
+/// Values of a day by local hour.
+fn hourly_values(values: &[AemetHourlyValue]) -> HashMap<u32, f32> {
+    values
+        .iter()
+        .filter_map(|v| Some((v.periodo.get(..2)?.parse().ok()?, v.value.parse().ok()?)))
+        .collect()
+}
+
+/**
+ * Adds the hourly forecasts of a municipality, pairing temperature and
+ * humidity of the same hour. Local times are converted to UTC by the
+ * timezone of the municipality. Hours with only one of them are skipped.
+ */
+pub fn add_forecast(municipality: &AemetMunicipality, forecast: &AemetMunicipalForecast, forecast_data: &mut ForecastData) {
+    let municipality = match to_municipality(municipality, forecast.provincia.clone()) {
+        Some(m) => m,
+        None => {
+            println!("Invalid position of municipality {}", municipality.id);
+            return;
+        }
+    };
+    let timezone = Location { lat: municipality.lat, lon: municipality.lon }.timezone();
+    let elaborated_at = match NaiveDateTime::parse_from_str(&forecast.elaborado, "%Y-%m-%dT%H:%M:%S").ok().and_then(|t| to_utc(&timezone, t)) {
+        Some(t) => t,
+        None => {
+            println!("Invalid elaboration time {} of municipality {}", forecast.elaborado, municipality.id);
+            return;
+        }
+    };
+    for day in &forecast.prediccion.dia {
+        let date = match NaiveDate::parse_from_str(day.fecha.get(..10).unwrap_or_default(), "%Y-%m-%d") {
+            Ok(d) => d,
+            Err(_) => {
+                forecast_data.skipped_forecasts += day.temperatura.len();
+                continue;
+            }
+        };
+        let temperatures = hourly_values(&day.temperatura);
+        let humidities = hourly_values(&day.humedad_relativa);
+        let mut hours: Vec<&u32> = temperatures.keys().chain(humidities.keys()).collect();
+        hours.sort();
+        hours.dedup();
+        for hour in hours {
+            let forecast_time = date.and_hms_opt(*hour, 0, 0).and_then(|t| to_utc(&timezone, t));
+            match (forecast_time, temperatures.get(hour), humidities.get(hour)) {
+                (Some(forecast_time), Some(temperature), Some(humidity)) => forecast_data.forecasts.push(Forecast {
+                    municipality_id: municipality.id.clone(),
+                    forecast_time,
+                    aerial_temperature: *temperature,
+                    relative_humidity: *humidity,
+                    elaborated_at,
+                }),
+                _ => forecast_data.skipped_forecasts += 1,
+            }
+        }
+    }
+    forecast_data.municipalities.push(municipality);
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(content: &str) -> Result<T, Error> {
+    serde_json::from_str(content).map_err(|err| Error::new(ErrorKind::InvalidData, format!("Parsing forecast data failed: {}", err)))
+}
+
+/**
+ * Loads the hourly forecasts of the municipalities in
+ * `WHEATR_FORECAST_MUNICIPALITIES` (comma separated INE codes) from AEMET
+ * OpenData, one request per municipality. Municipalities whose forecast
+ * cannot be loaded are left out.
+ */
+pub fn load_data() -> Result<ForecastData, Error> {
+    let codes = municipality_codes();
+    if codes.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} is not set", ENV_MUNICIPALITIES)));
+    }
+    let api_key: String = get_env_var_or(ENV_API_KEY, String::new());
+    let url: String = get_env_var_or(ENV_URL, DEFAULT_URL.to_string());
+    let url = url.trim_end_matches('/');
+    let municipalities: Vec<AemetMunicipality> = parse_json(&download_data_set(&format!("{}/{}", url, MUNICIPALITIES_PATH), &api_key)?)?;
+    let known: HashMap<&str, &AemetMunicipality> = municipalities.iter().map(|m| (m.id.trim_start_matches("id"), m)).collect();
+    let mut forecast_data = ForecastData::default();
+    for code in &codes {
+        let municipality = match known.get(code.as_str()) {
+            Some(m) => m,
+            None => {
+                println!("Municipality {} is unknown", code);
+                continue;
+            }
+        };
+        let forecasts = download_data_set(&format!("{}/{}/{}", url, FORECAST_PATH, code), &api_key)
+            .and_then(|content| parse_json::<Vec<AemetMunicipalForecast>>(&content));
+        match forecasts {
+            Ok(forecasts) => forecasts.iter().for_each(|f| add_forecast(municipality, f, &mut forecast_data)),
+            Err(e) => println!("Forecast of municipality {} is not loaded. {}", code, e),
+        }
+    }
+    Ok(forecast_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::met::parse_observation_time;
+
+    #[test]
+    fn pairs_hourly_values_in_utc() {
+        let municipalities: Vec<AemetMunicipality> = parse_json(include_str!("../../fixtures/aemet/municipios.json")).unwrap();
+        let forecasts: Vec<AemetMunicipalForecast> = parse_json(include_str!("../../fixtures/aemet/horaria-29067.json")).unwrap();
+        let mut data = ForecastData::default();
+        add_forecast(&municipalities[0], &forecasts[0], &mut data);
+
+        let municipality = &data.municipalities[0];
+        assert_eq!((municipality.id.as_str(), municipality.province.as_deref(), municipality.altitude), ("29067", Some("Málaga"), Some(8.0)));
+        assert_eq!(data.forecasts.len(), 5);
+        // local summer time is UTC+2
+        let first = &data.forecasts[0];
+        assert_eq!(first.forecast_time, parse_observation_time("2024-07-14T20:00:00Z").unwrap());
+        assert_eq!(first.elaborated_at, parse_observation_time("2024-07-14T07:48:30Z").unwrap());
+        assert_eq!((data.forecasts[4].aerial_temperature, data.forecasts[4].relative_humidity), (31.0, 55.0));
+        // no humidity at 23h
+        assert_eq!(data.skipped_forecasts, 1);
+    }
+}
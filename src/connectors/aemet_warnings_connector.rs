@@ -0,0 +1,246 @@
+use std::{
+    collections::HashSet,
+    io::{Cursor, Error, ErrorKind, Read},
+};
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use roxmltree::{Document, Node};
+
+use crate::{config::get_env_var_or, met::Warning};
+
+use super::aemet_connector::{download_data, ENV_API_KEY};
+
+pub const PROVIDER: &str = "aemet-warnings";
+
+const ENV_URL: &str = "WHEATR_AEMET_WARNINGS_URL";
+const ENV_AREA: &str = "WHEATR_WARNINGS_AREA";
+const DEFAULT_URL: &str = "https://opendata.aemet.es/opendata/api";
+const WARNINGS_PATH: &str = "avisos_cap/ultimoelaborado/area";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const PHENOMENON_CODE: &str = "AEMET-Meteoalerta fenomeno";
+const LEVEL_PARAMETER: &str = "AEMET-Meteoalerta nivel";
+const LANGUAGE: &str = "es-ES";
+
+/// CAP alert, with a warning per area of its phenomena of interest.
+struct CapAlert {
+    identifier: String,
+    msg_type: String,
+    /// Identifiers of the alerts updated or cancelled by this one.
+    references: Vec<String>,
+    warnings: Vec<Warning>,
+}
+
+/// Whether the warnings area (`esp` or the code of a region) is set.
+pub fn is_configured() -> bool {
+    !get_env_var_or(ENV_AREA, String::new()).is_empty()
+}
+
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<Node<'a, 'a>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node, name).and_then(|n| n.text()).map(|text| text.trim().to_string())
+}
+
+/// Value of the `eventCode` or `parameter` children of `info` named `value_name`.
+fn named_value(info: Node, element: &str, value_name: &str) -> Option<String> {
+    info.children()
+        .filter(|n| n.tag_name().name() == element)
+        .find(|n| child_text(*n, "valueName").as_deref() == Some(value_name))
+        .and_then(|n| child_text(n, "value"))
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|t| t.with_timezone(&Utc))
+}
+
+/// Kind of a Meteoalerta phenomenon, `AT;Temperaturas máximas` is heat and `BT;…` cold.
+fn warning_kind(phenomenon: &str) -> Option<&'static str> {
+    match phenomenon.split(';').next().map(str::trim) {
+        Some("AT") => Some("heat"),
+        Some("BT") => Some("cold"),
+        _ => None,
+    }
+}
+
+/// Points of a CAP polygon, `lat,lon lat,lon …`, as (lon, lat).
+fn parse_polygon(value: &str) -> Option<Vec<(f32, f32)>> {
+    value
+        .split_whitespace()
+        .map(|point| {
+            let (lat, lon) = point.split_once(',')?;
+            Some((lon.trim().parse().ok()?, lat.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/**
+ * Parses a CAP 1.2 alert of AEMET. The Spanish `info` is preferred to the
+ * English one, and only heat and cold warnings of a level above green are
+ * kept, one per area with all of its polygons.
+ */
+fn parse_alert(content: &str) -> Result<CapAlert, Error> {
+    let document = Document::parse(content).map_err(|err| Error::new(ErrorKind::InvalidData, format!("Parsing warning failed: {}", err)))?;
+    let alert = document.root_element();
+    let identifier = child_text(alert, "identifier").ok_or_else(|| Error::new(ErrorKind::InvalidData, "Warning without identifier"))?;
+    let msg_type = child_text(alert, "msgType").unwrap_or_default();
+    // references are `sender,identifier,sent` separated by whitespace
+    let references = child_text(alert, "references")
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|reference| reference.split(',').nth(1).map(str::to_string))
+        .collect();
+    let infos: Vec<Node> = alert.children().filter(|n| n.tag_name().name() == "info").collect();
+    let info = infos.iter().find(|i| child_text(**i, "language").as_deref() == Some(LANGUAGE)).or(infos.first());
+
+    let mut warnings = vec![];
+    let info = match info {
+        Some(i) => *i,
+        None => return Ok(CapAlert { identifier, msg_type, references, warnings }),
+    };
+    let kind = named_value(info, "eventCode", PHENOMENON_CODE).and_then(|phenomenon| warning_kind(&phenomenon));
+    let level = named_value(info, "parameter", LEVEL_PARAMETER).unwrap_or_default();
+    let onset = ["onset", "effective"].iter().find_map(|name| child_text(info, name)).or_else(|| child_text(alert, "sent"));
+    let onset = onset.as_deref().and_then(parse_time);
+    let expires = child_text(info, "expires").as_deref().and_then(parse_time);
+    if let (Some(kind), Some(onset), Some(expires)) = (kind, onset, expires) {
+        if level != "verde" {
+            let areas = info.children().filter(|n| n.tag_name().name() == "area");
+            for (n, area) in areas.enumerate() {
+                let polygons: Vec<Vec<(f32, f32)>> = area
+                    .children()
+                    .filter(|n| n.tag_name().name() == "polygon")
+                    .filter_map(|n| n.text().and_then(parse_polygon))
+                    .filter(|p| p.len() > 2)
+                    .collect();
+                if polygons.is_empty() {
+                    continue;
+                }
+                warnings.push(Warning {
+                    id: format!("{}#{}", identifier, n),
+                    kind: kind.to_string(),
+                    event: child_text(info, "event").unwrap_or_default(),
+                    level: level.clone(),
+                    severity: child_text(info, "severity").unwrap_or_default(),
+                    headline: child_text(info, "headline").unwrap_or_default(),
+                    area: child_text(area, "areaDesc").unwrap_or_default(),
+                    onset,
+                    expires,
+                    polygons,
+                });
+            }
+        }
+    }
+    Ok(CapAlert { identifier, msg_type, references, warnings })
+}
+
+/**
+ * XML documents of a download, which is a tar archive of CAP files,
+ * possibly gzipped, or a single CAP file.
+ */
+fn unpack(content: Vec<u8>) -> Result<Vec<String>, Error> {
+    let content = if content.starts_with(&GZIP_MAGIC) {
+        let mut unpacked = vec![];
+        GzDecoder::new(Cursor::new(content)).read_to_end(&mut unpacked)?;
+        unpacked
+    } else {
+        content
+    };
+    if content.trim_ascii_start().starts_with(b"<") {
+        return Ok(vec![String::from_utf8_lossy(&content).to_string()]);
+    }
+    let mut documents = vec![];
+    let mut archive = tar::Archive::new(Cursor::new(content));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let is_xml = entry.path()?.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("xml"));
+        if !is_xml {
+            continue;
+        }
+        let mut document = vec![];
+        entry.read_to_end(&mut document)?;
+        documents.push(String::from_utf8_lossy(&document).to_string());
+    }
+    Ok(documents)
+}
+
+/**
+ * Warnings in force or to come at `now` of a download of CAP alerts.
+ * Alerts updated or cancelled by another alert of the same download are
+ * left out, as are cancellations themselves and invalid files.
+ */
+pub fn read_warnings(content: Vec<u8>, now: DateTime<Utc>) -> Result<Vec<Warning>, Error> {
+    let mut alerts = vec![];
+    for document in unpack(content)? {
+        match parse_alert(&document) {
+            Ok(alert) => alerts.push(alert),
+            Err(e) => println!("Warning is skipped. {}", e),
+        }
+    }
+    let replaced: HashSet<String> = alerts.iter().flat_map(|a| a.references.iter().cloned()).collect();
+    Ok(alerts
+        .into_iter()
+        .filter(|a| a.msg_type != "Cancel" && !replaced.contains(&a.identifier))
+        .flat_map(|a| a.warnings)
+        .filter(|w| w.expires > now)
+        .collect())
+}
+
+/**
+ * Loads the latest warnings of `WHEATR_WARNINGS_AREA` from AEMET OpenData,
+ * which publishes them as a tar archive of CAP files.
+ */
+pub fn load_data() -> Result<Vec<Warning>, Error> {
+    let area: String = get_env_var_or(ENV_AREA, String::new());
+    if area.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} is not set", ENV_AREA)));
+    }
+    let api_key: String = get_env_var_or(ENV_API_KEY, String::new());
+    let url: String = get_env_var_or(ENV_URL, DEFAULT_URL.to_string());
+    let content = download_data(&format!("{}/{}/{}", url.trim_end_matches('/'), WARNINGS_PATH, area), &api_key)?;
+    read_warnings(content, Utc::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::met::parse_observation_time;
+
+    #[test]
+    fn reads_heat_and_cold_warnings_of_a_tar() {
+        let mut builder = tar::Builder::new(vec![]);
+        let files = [
+            ("heat.xml", include_str!("../../fixtures/aemet/avisos/heat.xml")),
+            ("storm.xml", include_str!("../../fixtures/aemet/avisos/storm.xml")),
+            ("cold.xml", include_str!("../../fixtures/aemet/avisos/cold.xml")),
+            ("cold-previous.xml", include_str!("../../fixtures/aemet/avisos/cold-previous.xml")),
+        ];
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+        }
+        let content = builder.into_inner().unwrap();
+
+        let now = parse_observation_time("2024-07-15T09:00:00Z").unwrap();
+        let warnings = read_warnings(content, now).unwrap();
+        // the storm is left out, as is the cold warning updated by another one
+        assert_eq!(warnings.len(), 3);
+        let heat = &warnings[0];
+        assert_eq!((heat.kind.as_str(), heat.level.as_str(), heat.area.as_str()), ("heat", "naranja", "Sol y Guadalhorce"));
+        assert_eq!(heat.headline, "Aviso naranja. Temperaturas máximas en Sol y Guadalhorce");
+        assert_eq!(heat.onset, parse_observation_time("2024-07-15T10:00:00Z").unwrap());
+        assert_eq!(heat.polygons.len(), 2);
+        assert_eq!((heat.polygons[0][0], heat.polygons[1][0]), ((-4.9, 36.9), (-5.4, 36.5)));
+        assert!(heat.id.ends_with("612901AT.1721030400#0"));
+        assert_eq!(warnings[1].area, "Campiña cordobesa");
+        assert_eq!((warnings[2].kind.as_str(), warnings[2].expires), ("cold", parse_observation_time("2024-07-16T06:59:59Z").unwrap()));
+
+        let later = parse_observation_time("2024-07-15T20:00:00Z").unwrap();
+        assert_eq!(read_warnings(include_bytes!("../../fixtures/aemet/avisos/heat.xml").to_vec(), later).unwrap().len(), 0);
+    }
+}
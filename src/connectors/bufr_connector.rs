@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::{
+    bufr::{self, tables::Tables, DataValue, Message},
+    calculators::location_data_calculations::calculate_relative_humidity,
+    config::get_env_var_or,
+    met::{MeteoData, Observation, Station},
+};
+
+use super::drop_dir::{read_dropped_files, DroppedData};
+
+pub const PROVIDER: &str = "bufr";
+
+const ENV_DIR: &str = "WHEATR_BUFR_DIR";
+/// International data category of surface land data in BUFR Table A.
+const SURFACE_LAND_CATEGORY: u8 = 0;
+const ZERO_CELSIUS: f64 = 273.15;
+
+/// Whether a drop directory to read BUFR files from is set.
+pub fn is_configured() -> bool {
+    !get_env_var_or(ENV_DIR, String::new()).is_empty()
+}
+
+/// First of the alternative elements present in the subset.
+fn first_number(subset: &[DataValue], descriptors: &[u32]) -> Option<f64> {
+    descriptors.iter().find_map(|d| bufr::number(subset, *d))
+}
+
+/// WMO block and station number (`08221`), otherwise the WIGOS identifier (`0-20000-0-08221`).
+fn station_id(subset: &[DataValue]) -> Option<String> {
+    if let (Some(block), Some(number)) = (bufr::number(subset, 1001), bufr::number(subset, 1002)) {
+        return Some(format!("{:02}{:03}", block as u32, number as u32));
+    }
+    let series = bufr::number(subset, 1125)?;
+    let issuer = bufr::number(subset, 1126)?;
+    let issue = bufr::number(subset, 1127)?;
+    Some(format!("{}-{}-{}-{}", series, issuer, issue, bufr::text(subset, 1128)?))
+}
+
+fn read_station(subset: &[DataValue]) -> Option<Station> {
+    let id = station_id(subset)?;
+    let name = [1015, 1019, 1018].iter().find_map(|d| bufr::text(subset, *d)).unwrap_or(&id).to_string();
+    Some(Station {
+        name,
+        lat: first_number(subset, &[5001, 5002])? as f32,
+        lon: first_number(subset, &[6001, 6002])? as f32,
+        altitude: first_number(subset, &[7030, 7001]).map(|a| a as f32),
+        provider: PROVIDER.to_string(),
+        id,
+    })
+}
+
+/// Time of the subset, the typical time of section 1 if it has none.
+fn observation_time(subset: &[DataValue], reference_time: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+    let part = |descriptor| bufr::number(subset, descriptor).map(|v| v as u32);
+    match (part(4001), part(4002), part(4003), part(4004), part(4005)) {
+        (Some(year), Some(month), Some(day), Some(hour), Some(minute)) => {
+            NaiveDate::from_ymd_opt(year as i32, month, day)?.and_hms_opt(hour, minute, 0).map(|time| time.and_utc())
+        }
+        _ => reference_time,
+    }
+}
+
+/**
+ * Adds the stations and observations of the subsets of a surface land
+ * message. Air temperature and humidity are the first ones of a subset,
+ * humidity computed from the dew point if not reported. Subsets without
+ * station, position, time, temperature or humidity are skipped.
+ */
+fn add_subsets(message: &Message, stations: &mut HashMap<String, Station>, meteo_data: &mut MeteoData) {
+    for subset in &message.subsets {
+        let station = read_station(subset);
+        let observation_time = observation_time(subset, message.reference_time);
+        let temperature = first_number(subset, &[12101, 12001]).map(|t| t - ZERO_CELSIUS);
+        let dew_point = first_number(subset, &[12103, 12003]).map(|t| t - ZERO_CELSIUS);
+        let humidity = bufr::number(subset, 13003).or_else(|| Some(calculate_relative_humidity(temperature? as f32, dew_point? as f32) as f64));
+        match (station, observation_time, temperature, humidity) {
+            (Some(station), Some(observation_time), Some(temperature), Some(humidity)) => {
+                meteo_data.observations.push(Observation {
+                    station_id: station.id.clone(),
+                    observation_time,
+                    aerial_temperature: temperature as f32,
+                    relative_humidity: humidity as f32,
+                });
+                stations.insert(station.id.clone(), station);
+            }
+            _ => meteo_data.skipped_observations += 1,
+        }
+    }
+}
+
+/**
+ * Decodes the BUFR messages of a file or bulletin into stations and
+ * observations. Messages that are not surface land data or cannot be
+ * decoded with the tables are left out.
+ */
+pub fn read_bufr(content: &[u8], tables: &Tables) -> MeteoData {
+    let mut stations = HashMap::new();
+    let mut meteo_data = MeteoData { stations: vec![], observations: vec![], skipped_observations: 0 };
+    for message in bufr::split_messages(content) {
+        match bufr::decode_message(message, tables) {
+            Ok(message) if message.data_category == SURFACE_LAND_CATEGORY => add_subsets(&message, &mut stations, &mut meteo_data),
+            Ok(message) => println!("BUFR message of data category {} is not surface land data", message.data_category),
+            Err(e) => println!("BUFR message decoding failed. {}", e),
+        }
+    }
+    meteo_data.stations = stations.into_values().collect();
+    meteo_data.stations.sort_by(|a, b| a.id.cmp(&b.id));
+    meteo_data
+}
+
+/// Reads a BUFR file with the bundled and local tables.
+pub fn load_file(path: &Path) -> Result<MeteoData, Error> {
+    let content = fs::read(path).map_err(|err| Error::other(format!("Opening {} failed: {}", path.display(), err)))?;
+    Ok(read_bufr(&content, &Tables::load()))
+}
+
+/**
+ * Loads the BUFR files dropped in `WHEATR_BUFR_DIR`, which are moved to its
+ * `processed` subdirectory once written.
+ */
+pub fn load_data() -> Result<DroppedData, Error> {
+    let dir: String = get_env_var_or(ENV_DIR, String::new());
+    if dir.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} is not set", ENV_DIR)));
+    }
+    let (files, contents) = read_dropped_files(Path::new(&dir), &|file| fs::read(file))?;
+    Ok(DroppedData { meteo_data: read_bufr(&contents.concat(), &Tables::load()), dir: PathBuf::from(dir), files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bufr::testing::MessageBuilder;
+
+    #[test]
+    fn reads_surface_land_observations() {
+        let mut builder = MessageBuilder::new(&[301001, 301011, 301012, 301021, 7030, 302032], 3, false);
+        for (station, temperature, dew_point, humidity) in [(221, Some(301.65), Some(283.35), None), (181, Some(297.15), None, Some(70.0)), (284, None, None, None)] {
+            builder.number(8, 7).number(station, 10).number(2024, 12).number(7, 4).number(14, 6).number(12, 5).number(0, 6);
+            builder.value(Some(40.4667), 25, 5, -9000000).value(Some(-3.5556), 26, 5, -18000000).value(Some(609.0), 17, 1, -4000);
+            builder.value(Some(1.5), 16, 2, 0).value(temperature, 16, 2, 0).value(dew_point, 16, 2, 0).value(humidity, 7, 0, 0);
+        }
+        let content = [b"ISMS01 LEMM 141200\r\r\n".as_slice(), &builder.build(), b"BUFR garbage 7777"].concat();
+        let data = read_bufr(&content, &Tables::bundled());
+
+        let ids: Vec<&str> = data.stations.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["08181", "08221"]);
+        assert_eq!((data.stations[1].name.as_str(), data.stations[1].altitude, data.stations[1].provider.as_str()), ("08221", Some(609.0), PROVIDER));
+        assert_eq!(data.observations.len(), 2);
+        assert_eq!(data.observations[0].observation_time.to_rfc3339(), "2024-07-14T12:00:00+00:00");
+        assert_eq!((data.observations[0].aerial_temperature * 10.0).round(), 285.0);
+        assert_eq!((data.observations[0].relative_humidity * 10.0).round(), 320.0);
+        assert_eq!((data.observations[1].aerial_temperature, data.observations[1].relative_humidity), (24.0, 70.0));
+        assert_eq!(data.skipped_observations, 1);
+    }
+}
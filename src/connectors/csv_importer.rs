@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Error, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::{
+    config::{invalid_input, parse_args},
+    met::{MeteoData, Observation, Station},
+};
+
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    fn to_celsius(self, value: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+            TemperatureUnit::Kelvin => value - 273.15,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HumidityUnit {
+    Percent,
+    /// 0–1
+    Fraction,
+}
+
+/// Header of the column each field is read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnMapping {
+    pub station_id: String,
+    /// Optional, the station id is used as name if the column is missing.
+    pub name: String,
+    pub lat: String,
+    pub lon: String,
+    /// Optional.
+    pub altitude: String,
+    pub time: String,
+    pub temperature: String,
+    pub humidity: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        ColumnMapping {
+            station_id: "station_id".to_string(),
+            name: "name".to_string(),
+            lat: "lat".to_string(),
+            lon: "lon".to_string(),
+            altitude: "altitude".to_string(),
+            time: "time".to_string(),
+            temperature: "temperature".to_string(),
+            humidity: "humidity".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvImportConfig {
+    /// Provider of the imported stations. Their ids are prefixed with it, so
+    /// they cannot collide with the stations of other providers.
+    pub source: String,
+    pub delimiter: u8,
+    pub columns: ColumnMapping,
+    pub temperature_unit: TemperatureUnit,
+    pub humidity_unit: HumidityUnit,
+    /// Timezone of times without an offset.
+    pub timezone: Tz,
+    /// chrono format of times without an offset. RFC 3339 times are always accepted.
+    pub time_format: String,
+}
+
+impl CsvImportConfig {
+    pub fn new(source: &str) -> CsvImportConfig {
+        CsvImportConfig {
+            source: source.to_string(),
+            delimiter: b',',
+            columns: ColumnMapping::default(),
+            temperature_unit: TemperatureUnit::Celsius,
+            humidity_unit: HumidityUnit::Percent,
+            timezone: Tz::UTC,
+            time_format: DEFAULT_TIME_FORMAT.to_string(),
+        }
+    }
+
+    /**
+     * Reads `--source <name> --file <path> [--delimiter <char>|tab]
+     * [--columns <field>=<header>,...] [--temperature-unit c|f|k]
+     * [--humidity-unit percent|fraction] [--timezone <name>] [--time-format
+     * <format>]`.
+     */
+    pub fn from_args(args: &[String]) -> Result<(CsvImportConfig, PathBuf), Error> {
+        let values = parse_args(args)?;
+        let required = |name: &str| values.get(name).map(String::as_str).ok_or_else(|| invalid_input(format!("Missing --{}", name)));
+
+        let source = required("source")?;
+        if source.is_empty() || source.contains(':') {
+            return Err(invalid_input(format!("Invalid source: {}", source)));
+        }
+        let mut config = CsvImportConfig::new(source);
+        if let Some(delimiter) = values.get("delimiter") {
+            config.delimiter = match delimiter.as_str() {
+                "tab" => b'\t',
+                d if d.len() == 1 => d.as_bytes()[0],
+                d => return Err(invalid_input(format!("Invalid delimiter: {}", d))),
+            };
+        }
+        if let Some(columns) = values.get("columns") {
+            for pair in columns.split(',') {
+                let (field, header) = pair.split_once('=').ok_or_else(|| invalid_input(format!("Invalid column mapping: {}", pair)))?;
+                let column = match field.trim() {
+                    "station_id" => &mut config.columns.station_id,
+                    "name" => &mut config.columns.name,
+                    "lat" => &mut config.columns.lat,
+                    "lon" => &mut config.columns.lon,
+                    "altitude" => &mut config.columns.altitude,
+                    "time" => &mut config.columns.time,
+                    "temperature" => &mut config.columns.temperature,
+                    "humidity" => &mut config.columns.humidity,
+                    other => return Err(invalid_input(format!("Unknown field: {}", other))),
+                };
+                *column = header.trim().to_string();
+            }
+        }
+        if let Some(unit) = values.get("temperature-unit") {
+            config.temperature_unit = match unit.to_lowercase().as_str() {
+                "c" | "celsius" => TemperatureUnit::Celsius,
+                "f" | "fahrenheit" => TemperatureUnit::Fahrenheit,
+                "k" | "kelvin" => TemperatureUnit::Kelvin,
+                _ => return Err(invalid_input(format!("Unknown temperature unit: {}, use c, f or k", unit))),
+            };
+        }
+        if let Some(unit) = values.get("humidity-unit") {
+            config.humidity_unit = match unit.as_str() {
+                "percent" => HumidityUnit::Percent,
+                "fraction" => HumidityUnit::Fraction,
+                _ => return Err(invalid_input(format!("Unknown humidity unit: {}, use percent or fraction", unit))),
+            };
+        }
+        if let Some(timezone) = values.get("timezone") {
+            config.timezone = timezone.parse().map_err(|_| invalid_input(format!("Unknown timezone: {}", timezone)))?;
+        }
+        if let Some(format) = values.get("time-format") {
+            config.time_format = format.clone();
+        }
+        Ok((config, PathBuf::from(required("file")?)))
+    }
+
+    fn parse_time(&self, value: &str) -> Option<DateTime<Utc>> {
+        if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+            return Some(time.with_timezone(&Utc));
+        }
+        let local = NaiveDateTime::parse_from_str(value, &self.time_format).ok()?;
+        // the first of the repeated hour when clocks go back
+        self.timezone.from_local_datetime(&local).earliest().map(|time| time.with_timezone(&Utc))
+    }
+}
+
+/// A number with a decimal point or comma, `None` if empty or invalid.
+fn parse_number(value: &str) -> Option<f32> {
+    value.trim().replace(',', ".").parse().ok().filter(|v: &f32| v.is_finite())
+}
+
+/**
+ * Reads stations and their observations from CSV with a header, one
+ * observation per row. Rows without a valid position are ignored, rows
+ * without a valid time, temperature or humidity are counted as skipped
+ * observations.
+ */
+pub fn read_csv<R: Read>(reader: R, config: &CsvImportConfig) -> Result<MeteoData, Error> {
+    let parsing_error = |err: csv::Error| Error::new(ErrorKind::InvalidData, format!("CSV parsing failed: {}", err));
+    let mut reader = csv::ReaderBuilder::new().delimiter(config.delimiter).trim(csv::Trim::All).from_reader(reader);
+    let headers = reader.headers().map_err(parsing_error)?.clone();
+    let index = |header: &str| headers.iter().position(|h| h == header);
+    let required = |header: &str| index(header).ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("CSV parsing failed: no {} column", header)));
+    let columns = &config.columns;
+    let (id_column, lat_column, lon_column) = (required(&columns.station_id)?, required(&columns.lat)?, required(&columns.lon)?);
+    let (time_column, temperature_column, humidity_column) = (required(&columns.time)?, required(&columns.temperature)?, required(&columns.humidity)?);
+    let (name_column, altitude_column) = (index(&columns.name), index(&columns.altitude));
+
+    let mut stations: Vec<Station> = vec![];
+    let mut station_indexes: HashMap<String, usize> = HashMap::new();
+    let mut observations: Vec<Observation> = vec![];
+    let mut skipped_observations = 0;
+    for (line, record) in reader.records().enumerate() {
+        let record = record.map_err(parsing_error)?;
+        let field = |column: usize| record.get(column).unwrap_or("");
+        let raw_id = field(id_column);
+        let (lat, lon) = match (parse_number(field(lat_column)), parse_number(field(lon_column))) {
+            (Some(lat), Some(lon)) if !raw_id.is_empty() && (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) => (lat, lon),
+            _ => {
+                println!("Invalid station in line {}: {:?}", line + 2, record);
+                continue;
+            }
+        };
+        let station = Station {
+            id: format!("{}:{}", config.source, raw_id),
+            name: name_column.map(field).filter(|n| !n.is_empty()).unwrap_or(raw_id).to_string(),
+            lat,
+            lon,
+            altitude: altitude_column.and_then(|c| parse_number(field(c))),
+            provider: config.source.clone(),
+        };
+        let observation_time = config.parse_time(field(time_column));
+        let temperature = parse_number(field(temperature_column)).map(|t| config.temperature_unit.to_celsius(t));
+        let humidity = parse_number(field(humidity_column)).map(|h| match config.humidity_unit {
+            HumidityUnit::Percent => h,
+            HumidityUnit::Fraction => h * 100.0,
+        });
+        match (observation_time, temperature, humidity) {
+            (Some(observation_time), Some(aerial_temperature), Some(relative_humidity)) if (0.0..=100.0).contains(&relative_humidity) => {
+                observations.push(Observation { station_id: station.id.clone(), observation_time, aerial_temperature, relative_humidity });
+            }
+            _ => skipped_observations += 1,
+        }
+        // the latest row describes the station
+        match station_indexes.get(&station.id) {
+            Some(i) => stations[*i] = station,
+            None => {
+                station_indexes.insert(station.id.clone(), stations.len());
+                stations.push(station);
+            }
+        }
+    }
+    Ok(MeteoData { stations, observations, skipped_observations })
+}
+
+pub fn load_file(path: &Path, config: &CsvImportConfig) -> Result<MeteoData, Error> {
+    let file = File::open(path).map_err(|err| Error::other(format!("Opening {} failed: {}", path.display(), err)))?;
+    read_csv(file, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{
+            db_writer::write_to_database,
+            memory_store::MemoryStore,
+            store::{ProviderFilter, Store},
+        },
+        met::{parse_observation_time, Location},
+    };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn imports_mapped_columns_and_units() {
+        let (config, path) = CsvImportConfig::from_args(&args(&[
+            "--source", "garden", "--file", "sensors.csv", "--delimiter", ";",
+            "--columns", "station_id=sensor,lat=latitud,lon=longitud,time=fecha,temperature=temp_f,humidity=hr",
+            "--temperature-unit", "f", "--humidity-unit", "fraction",
+            "--timezone", "Europe/Madrid", "--time-format", "%d/%m/%Y %H:%M",
+        ]))
+        .unwrap();
+        assert_eq!(path, PathBuf::from("sensors.csv"));
+
+        let csv = "sensor;name;latitud;longitud;fecha;temp_f;hr\n\
+                   s1;Huerto;40,41;-3,70;10/08/2023 12:00;95;0,4\n\
+                   s1;Huerto;40,41;-3,70;10/08/2023 13:00;;0,4\n\
+                   s2;;40,45;-3,65;2023-08-10T10:00:00Z;86;0,5\n\
+                   s3;Roto;;-3,6;10/08/2023 12:00;90;0,5\n";
+        let data = read_csv(csv.as_bytes(), &config).unwrap();
+
+        let ids: Vec<(&str, &str, &str)> = data.stations.iter().map(|s| (s.id.as_str(), s.name.as_str(), s.provider.as_str())).collect();
+        assert_eq!(ids, vec![("garden:s1", "Huerto", "garden"), ("garden:s2", "s2", "garden")]);
+        assert_eq!(data.skipped_observations, 1);
+        let first = &data.observations[0];
+        // 12:00 CEST
+        assert_eq!(first.observation_time, parse_observation_time("2023-08-10T10:00:00Z").unwrap());
+        assert_eq!((first.aerial_temperature, first.relative_humidity), (35.0, 40.0));
+        assert_eq!(data.observations[1].aerial_temperature, 30.0);
+    }
+
+    #[test]
+    fn imported_stations_can_be_excluded_from_interpolation() {
+        let store = MemoryStore::default();
+        let station = |id: &str, lat: f32, lon: f32, provider: &str| Station {
+            id: id.to_string(),
+            name: id.to_string(),
+            lat,
+            lon,
+            altitude: None,
+            provider: provider.to_string(),
+        };
+        let stations = vec![
+            station("a", 40.0, -3.0, "aemet"),
+            station("b", 40.2, -3.2, "aemet"),
+            station("c", 40.4, -3.0, "aemet"),
+        ];
+        write_to_database(&store, &MeteoData { stations, observations: vec![], skipped_observations: 0 }).unwrap();
+        let csv = "station_id,lat,lon,time,temperature,humidity\ns1,40.1,-3.1,2023-08-10T10:00:00,30,40\n";
+        write_to_database(&store, &read_csv(csv.as_bytes(), &CsvImportConfig::new("garden")).unwrap()).unwrap();
+
+        let loc = Location { lat: 40.1, lon: -3.1 };
+        let ids = |providers: &ProviderFilter| {
+            let mut ids: Vec<String> = store.get_closest_stations(&loc, providers).unwrap().iter().map(|s| s.id.clone()).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&ProviderFilter::default()), vec!["a", "b", "garden:s1"]);
+        assert_eq!(ids(&ProviderFilter { include: None, exclude: vec!["garden".to_string()] }), vec!["a", "b", "c"]);
+        assert_eq!(ids(&ProviderFilter { include: Some(vec!["aemet".to_string()]), exclude: vec![] }), vec!["a", "b", "c"]);
+    }
+}
@@ -1,9 +1,64 @@
-use std::io::Error;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Error,
+};
 
-use super::sqlite_connector::{write_observations_to_db, write_stations_to_db};
-use crate::met::MeteoData;
+use chrono::{DateTime, Utc};
 
-pub fn write_to_database(meteo_data: &MeteoData) -> Result<(), Error> {
-    write_stations_to_db(&meteo_data.stations)?;
-    write_observations_to_db(&meteo_data.observations)
+use super::store::Store;
+use crate::met::{MeteoData, Station};
+
+/**
+ * Writes stations and observations, returning the number of inserted
+ * observations. Station changes are considered valid from the latest
+ * observation of the station in the batch, or from now if it has none, so
+ * a replayed archive never backdates a relocation reported later.
+ */
+pub fn write_to_database(store: &dyn Store, meteo_data: &MeteoData) -> Result<usize, Error> {
+    let mut latest: HashMap<&str, DateTime<Utc>> = HashMap::new();
+    for observation in &meteo_data.observations {
+        let time = latest.entry(&observation.station_id).or_insert(observation.observation_time);
+        *time = (*time).max(observation.observation_time);
+    }
+    let now = Utc::now();
+    let mut stations_by_time: BTreeMap<DateTime<Utc>, Vec<Station>> = BTreeMap::new();
+    for station in &meteo_data.stations {
+        let valid_from = latest.get(station.id.as_str()).copied().unwrap_or(now);
+        stations_by_time.entry(valid_from).or_default().push(station.clone());
+    }
+    for (valid_from, stations) in stations_by_time {
+        store.upsert_stations(&stations, valid_from)?;
+    }
+    store.insert_observations(&meteo_data.observations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{memory_store::MemoryStore, store::ProviderFilter},
+        met::{parse_observation_time, testing::{observation, station}, Location, Observation},
+    };
+
+    #[test]
+    fn station_changes_are_dated_by_their_latest_observation() {
+        let store = MemoryStore::default();
+        let batch = |stations: Vec<Station>, observations: Vec<Observation>| MeteoData { stations, observations, skipped_observations: 0 };
+        let stations = vec![station("A", 36.7, -4.4), station("B", 37.0, -4.0), station("C", 37.5, -4.5)];
+        write_to_database(&store, &batch(stations, vec![observation("A", "2023-08-10T10:00:00", 30.0)])).unwrap();
+        let relocated = batch(
+            vec![station("A", 36.0, -5.0)],
+            vec![observation("A", "2023-08-10T11:00:00", 30.0), observation("A", "2023-08-10T13:00:00", 30.0)],
+        );
+        write_to_database(&store, &relocated).unwrap();
+
+        let location = Location { lat: 36.7, lon: -4.4 };
+        let at = |time: &str| store.get_closest_stations_at(&location, parse_observation_time(time).unwrap(), &ProviderFilter::default()).unwrap();
+        assert!(at("2023-08-10T12:00:00").iter().any(|s| s.id == "A" && s.lat == 36.7));
+        assert!(at("2023-08-10T13:00:00").iter().any(|s| s.id == "A" && s.lat == 36.0));
+
+        // a replayed archive does not move the station back
+        write_to_database(&store, &batch(vec![station("A", 36.7, -4.4)], vec![observation("A", "2021-01-09T06:00:00", 30.0)])).unwrap();
+        assert_eq!(store.get_station("A").unwrap().unwrap().lat, 36.0);
+    }
 }
@@ -1,34 +1,66 @@
 use reqwest::{self, blocking::Client, header};
-use std::io::{Error, ErrorKind};
+use serde::de::DeserializeOwned;
+use std::{cell::Cell, fmt::Display, io::{Error, ErrorKind}};
 
-pub fn download_content(url: &String, api_key: &String) -> Result<Vec<u8>, Error> {
+/// Error of a request answered with an unsuccessful HTTP status.
+#[derive(Debug)]
+pub struct HttpStatusError(pub u16);
+impl Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Response status is unsuccess: {}", self.0)
+    }
+}
+impl std::error::Error for HttpStatusError {}
+
+thread_local! {
+    /// Status of the latest response received by the thread.
+    static LAST_HTTP_STATUS: Cell<Option<u16>> = const { Cell::new(None) };
+}
+
+/// HTTP status of the latest response received by this thread since the previous call, if any.
+pub fn take_last_http_status() -> Option<u16> {
+    LAST_HTTP_STATUS.with(Cell::take)
+}
+
+/**
+ * HTTP status of a failed download, if it failed because of the status.
+ */
+pub fn get_http_status(err: &Error) -> Option<u16> {
+    err.get_ref()
+        .and_then(|inner| inner.downcast_ref::<HttpStatusError>())
+        .map(|status_error| status_error.0)
+}
+
+pub fn download_content(url: &str, api_key: &str) -> Result<Vec<u8>, Error> {
     let client_builder = Client::builder();
     let mut headers = header::HeaderMap::new();
-    headers.insert("api_key", header::HeaderValue::from_str(api_key.as_str()).unwrap());
+    headers.insert("api_key", header::HeaderValue::from_str(api_key).unwrap());
     let client_result = client_builder.default_headers(headers).build();
     let client = match client_result {
         Ok(cl) => cl,
-        Err(_err) => return Err(Error::new(ErrorKind::Other, "Client setup failed")),
+        Err(_err) => return Err(Error::other("Client setup failed")),
     };
 
     let response_result = client.get(url).send();
     let mut response = match response_result {
         Ok(resp) => resp,
-        Err(err) => return Err(Error::new(ErrorKind::Other, err)),
+        Err(err) => return Err(Error::other(err)),
     };
+    LAST_HTTP_STATUS.with(|status| status.set(Some(response.status().as_u16())));
 
     if response.status().is_success() {
         let mut content: Vec<u8> = vec![];
         match response.copy_to(&mut content) {
-            Ok(_) => return Ok(content),
-            Err(err) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Loading failed: {}", err),
-                ));
-            }
-        };
+            Ok(_) => Ok(content),
+            Err(err) => Err(Error::other(format!("Loading failed: {}", err))),
+        }
     } else {
-        return Err(Error::new(ErrorKind::Other, "Response status is unsuccess"));
+        Err(Error::other(HttpStatusError(response.status().as_u16())))
     }
 }
+
+/// Downloads and parses a JSON response.
+pub fn download_json<T: DeserializeOwned>(url: &str, api_key: &str) -> Result<T, Error> {
+    let content = download_content(url, api_key)?;
+    serde_json::from_slice(&content).map_err(|err| Error::new(ErrorKind::InvalidData, format!("Parsing response of {} failed: {}", url, err)))
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use crate::{ingestion::LoadedData, met::MeteoData};
+
+/// Subdirectory of a drop directory read files are moved to.
+const PROCESSED_DIR: &str = "processed";
+/// Subdirectory of a drop directory files that cannot be read are moved to.
+const FAILED_DIR: &str = "failed";
+
+/**
+ * Observations read, at least in part, from the files of a drop directory.
+ * The files are moved to its `processed` subdirectory once the
+ * observations are written, so they are read again if writing fails.
+ */
+pub struct DroppedData {
+    pub meteo_data: MeteoData,
+    pub dir: PathBuf,
+    pub files: Vec<PathBuf>,
+}
+
+impl LoadedData for DroppedData {
+    fn meteo_data(&self) -> &MeteoData {
+        &self.meteo_data
+    }
+
+    fn written(&self) -> Result<(), Error> {
+        move_files(&self.dir, &self.files, PROCESSED_DIR)
+    }
+}
+
+/// Files in the drop directory, oldest name first.
+fn dropped_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/**
+ * Reads the files in the drop directory by `read`, oldest name first,
+ * returning the read files with their contents. A file that cannot be read
+ * is moved to the `failed` subdirectory, so it does not fail later runs.
+ */
+pub fn read_dropped_files<T>(dir: &Path, read: &dyn Fn(&Path) -> Result<T, Error>) -> Result<(Vec<PathBuf>, Vec<T>), Error> {
+    let mut files = vec![];
+    let mut contents = vec![];
+    for file in dropped_files(dir)? {
+        match read(&file) {
+            Ok(content) => {
+                files.push(file);
+                contents.push(content);
+            }
+            Err(e) => {
+                println!("Dropped file {} is moved to {}, it cannot be read. {}", file.display(), FAILED_DIR, e);
+                if let Err(e) = move_files(dir, &[file], FAILED_DIR) {
+                    println!("Moving the dropped file failed. {}", e);
+                }
+            }
+        }
+    }
+    Ok((files, contents))
+}
+
+/// Moves files to a subdirectory of the drop directory.
+fn move_files(dir: &Path, files: &[PathBuf], subdir: &str) -> Result<(), Error> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let target = dir.join(subdir);
+    fs::create_dir_all(&target)?;
+    for file in files {
+        if let Some(name) = file.file_name() {
+            fs::rename(file, target.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connectors::memory_store::MemoryStore, ingestion::run_ingestion};
+
+    #[test]
+    fn dropped_files_are_moved_once_written() {
+        let dir = std::env::temp_dir().join(format!("wheatr-drop-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("1.txt"), "METAR LEMD 101200Z 25/12 Q1015=").unwrap();
+        fs::write(dir.join("2.txt"), [0xff, 0xfe]).unwrap();
+
+        let (files, contents) = read_dropped_files(&dir, &|file| fs::read_to_string(file)).unwrap();
+        assert_eq!(files, vec![dir.join("1.txt")]);
+        assert_eq!(contents.len(), 1);
+        // the unreadable file is set aside, the read one is kept until written
+        assert!(dir.join(FAILED_DIR).join("2.txt").is_file());
+        assert!(dir.join("1.txt").is_file());
+
+        let load_data = || {
+            let meteo_data = MeteoData { stations: vec![], observations: vec![], skipped_observations: 0 };
+            Ok(DroppedData { meteo_data, dir: dir.clone(), files: files.clone() })
+        };
+        assert!(run_ingestion(&MemoryStore::default(), "metar", &load_data).succeeded);
+        assert!(dir.join(PROCESSED_DIR).join("1.txt").is_file());
+        assert!(dropped_files(&dir).unwrap().is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
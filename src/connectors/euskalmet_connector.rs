@@ -0,0 +1,139 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Error, ErrorKind},
+};
+
+use chrono::{NaiveDate, NaiveTime, Utc};
+use serde::Deserialize;
+
+use crate::{
+    config::get_env_var_or,
+    met::{MeteoData, Observation, Station},
+};
+
+use super::downloader;
+
+pub const PROVIDER: &str = "euskalmet";
+
+const ENV_URL: &str = "WHEATR_EUSKALMET_URL";
+const STATIONS_PATH: &str = "stationList/stationList.json";
+const TEMPERATURE_MEASURE: &str = "21";
+const HUMIDITY_MEASURE: &str = "31";
+const METEOROLOGICAL: &str = "METEOROLOGICAL";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EuskalmetStation {
+    pub id: String,
+    pub name: String,
+    pub station_type: Option<String>,
+    pub lat: f32,
+    pub lon: f32,
+    pub altitude: Option<f32>,
+}
+
+/// Values of a sensor by time of day (`HH:MM`, UTC).
+#[derive(Debug, Deserialize)]
+pub struct EuskalmetSensor {
+    pub data: BTreeMap<String, f32>,
+}
+
+/// Readings of a station on a day by measure and sensor.
+pub type EuskalmetReadings = HashMap<String, BTreeMap<String, EuskalmetSensor>>;
+
+/// Whether the URL of the station data is set.
+pub fn is_configured() -> bool {
+    !get_env_var_or(ENV_URL, String::new()).is_empty()
+}
+
+/// Values of the first sensor of a measure.
+fn measure_values<'a>(readings: &'a EuskalmetReadings, measure: &str) -> Option<&'a BTreeMap<String, f32>> {
+    readings.get(measure).and_then(|sensors| sensors.values().next()).map(|sensor| &sensor.data)
+}
+
+/**
+ * Adds the observations of a station on a day, pairing temperature and
+ * humidity of the same time. Times with only one of them are skipped.
+ */
+pub fn add_readings(station: &EuskalmetStation, day: NaiveDate, readings: &EuskalmetReadings, meteo_data: &mut MeteoData) {
+    let station_id = format!("{}:{}", PROVIDER, station.id);
+    let temperatures = measure_values(readings, TEMPERATURE_MEASURE);
+    let humidities = measure_values(readings, HUMIDITY_MEASURE);
+    let mut times: Vec<&String> = temperatures.into_iter().chain(humidities).flat_map(|values| values.keys()).collect();
+    times.sort();
+    times.dedup();
+    let mut observed = false;
+    for time in times {
+        let temperature = temperatures.and_then(|values| values.get(time));
+        let humidity = humidities.and_then(|values| values.get(time));
+        match (NaiveTime::parse_from_str(time, "%H:%M"), temperature, humidity) {
+            (Ok(time), Some(temperature), Some(humidity)) => {
+                meteo_data.observations.push(Observation {
+                    station_id: station_id.clone(),
+                    observation_time: day.and_time(time).and_utc(),
+                    aerial_temperature: *temperature,
+                    relative_humidity: *humidity,
+                });
+                observed = true;
+            }
+            _ => meteo_data.skipped_observations += 1,
+        }
+    }
+    if observed {
+        meteo_data.stations.push(Station {
+            id: station_id,
+            name: station.name.clone(),
+            lat: station.lat,
+            lon: station.lon,
+            altitude: station.altitude,
+            provider: PROVIDER.to_string(),
+        });
+    }
+}
+
+/**
+ * Loads today's readings of the meteorological stations from the station
+ * data of Euskalmet at `WHEATR_EUSKALMET_URL`, one request per station.
+ * Stations whose readings cannot be loaded are left out.
+ */
+pub fn load_data() -> Result<MeteoData, Error> {
+    let url: String = get_env_var_or(ENV_URL, String::new());
+    if url.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} is not set", ENV_URL)));
+    }
+    let url = url.trim_end_matches('/');
+    let stations: Vec<EuskalmetStation> = downloader::download_json(&format!("{}/{}", url, STATIONS_PATH), "")?;
+    let day = Utc::now().date_naive();
+    let mut meteo_data = MeteoData { stations: vec![], observations: vec![], skipped_observations: 0 };
+    for station in stations.iter().filter(|s| s.station_type.as_deref().is_none_or(|t| t == METEOROLOGICAL)) {
+        let readings_url = format!("{}/readings/{}/{}/readingsData.json", url, station.id, day.format("%Y/%m/%d"));
+        match downloader::download_json::<EuskalmetReadings>(&readings_url, "") {
+            Ok(readings) => add_readings(station, day, &readings, &mut meteo_data),
+            Err(e) => println!("Readings of Euskalmet station {} are not loaded. {}", station.id, e),
+        }
+    }
+    Ok(meteo_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::met::parse_observation_time;
+
+    #[test]
+    fn pairs_readings_of_a_day() {
+        let stations: Vec<EuskalmetStation> = serde_json::from_str(include_str!("../../fixtures/euskalmet/stationList/stationList.json")).unwrap();
+        let readings: EuskalmetReadings = serde_json::from_str(include_str!("../../fixtures/euskalmet/readings/C040.json")).unwrap();
+        let mut data = MeteoData { stations: vec![], observations: vec![], skipped_observations: 0 };
+        add_readings(&stations[0], NaiveDate::from_ymd_opt(2024, 7, 14).unwrap(), &readings, &mut data);
+
+        assert_eq!(data.stations.len(), 1);
+        assert_eq!((data.stations[0].id.as_str(), data.stations[0].altitude), ("euskalmet:C040", Some(3.0)));
+        assert_eq!(data.observations.len(), 2);
+        assert_eq!(data.observations[1].observation_time, parse_observation_time("2024-07-14T11:50:00Z").unwrap());
+        assert_eq!((data.observations[1].aerial_temperature, data.observations[1].relative_humidity), (24.3, 68.0));
+        // no humidity at 12:00
+        assert_eq!(data.skipped_observations, 1);
+        assert_eq!(stations[3].station_type.as_deref(), Some("HYDROLOGICAL"));
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::{Error, ErrorKind},
+    sync::RwLock,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::calculators::daily_summaries::summarize_daily;
+use crate::met::{Aggregate, DailySummary, Forecast, IngestionRun, Location, Municipality, Observation, Station, StationPeriod, Warning};
+
+use super::store::{ObservationFilter, ProviderFilter, StationFilter, Store};
+
+/**
+ * Store keeping everything in memory, following the same semantics as the
+ * SQLite store. Used by tests which should not touch `.met.sqlite`.
+ */
+#[derive(Default)]
+pub struct MemoryStore {
+    stations: RwLock<HashMap<String, Station>>,
+    station_history: RwLock<Vec<StationPeriod>>,
+    observations: RwLock<HashMap<(String, DateTime<Utc>), Observation>>,
+    daily_summaries: RwLock<HashMap<(String, NaiveDate), DailySummary>>,
+    ingestion_runs: RwLock<Vec<IngestionRun>>,
+    municipalities: RwLock<HashMap<String, Municipality>>,
+    forecasts: RwLock<HashMap<(String, DateTime<Utc>), Forecast>>,
+    warnings: RwLock<Vec<Warning>>,
+}
+
+fn closest_stations<'a>(loc: &Location, stations: impl IntoIterator<Item = &'a Station>) -> Result<[Station; 3], Error> {
+    loc.closest_stations(stations)
+        .map(|closest| closest.map(Station::clone))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Not Found: less than three stations are known"))
+}
+
+fn merge_aggregates(a: &Aggregate, a_samples: u32, b: &Aggregate, b_samples: u32) -> Aggregate {
+    Aggregate {
+        min: a.min.min(b.min),
+        max: a.max.max(b.max),
+        mean: (a.mean * a_samples as f32 + b.mean * b_samples as f32) / (a_samples + b_samples) as f32,
+    }
+}
+
+fn merge_summaries(a: &DailySummary, b: &DailySummary) -> DailySummary {
+    DailySummary {
+        station_id: a.station_id.clone(),
+        day: a.day,
+        samples: a.samples + b.samples,
+        aerial_temperature: merge_aggregates(&a.aerial_temperature, a.samples, &b.aerial_temperature, b.samples),
+        relative_humidity: merge_aggregates(&a.relative_humidity, a.samples, &b.relative_humidity, b.samples),
+        heat_index: merge_aggregates(&a.heat_index, a.samples, &b.heat_index, b.samples),
+    }
+}
+
+fn matches(filter: &StationFilter, station: &Station) -> bool {
+    filter.bbox.is_none_or(|b| {
+        (b.min_lat..=b.max_lat).contains(&station.lat) && (b.min_lon..=b.max_lon).contains(&station.lon)
+    })
+        && filter.provider.as_ref().is_none_or(|provider| &station.provider == provider)
+}
+
+fn allows(providers: &ProviderFilter, station: &Station) -> bool {
+    providers.include.as_ref().is_none_or(|include| include.contains(&station.provider)) && !providers.exclude.contains(&station.provider)
+}
+
+impl MemoryStore {
+    pub fn get_daily_summaries(&self) -> Vec<DailySummary> {
+        let mut summaries: Vec<DailySummary> = self.daily_summaries.read().unwrap().values().cloned().collect();
+        summaries.sort_by(|a, b| (&a.station_id, a.day).cmp(&(&b.station_id, b.day)));
+        summaries
+    }
+}
+
+impl Store for MemoryStore {
+    fn upsert_stations(&self, stations: &[Station], valid_from: DateTime<Utc>) -> Result<(), Error> {
+        let mut stored = self.stations.write().unwrap();
+        let mut history = self.station_history.write().unwrap();
+        let observations = self.observations.read().unwrap();
+        for station in stations {
+            match stored.get(&station.id) {
+                None => history.push(StationPeriod { station: station.clone(), valid_from: DateTime::<Utc>::UNIX_EPOCH, valid_to: None }),
+                Some(current) if current.differs_from(station) => {
+                    let observed_later = observations.values().any(|o| o.station_id == station.id && o.observation_time > valid_from);
+                    let current_period = history
+                        .iter_mut()
+                        .find(|p| p.station.id == station.id && p.valid_to.is_none() && p.valid_from < valid_from)
+                        .filter(|_| !observed_later);
+                    match current_period {
+                        Some(period) => period.valid_to = Some(valid_from),
+                        None => {
+                            println!("Station {} is not changed to {}: the change at {} is older than its known position", current, station, valid_from);
+                            continue;
+                        }
+                    }
+                    history.push(StationPeriod { station: station.clone(), valid_from, valid_to: None });
+                }
+                Some(_) => continue,
+            }
+            stored.insert(station.id.clone(), station.clone());
+        }
+        Ok(())
+    }
+
+    fn insert_observations(&self, observations: &[Observation]) -> Result<usize, Error> {
+        let mut stored = self.observations.write().unwrap();
+        let mut inserted = 0;
+        for observation in observations {
+            let key = (observation.station_id.clone(), observation.observation_time);
+            if let Entry::Vacant(entry) = stored.entry(key) {
+                entry.insert(observation.clone());
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    fn get_station(&self, id: &str) -> Result<Option<Station>, Error> {
+        Ok(self.stations.read().unwrap().get(id).cloned())
+    }
+
+    fn get_stations_with_latest_observation(&self, filter: &StationFilter) -> Result<Vec<(Station, Option<Observation>)>, Error> {
+        let observations = self.observations.read().unwrap();
+        let mut stations: Vec<(Station, Option<Observation>)> = self
+            .stations
+            .read()
+            .unwrap()
+            .values()
+            .filter(|station| matches(filter, station))
+            .map(|station| {
+                let latest = observations
+                    .values()
+                    .filter(|o| o.station_id == station.id)
+                    .max_by_key(|o| o.observation_time)
+                    .cloned();
+                (station.clone(), latest)
+            })
+            .collect();
+        stations.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+        Ok(stations)
+    }
+
+    fn get_closest_stations(&self, loc: &Location, providers: &ProviderFilter) -> Result<[Station; 3], Error> {
+        closest_stations(loc, self.stations.read().unwrap().values().filter(|s| allows(providers, s)))
+    }
+
+    fn get_closest_stations_at(&self, loc: &Location, at: DateTime<Utc>, providers: &ProviderFilter) -> Result<[Station; 3], Error> {
+        let history = self.station_history.read().unwrap();
+        closest_stations(loc, history.iter().filter(|p| p.is_valid_at(at) && allows(providers, &p.station)).map(|p| &p.station))
+    }
+
+    fn get_station_periods(&self, from: DateTime<Utc>, to: DateTime<Utc>, providers: &ProviderFilter) -> Result<Vec<StationPeriod>, Error> {
+        let mut periods: Vec<StationPeriod> = self
+            .station_history
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|p| p.valid_from <= to && p.valid_to.is_none_or(|valid_to| valid_to > from) && allows(providers, &p.station))
+            .cloned()
+            .collect();
+        periods.sort_by(|a, b| (&a.station.id, a.valid_from).cmp(&(&b.station.id, b.valid_from)));
+        Ok(periods)
+    }
+
+    fn get_latest_observations(&self, stations: &[Station; 3]) -> Result<[Observation; 3], Error> {
+        self.get_latest_observations_at(stations, DateTime::<Utc>::MAX_UTC)
+    }
+
+    fn get_latest_observations_at(&self, stations: &[Station; 3], at: DateTime<Utc>) -> Result<[Observation; 3], Error> {
+        let stored = self.observations.read().unwrap();
+        let mut latest_observations: [Observation; 3] = Default::default();
+        for (i, station) in stations.iter().enumerate() {
+            latest_observations[i] = stored
+                .values()
+                .filter(|o| o.station_id == station.id && o.observation_time <= at)
+                .max_by_key(|o| o.observation_time)
+                .cloned()
+                .unwrap_or_default();
+        }
+        Ok(latest_observations)
+    }
+
+    fn get_observations_between(&self, station_ids: &[String], from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Observation>, Error> {
+        let mut observations: Vec<Observation> = self
+            .observations
+            .read()
+            .unwrap()
+            .values()
+            .filter(|o| station_ids.contains(&o.station_id) && o.observation_time >= from && o.observation_time <= to)
+            .cloned()
+            .collect();
+        observations.sort_by_key(|o| o.observation_time);
+        Ok(observations)
+    }
+
+    fn for_each_observation(&self, filter: &ObservationFilter, f: &mut dyn FnMut(Observation) -> Result<(), Error>) -> Result<(), Error> {
+        let mut observations: Vec<Observation> = self
+            .observations
+            .read()
+            .unwrap()
+            .values()
+            .filter(|o| filter.station_ids.as_ref().is_none_or(|ids| ids.contains(&o.station_id)))
+            .filter(|o| filter.from.is_none_or(|from| o.observation_time >= from))
+            .filter(|o| filter.to.is_none_or(|to| o.observation_time <= to))
+            .cloned()
+            .collect();
+        observations.sort_by(|a, b| (a.observation_time, &a.station_id).cmp(&(b.observation_time, &b.station_id)));
+        observations.into_iter().try_for_each(f)
+    }
+
+    fn roll_up_observations_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut observations = self.observations.write().unwrap();
+        let expired: Vec<Observation> = observations.values().filter(|o| o.observation_time < before).cloned().collect();
+        observations.retain(|_, o| o.observation_time >= before);
+        let mut stored = self.daily_summaries.write().unwrap();
+        for summary in summarize_daily(&expired) {
+            let key = (summary.station_id.clone(), summary.day);
+            let merged = match stored.get(&key) {
+                Some(previous) => merge_summaries(previous, &summary),
+                None => summary,
+            };
+            stored.insert(key, merged);
+        }
+        Ok(expired.len())
+    }
+
+    fn delete_daily_summaries_before(&self, before: NaiveDate) -> Result<usize, Error> {
+        let mut stored = self.daily_summaries.write().unwrap();
+        let count = stored.len();
+        stored.retain(|_, s| s.day >= before);
+        Ok(count - stored.len())
+    }
+
+    fn insert_ingestion_run(&self, run: &IngestionRun) -> Result<(), Error> {
+        self.ingestion_runs.write().unwrap().push(run.clone());
+        Ok(())
+    }
+
+    fn get_ingestion_runs(&self, limit: usize) -> Result<Vec<IngestionRun>, Error> {
+        let mut runs: Vec<IngestionRun> = self.ingestion_runs.read().unwrap().iter().rev().cloned().collect();
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+        runs.truncate(limit);
+        Ok(runs)
+    }
+
+    fn upsert_municipalities(&self, municipalities: &[Municipality]) -> Result<(), Error> {
+        let mut stored = self.municipalities.write().unwrap();
+        for municipality in municipalities {
+            let province = municipality.province.clone().or_else(|| stored.get(&municipality.id).and_then(|m| m.province.clone()));
+            stored.insert(municipality.id.clone(), Municipality { province, ..municipality.clone() });
+        }
+        Ok(())
+    }
+
+    fn upsert_forecasts(&self, forecasts: &[Forecast]) -> Result<usize, Error> {
+        let mut stored = self.forecasts.write().unwrap();
+        let mut written = 0;
+        for forecast in forecasts {
+            let key = (forecast.municipality_id.clone(), forecast.forecast_time);
+            if stored.get(&key).is_none_or(|previous| forecast.elaborated_at >= previous.elaborated_at) {
+                stored.insert(key, forecast.clone());
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    fn get_closest_municipality(&self, loc: &Location) -> Result<Option<Municipality>, Error> {
+        let forecasts = self.forecasts.read().unwrap();
+        let distance = |m: &Municipality| loc.ranking_distance(m.lat, m.lon);
+        Ok(self
+            .municipalities
+            .read()
+            .unwrap()
+            .values()
+            .filter(|m| forecasts.keys().any(|(id, _)| *id == m.id))
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .cloned())
+    }
+
+    fn get_municipality(&self, id: &str) -> Result<Option<Municipality>, Error> {
+        Ok(self.municipalities.read().unwrap().get(id).cloned())
+    }
+
+    fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        let mut municipalities: Vec<Municipality> = self.municipalities.read().unwrap().values().cloned().collect();
+        municipalities.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(municipalities)
+    }
+
+    fn get_forecasts(&self, municipality_id: &str, from: DateTime<Utc>) -> Result<Vec<Forecast>, Error> {
+        let mut forecasts: Vec<Forecast> = self
+            .forecasts
+            .read()
+            .unwrap()
+            .values()
+            .filter(|f| f.municipality_id == municipality_id && f.forecast_time >= from)
+            .cloned()
+            .collect();
+        forecasts.sort_by_key(|f| f.forecast_time);
+        Ok(forecasts)
+    }
+
+    fn delete_forecasts_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut stored = self.forecasts.write().unwrap();
+        let count = stored.len();
+        stored.retain(|_, f| f.forecast_time >= before);
+        Ok(count - stored.len())
+    }
+
+    fn replace_warnings(&self, warnings: &[Warning], now: DateTime<Utc>) -> Result<usize, Error> {
+        let mut stored = self.warnings.write().unwrap();
+        let replaced = |w: &Warning| w.expires > now && !warnings.iter().any(|n| n.id == w.id);
+        stored.retain(|w| !(replaced(w) && w.onset >= now));
+        for w in stored.iter_mut().filter(|w| replaced(w)) {
+            w.expires = now;
+        }
+        for warning in warnings {
+            stored.retain(|w| w.id != warning.id);
+            stored.push(warning.clone());
+        }
+        Ok(warnings.len())
+    }
+
+    fn delete_warnings_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut stored = self.warnings.write().unwrap();
+        let count = stored.len();
+        stored.retain(|w| w.expires >= before);
+        Ok(count - stored.len())
+    }
+
+    fn get_warnings_at(&self, at: DateTime<Utc>) -> Result<Vec<Warning>, Error> {
+        let mut warnings: Vec<Warning> = self.warnings.read().unwrap().iter().filter(|w| w.onset <= at && w.expires > at).cloned().collect();
+        warnings.sort_by(|a, b| (a.onset, &a.id).cmp(&(b.onset, &b.id)));
+        Ok(warnings)
+    }
+}
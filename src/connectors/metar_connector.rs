@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    calculators::location_data_calculations::calculate_relative_humidity,
+    config::get_env_var_or,
+    met::{resolve_report_time, MeteoData, Observation, Station},
+};
+
+use super::{
+    downloader,
+    drop_dir::{read_dropped_files, DroppedData},
+    station_table::load_station_table,
+};
+
+pub const PROVIDER: &str = "metar";
+
+const ENV_URL: &str = "WHEATR_METAR_URL";
+const ENV_DIR: &str = "WHEATR_METAR_DIR";
+const ENV_AIRPORTS_PATH: &str = "WHEATR_METAR_AIRPORTS_PATH";
+const AIRPORTS_CSV: &str = include_str!("../../data/airports.csv");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetarReport {
+    pub icao: String,
+    pub observation_time: DateTime<Utc>,
+    pub temperature: Option<f32>,
+    pub dew_point: Option<f32>,
+}
+
+/// Whether a URL or a drop directory to read METAR from is set.
+pub fn is_configured() -> bool {
+    !get_env_var_or(ENV_URL, String::new()).is_empty() || !get_env_var_or(ENV_DIR, String::new()).is_empty()
+}
+
+/**
+ * Splits raw text into reports. Reports are terminated by `=` in bulletins,
+ * otherwise there is one report per line.
+ */
+fn split_reports(text: &str) -> Vec<String> {
+    let reports: Vec<&str> = if text.contains('=') { text.split('=').collect() } else { text.lines().collect() };
+    reports.iter().map(|r| r.split_whitespace().collect::<Vec<&str>>().join(" ")).filter(|r| !r.is_empty()).collect()
+}
+
+/// Temperature in whole degrees, `M` meaning minus (e.g. `M05`).
+fn parse_temperature(value: &str) -> Option<f32> {
+    let (sign, digits) = match value.strip_prefix('M') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, value),
+    };
+    if digits.len() != 2 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<f32>().ok().map(|t| sign * t)
+}
+
+/// The `TT/TdTd` group, the dew point may be missing (`25/` or `25//`).
+fn parse_temperature_group(token: &str) -> Option<(f32, Option<f32>)> {
+    let (temperature, dew_point) = token.split_once('/')?;
+    let temperature = parse_temperature(temperature)?;
+    match dew_point {
+        "" | "//" => Some((temperature, None)),
+        dew_point => Some((temperature, Some(parse_temperature(dew_point)?))),
+    }
+}
+
+/// Time of a `DDHHMMZ` group.
+fn parse_time(token: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let digits = token.strip_suffix('Z').filter(|d| d.len() == 6 && d.bytes().all(|b| b.is_ascii_digit()))?;
+    resolve_report_time(digits[0..2].parse().ok()?, digits[2..4].parse().ok()?, digits[4..6].parse().ok()?, now)
+}
+
+fn is_icao(token: &str) -> bool {
+    token.len() == 4 && token.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+}
+
+/**
+ * Parses a METAR or SPECI report, which starts at the station and time
+ * groups, skipping any bulletin heading. Groups after the remarks or a
+ * trend forecast are ignored; `None` if there is no station or time.
+ */
+pub fn parse_report(report: &str, now: DateTime<Utc>) -> Option<MetarReport> {
+    let tokens: Vec<&str> = report.split_whitespace().collect();
+    let (start, observation_time) = tokens
+        .windows(2)
+        .enumerate()
+        .find_map(|(i, pair)| if is_icao(pair[0]) { parse_time(pair[1], now).map(|t| (i, t)) } else { None })?;
+    let (temperature, dew_point) = tokens[start + 2..]
+        .iter()
+        .take_while(|t| !matches!(**t, "RMK" | "TEMPO" | "BECMG" | "NOSIG"))
+        .find_map(|t| parse_temperature_group(t))
+        .map_or((None, None), |(t, td)| (Some(t), td));
+    Some(MetarReport { icao: tokens[start].to_string(), observation_time, temperature, dew_point })
+}
+
+/**
+ * Converts the reports of known airports into stations and observations,
+ * humidity computed from the dew point. Reports without temperature or dew
+ * point, or of airports missing from the table, are skipped.
+ */
+pub fn read_metar(text: &str, airports: &HashMap<String, Station>, now: DateTime<Utc>) -> MeteoData {
+    let mut stations: HashMap<String, Station> = HashMap::new();
+    let mut observations = vec![];
+    let mut skipped_observations = 0;
+    for report in split_reports(text) {
+        let parsed = match parse_report(&report, now) {
+            Some(p) => p,
+            None => continue,
+        };
+        let station = match airports.get(&parsed.icao) {
+            Some(s) => s,
+            None => {
+                println!("Unknown airport {} in METAR: {}", parsed.icao, report);
+                skipped_observations += 1;
+                continue;
+            }
+        };
+        match (parsed.temperature, parsed.dew_point) {
+            (Some(temperature), Some(dew_point)) => observations.push(Observation {
+                station_id: station.id.clone(),
+                observation_time: parsed.observation_time,
+                aerial_temperature: temperature,
+                relative_humidity: calculate_relative_humidity(temperature, dew_point),
+            }),
+            _ => skipped_observations += 1,
+        }
+        stations.insert(station.id.clone(), station.clone());
+    }
+    let mut stations: Vec<Station> = stations.into_values().collect();
+    stations.sort_by(|a, b| a.id.cmp(&b.id));
+    MeteoData { stations, observations, skipped_observations }
+}
+
+/**
+ * Loads METAR from `WHEATR_METAR_URL` and from the files dropped in
+ * `WHEATR_METAR_DIR`, which are moved to its `processed` subdirectory once
+ * written.
+ */
+pub fn load_data() -> Result<DroppedData, Error> {
+    let url: String = get_env_var_or(ENV_URL, String::new());
+    let dir: String = get_env_var_or(ENV_DIR, String::new());
+    if url.is_empty() && dir.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("Neither {} nor {} is set", ENV_URL, ENV_DIR)));
+    }
+    let airports = load_station_table(ENV_AIRPORTS_PATH, AIRPORTS_CSV, PROVIDER);
+
+    // sources are split on their own, as some terminate reports by `=` and some do not
+    let mut text = String::new();
+    let mut append = |source: &str| split_reports(source).iter().for_each(|report| text.push_str(&format!("{}=\n", report)));
+    if !url.is_empty() {
+        append(&String::from_utf8_lossy(&downloader::download_content(&url, "")?));
+    }
+    let mut files = vec![];
+    if !dir.is_empty() {
+        let contents;
+        (files, contents) = read_dropped_files(Path::new(&dir), &|file| fs::read_to_string(file))?;
+        contents.iter().for_each(|content| append(content));
+    }
+    Ok(DroppedData { meteo_data: read_metar(&text, &airports, Utc::now()), dir: PathBuf::from(dir), files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connectors::station_table::read_station_table, met::parse_observation_time};
+
+    fn time(value: &str) -> DateTime<Utc> {
+        parse_observation_time(value).unwrap()
+    }
+
+    #[test]
+    fn parses_reports() {
+        let now = time("2023-08-10T12:10:00Z");
+        assert_eq!(
+            parse_report("METAR LEMD 101200Z 24010KT 9999 FEW040 34/M02 Q1015 NOSIG", now),
+            Some(MetarReport { icao: "LEMD".to_string(), observation_time: time("2023-08-10T12:00:00Z"), temperature: Some(34.0), dew_point: Some(-2.0) })
+        );
+        // a report of the last day of the previous month
+        let report = parse_report("LEBL 312330Z VRB02KT CAVOK M01/ Q1020 RMK 05/04", time("2023-08-01T00:10:00Z")).unwrap();
+        assert_eq!((report.observation_time, report.temperature, report.dew_point), (time("2023-07-31T23:30:00Z"), Some(-1.0), None));
+        assert_eq!(parse_report("2023/08/10 12:00", now), None);
+    }
+
+    #[test]
+    fn reads_bulletins_of_known_airports() {
+        let airports = read_station_table(AIRPORTS_CSV, PROVIDER).unwrap();
+        let text = "SAES31 LEMM 101200\nMETAR LEMD 101200Z 24010KT 9999\n FEW040 25/12 Q1015=\nMETAR LEMG 101200Z 18008KT CAVOK 28/\nQ1014=\nMETAR XXXX 101200Z 20/10 Q1010=\nMETAR LEZL 101200Z NIL=";
+        let data = read_metar(text, &airports, time("2023-08-10T12:10:00Z"));
+
+        let ids: Vec<&str> = data.stations.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["LEMD", "LEMG", "LEZL"]);
+        assert_eq!(data.stations[0].provider, PROVIDER);
+        assert_eq!(data.observations.len(), 1);
+        assert_eq!(data.observations[0].aerial_temperature, 25.0);
+        assert_eq!((data.observations[0].relative_humidity * 10.0).round(), 443.0);
+        assert_eq!(data.skipped_observations, 3);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Error, ErrorKind},
+};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::Deserialize;
+
+use crate::{
+    config::get_env_var_or,
+    met::{MeteoData, Observation, Station},
+};
+
+use super::downloader;
+
+pub const PROVIDER: &str = "meteocat";
+
+const ENV_URL: &str = "WHEATR_METEOCAT_URL";
+/// Open data datasets of the XEMA automatic stations and their readings.
+const STATIONS_DATASET: &str = "yqwd-vj5e.json";
+const READINGS_DATASET: &str = "nzvn-apee.json";
+const TEMPERATURE_VARIABLE: &str = "32";
+const HUMIDITY_VARIABLE: &str = "33";
+/// Readings of the last hours are requested, as they are published with a delay.
+const READINGS_HOURS: i64 = 3;
+
+/// Station of the XEMA metadata, numbers are strings in the open data API.
+#[derive(Debug, Deserialize)]
+pub struct MeteocatStation {
+    pub codi_estacio: String,
+    pub nom_estacio: String,
+    pub latitud: String,
+    pub longitud: String,
+    pub altitud: Option<String>,
+}
+
+/// Reading of one variable of a station, in UTC.
+#[derive(Debug, Deserialize)]
+pub struct MeteocatReading {
+    pub codi_estacio: String,
+    pub codi_variable: String,
+    pub data_lectura: String,
+    pub valor_lectura: String,
+}
+
+/// Temperature and humidity of a station at a time.
+type ReadingPair = (Option<f32>, Option<f32>);
+
+/// Whether the URL of the open data API is set.
+pub fn is_configured() -> bool {
+    !get_env_var_or(ENV_URL, String::new()).is_empty()
+}
+
+fn to_station(station: &MeteocatStation) -> Option<Station> {
+    Some(Station {
+        id: format!("{}:{}", PROVIDER, station.codi_estacio),
+        name: station.nom_estacio.clone(),
+        lat: station.latitud.parse().ok()?,
+        lon: station.longitud.parse().ok()?,
+        altitude: station.altitud.as_ref().and_then(|a| a.parse().ok()),
+        provider: PROVIDER.to_string(),
+    })
+}
+
+fn parse_reading_time(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").ok().map(|time| time.and_utc())
+}
+
+/**
+ * Pairs the temperature and humidity readings of a station at the same
+ * time into observations. Readings of unknown stations or without the other
+ * variable are skipped.
+ */
+pub fn convert_to_data_objects(stations: &[MeteocatStation], readings: &[MeteocatReading]) -> MeteoData {
+    let known: HashMap<&str, Station> = stations.iter().filter_map(|s| to_station(s).map(|station| (s.codi_estacio.as_str(), station))).collect();
+    let mut pairs: BTreeMap<(&str, DateTime<Utc>), ReadingPair> = BTreeMap::new();
+    let mut skipped_observations = 0;
+    for reading in readings {
+        let (time, value) = match (parse_reading_time(&reading.data_lectura), reading.valor_lectura.parse::<f32>()) {
+            (Some(time), Ok(value)) => (time, value),
+            _ => {
+                println!("Invalid Meteocat reading of {} at {}: {}", reading.codi_estacio, reading.data_lectura, reading.valor_lectura);
+                skipped_observations += 1;
+                continue;
+            }
+        };
+        let pair = pairs.entry((reading.codi_estacio.as_str(), time)).or_default();
+        match reading.codi_variable.as_str() {
+            TEMPERATURE_VARIABLE => pair.0 = Some(value),
+            HUMIDITY_VARIABLE => pair.1 = Some(value),
+            _ => (),
+        }
+    }
+
+    let mut used_stations: BTreeMap<&str, Station> = BTreeMap::new();
+    let mut observations = vec![];
+    for ((code, observation_time), pair) in pairs {
+        match (known.get(code), pair) {
+            (Some(station), (Some(temperature), Some(humidity))) => {
+                observations.push(Observation { station_id: station.id.clone(), observation_time, aerial_temperature: temperature, relative_humidity: humidity });
+                used_stations.insert(code, station.clone());
+            }
+            _ => skipped_observations += 1,
+        }
+    }
+    MeteoData { stations: used_stations.into_values().collect(), observations, skipped_observations }
+}
+
+/**
+ * Loads the temperature and humidity readings of the last hours from the
+ * Socrata API of the Catalan open data portal at `WHEATR_METEOCAT_URL`.
+ */
+pub fn load_data() -> Result<MeteoData, Error> {
+    let url: String = get_env_var_or(ENV_URL, String::new());
+    if url.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} is not set", ENV_URL)));
+    }
+    let url = url.trim_end_matches('/');
+    let since = (Utc::now() - Duration::hours(READINGS_HOURS)).format("%Y-%m-%dT%H:%M:%S");
+    let stations: Vec<MeteocatStation> = downloader::download_json(&format!("{}/{}", url, STATIONS_DATASET), "")?;
+    let readings: Vec<MeteocatReading> = downloader::download_json(&format!(
+        "{}/{}?$where=data_lectura>='{}' AND codi_variable in('{}','{}')&$limit=50000",
+        url, READINGS_DATASET, since, TEMPERATURE_VARIABLE, HUMIDITY_VARIABLE
+    ), "")?;
+    Ok(convert_to_data_objects(&stations, &readings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::met::parse_observation_time;
+
+    #[test]
+    fn pairs_readings_of_known_stations() {
+        let stations: Vec<MeteocatStation> = serde_json::from_str(include_str!("../../fixtures/meteocat/yqwd-vj5e.json")).unwrap();
+        let readings: Vec<MeteocatReading> = serde_json::from_str(include_str!("../../fixtures/meteocat/nzvn-apee.json")).unwrap();
+        let data = convert_to_data_objects(&stations, &readings);
+
+        let ids: Vec<&str> = data.stations.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["meteocat:D5", "meteocat:X4", "meteocat:XL"]);
+        assert_eq!((data.stations[0].lat, data.stations[0].altitude), (41.41843, Some(411.0)));
+        assert_eq!(data.observations.len(), 4);
+        let latest = &data.observations[1];
+        assert_eq!((latest.station_id.as_str(), latest.observation_time), ("meteocat:D5", parse_observation_time("2024-07-14T12:30:00Z").unwrap()));
+        assert_eq!((latest.aerial_temperature, latest.relative_humidity), (27.9, 55.0));
+        // XL at 12:30 lacks humidity, ZZ is unknown
+        assert_eq!(data.skipped_observations, 2);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    config::get_env_var_or,
+    met::{parse_observation_time, MeteoData, Observation, Station},
+};
+
+use super::downloader;
+
+pub const PROVIDER: &str = "meteogalicia";
+
+const ENV_URL: &str = "WHEATR_METEOGALICIA_URL";
+const STATIONS_PATH: &str = "listaEstacionsMeteo.action";
+const READINGS_PATH: &str = "ultimos10minEstacionsMeteo.action";
+const TEMPERATURE_PARAMETER: &str = "TA_AVG_1.5m";
+const HUMIDITY_PARAMETER: &str = "HR_AVG_1.5m";
+/// Validation code of values that passed quality control.
+const VALID: i32 = 1;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteoGaliciaStation {
+    pub id_estacion: u32,
+    pub estacion: String,
+    pub lat: f32,
+    pub lon: f32,
+    pub altitude: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteoGaliciaStations {
+    pub lista_estacions_meteo: Vec<MeteoGaliciaStation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteoGaliciaMeasure {
+    pub codigo_parametro: String,
+    pub ln_codigo_validacion: i32,
+    pub valor: f32,
+}
+
+/// The latest 10 minute values of a station.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteoGaliciaReading {
+    pub id_estacion: u32,
+    #[serde(rename = "dataUTC")]
+    pub data_utc: String,
+    pub lista_medidas: Vec<MeteoGaliciaMeasure>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteoGaliciaReadings {
+    pub list_ultimos10min: Vec<MeteoGaliciaReading>,
+}
+
+/// Whether the URL of the observation service is set.
+pub fn is_configured() -> bool {
+    !get_env_var_or(ENV_URL, String::new()).is_empty()
+}
+
+fn valid_value(reading: &MeteoGaliciaReading, parameter: &str) -> Option<f32> {
+    reading.lista_medidas.iter().find(|m| m.codigo_parametro == parameter && m.ln_codigo_validacion == VALID).map(|m| m.valor)
+}
+
+/**
+ * Converts the latest values of the stations into observations. Values
+ * that did not pass quality control are left out, so are readings of
+ * unknown stations or without temperature or humidity.
+ */
+pub fn convert_to_data_objects(stations: &MeteoGaliciaStations, readings: &MeteoGaliciaReadings) -> MeteoData {
+    let known: HashMap<u32, &MeteoGaliciaStation> = stations.lista_estacions_meteo.iter().map(|s| (s.id_estacion, s)).collect();
+    let mut used_stations = vec![];
+    let mut observations = vec![];
+    let mut skipped_observations = 0;
+    for reading in &readings.list_ultimos10min {
+        let station = known.get(&reading.id_estacion);
+        let observation_time = parse_observation_time(&reading.data_utc).ok();
+        match (station, observation_time, valid_value(reading, TEMPERATURE_PARAMETER), valid_value(reading, HUMIDITY_PARAMETER)) {
+            (Some(station), Some(observation_time), Some(temperature), Some(humidity)) => {
+                let station = Station {
+                    id: format!("{}:{}", PROVIDER, station.id_estacion),
+                    name: station.estacion.clone(),
+                    lat: station.lat,
+                    lon: station.lon,
+                    altitude: station.altitude,
+                    provider: PROVIDER.to_string(),
+                };
+                observations.push(Observation { station_id: station.id.clone(), observation_time, aerial_temperature: temperature, relative_humidity: humidity });
+                used_stations.push(station);
+            }
+            _ => skipped_observations += 1,
+        }
+    }
+    MeteoData { stations: used_stations, observations, skipped_observations }
+}
+
+/**
+ * Loads the latest 10 minute values of the stations from the observation
+ * service of MeteoGalicia at `WHEATR_METEOGALICIA_URL`.
+ */
+pub fn load_data() -> Result<MeteoData, Error> {
+    let url: String = get_env_var_or(ENV_URL, String::new());
+    if url.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} is not set", ENV_URL)));
+    }
+    let url = url.trim_end_matches('/');
+    let stations: MeteoGaliciaStations = downloader::download_json(&format!("{}/{}", url, STATIONS_PATH), "")?;
+    let readings: MeteoGaliciaReadings = downloader::download_json(&format!("{}/{}", url, READINGS_PATH), "")?;
+    Ok(convert_to_data_objects(&stations, &readings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_valid_values() {
+        let stations: MeteoGaliciaStations = serde_json::from_str(include_str!("../../fixtures/meteogalicia/listaEstacionsMeteo.action")).unwrap();
+        let readings: MeteoGaliciaReadings = serde_json::from_str(include_str!("../../fixtures/meteogalicia/ultimos10minEstacionsMeteo.action")).unwrap();
+        let data = convert_to_data_objects(&stations, &readings);
+
+        let ids: Vec<&str> = data.stations.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["meteogalicia:14000", "meteogalicia:10800"]);
+        assert_eq!(data.observations[0].observation_time, parse_observation_time("2024-07-14T12:00:00Z").unwrap());
+        assert_eq!((data.observations[1].aerial_temperature, data.observations[1].relative_humidity), (26.02, 52.0));
+        // the humidity of Santiago did not pass quality control
+        assert_eq!(data.skipped_observations, 1);
+    }
+}
@@ -1,4 +1,22 @@
 pub mod aemet_connector;
+pub mod aemet_forecast_connector;
+pub mod aemet_warnings_connector;
+pub mod bufr_connector;
+pub mod csv_importer;
 pub mod db_writer;
 pub mod downloader;
+pub mod drop_dir;
+pub mod euskalmet_connector;
+pub mod metar_connector;
+pub mod meteocat_connector;
+pub mod meteogalicia_connector;
+#[cfg(test)]
+pub mod memory_store;
+#[cfg(feature = "postgres")]
+pub mod postgres_connector;
 pub mod sqlite_connector;
+pub mod sqlite_migrations;
+pub mod sqlite_pool;
+pub mod station_table;
+pub mod store;
+pub mod synop_decoder;
@@ -0,0 +1,856 @@
+use std::io::{Error, ErrorKind};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use postgres::{fallible_iterator::FallibleIterator, types::ToSql, Client, NoTls, Row, Statement, Transaction};
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+
+use crate::calculators::daily_summaries::{next_day_start, summarize_daily};
+use crate::met::{Forecast, IngestionRun, Location, Municipality, Observation, Station, StationPeriod, Warning};
+
+use super::store::{in_station_order, Migration, ObservationFilter, ProviderFilter, StationFilter, Store};
+
+const POOL_SIZE: u32 = 8;
+
+const STMT_GET_CLOSEST_STATIONS: &str = "SELECT id, name, lat, lon, altitude, provider FROM stations
+    WHERE ($3::text[] IS NULL OR provider = ANY($3)) AND provider <> ALL($4)
+    ORDER BY position <-> ST_SetSRID(ST_MakePoint($2, $1), 4326)::geography LIMIT 10";
+const STMT_GET_CLOSEST_STATIONS_AT: &str = "SELECT h.station_id AS id, h.name, h.lat, h.lon, h.altitude, s.provider FROM station_history h JOIN stations s ON s.id = h.station_id
+    WHERE h.valid_from <= $3 AND (h.valid_to IS NULL OR h.valid_to > $3) AND ($4::text[] IS NULL OR s.provider = ANY($4)) AND s.provider <> ALL($5)
+    ORDER BY h.position <-> ST_SetSRID(ST_MakePoint($2, $1), 4326)::geography LIMIT 10";
+const STMT_GET_STATION_PERIODS: &str = "SELECT h.station_id AS id, h.name, h.lat, h.lon, h.altitude, s.provider, h.valid_from, h.valid_to FROM station_history h JOIN stations s ON s.id = h.station_id
+    WHERE h.valid_from <= $2 AND (h.valid_to IS NULL OR h.valid_to > $1) AND ($3::text[] IS NULL OR s.provider = ANY($3)) AND s.provider <> ALL($4)
+    ORDER BY h.station_id, h.valid_from";
+const STMT_GET_STATIONS_WITH_LATEST_OBSERVATION: &str = "SELECT s.id, s.name, s.lat, s.lon, s.altitude, s.provider, o.station_id, o.observation_time, o.air_temperature, o.rel_humidity
+    FROM stations s LEFT JOIN LATERAL (SELECT * FROM observations WHERE station_id = s.id ORDER BY observation_time DESC LIMIT 1) o ON true
+    WHERE ($1::text IS NULL OR s.provider = $1)
+        AND ($2::real IS NULL OR s.position && ST_MakeEnvelope($3::real, $2, $5::real, $4::real, 4326)::geography)
+    ORDER BY s.id";
+const STMT_GET_LATEST_OBSERVATIONS: &str = "SELECT DISTINCT ON (station_id) station_id, observation_time, air_temperature, rel_humidity FROM observations WHERE station_id = ANY($1) ORDER BY station_id, observation_time DESC";
+const STMT_GET_LATEST_OBSERVATIONS_AT: &str = "SELECT DISTINCT ON (station_id) station_id, observation_time, air_temperature, rel_humidity FROM observations WHERE station_id = ANY($1) AND observation_time <= $2 ORDER BY station_id, observation_time DESC";
+const STMT_GET_STATION: &str = "SELECT id, name, lat, lon, altitude, provider FROM stations WHERE id = $1";
+const STMT_GET_STATION_FOR_UPDATE: &str = "SELECT id, name, lat, lon, altitude, provider FROM stations WHERE id = $1 FOR UPDATE";
+const STMT_INSERT_STATION: &str = "INSERT INTO stations (id, name, lat, lon, altitude, provider) VALUES ($1, $2, $3, $4, $5, $6)";
+const STMT_UPDATE_STATION: &str = "UPDATE stations SET name = $2, lat = $3, lon = $4, altitude = $5 WHERE id = $1";
+const STMT_CLOSE_STATION_HISTORY: &str = "UPDATE station_history SET valid_to = $2 WHERE station_id = $1 AND valid_to IS NULL AND valid_from < $2
+    AND NOT EXISTS (SELECT 1 FROM observations WHERE station_id = $1 AND observation_time > $2)";
+const STMT_INSERT_STATION_HISTORY: &str = "INSERT INTO station_history (station_id, name, lat, lon, altitude, valid_from) VALUES ($1, $2, $3, $4, $5, $6)";
+const STMT_SET_OBSERVATION: &str = "INSERT INTO observations (station_id, observation_time, air_temperature, rel_humidity) VALUES ($1, $2, $3, $4) ON CONFLICT (station_id, observation_time) DO NOTHING";
+
+const STMT_GET_FIRST_OBSERVATION_TIME_BEFORE: &str = "SELECT MIN(observation_time) FROM observations WHERE observation_time < $1";
+const STMT_DELETE_OBSERVATIONS_BEFORE: &str = "DELETE FROM observations WHERE observation_time < $1 RETURNING station_id, observation_time, air_temperature, rel_humidity";
+const STMT_MERGE_DAILY_SUMMARY: &str = "INSERT INTO daily_observations AS d VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+    ON CONFLICT (station_id, day) DO UPDATE SET
+        samples = d.samples + excluded.samples,
+        air_temperature_min = LEAST(d.air_temperature_min, excluded.air_temperature_min),
+        air_temperature_max = GREATEST(d.air_temperature_max, excluded.air_temperature_max),
+        air_temperature_mean = (d.air_temperature_mean * d.samples + excluded.air_temperature_mean * excluded.samples) / (d.samples + excluded.samples),
+        rel_humidity_min = LEAST(d.rel_humidity_min, excluded.rel_humidity_min),
+        rel_humidity_max = GREATEST(d.rel_humidity_max, excluded.rel_humidity_max),
+        rel_humidity_mean = (d.rel_humidity_mean * d.samples + excluded.rel_humidity_mean * excluded.samples) / (d.samples + excluded.samples),
+        heat_index_min = LEAST(d.heat_index_min, excluded.heat_index_min),
+        heat_index_max = GREATEST(d.heat_index_max, excluded.heat_index_max),
+        heat_index_mean = (d.heat_index_mean * d.samples + excluded.heat_index_mean * excluded.samples) / (d.samples + excluded.samples)";
+const STMT_GET_OBSERVATIONS_BETWEEN: &str = "SELECT station_id, observation_time, air_temperature, rel_humidity FROM observations WHERE station_id = ANY($1) AND observation_time BETWEEN $2 AND $3 ORDER BY observation_time ASC";
+const STMT_GET_OBSERVATIONS_FILTERED: &str = "SELECT station_id, observation_time, air_temperature, rel_humidity FROM observations
+    WHERE ($1::text[] IS NULL OR station_id = ANY($1))
+        AND ($2::timestamptz IS NULL OR observation_time >= $2)
+        AND ($3::timestamptz IS NULL OR observation_time <= $3)
+    ORDER BY observation_time ASC, station_id ASC";
+const STMT_DELETE_DAILY_SUMMARIES_BEFORE: &str = "DELETE FROM daily_observations WHERE day < $1";
+const STMT_INSERT_INGESTION_RUN: &str = "INSERT INTO ingestion_runs (provider, started_at, finished_at, succeeded, http_status, error, parsed_stations, parsed_observations, inserted_observations, duplicated_observations, skipped_observations)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)";
+const STMT_GET_INGESTION_RUNS: &str = "SELECT * FROM ingestion_runs ORDER BY started_at DESC, id DESC LIMIT $1";
+const STMT_UPSERT_MUNICIPALITY: &str = "INSERT INTO municipalities AS m (id, name, province, lat, lon, altitude) VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT (id) DO UPDATE SET name = excluded.name, province = COALESCE(excluded.province, m.province), lat = excluded.lat, lon = excluded.lon, altitude = excluded.altitude";
+const STMT_UPSERT_FORECAST: &str = "INSERT INTO forecasts AS f (municipality_id, forecast_time, air_temperature, rel_humidity, elaborated_at) VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (municipality_id, forecast_time) DO UPDATE SET air_temperature = excluded.air_temperature, rel_humidity = excluded.rel_humidity, elaborated_at = excluded.elaborated_at
+    WHERE excluded.elaborated_at >= f.elaborated_at";
+const STMT_GET_CLOSEST_MUNICIPALITY: &str = "SELECT id, name, province, lat, lon, altitude FROM municipalities m
+    WHERE EXISTS (SELECT 1 FROM forecasts WHERE municipality_id = m.id)
+    ORDER BY position <-> ST_SetSRID(ST_MakePoint($2, $1), 4326)::geography LIMIT 1";
+const STMT_GET_MUNICIPALITY: &str = "SELECT id, name, province, lat, lon, altitude FROM municipalities WHERE id = $1";
+const STMT_GET_MUNICIPALITIES: &str = "SELECT id, name, province, lat, lon, altitude FROM municipalities ORDER BY id ASC";
+const STMT_GET_FORECASTS: &str = "SELECT * FROM forecasts WHERE municipality_id = $1 AND forecast_time >= $2 ORDER BY forecast_time ASC";
+const STMT_DELETE_FORECASTS_BEFORE: &str = "DELETE FROM forecasts WHERE forecast_time < $1";
+const STMT_END_REPLACED_WARNINGS: &str = "UPDATE warnings SET expires = $1 WHERE onset < $1 AND expires > $1 AND id <> ALL($2)";
+const STMT_DELETE_REPLACED_WARNINGS: &str = "DELETE FROM warnings WHERE onset >= $1 AND id <> ALL($2)";
+const STMT_DELETE_WARNINGS_BEFORE: &str = "DELETE FROM warnings WHERE expires < $1";
+const STMT_UPSERT_WARNING: &str = "INSERT INTO warnings (id, kind, event, level, severity, headline, area, onset, expires, polygon)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+    ON CONFLICT (id) DO UPDATE SET kind = excluded.kind, event = excluded.event, level = excluded.level, severity = excluded.severity, headline = excluded.headline,
+        area = excluded.area, onset = excluded.onset, expires = excluded.expires, polygon = excluded.polygon";
+const STMT_GET_WARNINGS_AT: &str = "SELECT * FROM warnings WHERE onset <= $1 AND expires > $1 ORDER BY onset ASC, id ASC";
+
+const STMT_CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER NOT NULL PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at TIMESTAMPTZ NOT NULL
+)";
+const STMT_HAS_SCHEMA_VERSION: &str = "SELECT to_regclass('schema_version') IS NOT NULL";
+const STMT_GET_SCHEMA_VERSION: &str = "SELECT COALESCE(MAX(version), 0) FROM schema_version";
+const STMT_SET_SCHEMA_VERSION: &str =
+    "INSERT INTO schema_version (version, description, applied_at) VALUES ($1, $2, $3)";
+
+/**
+ * Schema changes of the PostgreSQL store. Station positions are kept in a
+ * generated geography column with a GiST index, so nearest-station queries
+ * are answered by an index scan.
+ */
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create stations and observations tables with PostGIS positions",
+        sql: "CREATE EXTENSION IF NOT EXISTS postgis;
+        CREATE TABLE IF NOT EXISTS stations (
+            id TEXT NOT NULL PRIMARY KEY,
+            name TEXT,
+            lat REAL NOT NULL,
+            lon REAL NOT NULL,
+            position geography(Point, 4326) GENERATED ALWAYS AS (ST_SetSRID(ST_MakePoint(lon, lat), 4326)::geography) STORED
+        );
+        CREATE INDEX IF NOT EXISTS stations_position ON stations USING GIST (position);
+        CREATE TABLE IF NOT EXISTS observations (
+            station_id TEXT NOT NULL REFERENCES stations(id) ON DELETE CASCADE,
+            observation_time TIMESTAMPTZ NOT NULL,
+            air_temperature REAL NOT NULL,
+            rel_humidity REAL NOT NULL,
+            PRIMARY KEY(station_id, observation_time)
+        );",
+    },
+    Migration {
+        version: 2,
+        description: "Create daily_observations table for roll-ups of expired observations",
+        sql: "CREATE TABLE IF NOT EXISTS daily_observations (
+            station_id TEXT NOT NULL REFERENCES stations(id) ON DELETE CASCADE,
+            day DATE NOT NULL,
+            samples INTEGER NOT NULL,
+            air_temperature_min REAL NOT NULL,
+            air_temperature_max REAL NOT NULL,
+            air_temperature_mean REAL NOT NULL,
+            rel_humidity_min REAL NOT NULL,
+            rel_humidity_max REAL NOT NULL,
+            rel_humidity_mean REAL NOT NULL,
+            heat_index_min REAL NOT NULL,
+            heat_index_max REAL NOT NULL,
+            heat_index_mean REAL NOT NULL,
+            PRIMARY KEY(station_id, day)
+        );",
+    },
+    Migration {
+        version: 3,
+        description: "Add station altitude and station_history table",
+        sql: "ALTER TABLE stations ADD COLUMN IF NOT EXISTS altitude REAL;
+        CREATE TABLE IF NOT EXISTS station_history (
+            station_id TEXT NOT NULL REFERENCES stations(id) ON DELETE CASCADE,
+            name TEXT,
+            lat REAL NOT NULL,
+            lon REAL NOT NULL,
+            altitude REAL,
+            valid_from TIMESTAMPTZ NOT NULL,
+            valid_to TIMESTAMPTZ,
+            position geography(Point, 4326) GENERATED ALWAYS AS (ST_SetSRID(ST_MakePoint(lon, lat), 4326)::geography) STORED,
+            PRIMARY KEY(station_id, valid_from)
+        );
+        CREATE INDEX IF NOT EXISTS station_history_position ON station_history USING GIST (position);
+        INSERT INTO station_history (station_id, name, lat, lon, altitude, valid_from)
+            SELECT id, name, lat, lon, altitude, 'epoch' FROM stations;",
+    },
+    Migration {
+        version: 4,
+        description: "Create ingestion_runs table",
+        sql: "CREATE TABLE IF NOT EXISTS ingestion_runs (
+            id BIGSERIAL PRIMARY KEY,
+            provider TEXT NOT NULL,
+            started_at TIMESTAMPTZ NOT NULL,
+            finished_at TIMESTAMPTZ NOT NULL,
+            succeeded BOOLEAN NOT NULL,
+            http_status INTEGER,
+            error TEXT,
+            parsed_stations BIGINT NOT NULL,
+            parsed_observations BIGINT NOT NULL,
+            inserted_observations BIGINT NOT NULL,
+            duplicated_observations BIGINT NOT NULL,
+            skipped_observations BIGINT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS ingestion_runs_started_at ON ingestion_runs (started_at);",
+    },
+    Migration {
+        version: 5,
+        description: "Add station provider",
+        sql: "ALTER TABLE stations ADD COLUMN IF NOT EXISTS provider TEXT NOT NULL DEFAULT 'aemet';
+        CREATE INDEX IF NOT EXISTS stations_provider ON stations (provider);",
+    },
+    Migration {
+        version: 6,
+        description: "Create municipalities and forecasts tables",
+        sql: "CREATE TABLE IF NOT EXISTS municipalities (
+            id TEXT NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            province TEXT,
+            lat REAL NOT NULL,
+            lon REAL NOT NULL,
+            altitude REAL,
+            position geography(Point, 4326) GENERATED ALWAYS AS (ST_SetSRID(ST_MakePoint(lon, lat), 4326)::geography) STORED
+        );
+        CREATE INDEX IF NOT EXISTS municipalities_position ON municipalities USING GIST (position);
+        CREATE TABLE IF NOT EXISTS forecasts (
+            municipality_id TEXT NOT NULL REFERENCES municipalities(id) ON DELETE CASCADE,
+            forecast_time TIMESTAMPTZ NOT NULL,
+            air_temperature REAL NOT NULL,
+            rel_humidity REAL NOT NULL,
+            elaborated_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY(municipality_id, forecast_time)
+        );
+        CREATE INDEX IF NOT EXISTS forecasts_time ON forecasts (forecast_time);",
+    },
+    Migration {
+        version: 7,
+        description: "Create warnings table",
+        sql: "CREATE TABLE IF NOT EXISTS warnings (
+            id TEXT NOT NULL PRIMARY KEY,
+            kind TEXT NOT NULL,
+            event TEXT NOT NULL,
+            level TEXT NOT NULL,
+            severity TEXT NOT NULL,
+            headline TEXT NOT NULL,
+            area TEXT NOT NULL,
+            onset TIMESTAMPTZ NOT NULL,
+            expires TIMESTAMPTZ NOT NULL,
+            polygon TEXT NOT NULL
+        );",
+    },
+];
+
+/**
+ * Store backed by PostgreSQL with PostGIS, for deployments shared by
+ * several instances. Available with the `postgres` feature.
+ */
+pub struct PostgresStore {
+    pool: Pool<PostgresConnectionManager<NoTls>>,
+}
+
+impl PostgresStore {
+    pub fn open(url: &str) -> Result<PostgresStore, Error> {
+        let config = url
+            .parse()
+            .map_err(|err| Error::other(format!("Invalid database url: {}", err)))?;
+        let manager = PostgresConnectionManager::new(config, NoTls);
+        match Pool::builder().max_size(POOL_SIZE).build(manager) {
+            Ok(pool) => Ok(PostgresStore { pool }),
+            Err(err) => Err(Error::other(format!("Database opening failed: {}", err))),
+        }
+    }
+
+    fn get_connection(&self) -> Result<PooledConnection<PostgresConnectionManager<NoTls>>, Error> {
+        self.pool
+            .get()
+            .map_err(|err| Error::other(format!("Database opening failed: {}", err)))
+    }
+
+    /**
+     * Closest stations among the ten nearest ones found by the index,
+     * skipping co-located stations and ranked like the other stores do.
+     */
+    fn query_closest_stations(&self, loc: &Location, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<[Station; 3], Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(query, params)
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        let candidates: Vec<Station> = rows.iter().map(read_station).collect();
+        loc.closest_stations(&candidates)
+            .map(|closest| closest.map(Station::clone))
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Not Found: less than three stations are known"))
+    }
+
+    fn query_latest_observations(&self, stations: &[Station; 3], query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<[Observation; 3], Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(query, params)
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(in_station_order(stations, rows.iter().map(read_observation).collect()))
+    }
+
+    fn write_items<T>(
+        &self,
+        items: &[T],
+        write_statement: &str,
+        write_item: &dyn Fn(&mut Transaction, &Statement, &T) -> Result<u64, postgres::Error>,
+    ) -> Result<usize, Error> {
+        let mut connection = self.get_connection()?;
+        let result = connection.transaction().and_then(|mut transaction| {
+            let stmt = transaction.prepare(write_statement)?;
+            let mut changed_rows = 0;
+            for item in items {
+                changed_rows += write_item(&mut transaction, &stmt, item)?;
+            }
+            transaction.commit().map(|_| changed_rows as usize)
+        });
+        match result {
+            Ok(changed_rows) => Ok(changed_rows),
+            Err(err) => {
+                println!("Error with connection: {}", err);
+                Err(Error::other(format!("Data saving failed: {}", err)))
+            }
+        }
+    }
+}
+
+fn read_station(row: &Row) -> Station {
+    Station {
+        id: row.get("id"),
+        name: row.get("name"),
+        lat: row.get("lat"),
+        lon: row.get("lon"),
+        altitude: row.get("altitude"),
+        provider: row.get("provider"),
+    }
+}
+
+/**
+ * Same semantics as the SQLite store: a changed station closes its current
+ * history period at `valid_from` and opens a new one, changes dated before
+ * the current period are ignored, and the first known state is valid since
+ * ever.
+ */
+fn upsert_station(transaction: &mut Transaction, station: &Station, valid_from: &DateTime<Utc>) -> Result<(), postgres::Error> {
+    let params: [&(dyn ToSql + Sync); 5] = [&station.id, &station.name, &station.lat, &station.lon, &station.altitude];
+    let current = transaction.query_opt(STMT_GET_STATION_FOR_UPDATE, &[&station.id])?.map(|row| read_station(&row));
+    match current {
+        None => {
+            transaction.execute(STMT_INSERT_STATION, &[&params[..], &[&station.provider]].concat())?;
+            let since_ever = DateTime::<Utc>::UNIX_EPOCH;
+            transaction.execute(STMT_INSERT_STATION_HISTORY, &[&params[..], &[&since_ever]].concat())?;
+        }
+        Some(current) if current.differs_from(station) => {
+            if transaction.execute(STMT_CLOSE_STATION_HISTORY, &[&station.id, valid_from])? > 0 {
+                println!("Station {} changed to {}", current, station);
+                transaction.execute(STMT_UPDATE_STATION, &params)?;
+                transaction.execute(STMT_INSERT_STATION_HISTORY, &[&params[..], &[valid_from]].concat())?;
+            } else {
+                println!("Station {} is not changed to {}: the change at {} is older than its known position", current, station, valid_from);
+            }
+        }
+        Some(_) => {}
+    }
+    Ok(())
+}
+
+fn read_ingestion_run(row: &Row) -> IngestionRun {
+    let http_status: Option<i32> = row.get("http_status");
+    let count = |column: &str| row.get::<_, i64>(column) as usize;
+    IngestionRun {
+        provider: row.get("provider"),
+        started_at: row.get("started_at"),
+        finished_at: row.get("finished_at"),
+        succeeded: row.get("succeeded"),
+        http_status: http_status.map(|status| status as u16),
+        error: row.get("error"),
+        parsed_stations: count("parsed_stations"),
+        parsed_observations: count("parsed_observations"),
+        inserted_observations: count("inserted_observations"),
+        duplicated_observations: count("duplicated_observations"),
+        skipped_observations: count("skipped_observations"),
+    }
+}
+
+fn read_municipality(row: &Row) -> Municipality {
+    Municipality {
+        id: row.get("id"),
+        name: row.get("name"),
+        province: row.get("province"),
+        lat: row.get("lat"),
+        lon: row.get("lon"),
+        altitude: row.get("altitude"),
+    }
+}
+
+fn read_forecast(row: &Row) -> Forecast {
+    Forecast {
+        municipality_id: row.get("municipality_id"),
+        forecast_time: row.get("forecast_time"),
+        aerial_temperature: row.get("air_temperature"),
+        relative_humidity: row.get("rel_humidity"),
+        elaborated_at: row.get("elaborated_at"),
+    }
+}
+
+fn read_warning(row: &Row) -> Warning {
+    let polygons: String = row.get("polygon");
+    Warning {
+        id: row.get("id"),
+        kind: row.get("kind"),
+        event: row.get("event"),
+        level: row.get("level"),
+        severity: row.get("severity"),
+        headline: row.get("headline"),
+        area: row.get("area"),
+        onset: row.get("onset"),
+        expires: row.get("expires"),
+        polygons: Warning::polygons_from_json(&polygons),
+    }
+}
+
+fn read_observation(row: &Row) -> Observation {
+    Observation {
+        station_id: row.get("station_id"),
+        observation_time: row.get("observation_time"),
+        aerial_temperature: row.get("air_temperature"),
+        relative_humidity: row.get("rel_humidity"),
+    }
+}
+
+/// Version of the schema, 0 if no migration was applied. The database is only read.
+fn get_schema_version(client: &mut Client) -> Result<u32, postgres::Error> {
+    let has_schema_version: bool = client.query_one(STMT_HAS_SCHEMA_VERSION, &[])?.get(0);
+    if !has_schema_version {
+        return Ok(0);
+    }
+    let version: i32 = client.query_one(STMT_GET_SCHEMA_VERSION, &[])?.get(0);
+    Ok(version as u32)
+}
+
+fn migrate(client: &mut Client) -> Result<Vec<u32>, postgres::Error> {
+    client.execute(STMT_CREATE_SCHEMA_VERSION, &[])?;
+    let version = get_schema_version(client)?;
+    let mut applied = vec![];
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        let mut transaction = client.transaction()?;
+        transaction.batch_execute(migration.sql)?;
+        transaction.execute(
+            STMT_SET_SCHEMA_VERSION,
+            &[&(migration.version as i32), &migration.description, &Utc::now()],
+        )?;
+        transaction.commit()?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+impl Store for PostgresStore {
+    fn upsert_stations(&self, stations: &[Station], valid_from: DateTime<Utc>) -> Result<(), Error> {
+        let mut connection = self.get_connection()?;
+        let result = connection.transaction().and_then(|mut transaction| {
+            for station in stations {
+                upsert_station(&mut transaction, station, &valid_from)?;
+            }
+            transaction.commit()
+        });
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                println!("Error with connection: {}", err);
+                Err(Error::other(format!("Data saving failed: {}", err)))
+            }
+        }
+    }
+
+    fn insert_observations(&self, observations: &[Observation]) -> Result<usize, Error> {
+        self.write_items(observations, STMT_SET_OBSERVATION, &|transaction, stmt, observation| {
+            transaction.execute(
+                stmt,
+                &[
+                    &observation.station_id,
+                    &observation.observation_time,
+                    &observation.aerial_temperature,
+                    &observation.relative_humidity,
+                ],
+            )
+        })
+    }
+
+    fn get_station(&self, id: &str) -> Result<Option<Station>, Error> {
+        let mut connection = self.get_connection()?;
+        match connection.query_opt(STMT_GET_STATION, &[&id]) {
+            Ok(row) => Ok(row.map(|row| read_station(&row))),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_stations_with_latest_observation(&self, filter: &StationFilter) -> Result<Vec<(Station, Option<Observation>)>, Error> {
+        let mut connection = self.get_connection()?;
+        let bbox = filter.bbox;
+        let rows = connection
+            .query(
+                STMT_GET_STATIONS_WITH_LATEST_OBSERVATION,
+                &[
+                    &filter.provider,
+                    &bbox.map(|b| b.min_lat),
+                    &bbox.map(|b| b.min_lon),
+                    &bbox.map(|b| b.max_lat),
+                    &bbox.map(|b| b.max_lon),
+                ],
+            )
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let station_id: Option<String> = row.get("station_id");
+                (read_station(row), station_id.map(|_| read_observation(row)))
+            })
+            .collect())
+    }
+
+    fn get_closest_stations(&self, loc: &Location, providers: &ProviderFilter) -> Result<[Station; 3], Error> {
+        self.query_closest_stations(loc, STMT_GET_CLOSEST_STATIONS, &[&(loc.lat as f64), &(loc.lon as f64), &providers.include, &providers.exclude])
+    }
+
+    fn get_closest_stations_at(&self, loc: &Location, at: DateTime<Utc>, providers: &ProviderFilter) -> Result<[Station; 3], Error> {
+        self.query_closest_stations(
+            loc,
+            STMT_GET_CLOSEST_STATIONS_AT,
+            &[&(loc.lat as f64), &(loc.lon as f64), &at, &providers.include, &providers.exclude],
+        )
+    }
+
+    fn get_station_periods(&self, from: DateTime<Utc>, to: DateTime<Utc>, providers: &ProviderFilter) -> Result<Vec<StationPeriod>, Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(STMT_GET_STATION_PERIODS, &[&from, &to, &providers.include, &providers.exclude])
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(rows
+            .iter()
+            .map(|row| StationPeriod { station: read_station(row), valid_from: row.get("valid_from"), valid_to: row.get("valid_to") })
+            .collect())
+    }
+
+    fn get_latest_observations(&self, stations: &[Station; 3]) -> Result<[Observation; 3], Error> {
+        let station_ids: Vec<&str> = stations.iter().map(|s| s.id.as_str()).collect();
+        self.query_latest_observations(stations, STMT_GET_LATEST_OBSERVATIONS, &[&station_ids])
+    }
+
+    fn get_latest_observations_at(&self, stations: &[Station; 3], at: DateTime<Utc>) -> Result<[Observation; 3], Error> {
+        let station_ids: Vec<&str> = stations.iter().map(|s| s.id.as_str()).collect();
+        self.query_latest_observations(stations, STMT_GET_LATEST_OBSERVATIONS_AT, &[&station_ids, &at])
+    }
+
+    fn get_observations_between(&self, station_ids: &[String], from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Observation>, Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(STMT_GET_OBSERVATIONS_BETWEEN, &[&station_ids, &from, &to])
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(rows.iter().map(read_observation).collect())
+    }
+
+    fn for_each_observation(&self, filter: &ObservationFilter, f: &mut dyn FnMut(Observation) -> Result<(), Error>) -> Result<(), Error> {
+        let mut connection = self.get_connection()?;
+        let params: [&dyn ToSql; 3] = [&filter.station_ids, &filter.from, &filter.to];
+        let mut rows = connection
+            .query_raw(STMT_GET_OBSERVATIONS_FILTERED, params)
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        while let Some(row) = rows.next().map_err(|err| Error::other(format!("Data loading failed: {}", err)))? {
+            f(read_observation(&row))?;
+        }
+        Ok(())
+    }
+
+    fn roll_up_observations_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut connection = self.get_connection()?;
+        let mut rolled_up = 0;
+        loop {
+            let result = connection.transaction().and_then(|mut transaction| {
+                let first: Option<DateTime<Utc>> = transaction.query_one(STMT_GET_FIRST_OBSERVATION_TIME_BEFORE, &[&before])?.get(0);
+                let day_end = match first {
+                    Some(first) => next_day_start(first).min(before),
+                    None => return Ok(None),
+                };
+                let rows = transaction.query(STMT_DELETE_OBSERVATIONS_BEFORE, &[&day_end])?;
+                let observations: Vec<Observation> = rows.iter().map(read_observation).collect();
+                for s in summarize_daily(&observations) {
+                    transaction.execute(
+                        STMT_MERGE_DAILY_SUMMARY,
+                        &[
+                            &s.station_id,
+                            &s.day,
+                            &(s.samples as i32),
+                            &s.aerial_temperature.min,
+                            &s.aerial_temperature.max,
+                            &s.aerial_temperature.mean,
+                            &s.relative_humidity.min,
+                            &s.relative_humidity.max,
+                            &s.relative_humidity.mean,
+                            &s.heat_index.min,
+                            &s.heat_index.max,
+                            &s.heat_index.mean,
+                        ],
+                    )?;
+                }
+                transaction.commit().map(|_| Some(observations.len()))
+            });
+            match result {
+                Ok(Some(count)) => rolled_up += count,
+                Ok(None) => return Ok(rolled_up),
+                Err(err) => return Err(Error::other(format!("Data rolling up failed: {}", err))),
+            }
+        }
+    }
+
+    fn delete_daily_summaries_before(&self, before: NaiveDate) -> Result<usize, Error> {
+        let mut connection = self.get_connection()?;
+        match connection.execute(STMT_DELETE_DAILY_SUMMARIES_BEFORE, &[&before]) {
+            Ok(count) => Ok(count as usize),
+            Err(err) => Err(Error::other(format!("Data deleting failed: {}", err))),
+        }
+    }
+
+    fn insert_ingestion_run(&self, run: &IngestionRun) -> Result<(), Error> {
+        let mut connection = self.get_connection()?;
+        let result = connection.execute(
+            STMT_INSERT_INGESTION_RUN,
+            &[
+                &run.provider,
+                &run.started_at,
+                &run.finished_at,
+                &run.succeeded,
+                &run.http_status.map(|status| status as i32),
+                &run.error,
+                &(run.parsed_stations as i64),
+                &(run.parsed_observations as i64),
+                &(run.inserted_observations as i64),
+                &(run.duplicated_observations as i64),
+                &(run.skipped_observations as i64),
+            ],
+        );
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::other(format!("Data saving failed: {}", err))),
+        }
+    }
+
+    fn get_ingestion_runs(&self, limit: usize) -> Result<Vec<IngestionRun>, Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(STMT_GET_INGESTION_RUNS, &[&(limit as i64)])
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(rows.iter().map(read_ingestion_run).collect())
+    }
+
+    fn upsert_municipalities(&self, municipalities: &[Municipality]) -> Result<(), Error> {
+        self.write_items(municipalities, STMT_UPSERT_MUNICIPALITY, &|transaction, stmt, m| {
+            transaction.execute(stmt, &[&m.id, &m.name, &m.province, &m.lat, &m.lon, &m.altitude])
+        })
+        .map(|_| ())
+    }
+
+    fn upsert_forecasts(&self, forecasts: &[Forecast]) -> Result<usize, Error> {
+        self.write_items(forecasts, STMT_UPSERT_FORECAST, &|transaction, stmt, f| {
+            transaction.execute(
+                stmt,
+                &[&f.municipality_id, &f.forecast_time, &f.aerial_temperature, &f.relative_humidity, &f.elaborated_at],
+            )
+        })
+    }
+
+    fn get_closest_municipality(&self, loc: &Location) -> Result<Option<Municipality>, Error> {
+        let mut connection = self.get_connection()?;
+        match connection.query_opt(STMT_GET_CLOSEST_MUNICIPALITY, &[&(loc.lat as f64), &(loc.lon as f64)]) {
+            Ok(row) => Ok(row.map(|row| read_municipality(&row))),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_municipality(&self, id: &str) -> Result<Option<Municipality>, Error> {
+        let mut connection = self.get_connection()?;
+        match connection.query_opt(STMT_GET_MUNICIPALITY, &[&id]) {
+            Ok(row) => Ok(row.map(|row| read_municipality(&row))),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(STMT_GET_MUNICIPALITIES, &[])
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(rows.iter().map(read_municipality).collect())
+    }
+
+    fn get_forecasts(&self, municipality_id: &str, from: DateTime<Utc>) -> Result<Vec<Forecast>, Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(STMT_GET_FORECASTS, &[&municipality_id, &from])
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(rows.iter().map(read_forecast).collect())
+    }
+
+    fn delete_forecasts_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut connection = self.get_connection()?;
+        match connection.execute(STMT_DELETE_FORECASTS_BEFORE, &[&before]) {
+            Ok(count) => Ok(count as usize),
+            Err(err) => Err(Error::other(format!("Data deleting failed: {}", err))),
+        }
+    }
+
+    fn replace_warnings(&self, warnings: &[Warning], now: DateTime<Utc>) -> Result<usize, Error> {
+        let ids: Vec<&str> = warnings.iter().map(|w| w.id.as_str()).collect();
+        let mut connection = self.get_connection()?;
+        let result = connection.transaction().and_then(|mut transaction| {
+            transaction.execute(STMT_END_REPLACED_WARNINGS, &[&now, &ids])?;
+            transaction.execute(STMT_DELETE_REPLACED_WARNINGS, &[&now, &ids])?;
+            let stmt = transaction.prepare(STMT_UPSERT_WARNING)?;
+            let mut stored = 0;
+            for w in warnings {
+                let polygons = serde_json::to_string(&w.polygons).unwrap_or_default();
+                stored += transaction.execute(
+                    &stmt,
+                    &[&w.id, &w.kind, &w.event, &w.level, &w.severity, &w.headline, &w.area, &w.onset, &w.expires, &polygons],
+                )?;
+            }
+            transaction.commit().map(|_| stored as usize)
+        });
+        result.map_err(|err| Error::other(format!("Data saving failed: {}", err)))
+    }
+
+    fn delete_warnings_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut connection = self.get_connection()?;
+        match connection.execute(STMT_DELETE_WARNINGS_BEFORE, &[&before]) {
+            Ok(count) => Ok(count as usize),
+            Err(err) => Err(Error::other(format!("Data deleting failed: {}", err))),
+        }
+    }
+
+    fn get_warnings_at(&self, at: DateTime<Utc>) -> Result<Vec<Warning>, Error> {
+        let mut connection = self.get_connection()?;
+        let rows = connection
+            .query(STMT_GET_WARNINGS_AT, &[&at])
+            .map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        Ok(rows.iter().map(read_warning).collect())
+    }
+
+    fn compact(&self) -> Result<(), Error> {
+        let mut connection = self.get_connection()?;
+        connection
+            .batch_execute("VACUUM ANALYZE")
+            .map_err(|err| Error::other(format!("Database compaction failed: {}", err)))
+    }
+
+    fn migrate(&self) -> Result<Vec<u32>, Error> {
+        let mut connection = self.get_connection()?;
+        match migrate(&mut connection) {
+            Ok(applied) => Ok(applied),
+            Err(err) => Err(Error::other(format!("Database migration failed: {}", err))),
+        }
+    }
+
+    fn get_pending_migrations(&self) -> Result<Vec<&'static Migration>, Error> {
+        let mut connection = self.get_connection()?;
+        match get_schema_version(&mut connection) {
+            Ok(version) => Ok(MIGRATIONS.iter().filter(|m| m.version > version).collect()),
+            Err(err) => Err(Error::other(format!("Reading schema version failed: {}", err))),
+        }
+    }
+}
+
+/**
+ * These tests need a running PostgreSQL with PostGIS available, e.g.
+ * `WHEATR_TEST_DB_URL=postgres://postgres@localhost/wheatr_test cargo test --features postgres -- --ignored --test-threads 1`
+ * as the CI workflow runs them. They share the database, so they run one at a time.
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::met::parse_observation_time;
+
+    fn open_test_store() -> PostgresStore {
+        let url = std::env::var("WHEATR_TEST_DB_URL").expect("WHEATR_TEST_DB_URL is undefined");
+        let store = PostgresStore::open(&url).unwrap();
+        store
+            .get_connection()
+            .unwrap()
+            .batch_execute("DROP TABLE IF EXISTS warnings, forecasts, municipalities, ingestion_runs, station_history, daily_observations, observations, stations, schema_version")
+            .unwrap();
+        store
+    }
+
+    fn station(id: &str, name: &str, lat: f32, lon: f32) -> Station {
+        Station { id: id.to_string(), name: name.to_string(), lat, lon, altitude: None, provider: "aemet".to_string() }
+    }
+
+    fn observation(station_id: &str, time: &str, aerial_temperature: f32) -> Observation {
+        Observation {
+            station_id: station_id.to_string(),
+            observation_time: parse_observation_time(time).unwrap(),
+            aerial_temperature,
+            relative_humidity: 40.0,
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn store_roundtrip() {
+        let store = open_test_store();
+        assert_eq!(store.migrate().unwrap(), vec![1, 2, 3, 4, 5, 6, 7]);
+        assert!(store.get_pending_migrations().unwrap().is_empty());
+
+        let relocated_at = parse_observation_time("2023-08-10T12:00:00").unwrap();
+        store
+            .upsert_stations(
+                &[
+                    station("6155A", "MALAGA AEROPUERTO", 36.66612, -4.482307),
+                    station("6156X", "MALAGA CMT", 36.717785, -4.48167),
+                    station("6172O", "MALAGA PUERTO", 36.716663, -4.41972),
+                    station("5402", "CORDOBA AEROPUERTO", 37.844166, -4.846111),
+                ],
+                relocated_at,
+            )
+            .unwrap();
+        store.upsert_stations(&[station("6155A", "RELOCATED", 0.0, 0.0)], relocated_at).unwrap();
+        store
+            .insert_observations(&[
+                observation("6155A", "2023-08-10T09:00:00", 29.0),
+                observation("6155A", "2023-08-10T10:00:00", 30.0),
+                observation("6156X", "2023-08-10T10:00:00", 31.0),
+                observation("6172O", "2023-08-10T10:00:00", 32.0),
+            ])
+            .unwrap();
+        store.insert_observations(&[observation("6155A", "2023-08-10T10:00:00", 0.0)]).unwrap();
+
+        let location = Location { lat: 36.7, lon: -4.45 };
+        let closest = store.get_closest_stations(&location, &ProviderFilter::default()).unwrap();
+        let mut ids: Vec<&str> = closest.iter().map(|s| s.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["5402", "6156X", "6172O"]);
+
+        let at = parse_observation_time("2023-08-10T09:30:00").unwrap();
+        let closest_at = store.get_closest_stations_at(&location, at, &ProviderFilter::default()).unwrap();
+        assert!(closest_at.iter().any(|s| s.name == "MALAGA AEROPUERTO"));
+        let periods = store.get_station_periods(at, relocated_at, &ProviderFilter::default()).unwrap();
+        let names: Vec<&str> = periods.iter().map(|p| p.station.name.as_str()).collect();
+        assert_eq!(names, vec!["CORDOBA AEROPUERTO", "MALAGA AEROPUERTO", "RELOCATED", "MALAGA CMT", "MALAGA PUERTO"]);
+
+        let latest = store.get_latest_observations_at(&closest_at, at).unwrap();
+        // only the airport had reported by then, at its place among the stations
+        for (observation, station) in latest.iter().zip(&closest_at) {
+            assert_eq!(observation.station_id, if station.id == "6155A" { "6155A" } else { "" });
+        }
+        let malaga_airport = latest.iter().find(|o| o.station_id == "6155A").unwrap();
+        assert_eq!(malaga_airport.aerial_temperature, 29.0);
+    }
+
+    /// The same stations as `closest_stations_across_the_meridian` of the SQLite store.
+    #[test]
+    #[ignore]
+    fn closest_stations_like_sqlite() {
+        let store = open_test_store();
+        store.migrate().unwrap();
+        store
+            .upsert_stations(
+                &[
+                    station("west", "WEST", 39.5, -0.5),
+                    station("north", "NORTH", 39.9, -0.3),
+                    station("far-north", "FAR NORTH", 40.3, -0.3),
+                    station("east", "EAST", 39.5, 0.8),
+                    station("north-copy", "NORTH", 39.9, -0.3),
+                ],
+                parse_observation_time("2023-08-10T00:00:00").unwrap(),
+            )
+            .unwrap();
+        let location = Location { lat: 39.5, lon: -0.3 };
+        let at = parse_observation_time("2023-08-10T12:00:00").unwrap();
+        let ids = |stations: [Station; 3]| stations.map(|s| s.id);
+
+        let closest = ids(store.get_closest_stations(&location, &ProviderFilter::default()).unwrap());
+        assert_eq!(closest, ["west", "north", "far-north"]);
+        assert_eq!(ids(store.get_closest_stations_at(&location, at, &ProviderFilter::default()).unwrap()), closest);
+    }
+}
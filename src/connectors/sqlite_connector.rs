@@ -1,177 +1,1051 @@
-use std::{collections::HashMap, io::Error};
+use std::io::{Error, ErrorKind};
 
-use rusqlite::{Connection, Rows, ToSql};
+use chrono::{DateTime, NaiveDate, Utc};
 
-use crate::met::{Location, Observation, Station, ToSqlParams};
+use r2d2::{Pool, PooledConnection};
+use rusqlite::{ffi, named_params, OptionalExtension, Row, Rows, ToSql, Transaction, TransactionBehavior};
 
-const STMT_GET_CLOSEST_STATIONS: &str = "SELECT id, name, lat, lon, (ABS(lat)-:my_lat) * (ABS(lat)-:my_lat) + (ABS(lon)-:my_lon) * (ABS(lon)-:my_lon) as diff FROM stations GROUP BY lat, lon ORDER BY diff ASC LIMIT 3";
-const STMT_GET_LATEST_OBSERVATIONS: &str =  "SELECT * FROM observations WHERE station_id IN (:s1, :s2, :s3) ORDER BY observation_time DESC, station_id ASC LIMIT 12";
-const STMT_SET_STATION: &str =
-    "INSERT INTO stations (id, name, lat, lon) VALUES (:id, :name, :lat, :lon) ON CONFLICT (id) DO NOTHING";
+use crate::calculators::daily_summaries::{next_day_start, summarize_daily};
+use crate::met::{format_observation_time, DailySummary, Forecast, IngestionRun, Location, Municipality, Observation, Station, StationPeriod, ToSqlParams, Warning};
+
+use super::sqlite_migrations;
+use super::store::{in_station_order, Migration, ObservationFilter, ProviderFilter, StationFilter, Store};
+use super::sqlite_pool::{create_pool, SqliteConnectionManager};
+
+const STMT_GET_CLOSEST_STATIONS: &str = "SELECT id, name, lat, lon, altitude, provider, (lat-:my_lat) * (lat-:my_lat) + (lon-:my_lon) * (lon-:my_lon) * :lon_scale as diff FROM stations
+    WHERE (:include IS NULL OR provider IN (SELECT value FROM json_each(:include))) AND provider NOT IN (SELECT value FROM json_each(:exclude))
+    GROUP BY lat, lon ORDER BY diff ASC LIMIT 3";
+const STMT_GET_LATEST_OBSERVATIONS: &str = "SELECT * FROM observations o WHERE station_id IN (:s1, :s2, :s3)
+    AND observation_time = (SELECT MAX(observation_time) FROM observations WHERE station_id = o.station_id)";
+const STMT_GET_LATEST_OBSERVATIONS_AT: &str = "SELECT * FROM observations o WHERE station_id IN (:s1, :s2, :s3)
+    AND observation_time = (SELECT MAX(observation_time) FROM observations WHERE station_id = o.station_id AND observation_time <= :at)";
+const STMT_GET_CLOSEST_STATIONS_AT: &str = "SELECT h.station_id AS id, h.name, h.lat, h.lon, h.altitude, s.provider, (h.lat-:my_lat) * (h.lat-:my_lat) + (h.lon-:my_lon) * (h.lon-:my_lon) * :lon_scale as diff FROM station_history h JOIN stations s ON s.id = h.station_id WHERE h.valid_from <= :at AND (h.valid_to IS NULL OR h.valid_to > :at)
+        AND (:include IS NULL OR s.provider IN (SELECT value FROM json_each(:include))) AND s.provider NOT IN (SELECT value FROM json_each(:exclude))
+    GROUP BY h.lat, h.lon ORDER BY diff ASC LIMIT 3";
+const STMT_GET_STATION_PERIODS: &str = "SELECT h.station_id AS id, h.name, h.lat, h.lon, h.altitude, s.provider, h.valid_from, h.valid_to FROM station_history h JOIN stations s ON s.id = h.station_id
+    WHERE h.valid_from <= :to AND (h.valid_to IS NULL OR h.valid_to > :from)
+        AND (:include IS NULL OR s.provider IN (SELECT value FROM json_each(:include))) AND s.provider NOT IN (SELECT value FROM json_each(:exclude))
+    ORDER BY h.station_id, h.valid_from";
+const STMT_GET_STATIONS_WITH_LATEST_OBSERVATION: &str = "SELECT s.id, s.name, s.lat, s.lon, s.altitude, s.provider, o.station_id, o.observation_time, o.air_temperature, o.rel_humidity
+    FROM stations s LEFT JOIN observations o ON o.station_id = s.id AND o.observation_time = (SELECT MAX(observation_time) FROM observations WHERE station_id = s.id)
+    WHERE (:provider IS NULL OR s.provider = :provider)
+        AND (:min_lat IS NULL OR (s.lat BETWEEN :min_lat AND :max_lat AND s.lon BETWEEN :min_lon AND :max_lon))
+    ORDER BY s.id";
+const STMT_GET_STATION: &str = "SELECT * FROM stations WHERE id = :id";
+const STMT_INSERT_STATION: &str = "INSERT INTO stations (id, name, lat, lon, altitude, provider) VALUES (:id, :name, :lat, :lon, :altitude, :provider)";
+const STMT_UPDATE_STATION: &str = "UPDATE stations SET name = :name, lat = :lat, lon = :lon, altitude = :altitude WHERE id = :id";
+const STMT_CLOSE_STATION_HISTORY: &str = "UPDATE station_history SET valid_to = :valid_from WHERE station_id = :id AND valid_to IS NULL AND valid_from < :valid_from
+    AND NOT EXISTS (SELECT 1 FROM observations WHERE station_id = :id AND observation_time > :valid_from)";
+const STMT_INSERT_STATION_HISTORY: &str = "INSERT INTO station_history (station_id, name, lat, lon, altitude, valid_from) VALUES (:id, :name, :lat, :lon, :altitude, :valid_from)";
+const STMT_GET_OBSERVATIONS_BETWEEN: &str = "SELECT * FROM observations WHERE station_id IN (SELECT value FROM json_each(:station_ids)) AND observation_time BETWEEN :from AND :to ORDER BY observation_time ASC";
+const STMT_GET_OBSERVATIONS_FILTERED: &str = "SELECT * FROM observations
+    WHERE (:station_ids IS NULL OR station_id IN (SELECT value FROM json_each(:station_ids)))
+        AND (:from IS NULL OR observation_time >= :from)
+        AND (:to IS NULL OR observation_time <= :to)
+    ORDER BY observation_time ASC, station_id ASC";
+const STMT_GET_FIRST_OBSERVATION_TIME_BEFORE: &str = "SELECT MIN(observation_time) FROM observations WHERE observation_time < :before";
+const STMT_DELETE_OBSERVATIONS_BEFORE: &str = "DELETE FROM observations WHERE observation_time < :before RETURNING *";
+const STMT_MERGE_DAILY_SUMMARY: &str = "INSERT INTO daily_observations VALUES (:station_id, :day, :samples, :t_min, :t_max, :t_mean, :h_min, :h_max, :h_mean, :hi_min, :hi_max, :hi_mean)
+    ON CONFLICT (station_id, day) DO UPDATE SET
+        samples = samples + excluded.samples,
+        air_temperature_min = MIN(air_temperature_min, excluded.air_temperature_min),
+        air_temperature_max = MAX(air_temperature_max, excluded.air_temperature_max),
+        air_temperature_mean = (air_temperature_mean * samples + excluded.air_temperature_mean * excluded.samples) / (samples + excluded.samples),
+        rel_humidity_min = MIN(rel_humidity_min, excluded.rel_humidity_min),
+        rel_humidity_max = MAX(rel_humidity_max, excluded.rel_humidity_max),
+        rel_humidity_mean = (rel_humidity_mean * samples + excluded.rel_humidity_mean * excluded.samples) / (samples + excluded.samples),
+        heat_index_min = MIN(heat_index_min, excluded.heat_index_min),
+        heat_index_max = MAX(heat_index_max, excluded.heat_index_max),
+        heat_index_mean = (heat_index_mean * samples + excluded.heat_index_mean * excluded.samples) / (samples + excluded.samples)";
+const STMT_DELETE_DAILY_SUMMARIES_BEFORE: &str = "DELETE FROM daily_observations WHERE day < :before";
+const STMT_INSERT_INGESTION_RUN: &str = "INSERT INTO ingestion_runs (provider, started_at, finished_at, succeeded, http_status, error, parsed_stations, parsed_observations, inserted_observations, duplicated_observations, skipped_observations)
+    VALUES (:provider, :started_at, :finished_at, :succeeded, :http_status, :error, :parsed_stations, :parsed_observations, :inserted_observations, :duplicated_observations, :skipped_observations)";
+const STMT_GET_INGESTION_RUNS: &str = "SELECT * FROM ingestion_runs ORDER BY started_at DESC, id DESC LIMIT :limit";
 const STMT_SET_OBSERVATION: &str = "INSERT INTO observations (station_id, observation_time, air_temperature, rel_humidity) VALUES (:station_id, :observation_time, :air_temperature, :rel_humidity) ON CONFLICT (station_id, observation_time) DO NOTHING";
+const STMT_UPSERT_MUNICIPALITY: &str = "INSERT INTO municipalities (id, name, province, lat, lon, altitude) VALUES (:id, :name, :province, :lat, :lon, :altitude)
+    ON CONFLICT (id) DO UPDATE SET name = excluded.name, province = COALESCE(excluded.province, province), lat = excluded.lat, lon = excluded.lon, altitude = excluded.altitude";
+const STMT_UPSERT_FORECAST: &str = "INSERT INTO forecasts (municipality_id, forecast_time, air_temperature, rel_humidity, elaborated_at) VALUES (:municipality_id, :forecast_time, :air_temperature, :rel_humidity, :elaborated_at)
+    ON CONFLICT (municipality_id, forecast_time) DO UPDATE SET air_temperature = excluded.air_temperature, rel_humidity = excluded.rel_humidity, elaborated_at = excluded.elaborated_at
+    WHERE excluded.elaborated_at >= elaborated_at";
+const STMT_GET_CLOSEST_MUNICIPALITY: &str = "SELECT *, (lat-:my_lat) * (lat-:my_lat) + (lon-:my_lon) * (lon-:my_lon) * :lon_scale as diff FROM municipalities m
+    WHERE EXISTS (SELECT 1 FROM forecasts WHERE municipality_id = m.id) ORDER BY diff ASC LIMIT 1";
+const STMT_GET_MUNICIPALITY: &str = "SELECT * FROM municipalities WHERE id = :id";
+const STMT_GET_MUNICIPALITIES: &str = "SELECT * FROM municipalities ORDER BY id ASC";
+const STMT_GET_FORECASTS: &str = "SELECT * FROM forecasts WHERE municipality_id = :municipality_id AND forecast_time >= :from ORDER BY forecast_time ASC";
+const STMT_DELETE_FORECASTS_BEFORE: &str = "DELETE FROM forecasts WHERE forecast_time < :before";
+const STMT_END_REPLACED_WARNINGS: &str = "UPDATE warnings SET expires = :now WHERE onset < :now AND expires > :now AND id NOT IN (SELECT value FROM json_each(:ids))";
+const STMT_DELETE_REPLACED_WARNINGS: &str = "DELETE FROM warnings WHERE onset >= :now AND id NOT IN (SELECT value FROM json_each(:ids))";
+const STMT_DELETE_WARNINGS_BEFORE: &str = "DELETE FROM warnings WHERE expires < :before";
+const STMT_UPSERT_WARNING: &str = "INSERT INTO warnings (id, kind, event, level, severity, headline, area, onset, expires, polygon)
+    VALUES (:id, :kind, :event, :level, :severity, :headline, :area, :onset, :expires, :polygon)
+    ON CONFLICT (id) DO UPDATE SET kind = excluded.kind, event = excluded.event, level = excluded.level, severity = excluded.severity, headline = excluded.headline,
+        area = excluded.area, onset = excluded.onset, expires = excluded.expires, polygon = excluded.polygon";
+const STMT_GET_WARNINGS_AT: &str = "SELECT * FROM warnings WHERE onset <= :at AND expires > :at ORDER BY onset ASC, id ASC";
 
-pub fn get_connection() -> Result<Connection, rusqlite::Error> {
-    let connection = Connection::open(".met.sqlite")?;
-    connection.execute_batch(
-        "BEGIN;
-        CREATE TABLE IF NOT EXISTS stations (
-            id TEXT NOT NULL UNIQUE,
-            name TEXT,
-            lat	REAL NOT NULL,
-            lon	REAL NOT NULL,
-            PRIMARY KEY(id)
-        );
-        CREATE TABLE IF NOT EXISTS observations (
-            station_id TEXT NOT NULL,
-            observation_time TEXT NOT NULL,
-            air_temperature REAL NOT NULL,
-            rel_humidity REAL NOT NULL,
-            PRIMARY KEY(observation_time,station_id),
-            FOREIGN KEY(station_id) REFERENCES stations(id) ON DELETE CASCADE
-        );
-        COMMIT;",
-    )?;
-    Ok(connection)
-}
-
-fn run_get_stmt<T>(
-    query: &str,
-    params: &[(&str, &dyn ToSql)],
-    rows_mapper: &dyn Fn(Rows) -> Result<T, rusqlite::Error>,
-) -> Result<T, rusqlite::Error> {
-    let connection = get_connection()?;
-    let mut stmt = (&connection).prepare(query)?;
-    let rows = stmt.query(params)?;
-    rows_mapper(rows)
-}
-
-pub fn get_closest_stations_from_db(loc: &Location) -> Result<[Station; 3], Error> {
-    fn extract_closest_stations(mut rows: Rows) -> Result<[Station; 3], rusqlite::Error> {
-        let mut closest_stations: Vec<Station> = Vec::new();
-        let mut i = true;
-        while i {
-            match rows.next() {
-                Ok(None) => i = false,
-                Ok(Some(r)) => {
-                    let read_station = Station {
-                        id: r.get_unwrap("id"),
-                        name: r.get_unwrap("name"),
-                        lat: r.get_unwrap("lat"),
-                        lon: r.get_unwrap("lon"),
-                    };
-                    closest_stations.push(read_station);
-                }
-                Err(_) => {}
-            };
+pub struct SqliteStore {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+fn pool_error(err: r2d2::Error) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_CANTOPEN), Some(err.to_string()))
+}
+
+impl SqliteStore {
+    /**
+     * Opens the database at `path` with a connection pool shared by the API
+     * handlers and the ingestion job.
+     */
+    pub fn open(path: &str) -> Result<SqliteStore, Error> {
+        match create_pool(path) {
+            Ok(pool) => Ok(SqliteStore { pool }),
+            Err(err) => Err(Error::other(format!("Database opening failed: {}", err))),
+        }
+    }
+
+    pub fn get_connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, rusqlite::Error> {
+        self.pool.get().map_err(pool_error)
+    }
+
+    fn run_get_stmt<T>(
+        &self,
+        query: &str,
+        params: &[(&str, &dyn ToSql)],
+        rows_mapper: &dyn Fn(Rows) -> Result<T, rusqlite::Error>,
+    ) -> Result<T, rusqlite::Error> {
+        let connection = self.get_connection()?;
+        let mut stmt = connection.prepare_cached(query)?;
+        let rows = stmt.query(params)?;
+        rows_mapper(rows)
+    }
+
+    fn write_items_to_db<T: ToSqlParams>(
+        &self,
+        items: &[T],
+        write_statement: &str,
+    ) -> Result<usize, rusqlite::Error> {
+        let mut connection = self.get_connection()?;
+        let transaction = connection.transaction()?;
+        let mut changed_rows = 0;
+        {
+            let mut stmt = transaction.prepare_cached(write_statement)?;
+            for item in items {
+                let params = item.to_sql_params();
+                changed_rows += stmt.execute(params)?;
+            }
         }
-        Ok(closest_stations.try_into().unwrap())
+        transaction.commit()?;
+        Ok(changed_rows)
+    }
+}
+
+fn read_station(row: &Row) -> Station {
+    Station {
+        id: row.get_unwrap("id"),
+        name: row.get_unwrap("name"),
+        lat: row.get_unwrap("lat"),
+        lon: row.get_unwrap("lon"),
+        altitude: row.get_unwrap("altitude"),
+        provider: row.get_unwrap("provider"),
+    }
+}
+
+fn extract_station_periods(mut rows: Rows) -> Result<Vec<StationPeriod>, rusqlite::Error> {
+    let mut periods = vec![];
+    while let Some(row) = rows.next()? {
+        periods.push(StationPeriod { station: read_station(row), valid_from: row.get("valid_from")?, valid_to: row.get("valid_to")? });
     }
+    Ok(periods)
+}
 
-    match run_get_stmt::<[Station; 3]>(
-        STMT_GET_CLOSEST_STATIONS,
-        &[(":my_lat", &loc.lat.abs()), (":my_lon", &loc.lon.abs())],
-        &extract_closest_stations,
-    ) {
-        Ok(result) => return Ok(result),
-        Err(err) => {
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                format!("Data loading failed: {}", err),
-            ))
+/**
+ * Inserts a new station, or updates a known one whose name, position or
+ * altitude changed. The history period of the previous state is closed at
+ * `valid_from` and a new one is opened. Changes dated before the start of
+ * the current period are ignored. The first known state of a station is
+ * considered valid since ever.
+ */
+fn upsert_station(transaction: &Transaction, station: &Station, valid_from: &str) -> Result<(), rusqlite::Error> {
+    let params = named_params! {
+        ":id": station.id,
+        ":name": station.name,
+        ":lat": station.lat,
+        ":lon": station.lon,
+        ":altitude": station.altitude,
+    };
+    let current = transaction
+        .prepare_cached(STMT_GET_STATION)?
+        .query_row(&[(":id", &station.id)], |row| Ok(read_station(row)))
+        .optional()?;
+    match current {
+        None => {
+            let mut station_params = params.to_vec();
+            station_params.push((":provider", &station.provider));
+            transaction.prepare_cached(STMT_INSERT_STATION)?.execute(station_params.as_slice())?;
+            let mut history_params = params.to_vec();
+            history_params.push((":valid_from", &"1970-01-01T00:00:00Z"));
+            transaction.prepare_cached(STMT_INSERT_STATION_HISTORY)?.execute(history_params.as_slice())?;
+        }
+        Some(current) if current.differs_from(station) => {
+            let closed = transaction
+                .prepare_cached(STMT_CLOSE_STATION_HISTORY)?
+                .execute(&[(":id", &station.id as &dyn ToSql), (":valid_from", &valid_from)])?;
+            if closed > 0 {
+                println!("Station {} changed to {}", current, station);
+                transaction.prepare_cached(STMT_UPDATE_STATION)?.execute(params)?;
+                let mut history_params = params.to_vec();
+                history_params.push((":valid_from", &valid_from));
+                transaction.prepare_cached(STMT_INSERT_STATION_HISTORY)?.execute(history_params.as_slice())?;
+            } else {
+                println!("Station {} is not changed to {}: the change at {} is older than its known position", current, station, valid_from);
+            }
         }
+        Some(_) => {}
+    }
+    Ok(())
+}
+
+/// The included (if limited) and excluded providers as JSON arrays for `json_each`.
+fn provider_params(providers: &ProviderFilter) -> Result<(Option<String>, String), Error> {
+    let to_json = |providers: &Vec<String>| serde_json::to_string(providers).map_err(|err| Error::other(format!("Data loading failed: {}", err)));
+    Ok((providers.include.as_ref().map(to_json).transpose()?, to_json(&providers.exclude)?))
+}
+
+fn extract_closest_stations(mut rows: Rows) -> Result<Vec<Station>, rusqlite::Error> {
+    let mut closest_stations: Vec<Station> = Vec::new();
+    let mut i = true;
+    while i {
+        match rows.next() {
+            Ok(None) => i = false,
+            Ok(Some(r)) => closest_stations.push(read_station(r)),
+            Err(_) => {}
+        };
+    }
+    Ok(closest_stations)
+}
+
+fn to_closest_stations(stations: Vec<Station>) -> Result<[Station; 3], Error> {
+    stations
+        .try_into()
+        .map_err(|_| Error::new(ErrorKind::NotFound, "Not Found: less than three stations are known"))
+}
+
+/// Adds daily summaries, merging them into already stored summaries of the same station and day.
+fn merge_daily_summaries(transaction: &Transaction, summaries: &[DailySummary]) -> Result<(), rusqlite::Error> {
+    let mut stmt = transaction.prepare_cached(STMT_MERGE_DAILY_SUMMARY)?;
+    for s in summaries {
+        stmt.execute(named_params! {
+            ":station_id": s.station_id,
+            ":day": s.day,
+            ":samples": s.samples,
+            ":t_min": s.aerial_temperature.min,
+            ":t_max": s.aerial_temperature.max,
+            ":t_mean": s.aerial_temperature.mean,
+            ":h_min": s.relative_humidity.min,
+            ":h_max": s.relative_humidity.max,
+            ":h_mean": s.relative_humidity.mean,
+            ":hi_min": s.heat_index.min,
+            ":hi_max": s.heat_index.max,
+            ":hi_mean": s.heat_index.mean,
+        })?;
+    }
+    Ok(())
+}
+
+fn read_observation(row: &Row) -> Observation {
+    Observation {
+        station_id: row.get_unwrap("station_id"),
+        observation_time: row.get_unwrap("observation_time"),
+        aerial_temperature: row.get_unwrap("air_temperature"),
+        relative_humidity: row.get_unwrap("rel_humidity"),
+    }
+}
+
+fn extract_observations(mut rows: Rows) -> Result<Vec<Observation>, rusqlite::Error> {
+    let mut observations = vec![];
+    while let Some(row) = rows.next()? {
+        observations.push(read_observation(row));
+    }
+    Ok(observations)
+}
+
+fn extract_stations_with_latest_observation(mut rows: Rows) -> Result<Vec<(Station, Option<Observation>)>, rusqlite::Error> {
+    let mut stations = vec![];
+    while let Some(row) = rows.next()? {
+        let station_id: Option<String> = row.get("station_id")?;
+        stations.push((read_station(row), station_id.map(|_| read_observation(row))));
     }
+    Ok(stations)
 }
 
-pub fn get_latest_observations(stations: &[Station; 3]) -> Result<[Observation; 3], Error> {
-    fn extract_latest_observations(mut rows: Rows) -> Result<[Observation; 3], rusqlite::Error> {
-        let mut latest_observations_map: HashMap<String, Observation> = HashMap::new();
-        let mut latest_observations: [Observation; 3] = Default::default();
-        let mut i = true;
-        while i {
-            match rows.next() {
-                Ok(None) => {
-                    i = false;
+fn extract_ingestion_runs(mut rows: Rows) -> Result<Vec<IngestionRun>, rusqlite::Error> {
+    let mut runs = vec![];
+    while let Some(row) = rows.next()? {
+        runs.push(IngestionRun {
+            provider: row.get("provider")?,
+            started_at: row.get("started_at")?,
+            finished_at: row.get("finished_at")?,
+            succeeded: row.get("succeeded")?,
+            http_status: row.get("http_status")?,
+            error: row.get("error")?,
+            parsed_stations: row.get("parsed_stations")?,
+            parsed_observations: row.get("parsed_observations")?,
+            inserted_observations: row.get("inserted_observations")?,
+            duplicated_observations: row.get("duplicated_observations")?,
+            skipped_observations: row.get("skipped_observations")?,
+        });
+    }
+    Ok(runs)
+}
+
+fn read_municipality(row: &Row) -> Municipality {
+    Municipality {
+        id: row.get_unwrap("id"),
+        name: row.get_unwrap("name"),
+        province: row.get_unwrap("province"),
+        lat: row.get_unwrap("lat"),
+        lon: row.get_unwrap("lon"),
+        altitude: row.get_unwrap("altitude"),
+    }
+}
+
+fn extract_forecasts(mut rows: Rows) -> Result<Vec<Forecast>, rusqlite::Error> {
+    let mut forecasts = vec![];
+    while let Some(row) = rows.next()? {
+        forecasts.push(Forecast {
+            municipality_id: row.get("municipality_id")?,
+            forecast_time: row.get("forecast_time")?,
+            aerial_temperature: row.get("air_temperature")?,
+            relative_humidity: row.get("rel_humidity")?,
+            elaborated_at: row.get("elaborated_at")?,
+        });
+    }
+    Ok(forecasts)
+}
+
+fn extract_warnings(mut rows: Rows) -> Result<Vec<Warning>, rusqlite::Error> {
+    let mut warnings = vec![];
+    while let Some(row) = rows.next()? {
+        let polygons: String = row.get("polygon")?;
+        warnings.push(Warning {
+            id: row.get("id")?,
+            kind: row.get("kind")?,
+            event: row.get("event")?,
+            level: row.get("level")?,
+            severity: row.get("severity")?,
+            headline: row.get("headline")?,
+            area: row.get("area")?,
+            onset: row.get("onset")?,
+            expires: row.get("expires")?,
+            polygons: Warning::polygons_from_json(&polygons),
+        });
+    }
+    Ok(warnings)
+}
+
+impl Store for SqliteStore {
+    fn get_station(&self, id: &str) -> Result<Option<Station>, Error> {
+        let result = self
+            .get_connection()
+            .and_then(|connection| {
+                connection
+                    .prepare_cached(STMT_GET_STATION)?
+                    .query_row(&[(":id", &id)], |row| Ok(read_station(row)))
+                    .optional()
+            });
+        result.map_err(|err| Error::other(format!("Data loading failed: {}", err)))
+    }
+
+    fn get_stations_with_latest_observation(&self, filter: &StationFilter) -> Result<Vec<(Station, Option<Observation>)>, Error> {
+        let bbox = filter.bbox;
+        match self.run_get_stmt(
+            STMT_GET_STATIONS_WITH_LATEST_OBSERVATION,
+            &[
+                (":provider", &filter.provider),
+                (":min_lat", &bbox.map(|b| b.min_lat)),
+                (":min_lon", &bbox.map(|b| b.min_lon)),
+                (":max_lat", &bbox.map(|b| b.max_lat)),
+                (":max_lon", &bbox.map(|b| b.max_lon)),
+            ],
+            &extract_stations_with_latest_observation,
+        ) {
+            Ok(result) => Ok(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_closest_stations(&self, loc: &Location, providers: &ProviderFilter) -> Result<[Station; 3], Error> {
+        let (include, exclude) = provider_params(providers)?;
+        match self.run_get_stmt::<Vec<Station>>(
+            STMT_GET_CLOSEST_STATIONS,
+            &[(":my_lat", &loc.lat), (":my_lon", &loc.lon), (":lon_scale", &loc.lon_scale()), (":include", &include), (":exclude", &exclude)],
+            &extract_closest_stations,
+        ) {
+            Ok(result) => to_closest_stations(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_station_periods(&self, from: DateTime<Utc>, to: DateTime<Utc>, providers: &ProviderFilter) -> Result<Vec<StationPeriod>, Error> {
+        let (include, exclude) = provider_params(providers)?;
+        match self.run_get_stmt(
+            STMT_GET_STATION_PERIODS,
+            &[
+                (":from", &format_observation_time(&from) as &dyn ToSql),
+                (":to", &format_observation_time(&to)),
+                (":include", &include),
+                (":exclude", &exclude),
+            ],
+            &extract_station_periods,
+        ) {
+            Ok(result) => Ok(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_latest_observations(&self, stations: &[Station; 3]) -> Result<[Observation; 3], Error> {
+        match self.run_get_stmt(
+            STMT_GET_LATEST_OBSERVATIONS,
+            &[
+                (":s1", &stations[0].id),
+                (":s2", &stations[1].id),
+                (":s3", &stations[2].id),
+            ],
+            &extract_observations,
+        ) {
+            Ok(result) => Ok(in_station_order(stations, result)),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_latest_observations_at(&self, stations: &[Station; 3], at: DateTime<Utc>) -> Result<[Observation; 3], Error> {
+        match self.run_get_stmt(
+            STMT_GET_LATEST_OBSERVATIONS_AT,
+            &[
+                (":s1", &stations[0].id),
+                (":s2", &stations[1].id),
+                (":s3", &stations[2].id),
+                (":at", &format_observation_time(&at)),
+            ],
+            &extract_observations,
+        ) {
+            Ok(result) => Ok(in_station_order(stations, result)),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn get_closest_stations_at(&self, loc: &Location, at: DateTime<Utc>, providers: &ProviderFilter) -> Result<[Station; 3], Error> {
+        let (include, exclude) = provider_params(providers)?;
+        match self.run_get_stmt::<Vec<Station>>(
+            STMT_GET_CLOSEST_STATIONS_AT,
+            &[
+                (":my_lat", &loc.lat),
+                (":my_lon", &loc.lon),
+                (":lon_scale", &loc.lon_scale()),
+                (":at", &format_observation_time(&at)),
+                (":include", &include),
+                (":exclude", &exclude),
+            ],
+            &extract_closest_stations,
+        ) {
+            Ok(result) => to_closest_stations(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn upsert_stations(&self, stations: &[Station], valid_from: DateTime<Utc>) -> Result<(), Error> {
+        let valid_from = format_observation_time(&valid_from);
+        let result = self.get_connection().and_then(|mut connection| {
+            let transaction = connection.transaction()?;
+            for station in stations {
+                upsert_station(&transaction, station, &valid_from)?;
+            }
+            transaction.commit()
+        });
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                println!("Error with connection: {}", err);
+                Err(Error::other(format!("Data saving failed: {}", err)))
+            }
+        }
+    }
+
+    fn insert_observations(&self, observations: &[Observation]) -> Result<usize, Error> {
+        match self.write_items_to_db::<Observation>(observations, STMT_SET_OBSERVATION) {
+            Ok(inserted) => Ok(inserted),
+            Err(err) => {
+                println!("Error with connection: {}", err);
+                Err(Error::other(format!("Data saving failed: {}", err)))
+            }
+        }
+    }
+
+    fn get_observations_between(&self, station_ids: &[String], from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Observation>, Error> {
+        let station_ids = serde_json::to_string(station_ids).map_err(|err| Error::other(format!("Data loading failed: {}", err)))?;
+        match self.run_get_stmt(
+            STMT_GET_OBSERVATIONS_BETWEEN,
+            &[
+                (":station_ids", &station_ids),
+                (":from", &format_observation_time(&from)),
+                (":to", &format_observation_time(&to)),
+            ],
+            &extract_observations,
+        ) {
+            Ok(result) => Ok(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn for_each_observation(&self, filter: &ObservationFilter, f: &mut dyn FnMut(Observation) -> Result<(), Error>) -> Result<(), Error> {
+        let loading_error = |err: rusqlite::Error| Error::other(format!("Data loading failed: {}", err));
+        let station_ids = match &filter.station_ids {
+            Some(ids) => Some(serde_json::to_string(ids).map_err(|err| Error::other(format!("Data loading failed: {}", err)))?),
+            None => None,
+        };
+        let connection = self.get_connection().map_err(loading_error)?;
+        let mut stmt = connection.prepare_cached(STMT_GET_OBSERVATIONS_FILTERED).map_err(loading_error)?;
+        let mut rows = stmt
+            .query(named_params! {
+                ":station_ids": station_ids,
+                ":from": filter.from.as_ref().map(format_observation_time),
+                ":to": filter.to.as_ref().map(format_observation_time),
+            })
+            .map_err(loading_error)?;
+        while let Some(row) = rows.next().map_err(loading_error)? {
+            f(read_observation(row))?;
+        }
+        Ok(())
+    }
+
+    fn roll_up_observations_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let result = self.get_connection().and_then(|mut connection| {
+            let mut rolled_up = 0;
+            loop {
+                let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                let first: Option<DateTime<Utc>> = transaction.query_row(
+                    STMT_GET_FIRST_OBSERVATION_TIME_BEFORE,
+                    &[(":before", &format_observation_time(&before))],
+                    |row| row.get(0),
+                )?;
+                let day_end = match first {
+                    Some(first) => next_day_start(first).min(before),
+                    None => return Ok(rolled_up),
+                };
+                let observations = transaction
+                    .prepare_cached(STMT_DELETE_OBSERVATIONS_BEFORE)?
+                    .query(&[(":before", &format_observation_time(&day_end))])
+                    .and_then(extract_observations)?;
+                merge_daily_summaries(&transaction, &summarize_daily(&observations))?;
+                transaction.commit()?;
+                rolled_up += observations.len();
+            }
+        });
+        result.map_err(|err| Error::other(format!("Data rolling up failed: {}", err)))
+    }
+
+    fn delete_daily_summaries_before(&self, before: NaiveDate) -> Result<usize, Error> {
+        let result = self
+            .get_connection()
+            .and_then(|connection| connection.execute(STMT_DELETE_DAILY_SUMMARIES_BEFORE, &[(":before", &before)]));
+        result.map_err(|err| Error::other(format!("Data deleting failed: {}", err)))
+    }
+
+    fn insert_ingestion_run(&self, run: &IngestionRun) -> Result<(), Error> {
+        let result = self.get_connection().and_then(|connection| {
+            connection.prepare_cached(STMT_INSERT_INGESTION_RUN)?.execute(named_params! {
+                ":provider": run.provider,
+                ":started_at": format_observation_time(&run.started_at),
+                ":finished_at": format_observation_time(&run.finished_at),
+                ":succeeded": run.succeeded,
+                ":http_status": run.http_status,
+                ":error": run.error,
+                ":parsed_stations": run.parsed_stations,
+                ":parsed_observations": run.parsed_observations,
+                ":inserted_observations": run.inserted_observations,
+                ":duplicated_observations": run.duplicated_observations,
+                ":skipped_observations": run.skipped_observations,
+            })
+        });
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::other(format!("Data saving failed: {}", err))),
+        }
+    }
+
+    fn get_ingestion_runs(&self, limit: usize) -> Result<Vec<IngestionRun>, Error> {
+        match self.run_get_stmt(STMT_GET_INGESTION_RUNS, &[(":limit", &limit)], &extract_ingestion_runs) {
+            Ok(result) => Ok(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
+        }
+    }
+
+    fn upsert_municipalities(&self, municipalities: &[Municipality]) -> Result<(), Error> {
+        let result = self.get_connection().and_then(|mut connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut stmt = transaction.prepare_cached(STMT_UPSERT_MUNICIPALITY)?;
+                for m in municipalities {
+                    stmt.execute(named_params! {
+                        ":id": m.id,
+                        ":name": m.name,
+                        ":province": m.province,
+                        ":lat": m.lat,
+                        ":lon": m.lon,
+                        ":altitude": m.altitude,
+                    })?;
                 }
-                Ok(Some(row)) => {
-                    let new_obs = Observation {
-                        station_id: row.get_unwrap("station_id"),
-                        observation_time: row.get_unwrap("observation_time"),
-                        aerial_temperature: row.get_unwrap("air_temperature"),
-                        relative_humidity: row.get_unwrap("rel_humidity"),
-                    };
-                    let prev_obs = latest_observations_map.get(&new_obs.station_id);
-                    if prev_obs.is_none()
-                        || prev_obs.unwrap().observation_time < new_obs.observation_time
-                    {
-                        latest_observations_map.insert(new_obs.station_id.clone(), new_obs);
-                    }
+            }
+            transaction.commit()
+        });
+        result.map_err(|err| Error::other(format!("Data saving failed: {}", err)))
+    }
+
+    fn upsert_forecasts(&self, forecasts: &[Forecast]) -> Result<usize, Error> {
+        let result = self.get_connection().and_then(|mut connection| {
+            let transaction = connection.transaction()?;
+            let mut changed_rows = 0;
+            {
+                let mut stmt = transaction.prepare_cached(STMT_UPSERT_FORECAST)?;
+                for f in forecasts {
+                    changed_rows += stmt.execute(named_params! {
+                        ":municipality_id": f.municipality_id,
+                        ":forecast_time": format_observation_time(&f.forecast_time),
+                        ":air_temperature": f.aerial_temperature,
+                        ":rel_humidity": f.relative_humidity,
+                        ":elaborated_at": format_observation_time(&f.elaborated_at),
+                    })?;
                 }
-                Err(_) => {}
             }
+            transaction.commit().map(|_| changed_rows)
+        });
+        result.map_err(|err| Error::other(format!("Data saving failed: {}", err)))
+    }
+
+    fn get_closest_municipality(&self, loc: &Location) -> Result<Option<Municipality>, Error> {
+        let result = self.get_connection().and_then(|connection| {
+            connection
+                .prepare_cached(STMT_GET_CLOSEST_MUNICIPALITY)?
+                .query_row(&[(":my_lat", &loc.lat), (":my_lon", &loc.lon), (":lon_scale", &loc.lon_scale())], |row| Ok(read_municipality(row)))
+                .optional()
+        });
+        result.map_err(|err| Error::other(format!("Data loading failed: {}", err)))
+    }
+
+    fn get_municipality(&self, id: &str) -> Result<Option<Municipality>, Error> {
+        let result = self.get_connection().and_then(|connection| {
+            connection
+                .prepare_cached(STMT_GET_MUNICIPALITY)?
+                .query_row(&[(":id", &id)], |row| Ok(read_municipality(row)))
+                .optional()
+        });
+        result.map_err(|err| Error::other(format!("Data loading failed: {}", err)))
+    }
+
+    fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        let result = self.run_get_stmt(STMT_GET_MUNICIPALITIES, &[], &|mut rows| {
+            let mut municipalities = vec![];
+            while let Some(row) = rows.next()? {
+                municipalities.push(read_municipality(row));
+            }
+            Ok(municipalities)
+        });
+        result.map_err(|err| Error::other(format!("Data loading failed: {}", err)))
+    }
+
+    fn get_forecasts(&self, municipality_id: &str, from: DateTime<Utc>) -> Result<Vec<Forecast>, Error> {
+        match self.run_get_stmt(
+            STMT_GET_FORECASTS,
+            &[(":municipality_id", &municipality_id), (":from", &format_observation_time(&from))],
+            &extract_forecasts,
+        ) {
+            Ok(result) => Ok(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
         }
-        let mut j = 0;
-        latest_observations_map.values().for_each(|o| {
-            latest_observations[j] = o.clone();
-            j = j + 1;
+    }
+
+    fn delete_forecasts_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let result = self.get_connection().and_then(|connection| {
+            connection.execute(STMT_DELETE_FORECASTS_BEFORE, &[(":before", &format_observation_time(&before))])
         });
-        Ok(latest_observations.try_into().unwrap())
-    }
-
-    match run_get_stmt(
-        STMT_GET_LATEST_OBSERVATIONS,
-        &[
-            (":s1", &stations[0].id),
-            (":s2", &stations[1].id),
-            (":s3", &stations[2].id),
-        ],
-        &extract_latest_observations,
-    ) {
-        Ok(result) => return Ok(result),
-        Err(err) => {
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                format!("Data loading failed: {}", err),
-            ))
-        }
-    }
-}
-
-fn write_items_to_db<T: ToSqlParams>(
-    items: &Vec<T>,
-    write_statement: &str,
-) -> Result<(), rusqlite::Error> {
-    let mut connection = get_connection()?;
-    let transaction = connection.transaction()?;
-    for item in items {
-        let params = item.to_sql_params();
-        transaction.execute(write_statement, params)?;
-    }
-    transaction.commit()?;
-    Ok(())
-}
+        result.map_err(|err| Error::other(format!("Data deleting failed: {}", err)))
+    }
+
+    fn replace_warnings(&self, warnings: &[Warning], now: DateTime<Utc>) -> Result<usize, Error> {
+        let ids: Vec<&str> = warnings.iter().map(|w| w.id.as_str()).collect();
+        let ids = serde_json::to_string(&ids).map_err(|err| Error::other(format!("Data saving failed: {}", err)))?;
+        let now = format_observation_time(&now);
+        let result = self.get_connection().and_then(|mut connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute(STMT_END_REPLACED_WARNINGS, &[(":now", &now), (":ids", &ids)])?;
+            transaction.execute(STMT_DELETE_REPLACED_WARNINGS, &[(":now", &now), (":ids", &ids)])?;
+            let mut stored = 0;
+            {
+                let mut stmt = transaction.prepare_cached(STMT_UPSERT_WARNING)?;
+                for w in warnings {
+                    stored += stmt.execute(named_params! {
+                        ":id": w.id,
+                        ":kind": w.kind,
+                        ":event": w.event,
+                        ":level": w.level,
+                        ":severity": w.severity,
+                        ":headline": w.headline,
+                        ":area": w.area,
+                        ":onset": format_observation_time(&w.onset),
+                        ":expires": format_observation_time(&w.expires),
+                        ":polygon": serde_json::to_string(&w.polygons).unwrap_or_default(),
+                    })?;
+                }
+            }
+            transaction.commit().map(|_| stored)
+        });
+        result.map_err(|err| Error::other(format!("Data saving failed: {}", err)))
+    }
+
+    fn delete_warnings_before(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let result = self.get_connection().and_then(|connection| {
+            connection.execute(STMT_DELETE_WARNINGS_BEFORE, &[(":before", &format_observation_time(&before))])
+        });
+        result.map_err(|err| Error::other(format!("Data deleting failed: {}", err)))
+    }
 
-pub fn write_stations_to_db(stations: &Vec<Station>) -> Result<(), Error> {
-    match write_items_to_db::<Station>(stations, STMT_SET_STATION) {
-        Ok(_) => return Ok(()),
-        Err(err) => {
-            println!("Error with connection: {}", err);
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                format!("Data saving failed: {}", err),
-            ));
+    fn get_warnings_at(&self, at: DateTime<Utc>) -> Result<Vec<Warning>, Error> {
+        match self.run_get_stmt(STMT_GET_WARNINGS_AT, &[(":at", &format_observation_time(&at))], &extract_warnings) {
+            Ok(result) => Ok(result),
+            Err(err) => Err(Error::other(format!("Data loading failed: {}", err))),
         }
-    };
+    }
+
+    fn compact(&self) -> Result<(), Error> {
+        let result = self
+            .get_connection()
+            .and_then(|connection| connection.execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);"));
+        result.map_err(|err| Error::other(format!("Database compaction failed: {}", err)))
+    }
+
+    fn migrate(&self) -> Result<Vec<u32>, Error> {
+        let mut connection = self.get_connection().map_err(|err| Error::other(format!("Database opening failed: {}", err)))?;
+        match sqlite_migrations::migrate(&mut connection) {
+            Ok(applied) => Ok(applied),
+            Err(err) => Err(Error::other(format!("Database migration failed: {}", err))),
+        }
+    }
+
+    fn get_pending_migrations(&self) -> Result<Vec<&'static Migration>, Error> {
+        let connection = self.get_connection().map_err(|err| Error::other(format!("Database opening failed: {}", err)))?;
+        match sqlite_migrations::get_pending_migrations(&connection) {
+            Ok(pending) => Ok(pending),
+            Err(err) => Err(Error::other(format!("Reading schema version failed: {}", err))),
+        }
+    }
 }
 
-pub fn write_observations_to_db(observations: &Vec<Observation>) -> Result<(), Error> {
-    match write_items_to_db::<Observation>(observations, STMT_SET_OBSERVATION) {
-        Ok(_) => return Ok(()),
-        Err(err) => {
-            println!("Error with connection: {}", err);
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                format!("Data saving failed: {}", err),
-            ));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connectors::memory_store::MemoryStore, met::{parse_observation_time, testing}};
+
+    fn open_test_store(name: &str) -> SqliteStore {
+        let path = std::env::temp_dir().join(format!("wheatr-{}-{}.sqlite", name, std::process::id()));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
         }
-    };
+        let store = SqliteStore::open(path.to_str().unwrap()).unwrap();
+        store.migrate().unwrap();
+        store
+    }
+
+    fn observation(time: &str, aerial_temperature: f32, relative_humidity: f32) -> Observation {
+        Observation {
+            station_id: "6155A".to_string(),
+            observation_time: parse_observation_time(time).unwrap(),
+            aerial_temperature,
+            relative_humidity,
+        }
+    }
+
+    #[test]
+    fn roll_up_late_observations_into_the_same_day() {
+        let store = open_test_store("daily-summaries");
+        store
+            .upsert_stations(&[Station { id: "6155A".to_string(), ..Default::default() }], Utc::now())
+            .unwrap();
+        let cutoff = parse_observation_time("2023-08-11T00:00:00").unwrap();
+
+        store
+            .insert_observations(&[
+                observation("2023-08-09T10:00:00", 30.0, 40.0),
+                observation("2023-08-10T10:00:00", 20.0, 40.0),
+                observation("2023-08-10T11:00:00", 24.0, 40.0),
+                observation("2023-08-11T10:00:00", 25.0, 40.0),
+            ])
+            .unwrap();
+        assert_eq!(store.roll_up_observations_before(cutoff).unwrap(), 3);
+        assert_eq!(store.roll_up_observations_before(cutoff).unwrap(), 0);
+        store.insert_observations(&[observation("2023-08-10T12:00:00", 29.0, 70.0)]).unwrap();
+        assert_eq!(store.roll_up_observations_before(cutoff).unwrap(), 1);
+
+        let connection = store.get_connection().unwrap();
+        let merged: (u32, f32, f32, f32, f32) = connection
+            .query_row(
+                "SELECT samples, air_temperature_min, air_temperature_max, air_temperature_mean, rel_humidity_mean FROM daily_observations WHERE day = '2023-08-10'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+            )
+            .unwrap();
+        assert_eq!(merged, (3, 20.0, 29.0, 24.333334, 50.0));
+        assert_eq!(
+            store.delete_daily_summaries_before(NaiveDate::from_ymd_opt(2023, 8, 11).unwrap()).unwrap(),
+            2
+        );
+        let mut remaining = 0;
+        store
+            .for_each_observation(&ObservationFilter::default(), &mut |_| {
+                remaining += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(remaining, 1);
+    }
+
+    #[test]
+    fn relocated_station_keeps_its_history() {
+        let store = open_test_store("station-history");
+        let station = |id: &str, name: &str, lat: f32, lon: f32| Station {
+            id: id.to_string(),
+            name: name.to_string(),
+            lat,
+            lon,
+            altitude: None,
+            provider: "aemet".to_string(),
+        };
+        let relocated_at = parse_observation_time("2023-08-10T12:00:00").unwrap();
+        store
+            .upsert_stations(
+                &[
+                    station("6155A", "MALAGA AEROPUERTO", 36.66612, -4.482307),
+                    station("6156X", "MALAGA CMT", 36.717785, -4.48167),
+                    station("6172O", "MALAGA PUERTO", 36.716663, -4.41972),
+                    station("5402", "CORDOBA AEROPUERTO", 37.844166, -4.846111),
+                ],
+                relocated_at,
+            )
+            .unwrap();
+        store.upsert_stations(&[station("6155A", "RELOCATED", 0.0, 0.0)], relocated_at).unwrap();
+
+        let location = Location { lat: 36.7, lon: -4.45 };
+        let closest = store.get_closest_stations(&location, &ProviderFilter::default()).unwrap();
+        assert!(closest.iter().all(|s| s.id != "6155A"));
+
+        let before = parse_observation_time("2023-08-10T09:00:00").unwrap();
+        let closest_before = store.get_closest_stations_at(&location, before, &ProviderFilter::default()).unwrap();
+        assert!(closest_before.iter().any(|s| s.name == "MALAGA AEROPUERTO"));
+
+        let periods = store.get_station_periods(before, relocated_at, &ProviderFilter::default()).unwrap();
+        let malaga_airport: Vec<(&str, Option<DateTime<Utc>>)> =
+            periods.iter().filter(|p| p.station.id == "6155A").map(|p| (p.station.name.as_str(), p.valid_to)).collect();
+        assert_eq!(malaga_airport, vec![("MALAGA AEROPUERTO", Some(relocated_at)), ("RELOCATED", None)]);
+        assert_eq!(store.get_station_periods(relocated_at + chrono::Duration::hours(1), relocated_at + chrono::Duration::hours(2), &ProviderFilter::default()).unwrap().len(), 4);
+
+        // changes older than the open period or than stored observations are not applied
+        store.insert_observations(&[observation("2023-08-10T13:00:00", 30.0, 40.0)]).unwrap();
+        for replayed_at in ["2021-01-09T06:00:00", "2023-08-10T12:30:00"] {
+            store
+                .upsert_stations(&[station("6155A", "MALAGA AEROPUERTO", 36.66612, -4.482307)], parse_observation_time(replayed_at).unwrap())
+                .unwrap();
+            assert_eq!(store.get_station("6155A").unwrap().unwrap().name, "RELOCATED");
+        }
+        let connection = store.get_connection().unwrap();
+        let periods: u32 = connection.query_row("SELECT COUNT(*) FROM station_history WHERE station_id = '6155A'", [], |row| row.get(0)).unwrap();
+        assert_eq!(periods, 2);
+    }
+
+    #[test]
+    fn closest_stations_of_providers() {
+        let store = open_test_store("closest-providers");
+        let station = |id: &str, lat: f32, lon: f32, provider: &str| Station {
+            id: id.to_string(),
+            name: id.to_string(),
+            lat,
+            lon,
+            altitude: None,
+            provider: provider.to_string(),
+        };
+        store
+            .upsert_stations(
+                &[
+                    station("a", 40.0, -3.0, "aemet"),
+                    station("b", 40.2, -3.2, "aemet"),
+                    station("c", 40.4, -3.0, "aemet"),
+                    station("garden:s1", 40.1, -3.1, "garden"),
+                ],
+                parse_observation_time("2023-08-10T00:00:00").unwrap(),
+            )
+            .unwrap();
+        let location = Location { lat: 40.1, lon: -3.1 };
+        let at = parse_observation_time("2023-08-10T12:00:00").unwrap();
+        let providers = |include: Option<&str>, exclude: &[&str]| ProviderFilter {
+            include: include.map(|p| vec![p.to_string()]),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+        };
+        let has_garden = |stations: [Station; 3]| stations.iter().any(|s| s.provider == "garden");
+
+        assert!(has_garden(store.get_closest_stations(&location, &ProviderFilter::default()).unwrap()));
+        assert!(!has_garden(store.get_closest_stations(&location, &providers(None, &["garden"])).unwrap()));
+        assert!(!has_garden(store.get_closest_stations_at(&location, at, &providers(Some("aemet"), &[])).unwrap()));
+        assert!(store.get_closest_stations(&location, &providers(Some("garden"), &[])).is_err());
+    }
+
+    #[test]
+    fn closest_stations_across_the_meridian() {
+        let store = open_test_store("closest-meridian");
+        let memory_store = MemoryStore::default();
+        let station = |id: &str, lat: f32, lon: f32| Station {
+            id: id.to_string(),
+            name: id.to_string(),
+            lat,
+            lon,
+            altitude: None,
+            provider: "aemet".to_string(),
+        };
+        let stations = [
+            station("west", 39.5, -0.5),
+            station("north", 39.9, -0.3),
+            station("far-north", 40.3, -0.3),
+            // as close as "west" by the absolute longitude
+            station("east", 39.5, 0.8),
+        ];
+        let valid_from = parse_observation_time("2023-08-10T00:00:00").unwrap();
+        store.upsert_stations(&stations, valid_from).unwrap();
+        memory_store.upsert_stations(&stations, valid_from).unwrap();
+        let location = Location { lat: 39.5, lon: -0.3 };
+        let at = parse_observation_time("2023-08-10T12:00:00").unwrap();
+        let ids = |stations: [Station; 3]| stations.map(|s| s.id);
+
+        let closest = ids(store.get_closest_stations(&location, &ProviderFilter::default()).unwrap());
+        assert_eq!(closest, ["west", "north", "far-north"]);
+        assert_eq!(ids(store.get_closest_stations_at(&location, at, &ProviderFilter::default()).unwrap()), closest);
+        assert_eq!(ids(memory_store.get_closest_stations(&location, &ProviderFilter::default()).unwrap()), closest);
+        assert_eq!(ids(memory_store.get_closest_stations_at(&location, at, &ProviderFilter::default()).unwrap()), closest);
+    }
+
+    #[test]
+    fn latest_observation_of_every_station_in_their_order() {
+        let store = open_test_store("latest-observations");
+        let stations = [testing::station("hourly-1", 36.7, -4.5), testing::station("ten-minutes", 36.7, -4.4), testing::station("hourly-2", 36.8, -4.4)];
+        store.upsert_stations(&stations, Utc::now()).unwrap();
+        let mut observations = vec![];
+        // the ten minute feed is an hour ahead of the hourly ones
+        for hour in 8..13 {
+            if hour < 12 {
+                observations.push(testing::observation("hourly-1", &format!("2023-08-10T{:02}:00:00", hour), 20.0));
+                observations.push(testing::observation("hourly-2", &format!("2023-08-10T{:02}:00:00", hour), 22.0));
+            }
+            for minute in (0..60).step_by(10) {
+                observations.push(testing::observation("ten-minutes", &format!("2023-08-10T{:02}:{:02}:00", hour, minute), 21.0));
+            }
+        }
+        store.insert_observations(&observations).unwrap();
+        let times = |observations: [Observation; 3]| observations.map(|o| (o.station_id, format_observation_time(&o.observation_time)));
+
+        assert_eq!(
+            times(store.get_latest_observations(&stations).unwrap()),
+            [
+                ("hourly-1".to_string(), "2023-08-10T11:00:00Z".to_string()),
+                ("ten-minutes".to_string(), "2023-08-10T12:50:00Z".to_string()),
+                ("hourly-2".to_string(), "2023-08-10T11:00:00Z".to_string()),
+            ]
+        );
+        let at = parse_observation_time("2023-08-10T10:05:00").unwrap();
+        let reordered = [stations[2].clone(), stations[1].clone(), stations[0].clone()];
+        assert_eq!(
+            times(store.get_latest_observations_at(&reordered, at).unwrap()),
+            [
+                ("hourly-2".to_string(), "2023-08-10T10:00:00Z".to_string()),
+                ("ten-minutes".to_string(), "2023-08-10T10:00:00Z".to_string()),
+                ("hourly-1".to_string(), "2023-08-10T10:00:00Z".to_string()),
+            ]
+        );
+        let before = parse_observation_time("2023-08-10T07:00:00").unwrap();
+        assert!(store.get_latest_observations_at(&stations, before).unwrap().iter().all(|o| o.station_id.is_empty()));
+    }
+
+    #[test]
+    fn later_forecast_elaborations_replace_earlier_ones() {
+        let store = open_test_store("forecasts");
+        let municipality = |id: &str, lat: f32, lon: f32| Municipality { id: id.to_string(), name: id.to_string(), lat, lon, ..Default::default() };
+        store
+            .upsert_municipalities(&[municipality("29067", 36.72, -4.42), municipality("28079", 40.42, -3.70), municipality("29051", 36.71, -4.43)])
+            .unwrap();
+        let forecast = |id: &str, time: &str, aerial_temperature: f32, elaborated_at: &str| Forecast {
+            municipality_id: id.to_string(),
+            forecast_time: parse_observation_time(time).unwrap(),
+            aerial_temperature,
+            relative_humidity: 50.0,
+            elaborated_at: parse_observation_time(elaborated_at).unwrap(),
+        };
+        let written = store
+            .upsert_forecasts(&[
+                forecast("29067", "2024-07-15T12:00:00Z", 30.0, "2024-07-14T09:00:00Z"),
+                forecast("29067", "2024-07-15T13:00:00Z", 31.0, "2024-07-14T09:00:00Z"),
+                forecast("28079", "2024-07-15T12:00:00Z", 35.0, "2024-07-14T09:00:00Z"),
+            ])
+            .unwrap();
+        assert_eq!(written, 3);
+        store.upsert_forecasts(&[forecast("29067", "2024-07-15T12:00:00Z", 32.0, "2024-07-14T15:00:00Z")]).unwrap();
+        assert_eq!(store.upsert_forecasts(&[forecast("29067", "2024-07-15T13:00:00Z", 0.0, "2024-07-13T15:00:00Z")]).unwrap(), 0);
+
+        // Torremolinos (29051) is closer but has no forecasts
+        let closest = store.get_closest_municipality(&Location { lat: 36.70, lon: -4.44 }).unwrap().unwrap();
+        assert_eq!(closest.id, "29067");
+        assert_eq!(store.get_municipalities().unwrap().iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), vec!["28079", "29051", "29067"]);
+        let forecasts = store.get_forecasts("29067", parse_observation_time("2024-07-15T12:00:00Z").unwrap()).unwrap();
+        let temperatures: Vec<f32> = forecasts.iter().map(|f| f.aerial_temperature).collect();
+        assert_eq!(temperatures, vec![32.0, 31.0]);
+        assert_eq!(store.delete_forecasts_before(parse_observation_time("2024-07-15T13:00:00Z").unwrap()).unwrap(), 2);
+    }
+
+    #[test]
+    fn replaced_warnings_end_and_expired_ones_are_kept() {
+        let store = open_test_store("warnings");
+        let warning = |id: &str, onset: &str, expires: &str| Warning {
+            id: id.to_string(),
+            kind: "heat".to_string(),
+            level: "naranja".to_string(),
+            onset: parse_observation_time(onset).unwrap(),
+            expires: parse_observation_time(expires).unwrap(),
+            polygons: vec![vec![(-4.9, 36.9), (-4.2, 36.9), (-4.2, 36.5)], vec![(-5.4, 36.5), (-5.2, 36.5), (-5.2, 36.4)]],
+            ..Default::default()
+        };
+        let time = |value: &str| parse_observation_time(value).unwrap();
+        store
+            .replace_warnings(
+                &[warning("old", "2024-07-14T10:00:00Z", "2024-07-16T18:59:59Z"), warning("tomorrow", "2024-07-16T10:00:00Z", "2024-07-16T18:59:59Z")],
+                time("2024-07-14T12:00:00Z"),
+            )
+            .unwrap();
+        let stored = store
+            .replace_warnings(
+                &[
+                    warning("later", "2024-07-15T16:00:00Z", "2024-07-15T18:59:59Z"),
+                    warning("current", "2024-07-15T10:00:00Z", "2024-07-15T18:59:59Z"),
+                ],
+                time("2024-07-15T12:00:00Z"),
+            )
+            .unwrap();
+        assert_eq!(stored, 2);
+
+        let warnings = store.get_warnings_at(time("2024-07-15T16:00:00Z")).unwrap();
+        assert_eq!(warnings.iter().map(|w| w.id.as_str()).collect::<Vec<_>>(), vec!["current", "later"]);
+        assert_eq!(warnings[0], warning("current", "2024-07-15T10:00:00Z", "2024-07-15T18:59:59Z"));
+        // the replaced warning ended when it was replaced, the one to come is gone
+        let past = store.get_warnings_at(time("2024-07-15T11:00:00Z")).unwrap();
+        assert_eq!(past.iter().map(|w| (w.id.as_str(), w.expires)).collect::<Vec<_>>(), vec![("old", time("2024-07-15T12:00:00Z")), ("current", time("2024-07-15T18:59:59Z"))]);
+        assert!(store.get_warnings_at(time("2024-07-16T12:00:00Z")).unwrap().is_empty());
+        assert_eq!(store.delete_warnings_before(time("2024-07-15T13:00:00Z")).unwrap(), 1);
+        assert!(store.get_warnings_at(time("2024-07-15T11:00:00Z")).unwrap().iter().all(|w| w.id != "old"));
+
+        // warnings stored with a single outline are still read
+        store.get_connection().unwrap().execute("UPDATE warnings SET polygon = '[[-4.9,36.9],[-4.2,36.9],[-4.2,36.5]]'", []).unwrap();
+        assert_eq!(store.get_warnings_at(time("2024-07-15T16:00:00Z")).unwrap()[0].polygons, vec![vec![(-4.9, 36.9), (-4.2, 36.9), (-4.2, 36.5)]]);
+    }
 }
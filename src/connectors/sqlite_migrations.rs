@@ -0,0 +1,241 @@
+use chrono::{SecondsFormat, Utc};
+use rusqlite::{Connection, OptionalExtension};
+
+use super::store::Migration;
+
+/**
+ * Schema changes of the SQLite store, applied in order. A migration must
+ * never be modified once released: add a new one with the next version
+ * number instead.
+ */
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create stations and observations tables",
+        sql: "CREATE TABLE IF NOT EXISTS stations (
+            id TEXT NOT NULL UNIQUE,
+            name TEXT,
+            lat	REAL NOT NULL,
+            lon	REAL NOT NULL,
+            PRIMARY KEY(id)
+        );
+        CREATE TABLE IF NOT EXISTS observations (
+            station_id TEXT NOT NULL,
+            observation_time TEXT NOT NULL,
+            air_temperature REAL NOT NULL,
+            rel_humidity REAL NOT NULL,
+            PRIMARY KEY(observation_time,station_id),
+            FOREIGN KEY(station_id) REFERENCES stations(id) ON DELETE CASCADE
+        );",
+    },
+    Migration {
+        version: 2,
+        description: "Store observation times as ISO-8601 UTC and index them per station",
+        sql: "UPDATE OR IGNORE observations SET observation_time = observation_time || 'Z' WHERE observation_time NOT LIKE '%Z';
+        DELETE FROM observations WHERE observation_time NOT LIKE '%Z';
+        CREATE INDEX IF NOT EXISTS observations_station_time ON observations (station_id, observation_time);",
+    },
+    Migration {
+        version: 3,
+        description: "Create daily_observations table for roll-ups of expired observations",
+        sql: "CREATE TABLE IF NOT EXISTS daily_observations (
+            station_id TEXT NOT NULL,
+            day TEXT NOT NULL,
+            samples INTEGER NOT NULL,
+            air_temperature_min REAL NOT NULL,
+            air_temperature_max REAL NOT NULL,
+            air_temperature_mean REAL NOT NULL,
+            rel_humidity_min REAL NOT NULL,
+            rel_humidity_max REAL NOT NULL,
+            rel_humidity_mean REAL NOT NULL,
+            heat_index_min REAL NOT NULL,
+            heat_index_max REAL NOT NULL,
+            heat_index_mean REAL NOT NULL,
+            PRIMARY KEY(station_id, day),
+            FOREIGN KEY(station_id) REFERENCES stations(id) ON DELETE CASCADE
+        );",
+    },
+    Migration {
+        version: 4,
+        description: "Add station altitude and station_history table",
+        sql: "ALTER TABLE stations ADD COLUMN altitude REAL;
+        CREATE TABLE IF NOT EXISTS station_history (
+            station_id TEXT NOT NULL,
+            name TEXT,
+            lat REAL NOT NULL,
+            lon REAL NOT NULL,
+            altitude REAL,
+            valid_from TEXT NOT NULL,
+            valid_to TEXT,
+            PRIMARY KEY(station_id, valid_from),
+            FOREIGN KEY(station_id) REFERENCES stations(id) ON DELETE CASCADE
+        );
+        INSERT INTO station_history (station_id, name, lat, lon, altitude, valid_from)
+            SELECT id, name, lat, lon, altitude, '1970-01-01T00:00:00Z' FROM stations;",
+    },
+    Migration {
+        version: 5,
+        description: "Create ingestion_runs table",
+        sql: "CREATE TABLE IF NOT EXISTS ingestion_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            provider TEXT NOT NULL,
+            started_at TEXT NOT NULL,
+            finished_at TEXT NOT NULL,
+            succeeded INTEGER NOT NULL,
+            http_status INTEGER,
+            error TEXT,
+            parsed_stations INTEGER NOT NULL,
+            parsed_observations INTEGER NOT NULL,
+            inserted_observations INTEGER NOT NULL,
+            duplicated_observations INTEGER NOT NULL,
+            skipped_observations INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS ingestion_runs_started_at ON ingestion_runs (started_at);",
+    },
+    Migration {
+        version: 6,
+        description: "Add station provider",
+        sql: "ALTER TABLE stations ADD COLUMN provider TEXT NOT NULL DEFAULT 'aemet';
+        CREATE INDEX IF NOT EXISTS stations_provider ON stations (provider);",
+    },
+    Migration {
+        version: 7,
+        description: "Create municipalities and forecasts tables",
+        sql: "CREATE TABLE IF NOT EXISTS municipalities (
+            id TEXT NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            province TEXT,
+            lat REAL NOT NULL,
+            lon REAL NOT NULL,
+            altitude REAL
+        );
+        CREATE TABLE IF NOT EXISTS forecasts (
+            municipality_id TEXT NOT NULL,
+            forecast_time TEXT NOT NULL,
+            air_temperature REAL NOT NULL,
+            rel_humidity REAL NOT NULL,
+            elaborated_at TEXT NOT NULL,
+            PRIMARY KEY(municipality_id, forecast_time),
+            FOREIGN KEY(municipality_id) REFERENCES municipalities(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS forecasts_time ON forecasts (forecast_time);",
+    },
+    Migration {
+        version: 8,
+        description: "Create warnings table",
+        sql: "CREATE TABLE IF NOT EXISTS warnings (
+            id TEXT NOT NULL PRIMARY KEY,
+            kind TEXT NOT NULL,
+            event TEXT NOT NULL,
+            level TEXT NOT NULL,
+            severity TEXT NOT NULL,
+            headline TEXT NOT NULL,
+            area TEXT NOT NULL,
+            onset TEXT NOT NULL,
+            expires TEXT NOT NULL,
+            polygon TEXT NOT NULL
+        );",
+    },
+];
+
+const STMT_CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER NOT NULL PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at TEXT NOT NULL
+)";
+const STMT_HAS_SCHEMA_VERSION: &str = "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')";
+const STMT_GET_SCHEMA_VERSION: &str = "SELECT MAX(version) FROM schema_version";
+const STMT_SET_SCHEMA_VERSION: &str =
+    "INSERT INTO schema_version (version, description, applied_at) VALUES (:version, :description, :applied_at)";
+
+/// Version of the schema, 0 if no migration was applied. The database is only read.
+pub fn get_schema_version(connection: &Connection) -> Result<u32, rusqlite::Error> {
+    let has_schema_version: bool = connection.query_row(STMT_HAS_SCHEMA_VERSION, [], |row| row.get(0))?;
+    if !has_schema_version {
+        return Ok(0);
+    }
+    let version: Option<u32> = connection
+        .query_row(STMT_GET_SCHEMA_VERSION, [], |row| row.get(0))
+        .optional()?
+        .flatten();
+    Ok(version.unwrap_or(0))
+}
+
+pub fn get_pending_migrations(connection: &Connection) -> Result<Vec<&'static Migration>, rusqlite::Error> {
+    let version = get_schema_version(connection)?;
+    Ok(MIGRATIONS.iter().filter(|m| m.version > version).collect())
+}
+
+/**
+ * Brings the schema up to the latest version. Every migration runs in its
+ * own transaction together with its `schema_version` record, so a failing
+ * migration leaves the database at the previous version.
+ */
+pub fn migrate(connection: &mut Connection) -> Result<Vec<u32>, rusqlite::Error> {
+    connection.execute(STMT_CREATE_SCHEMA_VERSION, [])?;
+    let pending: Vec<&Migration> = get_pending_migrations(connection)?;
+    let mut applied = vec![];
+    for migration in pending {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration.sql)?;
+        transaction.execute(
+            STMT_SET_SCHEMA_VERSION,
+            (
+                migration.version,
+                migration.description,
+                Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            ),
+        )?;
+        transaction.commit()?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_are_ordered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version as usize, i + 1);
+        }
+    }
+
+    #[test]
+    fn migrate_empty_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let latest = MIGRATIONS.last().unwrap().version;
+        assert_eq!(get_pending_migrations(&connection).unwrap().len(), MIGRATIONS.len());
+        // listing pending migrations leaves the database untouched
+        let tables: u32 = connection.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0)).unwrap();
+        assert_eq!(tables, 0);
+        assert_eq!(migrate(&mut connection).unwrap().len(), MIGRATIONS.len());
+        assert_eq!(get_schema_version(&connection).unwrap(), latest);
+        assert!(migrate(&mut connection).unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrate_legacy_observation_times() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(MIGRATIONS[0].sql).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO stations VALUES ('6155A', 'MALAGA AEROPUERTO', 36.66612, -4.482307);
+                INSERT INTO observations VALUES ('6155A', '2023-08-10T10:00:00', 30.1, 40.0);
+                INSERT INTO observations VALUES ('6155A', '2023-08-10T11:00:00', 31.2, 38.0);
+                INSERT INTO observations VALUES ('6155A', '2023-08-10T11:00:00Z', 31.2, 38.0);",
+            )
+            .unwrap();
+        migrate(&mut connection).unwrap();
+        let times: Vec<String> = connection
+            .prepare("SELECT observation_time FROM observations ORDER BY observation_time")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|t| t.unwrap())
+            .collect();
+        assert_eq!(times, vec!["2023-08-10T10:00:00Z", "2023-08-10T11:00:00Z"]);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{path::PathBuf, time::Duration};
+
+use r2d2::{ManageConnection, Pool};
+use rusqlite::{Connection, OpenFlags};
+
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const POOL_SIZE: u32 = 8;
+const STATEMENT_CACHE_CAPACITY: usize = 32;
+
+/**
+ * r2d2 connection manager for SQLite. Every connection is opened in WAL
+ * journaling mode, so API reads are not blocked by the hourly writer
+ * transaction, and waits up to `BUSY_TIMEOUT` for a lock instead of
+ * failing with SQLITE_BUSY immediately.
+ */
+pub struct SqliteConnectionManager {
+    path: PathBuf,
+}
+
+impl SqliteConnectionManager {
+    pub fn new(path: impl Into<PathBuf>) -> SqliteConnectionManager {
+        SqliteConnectionManager { path: path.into() }
+    }
+}
+
+impl ManageConnection for SqliteConnectionManager {
+    type Connection = Connection;
+    type Error = rusqlite::Error;
+
+    fn connect(&self) -> Result<Connection, rusqlite::Error> {
+        let connection = Connection::open_with_flags(&self.path, OpenFlags::default())?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        Ok(connection)
+    }
+
+    fn is_valid(&self, connection: &mut Connection) -> Result<(), rusqlite::Error> {
+        connection.execute_batch("SELECT 1")
+    }
+
+    fn has_broken(&self, _connection: &mut Connection) -> bool {
+        false
+    }
+}
+
+pub fn create_pool(path: impl Into<PathBuf>) -> Result<Pool<SqliteConnectionManager>, r2d2::Error> {
+    Pool::builder()
+        .max_size(POOL_SIZE)
+        .build(SqliteConnectionManager::new(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_use_wal_journal() {
+        let path = std::env::temp_dir().join(format!("wheatr-pool-{}.sqlite", std::process::id()));
+        let pool = create_pool(&path).unwrap();
+        let connection = pool.get().unwrap();
+        let journal_mode: String = connection
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+        drop(connection);
+        drop(pool);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
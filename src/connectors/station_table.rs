@@ -0,0 +1,50 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+};
+
+use serde::Deserialize;
+
+use crate::{config::get_env_var_or, met::Station};
+
+#[derive(Deserialize)]
+struct StationRecord {
+    id: String,
+    name: String,
+    lat: f32,
+    lon: f32,
+    altitude: Option<f32>,
+}
+
+/**
+ * Reads a CSV table of station locations with the columns `id`, `name`,
+ * `lat`, `lon` and `altitude` (may be empty), keyed by id. Reports of
+ * METAR, SYNOP and BUFR identify stations by code only, so their
+ * positions come from such tables.
+ */
+pub fn read_station_table(content: &str, provider: &str) -> Result<HashMap<String, Station>, Error> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).comment(Some(b'#')).from_reader(content.as_bytes());
+    let mut stations = HashMap::new();
+    for record in reader.deserialize() {
+        let record: StationRecord = record.map_err(|err| Error::new(ErrorKind::InvalidData, format!("Station table parsing failed: {}", err)))?;
+        let station = Station { id: record.id, name: record.name, lat: record.lat, lon: record.lon, altitude: record.altitude, provider: provider.to_string() };
+        stations.insert(station.id.clone(), station);
+    }
+    Ok(stations)
+}
+
+/**
+ * Loads the station table at the path set by `env_path`, or the bundled
+ * one if it is not set or cannot be read.
+ */
+pub fn load_station_table(env_path: &str, bundled: &str, provider: &str) -> HashMap<String, Station> {
+    let path: String = get_env_var_or(env_path, String::new());
+    if !path.is_empty() {
+        match fs::read_to_string(&path).and_then(|content| read_station_table(&content, provider)) {
+            Ok(stations) => return stations,
+            Err(e) => println!("Station table {} is not loaded. {}", path, e),
+        }
+    }
+    read_station_table(bundled, provider).expect("bundled station table is valid")
+}
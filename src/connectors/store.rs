@@ -0,0 +1,136 @@
+use std::io::Error;
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::met::{BoundingBox, Forecast, IngestionRun, Location, Municipality, Observation, Station, StationPeriod, Warning};
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StationFilter {
+    pub bbox: Option<BoundingBox>,
+    pub provider: Option<String>,
+}
+
+/// Providers whose stations are interpolated from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProviderFilter {
+    /// Only stations of these providers, of all of them if `None`.
+    pub include: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+}
+
+impl ProviderFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ObservationFilter {
+    /// Only observations of these stations, of all of them if `None`.
+    pub station_ids: Option<Vec<String>>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Places the observations at the index of their station, leaving stations without one as default.
+pub fn in_station_order(stations: &[Station; 3], observations: Vec<Observation>) -> [Observation; 3] {
+    let mut ordered: [Observation; 3] = Default::default();
+    for observation in observations {
+        if let Some(i) = stations.iter().position(|s| s.id == observation.station_id) {
+            ordered[i] = observation;
+        }
+    }
+    ordered
+}
+
+/**
+ * Storage of stations and observations. Implementations have to be safe to
+ * share between the HTTP handlers and the scheduled ingestion job.
+ */
+pub trait Store: Send + Sync {
+    /// Inserts new stations and updates changed ones. Changes are recorded in the
+    /// station history as valid from `valid_from`. A change older than the open
+    /// period or than a stored observation of the station is not applied.
+    fn upsert_stations(&self, stations: &[Station], valid_from: DateTime<Utc>) -> Result<(), Error>;
+    /// Inserts observations, returning how many were new. Duplicates of the same
+    /// station and time are ignored.
+    fn insert_observations(&self, observations: &[Observation]) -> Result<usize, Error>;
+    fn get_station(&self, id: &str) -> Result<Option<Station>, Error>;
+    /// Stations matching the filter ordered by id, with their latest observation if they have any.
+    fn get_stations_with_latest_observation(&self, filter: &StationFilter) -> Result<Vec<(Station, Option<Observation>)>, Error>;
+    fn get_closest_stations(&self, loc: &Location, providers: &ProviderFilter) -> Result<[Station; 3], Error>;
+    /// Closest stations by their position valid at `at`, for interpolating past observations.
+    fn get_closest_stations_at(&self, loc: &Location, at: DateTime<Utc>, providers: &ProviderFilter) -> Result<[Station; 3], Error>;
+    /// Station states of the providers valid at some time in `[from, to]`, ordered by station and time.
+    fn get_station_periods(&self, from: DateTime<Utc>, to: DateTime<Utc>, providers: &ProviderFilter) -> Result<Vec<StationPeriod>, Error>;
+    /// Latest observation of each of the stations, in their order. A station without
+    /// observations gets a default one.
+    fn get_latest_observations(&self, stations: &[Station; 3]) -> Result<[Observation; 3], Error>;
+    /// Latest observation of each of the stations recorded not after `at`, like `get_latest_observations`.
+    fn get_latest_observations_at(&self, stations: &[Station; 3], at: DateTime<Utc>) -> Result<[Observation; 3], Error>;
+
+    /// Observations of the stations recorded in `[from, to]`, oldest first.
+    fn get_observations_between(&self, station_ids: &[String], from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Observation>, Error>;
+    /// Passes the observations matching the filter to `f` ordered by time and station,
+    /// one by one without loading them all. Stops at the first error of `f`.
+    fn for_each_observation(&self, filter: &ObservationFilter, f: &mut dyn FnMut(Observation) -> Result<(), Error>) -> Result<(), Error>;
+    /**
+     * Deletes the observations recorded before `before` and merges them into
+     * the daily summaries of their station and UTC day. Every day is rolled
+     * up in its own transaction, so an observation is never summarized twice
+     * nor deleted without being summarized. Returns the number of rolled up
+     * observations.
+     */
+    fn roll_up_observations_before(&self, before: DateTime<Utc>) -> Result<usize, Error>;
+    /// Deletes summaries of days before `before`, returning the number of deleted rows.
+    fn delete_daily_summaries_before(&self, before: NaiveDate) -> Result<usize, Error>;
+    fn insert_ingestion_run(&self, run: &IngestionRun) -> Result<(), Error>;
+    /// The latest `limit` ingestion runs, newest first.
+    fn get_ingestion_runs(&self, limit: usize) -> Result<Vec<IngestionRun>, Error>;
+
+    /// Inserts new municipalities and updates the name, province and position of known ones.
+    fn upsert_municipalities(&self, municipalities: &[Municipality]) -> Result<(), Error>;
+    /// Stores forecasts, replacing the ones of the same municipality and hour from an
+    /// earlier elaboration. Returns how many were written.
+    fn upsert_forecasts(&self, forecasts: &[Forecast]) -> Result<usize, Error>;
+    /// Municipality with forecasts closest to `loc`, by the position of its town.
+    fn get_closest_municipality(&self, loc: &Location) -> Result<Option<Municipality>, Error>;
+    fn get_municipality(&self, id: &str) -> Result<Option<Municipality>, Error>;
+    /// All stored municipalities, by id.
+    fn get_municipalities(&self) -> Result<Vec<Municipality>, Error>;
+    /// Forecasts of a municipality for `from` and later, earliest first.
+    fn get_forecasts(&self, municipality_id: &str, from: DateTime<Utc>) -> Result<Vec<Forecast>, Error>;
+    /// Deletes forecasts for hours before `before`, returning the number of deleted rows.
+    fn delete_forecasts_before(&self, before: DateTime<Utc>) -> Result<usize, Error>;
+
+    /**
+     * Replaces the warnings not expired at `now` by `warnings`, returning how
+     * many were stored. Stored warnings missing from `warnings`, as they were
+     * updated or cancelled, end at `now` if they are in force and are deleted
+     * if they are to come. Expired warnings are kept for past requests.
+     */
+    fn replace_warnings(&self, warnings: &[Warning], now: DateTime<Utc>) -> Result<usize, Error>;
+    /// Deletes warnings expired before `before`, returning the number of deleted rows.
+    fn delete_warnings_before(&self, before: DateTime<Utc>) -> Result<usize, Error>;
+    /// Warnings in force at `at`, ordered by onset.
+    fn get_warnings_at(&self, at: DateTime<Utc>) -> Result<Vec<Warning>, Error>;
+
+    /// Reclaims the space left by deleted rows.
+    fn compact(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Brings the schema up to date, returning the applied migration versions.
+    fn migrate(&self) -> Result<Vec<u32>, Error> {
+        Ok(vec![])
+    }
+    fn get_pending_migrations(&self) -> Result<Vec<&'static Migration>, Error> {
+        Ok(vec![])
+    }
+}
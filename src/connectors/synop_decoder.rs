@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::{
+    calculators::location_data_calculations::calculate_relative_humidity,
+    config::{invalid_input, parse_args},
+    met::{parse_observation_time, resolve_report_time, MeteoData, Observation, Station},
+};
+
+use super::station_table::load_station_table;
+
+pub const PROVIDER: &str = "synop";
+
+const ENV_STATIONS_PATH: &str = "WHEATR_SYNOP_STATIONS_PATH";
+const STATIONS_CSV: &str = include_str!("../../data/synop_stations.csv");
+const KNOT: f32 = 0.514444;
+
+/// Section 1 of a land station report (FM-12 SYNOP).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SynopReport {
+    /// WMO block and station number (IIiii).
+    pub station: String,
+    pub observation_time: DateTime<Utc>,
+    /// °C
+    pub temperature: Option<f32>,
+    pub dew_point: Option<f32>,
+    /// %, reported instead of the dew point by some stations.
+    pub relative_humidity: Option<f32>,
+    /// hPa
+    pub station_pressure: Option<f32>,
+    pub sea_level_pressure: Option<f32>,
+    /// Degrees the wind blows from, 0 if calm, `None` if variable.
+    pub wind_direction: Option<u16>,
+    /// m/s
+    pub wind_speed: Option<f32>,
+}
+
+/// The YYGGiw group heading the reports of a bulletin.
+#[derive(Clone, Copy, Debug)]
+struct Heading {
+    day: u32,
+    hour: u32,
+    /// Whether the wind speed is in knots rather than m/s, `None` if unknown.
+    knots: Option<bool>,
+}
+
+/// Whether a token is a group of five ASCII characters, which can be sliced by byte.
+fn is_group(token: &str) -> bool {
+    token.len() == 5 && token.is_ascii()
+}
+
+fn parse_heading(group: &str) -> Option<Heading> {
+    if !is_group(group) {
+        return None;
+    }
+    let knots = match &group[4..5] {
+        "0" | "1" => Some(false),
+        "3" | "4" => Some(true),
+        _ => None,
+    };
+    Some(Heading { day: group[0..2].parse().ok()?, hour: group[2..4].parse().ok()?, knots })
+}
+
+/// `snTTT`: sign (0 positive, 1 negative) and tenths of °C.
+fn parse_signed_tenths(group: &str) -> Option<f32> {
+    let value: f32 = group[1..4].parse().ok()?;
+    match &group[0..1] {
+        "0" => Some(value / 10.0),
+        "1" => Some(-value / 10.0),
+        _ => None,
+    }
+}
+
+/// Tenths of hPa without the thousands digit.
+fn parse_pressure(digits: &str) -> Option<f32> {
+    let tenths: f32 = digits.parse().ok()?;
+    Some(if digits.starts_with('0') { 1000.0 + tenths / 10.0 } else { tenths / 10.0 })
+}
+
+/**
+ * Decodes section 1 of a report: `IIiii iRixhVV Nddff (00fff) 1snTTT
+ * 2snTdTdTd|29UUU 3P0P0P0P0 4PPPP ...` up to the next section.
+ */
+fn decode_report(groups: &[&str], heading: Heading, observation_time: DateTime<Utc>) -> Option<SynopReport> {
+    let station = groups.first().filter(|g| g.len() == 5 && g.bytes().all(|b| b.is_ascii_digit()))?;
+    let wind = groups.get(2).filter(|g| is_group(g))?;
+    let mut rest = &groups[3.min(groups.len())..];
+    let mut speed = &wind[3..5];
+    if speed == "99" {
+        // 100 units or more follow in 00fff
+        speed = rest.first().and_then(|g| g.strip_prefix("00"))?;
+        rest = &rest[1..];
+    }
+    let mut report = SynopReport { station: station.to_string(), observation_time, ..Default::default() };
+    report.wind_speed = match (speed.parse::<f32>(), heading.knots) {
+        (Ok(speed), Some(true)) => Some(speed * KNOT),
+        (Ok(speed), Some(false)) => Some(speed),
+        _ => None,
+    };
+    report.wind_direction = match wind[1..3].parse::<u16>() {
+        Ok(99) => None,
+        Ok(tens) if tens <= 36 => Some(tens * 10),
+        _ => None,
+    };
+
+    for group in rest.iter().take_while(|g| is_group(g) && !g.starts_with("222")) {
+        match &group[0..1] {
+            "1" => report.temperature = parse_signed_tenths(&group[1..5]),
+            "2" if group.starts_with("29") => report.relative_humidity = group[2..5].parse().ok().filter(|rh| *rh <= 100.0),
+            "2" => report.dew_point = parse_signed_tenths(&group[1..5]),
+            "3" => report.station_pressure = parse_pressure(&group[1..5]),
+            // 4a3hhh, the geopotential of a standard level, if it does not start with 0 or 9
+            "4" if matches!(&group[1..2], "0" | "9") => report.sea_level_pressure = parse_pressure(&group[1..5]),
+            _ => (),
+        }
+    }
+    Some(report)
+}
+
+/**
+ * Time given by an archive prefix like `08221,2023,08,10,12,00,` before
+ * `AAXX`, returning it with the rest of the token.
+ */
+fn split_archive_prefix(token: &str) -> Option<(DateTime<Utc>, &str)> {
+    let parts: Vec<&str> = token.splitn(7, ',').collect();
+    let [_, year, month, day, hour, minute, rest] = parts[..] else { return None };
+    let time = NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)?
+        .and_hms_opt(hour.parse().ok()?, minute.parse().ok()?, 0)?
+        .and_utc();
+    Some((time, rest))
+}
+
+/**
+ * Decodes the `=` terminated land station reports of SYNOP bulletins or
+ * archives. A report belongs to the latest `AAXX YYGGiw` heading; as it
+ * gives no month, reports are dated not later than an hour after
+ * `reference` unless an archive prefix gives the full time. NIL reports are
+ * left out.
+ */
+pub fn decode_synop(text: &str, reference: DateTime<Utc>) -> Vec<SynopReport> {
+    let mut heading: Option<Heading> = None;
+    let mut reports = vec![];
+    for chunk in text.split('=') {
+        let mut tokens: Vec<&str> = chunk.split_whitespace().collect();
+        let mut archive_time = None;
+        if let Some((time, rest)) = tokens.first().and_then(|t| split_archive_prefix(t)) {
+            archive_time = Some(time);
+            tokens[0] = rest;
+        }
+        if let Some(position) = tokens.iter().position(|t| *t == "AAXX") {
+            heading = tokens.get(position + 1).and_then(|g| parse_heading(g));
+            tokens.drain(..(position + 2).min(tokens.len()));
+        }
+        let heading = match heading {
+            Some(h) if !tokens.is_empty() && !tokens.contains(&"NIL") => h,
+            _ => continue,
+        };
+        let observation_time = match archive_time.or_else(|| resolve_report_time(heading.day, heading.hour, 0, reference)) {
+            Some(t) => t,
+            None => continue,
+        };
+        if let Some(report) = decode_report(&tokens, heading, observation_time) {
+            reports.push(report);
+        }
+    }
+    reports
+}
+
+/**
+ * Converts the reports of stations in the table into stations and
+ * observations. Humidity is the reported one or computed from the dew
+ * point; pressure and wind are not stored. Reports of unknown stations or
+ * without temperature or humidity are skipped.
+ */
+pub fn read_synop(text: &str, stations: &HashMap<String, Station>, reference: DateTime<Utc>) -> MeteoData {
+    let mut known: HashMap<String, Station> = HashMap::new();
+    let mut observations = vec![];
+    let mut skipped_observations = 0;
+    for report in decode_synop(text, reference) {
+        let station = match stations.get(&report.station) {
+            Some(s) => s,
+            None => {
+                println!("Unknown SYNOP station {}", report.station);
+                skipped_observations += 1;
+                continue;
+            }
+        };
+        let humidity = report.relative_humidity.or_else(|| Some(calculate_relative_humidity(report.temperature?, report.dew_point?)));
+        match (report.temperature, humidity) {
+            (Some(temperature), Some(humidity)) => observations.push(Observation {
+                station_id: station.id.clone(),
+                observation_time: report.observation_time,
+                aerial_temperature: temperature,
+                relative_humidity: humidity,
+            }),
+            _ => skipped_observations += 1,
+        }
+        known.insert(station.id.clone(), station.clone());
+    }
+    let mut stations: Vec<Station> = known.into_values().collect();
+    stations.sort_by(|a, b| a.id.cmp(&b.id));
+    MeteoData { stations, observations, skipped_observations }
+}
+
+/// Reads `--file <path> [--reference <time>]`, the reference defaulting to now.
+pub fn from_args(args: &[String]) -> Result<(PathBuf, DateTime<Utc>), Error> {
+    let values = parse_args(args)?;
+    let path = values.get("file").ok_or_else(|| invalid_input("Missing --file".to_string()))?;
+    let reference = match values.get("reference") {
+        Some(r) => parse_observation_time(r).map_err(|_| invalid_input(format!("{} is not an ISO-8601 time", r)))?,
+        None => Utc::now(),
+    };
+    Ok((PathBuf::from(path), reference))
+}
+
+/// Reads a SYNOP file, locating stations by the table at `WHEATR_SYNOP_STATIONS_PATH` or the bundled one.
+pub fn load_file(path: &Path, reference: DateTime<Utc>) -> Result<MeteoData, Error> {
+    let text = fs::read_to_string(path).map_err(|err| Error::other(format!("Opening {} failed: {}", path.display(), err)))?;
+    let stations = load_station_table(ENV_STATIONS_PATH, STATIONS_CSV, PROVIDER);
+    Ok(read_synop(&text, &stations, reference))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connectors::station_table::read_station_table;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        parse_observation_time(value).unwrap()
+    }
+
+    #[test]
+    fn decodes_section_1() {
+        let bulletin = "SMES01 LEMM 101200\nAAXX 10124\n08221 32970 71508 10285 20102 39421 40152 57010 333 10312=\n\
+                        08181 12965 19999 00105 11023 29085 30153 49981=\n08160 NIL=";
+        let reports = decode_synop(bulletin, time("2023-08-20T00:00:00Z"));
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(
+            reports[0],
+            SynopReport {
+                station: "08221".to_string(),
+                observation_time: time("2023-08-10T12:00:00Z"),
+                temperature: Some(28.5),
+                dew_point: Some(10.2),
+                relative_humidity: None,
+                station_pressure: Some(942.1),
+                sea_level_pressure: Some(1015.2),
+                wind_direction: Some(150),
+                wind_speed: Some(8.0 * KNOT),
+            }
+        );
+        let gale = &reports[1];
+        assert_eq!((gale.wind_direction, gale.wind_speed), (None, Some(105.0 * KNOT)));
+        assert_eq!((gale.temperature, gale.relative_humidity), (Some(-2.3), Some(85.0)));
+        assert_eq!((gale.station_pressure, gale.sea_level_pressure), (Some(1015.3), Some(998.1)));
+
+        // groups with multi-byte characters are not sliced
+        let garbled = decode_synop("AAXX é124\n08221 32970 71508=\nAAXX 10124\n08221 32970 71é0=\n08221 32970 71508 1é28 20102=", time("2023-08-20T00:00:00Z"));
+        assert_eq!(garbled.len(), 1);
+        assert_eq!((garbled[0].temperature, garbled[0].dew_point), (None, None));
+    }
+
+    #[test]
+    fn replays_archives_into_observations() {
+        let stations = read_station_table(STATIONS_CSV, PROVIDER).unwrap();
+        let archive = "08221,2021,01,09,06,00,AAXX 09061 08221 41/// /0000 11052 21061 39312 40321=\n\
+                       99999,2021,01,09,06,00,AAXX 09061 99999 41/// /0000 10010 20000=\n\
+                       08222,2021,01,09,06,00,AAXX 09061 08222 41/// /0000 11040=";
+        let data = read_synop(archive, &stations, Utc::now());
+
+        let ids: Vec<&str> = data.stations.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["08221", "08222"]);
+        assert_eq!(data.observations.len(), 1);
+        assert_eq!(data.observations[0].observation_time, time("2021-01-09T06:00:00Z"));
+        assert_eq!(data.observations[0].aerial_temperature, -5.2);
+        assert_eq!(data.skipped_observations, 2);
+    }
+}
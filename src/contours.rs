@@ -0,0 +1,343 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::{json, Value};
+
+use crate::{
+    grid::{FieldGrid, FieldVariable, GriddedField},
+    met::Location,
+    territory::{ring_contains, Territory},
+};
+
+/// Most levels accepted in a single contour request.
+pub const MAX_CONTOUR_LEVELS: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContourGeometry {
+    /// Isolines at each level.
+    Lines,
+    /// Areas at or above each level, with holes where the value drops below it.
+    Polygons,
+}
+
+impl ContourGeometry {
+    pub fn from_name(name: &str) -> Option<ContourGeometry> {
+        match name {
+            "lines" => Some(ContourGeometry::Lines),
+            "polygons" => Some(ContourGeometry::Polygons),
+            _ => None,
+        }
+    }
+}
+
+/// Levels of the NOAA heat index categories and round temperature and humidity steps.
+pub fn default_levels(variable: FieldVariable) -> Vec<f32> {
+    match variable {
+        FieldVariable::Temperature => vec![0.0, 10.0, 20.0, 30.0, 40.0],
+        FieldVariable::Humidity => vec![20.0, 40.0, 60.0, 80.0],
+        FieldVariable::HeatIndex => vec![27.0, 32.0, 41.0, 54.0],
+    }
+}
+
+/// Edge between two grid nodes, lower node id first.
+type EdgeKey = (i64, i64);
+
+/// Points of a line or ring, in grid or (lon, lat) coordinates.
+type Line = Vec<(f32, f32)>;
+
+/**
+ * Grid values with missing nodes and, for polygons, a border of nodes
+ * around the grid taken as lower than any level, so every contour is closed.
+ */
+struct Lattice<'a> {
+    values: &'a [f32],
+    rows: i64,
+    cols: i64,
+    padded: bool,
+}
+
+impl Lattice<'_> {
+    fn value(&self, row: i64, col: i64) -> f32 {
+        if row < 0 || col < 0 || row >= self.rows || col >= self.cols {
+            return f32::NEG_INFINITY;
+        }
+        let value = self.values[(row * self.cols + col) as usize];
+        if value.is_nan() {
+            f32::NEG_INFINITY
+        } else {
+            value
+        }
+    }
+
+    fn node(&self, row: i64, col: i64) -> i64 {
+        (row + 1) * (self.cols + 2) + col + 1
+    }
+
+    fn position(&self, node: i64) -> (f32, f32) {
+        ((node % (self.cols + 2) - 1) as f32, (node / (self.cols + 2) - 1) as f32)
+    }
+
+    /**
+     * Where the level crosses the edge, in (col, row) grid coordinates.
+     * Always interpolated from the lower node, so neighbouring cells get
+     * the very same point.
+     */
+    fn crossing(&self, edge: EdgeKey, level: f32) -> (f32, f32) {
+        let (a, b) = (self.position(edge.0), self.position(edge.1));
+        let (va, vb) = (self.value(a.1 as i64, a.0 as i64), self.value(b.1 as i64, b.0 as i64));
+        let t = if va.is_finite() && vb.is_finite() { (level - va) / (vb - va) } else { 0.5 };
+        (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+    }
+
+    /**
+     * Marching squares: contour segments of every cell, keyed by the edge
+     * they start on and oriented with the values at or above the level on
+     * their left. Saddles are resolved by the mean of the cell.
+     */
+    fn segments(&self, level: f32) -> BTreeMap<EdgeKey, EdgeKey> {
+        let mut segments = BTreeMap::new();
+        let start = if self.padded { -1 } else { 0 };
+        for row in start..self.rows {
+            for col in start..self.cols {
+                // corners counter-clockwise from the south-west one
+                let corners = [(row, col), (row, col + 1), (row + 1, col + 1), (row + 1, col)];
+                let values = corners.map(|(r, c)| self.value(r, c));
+                if !self.padded && values.iter().any(|v| !v.is_finite()) {
+                    continue;
+                }
+                let mut crossings: Vec<(EdgeKey, bool)> = Vec::with_capacity(4);
+                for i in 0..4 {
+                    let j = (i + 1) % 4;
+                    let (inside, next_inside) = (values[i] >= level, values[j] >= level);
+                    if inside != next_inside {
+                        let (a, b) = (self.node(corners[i].0, corners[i].1), self.node(corners[j].0, corners[j].1));
+                        // leaving the area when walking around the cell
+                        crossings.push(((a.min(b), a.max(b)), inside));
+                    }
+                }
+                let connected = values.iter().sum::<f32>() / 4.0 >= level;
+                for (i, &(edge, exit)) in crossings.iter().enumerate() {
+                    if exit {
+                        let n = crossings.len();
+                        let entry = if connected { crossings[(i + 1) % n].0 } else { crossings[(i + n - 1) % n].0 };
+                        segments.insert(edge, entry);
+                    }
+                }
+            }
+        }
+        segments
+    }
+
+    /// Joins the segments into lines of (col, row) points, rings closed.
+    fn trace(&self, level: f32) -> Vec<Line> {
+        let mut segments = self.segments(level);
+        let ends: BTreeSet<EdgeKey> = segments.values().copied().collect();
+        let starts: Vec<EdgeKey> = segments.keys().filter(|edge| !ends.contains(edge)).copied().collect();
+        let mut lines = Vec::new();
+        for start in starts {
+            lines.push(self.follow(&mut segments, start, level));
+        }
+        while let Some(&start) = segments.keys().next() {
+            let mut ring = self.follow(&mut segments, start, level);
+            ring.push(ring[0]);
+            lines.push(ring);
+        }
+        lines
+    }
+
+    fn follow(&self, segments: &mut BTreeMap<EdgeKey, EdgeKey>, start: EdgeKey, level: f32) -> Line {
+        let mut line = vec![self.crossing(start, level)];
+        let mut edge = start;
+        while let Some(next) = segments.remove(&edge) {
+            if next != start {
+                line.push(self.crossing(next, level));
+            }
+            edge = next;
+        }
+        line
+    }
+}
+
+fn signed_area(ring: &[(f32, f32)]) -> f32 {
+    ring.windows(2).map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1).sum::<f32>() / 2.0
+}
+
+/**
+ * Groups rings into polygons: counter-clockwise rings are outlines,
+ * clockwise ones are holes of the smallest outline containing them.
+ */
+fn assemble_polygons(rings: Vec<Line>) -> Vec<Vec<Line>> {
+    let (outlines, holes): (Vec<_>, Vec<_>) = rings.into_iter().partition(|ring| signed_area(ring) > 0.0);
+    let mut polygons: Vec<(f32, Vec<Line>)> = outlines.into_iter().map(|r| (signed_area(&r), vec![r])).collect();
+    for hole in holes {
+        let loc = Location { lon: hole[0].0, lat: hole[0].1 };
+        let outline = polygons
+            .iter_mut()
+            .filter(|(_, rings)| ring_contains(&rings[0], &loc))
+            .min_by(|(a, _), (b, _)| a.total_cmp(b));
+        if let Some((_, rings)) = outline {
+            rings.push(hole);
+        }
+    }
+    polygons.into_iter().map(|(_, rings)| rings).collect()
+}
+
+fn to_lon_lat(grid: &FieldGrid, line: Line) -> Line {
+    let (lon_step, lat_step) = (
+        (grid.bbox.max_lon - grid.bbox.min_lon) / (grid.cols - 1) as f32,
+        (grid.bbox.max_lat - grid.bbox.min_lat) / (grid.rows - 1) as f32,
+    );
+    line.into_iter()
+        .map(|(col, row)| (grid.bbox.min_lon + col * lon_step, grid.bbox.min_lat + row * lat_step))
+        .collect()
+}
+
+fn coordinates(line: &[(f32, f32)]) -> Value {
+    json!(line.iter().map(|(lon, lat)| [lon, lat]).collect::<Vec<_>>())
+}
+
+/**
+ * Contours of a variable of the field at each level as a GeoJSON
+ * FeatureCollection, one MultiLineString or MultiPolygon feature per level.
+ * Nodes outside the territory are left out, so contours follow the coast
+ * and the borders at grid resolution.
+ */
+pub fn get_contours(
+    field: &GriddedField,
+    territory: &Territory,
+    variable: FieldVariable,
+    levels: &[f32],
+    geometry: ContourGeometry,
+) -> Value {
+    let masked: Vec<(&FieldGrid, Vec<f32>)> = field
+        .grids
+        .iter()
+        .filter(|grid| grid.rows > 1 && grid.cols > 1)
+        .map(|grid| {
+            let resolution = ((grid.bbox.max_lon - grid.bbox.min_lon) / (grid.cols - 1) as f32, (grid.bbox.max_lat - grid.bbox.min_lat) / (grid.rows - 1) as f32);
+            let values = grid
+                .values(variable)
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    let loc = Location {
+                        lat: grid.bbox.min_lat + (i / grid.cols) as f32 * resolution.1,
+                        lon: grid.bbox.min_lon + (i % grid.cols) as f32 * resolution.0,
+                    };
+                    if territory.contains(&loc) { *value } else { f32::NAN }
+                })
+                .collect();
+            (grid, values)
+        })
+        .collect();
+    let features: Vec<Value> = levels
+        .iter()
+        .map(|&level| {
+            let lines: Vec<Line> = masked
+                .iter()
+                .flat_map(|(grid, values)| {
+                    let lattice = Lattice {
+                        values,
+                        rows: grid.rows as i64,
+                        cols: grid.cols as i64,
+                        padded: geometry == ContourGeometry::Polygons,
+                    };
+                    lattice.trace(level).into_iter().map(|line| to_lon_lat(grid, line))
+                })
+                .collect();
+            let geometry = match geometry {
+                ContourGeometry::Lines => json!({
+                    "type": "MultiLineString",
+                    "coordinates": lines.iter().map(|line| coordinates(line)).collect::<Vec<_>>(),
+                }),
+                ContourGeometry::Polygons => json!({
+                    "type": "MultiPolygon",
+                    "coordinates": assemble_polygons(lines)
+                        .iter()
+                        .map(|rings| rings.iter().map(|ring| coordinates(ring)).collect::<Vec<_>>())
+                        .collect::<Vec<_>>(),
+                }),
+            };
+            json!({ "type": "Feature", "geometry": geometry, "properties": { "level": level } })
+        })
+        .collect();
+    json!({
+        "type": "FeatureCollection",
+        "variable": variable.name(),
+        "observation_time": field.observation_time,
+        "features": features,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::met::BoundingBox;
+
+    /// 7x7 grid of 1 degree from 0,0 with `values` given row by row from the south.
+    fn field(values: Vec<f32>) -> GriddedField {
+        GriddedField {
+            resolution: 1.0,
+            observation_time: Utc::now(),
+            generated_at: Utc::now(),
+            grids: vec![FieldGrid {
+                region: "test".to_string(),
+                bbox: BoundingBox { min_lat: 0.0, min_lon: 0.0, max_lat: 6.0, max_lon: 6.0 },
+                rows: 7,
+                cols: 7,
+                temperature: vec![],
+                humidity: vec![],
+                heat_index: values,
+            }],
+        }
+    }
+
+    fn everywhere() -> Territory {
+        Territory::from_geojson(r#"{"type":"Polygon","coordinates":[[[-1,-1],[7,-1],[7,7],[-1,7],[-1,-1]]]}"#).unwrap()
+    }
+
+    #[test]
+    fn ring_around_a_peak_and_a_hole() {
+        // a ring of 40 around a centre of 30 on a background of 20
+        let values: Vec<f32> = (0..49)
+            .map(|i: i32| match (i / 7 - 3).abs().max((i % 7 - 3).abs()) {
+                0 => 30.0,
+                1 => 40.0,
+                _ => 20.0,
+            })
+            .collect();
+        let field = field(values);
+
+        let lines = get_contours(&field, &everywhere(), FieldVariable::HeatIndex, &[35.0], ContourGeometry::Lines);
+        let lines = lines["features"][0]["geometry"]["coordinates"].as_array().unwrap();
+        // the outer and the inner edge of the ring, both closed
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line[0] == line[line.as_array().unwrap().len() - 1]));
+
+        let polygons = get_contours(&field, &everywhere(), FieldVariable::HeatIndex, &[35.0, 50.0], ContourGeometry::Polygons);
+        let polygon = &polygons["features"][0]["geometry"]["coordinates"];
+        assert_eq!(polygon.as_array().unwrap().len(), 1);
+        assert_eq!(polygon[0].as_array().unwrap().len(), 2);
+        // the outline crosses from the nodes of 20 three quarters of the way to those of 40
+        let outline: Vec<(f32, f32)> = polygon[0][0]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| (p[0].as_f64().unwrap() as f32, p[1].as_f64().unwrap() as f32))
+            .collect();
+        assert!((signed_area(&outline) - 6.125).abs() < 0.001);
+        assert_eq!(polygons["features"][1]["geometry"]["coordinates"], json!([]));
+    }
+
+    #[test]
+    fn polygons_are_closed_at_the_territory_border() {
+        let field = field(vec![30.0; 49]);
+        let west = Territory::from_geojson(r#"{"type":"Polygon","coordinates":[[[-1,-1],[3.5,-1],[3.5,7],[-1,7],[-1,-1]]]}"#).unwrap();
+        let polygons = get_contours(&field, &west, FieldVariable::HeatIndex, &[27.0], ContourGeometry::Polygons);
+        let polygon = &polygons["features"][0]["geometry"]["coordinates"];
+        assert_eq!(polygon.as_array().unwrap().len(), 1);
+        let lons: Vec<f64> = polygon[0][0].as_array().unwrap().iter().map(|p| p[0].as_f64().unwrap()).collect();
+        assert!(lons.iter().all(|lon| (-0.5..=3.5).contains(lon)));
+    }
+}
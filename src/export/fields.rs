@@ -0,0 +1,339 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Error, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::{
+    config::{invalid_input, parse_args},
+    connectors::store::{ProviderFilter, Store},
+    grid::{generate_grid, grid_shape, FieldGrid, FieldVariable, SPAIN_REGIONS},
+    met::{parse_observation_time, BoundingBox, Station},
+    snapshot::ObservationSnapshot,
+};
+
+use super::{
+    geotiff,
+    netcdf::{Dimension, Header, NcType, NetCdfWriter, Values, Variable},
+};
+
+/// Longest time range exported at once.
+pub const MAX_EXPORT_DAYS: i64 = 366;
+/// EPSG:4326 as OGC WKT, for the `crs_wkt` attribute of the grid mapping.
+const WGS84_WKT: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],\
+    PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],\
+    AXIS[\"Latitude\",NORTH],AXIS[\"Longitude\",EAST],AUTHORITY[\"EPSG\",\"4326\"]]";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    GeoTiff,
+    NetCdf,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub region: String,
+    pub resolution: f32,
+    pub output: PathBuf,
+}
+
+impl ExportOptions {
+    /**
+     * Reads `--format geotiff|netcdf --from <time> [--to <time>] [--region
+     * <name>] [--resolution <degrees>] --output <path>`. A single hour is
+     * exported if `--to` is missing.
+     */
+    pub fn from_args(args: &[String], default_resolution: f32) -> Result<ExportOptions, Error> {
+        let values = parse_args(args)?;
+        let required = |name: &str| values.get(name).map(String::as_str).ok_or_else(|| invalid_input(format!("Missing --{}", name)));
+        let time = |value: &str| parse_observation_time(value).map_err(|_| invalid_input(format!("{} is not an ISO-8601 time", value)));
+
+        let format = match required("format")? {
+            "geotiff" => ExportFormat::GeoTiff,
+            "netcdf" => ExportFormat::NetCdf,
+            other => return Err(invalid_input(format!("Unknown format: {}, use geotiff or netcdf", other))),
+        };
+        let from = time(required("from")?)?;
+        let to = match values.get("to") {
+            Some(to) => time(to)?,
+            None => from,
+        };
+        let region = values.get("region").map(String::as_str).unwrap_or("peninsula").to_string();
+        let resolution = match values.get("resolution") {
+            Some(r) => r.parse().ok().filter(|r: &f32| *r > 0.0).ok_or_else(|| invalid_input(format!("Invalid resolution: {}", r)))?,
+            None => default_resolution,
+        };
+        Ok(ExportOptions { format, from, to, region, resolution, output: PathBuf::from(required("output")?) })
+    }
+
+    fn region_bbox(&self) -> Result<&'static BoundingBox, Error> {
+        SPAIN_REGIONS.iter().find(|(name, _)| *name == self.region).map(|(_, bbox)| bbox).ok_or_else(|| {
+            let names: Vec<&str> = SPAIN_REGIONS.iter().map(|(name, _)| *name).collect();
+            invalid_input(format!("Unknown region: {}, use one of {}", self.region, names.join(", ")))
+        })
+    }
+
+    /// Whole hours in the time range.
+    fn hours(&self) -> Result<Vec<DateTime<Utc>>, Error> {
+        if self.from > self.to {
+            return Err(invalid_input("from is later than to".to_string()));
+        }
+        if self.to - self.from > Duration::days(MAX_EXPORT_DAYS) {
+            return Err(invalid_input(format!("Time range is longer than {} days", MAX_EXPORT_DAYS)));
+        }
+        let first_hour = self.from.duration_trunc(Duration::hours(1)).map_err(Error::other)?;
+        let mut hour = if first_hour < self.from { first_hour + Duration::hours(1) } else { first_hour };
+        let mut hours = vec![];
+        while hour <= self.to {
+            hours.push(hour);
+            hour += Duration::hours(1);
+        }
+        if hours.is_empty() {
+            return Err(invalid_input("No whole hour in the time range".to_string()));
+        }
+        Ok(hours)
+    }
+}
+
+/**
+ * Interpolates the region at every hour from the observations of the hour
+ * before, the same way as the gridded field, and passes the grids on.
+ * Stations are taken at their position at the hour. Hours without
+ * observations give grids without values.
+ */
+fn for_each_hour(
+    store: &dyn Store,
+    options: &ExportOptions,
+    hours: &[DateTime<Utc>],
+    mut write: impl FnMut(DateTime<Utc>, &FieldGrid) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let bbox = options.region_bbox()?;
+    let (Some(first_hour), Some(last_hour)) = (hours.first(), hours.last()) else { return Ok(()) };
+    let periods = store.get_station_periods(*first_hour, *last_hour, &ProviderFilter::default())?;
+    for hour in hours {
+        let stations: Vec<Station> = periods.iter().filter(|p| p.is_valid_at(*hour)).map(|p| p.station.clone()).collect();
+        let snapshot = ObservationSnapshot::load_at(store, &stations, *hour)?;
+        if snapshot.get_observation_time().is_none() {
+            println!("No observations before {}", hour);
+        }
+        write(*hour, &generate_grid(&snapshot, &options.region, bbox, options.resolution))?;
+    }
+    Ok(())
+}
+
+fn coordinate_variable(name: &str, dimension: usize, standard_name: &str, units: &str, axis: &str) -> Variable {
+    Variable {
+        name: name.to_string(),
+        dimensions: vec![dimension],
+        nc_type: NcType::Double,
+        attributes: vec![
+            ("standard_name".to_string(), Values::Text(standard_name.to_string())),
+            ("units".to_string(), Values::Text(units.to_string())),
+            ("axis".to_string(), Values::Text(axis.to_string())),
+        ],
+    }
+}
+
+fn field_variable(name: &str, standard_name: &str, long_name: &str, units: &str) -> Variable {
+    Variable {
+        name: name.to_string(),
+        dimensions: vec![0, 1, 2],
+        nc_type: NcType::Float,
+        attributes: vec![
+            ("standard_name".to_string(), Values::Text(standard_name.to_string())),
+            ("long_name".to_string(), Values::Text(long_name.to_string())),
+            ("units".to_string(), Values::Text(units.to_string())),
+            ("_FillValue".to_string(), Values::Float(vec![f32::NAN])),
+            ("grid_mapping".to_string(), Values::Text("crs".to_string())),
+        ],
+    }
+}
+
+/**
+ * CF-1.8 layout of the hourly fields: `time` (unlimited), `lat` and `lon`
+ * dimensions with their coordinate variables, a `crs` grid mapping and the
+ * three fields.
+ */
+fn netcdf_header(region: &str, rows: usize, cols: usize, now: DateTime<Utc>) -> Header {
+    let text = |name: &str, value: &str| (name.to_string(), Values::Text(value.to_string()));
+    Header {
+        dimensions: vec![
+            Dimension { name: "time".to_string(), len: None },
+            Dimension { name: "lat".to_string(), len: Some(rows) },
+            Dimension { name: "lon".to_string(), len: Some(cols) },
+        ],
+        attributes: vec![
+            text("Conventions", "CF-1.8"),
+            text("title", &format!("Wheatr hourly interpolated fields ({})", region)),
+            text("source", "Interpolated on the plane of the three closest weather stations"),
+            text("history", &format!("{} created by wheatr-server", now.to_rfc3339())),
+        ],
+        variables: vec![
+            coordinate_variable("lat", 1, "latitude", "degrees_north", "Y"),
+            coordinate_variable("lon", 2, "longitude", "degrees_east", "X"),
+            Variable {
+                name: "crs".to_string(),
+                dimensions: vec![],
+                nc_type: NcType::Int,
+                attributes: vec![
+                    text("grid_mapping_name", "latitude_longitude"),
+                    ("longitude_of_prime_meridian".to_string(), Values::Double(vec![0.0])),
+                    ("semi_major_axis".to_string(), Values::Double(vec![6378137.0])),
+                    ("inverse_flattening".to_string(), Values::Double(vec![298.257223563])),
+                    text("crs_wkt", WGS84_WKT),
+                ],
+            },
+            Variable {
+                name: "time".to_string(),
+                dimensions: vec![0],
+                nc_type: NcType::Double,
+                attributes: vec![
+                    text("standard_name", "time"),
+                    text("units", "hours since 1970-01-01 00:00:00"),
+                    text("calendar", "standard"),
+                    text("axis", "T"),
+                ],
+            },
+            field_variable("air_temperature", "air_temperature", "Air temperature", "degC"),
+            field_variable("relative_humidity", "relative_humidity", "Relative humidity", "%"),
+            field_variable("heat_index", "heat_index_of_air_temperature", "Heat index", "degC"),
+        ],
+    }
+}
+
+/// Writes the hourly fields of the region as a single NetCDF file.
+pub fn write_netcdf<W: Write>(writer: W, store: &dyn Store, options: &ExportOptions) -> Result<W, Error> {
+    let hours = options.hours()?;
+    let (bbox, rows, cols) = grid_shape(options.region_bbox()?, options.resolution);
+    let mut netcdf = NetCdfWriter::create(writer, netcdf_header(&options.region, rows, cols, Utc::now()), hours.len())?;
+    let step = options.resolution as f64;
+    netcdf.write_variable(&Values::Double((0..rows).map(|r| bbox.min_lat as f64 + r as f64 * step).collect()))?;
+    netcdf.write_variable(&Values::Double((0..cols).map(|c| bbox.min_lon as f64 + c as f64 * step).collect()))?;
+    netcdf.write_variable(&Values::Int(vec![0]))?;
+    for_each_hour(store, options, &hours, |hour, grid| {
+        netcdf.write_record(&[
+            Values::Double(vec![hour.timestamp() as f64 / 3600.0]),
+            Values::Float(grid.temperature.clone()),
+            Values::Float(grid.humidity.clone()),
+            Values::Float(grid.heat_index.clone()),
+        ])
+    })?;
+    netcdf.finish()
+}
+
+/// `fields.tif` becomes `fields_hi_20230810T1000Z.tif`.
+fn geotiff_path(output: &Path, variable: FieldVariable, hour: DateTime<Utc>) -> PathBuf {
+    let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("fields");
+    output.with_file_name(format!("{}_{}_{}.tif", stem, variable.name(), hour.format("%Y%m%dT%H%MZ")))
+}
+
+/**
+ * Exports the hourly fields, as one NetCDF file or one GeoTIFF per hour
+ * and variable, and returns the paths written.
+ */
+pub fn export_fields(store: &dyn Store, options: &ExportOptions) -> Result<Vec<PathBuf>, Error> {
+    match options.format {
+        ExportFormat::NetCdf => {
+            let file = File::create(&options.output)?;
+            write_netcdf(BufWriter::new(file), store, options)?;
+            Ok(vec![options.output.clone()])
+        }
+        ExportFormat::GeoTiff => {
+            let hours = options.hours()?;
+            let mut paths = vec![];
+            for_each_hour(store, options, &hours, |hour, grid| {
+                for variable in [FieldVariable::Temperature, FieldVariable::Humidity, FieldVariable::HeatIndex] {
+                    let path = geotiff_path(&options.output, variable, hour);
+                    geotiff::write_geotiff(BufWriter::new(File::create(&path)?), grid, variable)?;
+                    paths.push(path);
+                }
+                Ok(())
+            })?;
+            Ok(paths)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use tiff::{
+        decoder::{Decoder, DecodingResult},
+        tags::Tag,
+    };
+
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{testing::{observation, station}, MeteoData},
+    };
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn export_hourly_fields() {
+        let store = MemoryStore::default();
+        let meteo_data = MeteoData {
+            stations: vec![station("A", 35.26, -2.97), station("B", 35.32, -2.97), station("C", 35.26, -2.91)],
+            observations: vec![
+                observation("A", "2023-08-10T09:00:00", 20.0),
+                observation("B", "2023-08-10T09:00:00", 20.0),
+                observation("C", "2023-08-10T09:00:00", 20.0),
+                observation("A", "2023-08-10T10:00:00", 30.0),
+                observation("B", "2023-08-10T10:00:00", 30.0),
+                observation("C", "2023-08-10T10:00:00", 30.0),
+            ],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+        // moved onto A after the exported hours, which still use its former position
+        store.upsert_stations(&[station("C", 35.26, -2.97)], parse_observation_time("2023-08-10T11:30:00").unwrap()).unwrap();
+        let options = ExportOptions::from_args(
+            &args("--format netcdf --from 2023-08-10T08:30:00Z --to 2023-08-10T11:00:00Z --region melilla --resolution 0.02 --output x.nc"),
+            0.05,
+        )
+        .unwrap();
+        assert_eq!(options.hours().unwrap().len(), 3);
+
+        let (bbox, rows, cols) = grid_shape(options.region_bbox().unwrap(), 0.02);
+        let netcdf = write_netcdf(Cursor::new(vec![]), &store, &options).unwrap().into_inner();
+        assert_eq!(&netcdf[..4], b"CDF\x02");
+        assert!(netcdf.windows(WGS84_WKT.len()).any(|w| w == WGS84_WKT.as_bytes()));
+        assert_eq!(u32::from_be_bytes(netcdf[4..8].try_into().unwrap()), 3);
+        let record_len = 8 + 3 * rows * cols * 4;
+        let last_record = &netcdf[netcdf.len() - record_len..];
+        assert_eq!(f64::from_be_bytes(last_record[..8].try_into().unwrap()), 1691665200.0 / 3600.0);
+        // at 11:00 the 10:00 observations are used
+        assert_eq!(f32::from_be_bytes(last_record[8..12].try_into().unwrap()), 30.0);
+
+        let dir = std::env::temp_dir().join(format!("wheatr-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let options = ExportOptions {
+            format: ExportFormat::GeoTiff,
+            from: parse_observation_time("2023-08-10T09:00:00Z").unwrap(),
+            output: dir.join("fields.tif"),
+            ..options
+        };
+        let options = ExportOptions { to: options.from, ..options };
+        let paths = export_fields(&store, &options).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[2].file_name().unwrap(), "fields_hi_20230810T0900Z.tif");
+        let mut decoder = Decoder::new(File::open(&paths[0]).unwrap()).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (cols as u32, rows as u32));
+        let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).unwrap();
+        assert!((tiepoint[3] + 2.98).abs() < 0.0001 && (tiepoint[4] - bbox.max_lat as f64 - 0.01).abs() < 0.0001);
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32(values) => assert!(values.iter().all(|v| *v == 20.0)),
+            _ => panic!("not a float image"),
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
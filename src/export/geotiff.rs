@@ -0,0 +1,56 @@
+use std::io::{Error, Seek, Write};
+
+use tiff::{
+    encoder::{colortype::Gray32Float, compression::Deflate, TiffEncoder},
+    tags::Tag,
+};
+
+use crate::grid::{FieldGrid, FieldVariable};
+
+/**
+ * GeoKey directory of a geographic EPSG:4326 raster whose pixels are areas:
+ * version 1.1.0, 3 keys of (id, location, count, value).
+ */
+const GEO_KEYS_EPSG_4326: [u16; 16] = [
+    1, 1, 0, 3, //
+    1024, 0, 1, 2, // GTModelTypeGeoKey: geographic
+    1025, 0, 1, 1, // GTRasterTypeGeoKey: pixel is area
+    2048, 0, 1, 4326, // GeographicTypeGeoKey
+];
+
+fn tiff_error(err: tiff::TiffError) -> Error {
+    Error::other(format!("GeoTIFF writing failed: {}", err))
+}
+
+/**
+ * Writes a variable of the grid as a single band float GeoTIFF in
+ * EPSG:4326, north up. Every node is the centre of a pixel, nodes without
+ * value are NaN, which is also set as the no-data value.
+ */
+pub fn write_geotiff<W: Write + Seek>(writer: W, grid: &FieldGrid, variable: FieldVariable) -> Result<(), Error> {
+    let lon_step = (grid.bbox.max_lon - grid.bbox.min_lon) as f64 / (grid.cols - 1).max(1) as f64;
+    let lat_step = (grid.bbox.max_lat - grid.bbox.min_lat) as f64 / (grid.rows - 1).max(1) as f64;
+    let values = grid.values(variable);
+    let north_up: Vec<f32> = (0..grid.rows).rev().flat_map(|row| &values[row * grid.cols..(row + 1) * grid.cols]).copied().collect();
+
+    let mut tiff = TiffEncoder::new(writer).map_err(tiff_error)?;
+    let mut image = tiff
+        .new_image_with_compression::<Gray32Float, _>(grid.cols as u32, grid.rows as u32, Deflate::default())
+        .map_err(tiff_error)?;
+    let encoder = image.encoder();
+    encoder.write_tag(Tag::ModelPixelScaleTag, &[lon_step, lat_step, 0.0][..]).map_err(tiff_error)?;
+    // the north-west corner of the first pixel
+    let tiepoint = [
+        0.0,
+        0.0,
+        0.0,
+        grid.bbox.min_lon as f64 - lon_step / 2.0,
+        grid.bbox.max_lat as f64 + lat_step / 2.0,
+        0.0,
+    ];
+    encoder.write_tag(Tag::ModelTiepointTag, &tiepoint[..]).map_err(tiff_error)?;
+    encoder.write_tag(Tag::GeoKeyDirectoryTag, &GEO_KEYS_EPSG_4326[..]).map_err(tiff_error)?;
+    encoder.write_tag(Tag::GdalNodata, "nan").map_err(tiff_error)?;
+    encoder.write_tag(Tag::ImageDescription, variable.name()).map_err(tiff_error)?;
+    image.write_data(&north_up).map_err(tiff_error)
+}
@@ -0,0 +1,5 @@
+pub mod fields;
+pub mod geotiff;
+pub mod netcdf;
+pub mod stream;
+pub mod tables;
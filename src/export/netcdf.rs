@@ -0,0 +1,238 @@
+use std::io::{Error, ErrorKind, Write};
+
+const NC_DIMENSION: u32 = 0x0A;
+const NC_VARIABLE: u32 = 0x0B;
+const NC_ATTRIBUTE: u32 = 0x0C;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NcType {
+    Char,
+    Int,
+    Float,
+    Double,
+}
+
+impl NcType {
+    fn code(self) -> u32 {
+        match self {
+            NcType::Char => 2,
+            NcType::Int => 4,
+            NcType::Float => 5,
+            NcType::Double => 6,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            NcType::Char => 1,
+            NcType::Int | NcType::Float => 4,
+            NcType::Double => 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Values {
+    Text(String),
+    Int(Vec<i32>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl Values {
+    fn nc_type(&self) -> NcType {
+        match self {
+            Values::Text(_) => NcType::Char,
+            Values::Int(_) => NcType::Int,
+            Values::Float(_) => NcType::Float,
+            Values::Double(_) => NcType::Double,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Values::Text(v) => v.len(),
+            Values::Int(v) => v.len(),
+            Values::Float(v) => v.len(),
+            Values::Double(v) => v.len(),
+        }
+    }
+
+    /// Big-endian values padded to a multiple of 4 bytes.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = match self {
+            Values::Text(v) => v.as_bytes().to_vec(),
+            Values::Int(v) => v.iter().flat_map(|x| x.to_be_bytes()).collect(),
+            Values::Float(v) => v.iter().flat_map(|x| x.to_be_bytes()).collect(),
+            Values::Double(v) => v.iter().flat_map(|x| x.to_be_bytes()).collect(),
+        };
+        bytes.resize(padded(bytes.len()), 0);
+        bytes
+    }
+}
+
+pub struct Dimension {
+    pub name: String,
+    /// `None` for the unlimited (record) dimension.
+    pub len: Option<usize>,
+}
+
+pub struct Variable {
+    pub name: String,
+    /// Indexes of the dimensions, the record dimension first if any.
+    pub dimensions: Vec<usize>,
+    pub nc_type: NcType,
+    pub attributes: Vec<(String, Values)>,
+}
+
+pub struct Header {
+    pub dimensions: Vec<Dimension>,
+    pub attributes: Vec<(String, Values)>,
+    pub variables: Vec<Variable>,
+}
+
+fn padded(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend((value as u32).to_be_bytes());
+}
+
+fn put_name(bytes: &mut Vec<u8>, name: &str) {
+    put_u32(bytes, name.len());
+    bytes.extend(Values::Text(name.to_string()).to_bytes());
+}
+
+fn put_attributes(bytes: &mut Vec<u8>, attributes: &[(String, Values)]) {
+    if attributes.is_empty() {
+        bytes.extend([0; 8]);
+        return;
+    }
+    put_u32(bytes, NC_ATTRIBUTE as usize);
+    put_u32(bytes, attributes.len());
+    for (name, values) in attributes {
+        put_name(bytes, name);
+        put_u32(bytes, values.nc_type().code() as usize);
+        put_u32(bytes, values.len());
+        bytes.extend(values.to_bytes());
+    }
+}
+
+impl Header {
+    fn is_record(&self, variable: &Variable) -> bool {
+        variable.dimensions.first().is_some_and(|d| self.dimensions[*d].len.is_none())
+    }
+
+    /// Values of the variable in the file, or in a record for record variables.
+    fn value_count(&self, variable: &Variable) -> usize {
+        variable.dimensions.iter().filter_map(|d| self.dimensions[*d].len).product()
+    }
+
+    /// The header bytes, with the data offsets following a header of `header_len` bytes.
+    fn to_bytes(&self, numrecs: usize, header_len: usize) -> Vec<u8> {
+        let mut bytes = b"CDF\x02".to_vec();
+        put_u32(&mut bytes, numrecs);
+        put_u32(&mut bytes, NC_DIMENSION as usize);
+        put_u32(&mut bytes, self.dimensions.len());
+        for dimension in &self.dimensions {
+            put_name(&mut bytes, &dimension.name);
+            put_u32(&mut bytes, dimension.len.unwrap_or(0));
+        }
+        put_attributes(&mut bytes, &self.attributes);
+        put_u32(&mut bytes, NC_VARIABLE as usize);
+        put_u32(&mut bytes, self.variables.len());
+        let fixed_len: usize = self.variables.iter().filter(|v| !self.is_record(v)).map(|v| self.vsize(v)).sum();
+        let (mut fixed_offset, mut record_offset) = (header_len, header_len + fixed_len);
+        for variable in &self.variables {
+            put_name(&mut bytes, &variable.name);
+            put_u32(&mut bytes, variable.dimensions.len());
+            variable.dimensions.iter().for_each(|d| put_u32(&mut bytes, *d));
+            put_attributes(&mut bytes, &variable.attributes);
+            put_u32(&mut bytes, variable.nc_type.code() as usize);
+            put_u32(&mut bytes, self.vsize(variable));
+            let offset = if self.is_record(variable) { &mut record_offset } else { &mut fixed_offset };
+            bytes.extend((*offset as u64).to_be_bytes());
+            *offset += self.vsize(variable);
+        }
+        bytes
+    }
+
+    fn vsize(&self, variable: &Variable) -> usize {
+        padded(self.value_count(variable) * variable.nc_type.size())
+    }
+}
+
+/**
+ * Writer of NetCDF classic files in the 64-bit offset format (CDF-2), which
+ * every NetCDF reader supports without linking the NetCDF C library. Fixed
+ * size variables are written first, in the order they are declared, then
+ * the records of the variables along the unlimited dimension.
+ */
+pub struct NetCdfWriter<W: Write> {
+    writer: W,
+    header: Header,
+    numrecs: usize,
+    /// Fixed size variables written so far, then records.
+    fixed_written: usize,
+    records_written: usize,
+}
+
+impl<W: Write> NetCdfWriter<W> {
+    /// Writes the header of a file with `numrecs` records.
+    pub fn create(mut writer: W, header: Header, numrecs: usize) -> Result<NetCdfWriter<W>, Error> {
+        let header_len = header.to_bytes(numrecs, 0).len();
+        writer.write_all(&header.to_bytes(numrecs, header_len))?;
+        Ok(NetCdfWriter { writer, header, numrecs, fixed_written: 0, records_written: 0 })
+    }
+
+    fn check(&self, variable: &Variable, values: &Values) -> Result<(), Error> {
+        if values.nc_type() != variable.nc_type || values.len() != self.header.value_count(variable) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("NetCDF writing failed: {} values of {} do not fit", values.len(), variable.name),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the next fixed size variable.
+    pub fn write_variable(&mut self, values: &Values) -> Result<(), Error> {
+        let variable = self
+            .header
+            .variables
+            .iter()
+            .filter(|v| !self.header.is_record(v))
+            .nth(self.fixed_written)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "NetCDF writing failed: no more fixed size variables"))?;
+        self.check(variable, values)?;
+        self.writer.write_all(&values.to_bytes())?;
+        self.fixed_written += 1;
+        Ok(())
+    }
+
+    /// Writes the next record, with values for every record variable in order.
+    pub fn write_record(&mut self, record: &[Values]) -> Result<(), Error> {
+        let record_variables: Vec<&Variable> = self.header.variables.iter().filter(|v| self.header.is_record(v)).collect();
+        let fixed_count = self.header.variables.len() - record_variables.len();
+        if self.fixed_written < fixed_count || self.records_written == self.numrecs || record.len() != record_variables.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "NetCDF writing failed: unexpected record"));
+        }
+        for (variable, values) in record_variables.iter().zip(record) {
+            self.check(variable, values)?;
+        }
+        for values in record {
+            self.writer.write_all(&values.to_bytes())?;
+        }
+        self.records_written += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.records_written != self.numrecs {
+            return Err(Error::other(format!("NetCDF writing failed: {} of {} records written", self.records_written, self.numrecs)));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
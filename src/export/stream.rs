@@ -0,0 +1,102 @@
+use std::{
+    io::{Error, ErrorKind, Write},
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+};
+
+use async_std::{
+    channel::{bounded, Receiver, Sender},
+    io::{BufReader, Read},
+    stream::Stream,
+};
+use tide::Body;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks waiting to be sent, so a slow client holds back the writer.
+const QUEUED_CHUNKS: usize = 4;
+
+type Chunk = Result<Vec<u8>, Error>;
+
+/// Blocking writer sending what is written in chunks to a `ChannelReader`.
+pub struct ChannelWriter {
+    sender: Sender<Chunk>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self, chunk: Chunk) -> Result<(), Error> {
+        self.sender.send_blocking(chunk).map_err(|_| Error::new(ErrorKind::BrokenPipe, "The response was closed"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::take(&mut self.buffer);
+        self.send(Ok(chunk))
+    }
+}
+
+struct ChannelReader {
+    receiver: Receiver<Chunk>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChannelReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+        let reader = self.get_mut();
+        while reader.position == reader.chunk.len() {
+            match Pin::new(&mut reader.receiver).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                Poll::Ready(Some(Ok(chunk))) => {
+                    reader.chunk = chunk;
+                    reader.position = 0;
+                }
+            }
+        }
+        let len = buf.len().min(reader.chunk.len() - reader.position);
+        buf[..len].copy_from_slice(&reader.chunk[reader.position..reader.position + len]);
+        reader.position += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+/**
+ * A response body written by `write` on its own thread, so blocking store
+ * reads and large exports are sent as they are produced instead of being
+ * collected in memory first. The writer fails with `BrokenPipe` once the
+ * client is gone; other errors abort the response.
+ */
+pub fn stream_body<F>(write: F) -> Body
+where
+    F: FnOnce(&mut ChannelWriter) -> Result<(), Error> + Send + 'static,
+{
+    let (sender, receiver) = bounded(QUEUED_CHUNKS);
+    thread::spawn(move || {
+        let mut writer = ChannelWriter { sender, buffer: Vec::with_capacity(CHUNK_SIZE) };
+        match write(&mut writer).and_then(|_| writer.flush()) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => (),
+            Err(e) => {
+                println!("Streaming the response failed. {}", e);
+                let _ = writer.send(Err(e));
+            }
+        }
+    });
+    Body::from_reader(BufReader::new(ChannelReader { receiver, chunk: Vec::new(), position: 0 }), None)
+}
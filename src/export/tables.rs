@@ -0,0 +1,507 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Error, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, FloatType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+use crate::{
+    config::{invalid_input, parse_args},
+    connectors::store::{ObservationFilter, StationFilter, Store},
+    met::{format_observation_time, parse_observation_time, BoundingBox, Observation, Station},
+};
+
+/// Rows buffered per Parquet row group.
+const ROW_GROUP_ROWS: usize = 65536;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Table {
+    Stations,
+    Observations,
+}
+
+impl Table {
+    pub fn from_name(name: &str) -> Option<Table> {
+        match name {
+            "stations" => Some(Table::Stations),
+            "observations" => Some(Table::Observations),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableFormat {
+    Csv,
+    Parquet,
+}
+
+impl TableFormat {
+    pub fn from_name(name: &str) -> Option<TableFormat> {
+        match name {
+            "csv" => Some(TableFormat::Csv),
+            "parquet" => Some(TableFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            TableFormat::Csv => "text/csv;charset=utf-8",
+            TableFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TableExport {
+    pub table: Table,
+    pub format: TableFormat,
+    /// Observations with the name, position and provider of their station.
+    pub join: bool,
+    pub stations: StationFilter,
+    /// Only these stations, of all of them if `None`.
+    pub station_ids: Option<Vec<String>>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl TableExport {
+    /**
+     * Reads the optional `from`, `to`, `bbox`, `provider`, `station` (comma
+     * separated ids) and `join` params, shared by the command line and the
+     * API.
+     */
+    pub fn from_params(table: Table, format: TableFormat, params: &HashMap<String, String>) -> Result<TableExport, Error> {
+        let time = |name: &str| -> Result<Option<DateTime<Utc>>, Error> {
+            match params.get(name) {
+                None => Ok(None),
+                Some(value) => parse_observation_time(value)
+                    .map(Some)
+                    .map_err(|_| invalid_input(format!("{} is not an ISO-8601 time", value))),
+            }
+        };
+        let bbox = match params.get("bbox") {
+            None => None,
+            Some(bbox) => Some(BoundingBox::parse(bbox).ok_or_else(|| invalid_input("bbox must be min_lon,min_lat,max_lon,max_lat".to_string()))?),
+        };
+        let join = match params.get("join").map(String::as_str) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(other) => return Err(invalid_input(format!("join must be true or false, not {}", other))),
+        };
+        let station_ids = params.get("station").map(|ids| ids.split(',').map(|id| id.trim().to_string()).filter(|id| !id.is_empty()).collect());
+        Ok(TableExport {
+            table,
+            format,
+            join,
+            stations: StationFilter { bbox, provider: params.get("provider").cloned() },
+            station_ids,
+            from: time("from")?,
+            to: time("to")?,
+        })
+    }
+
+    /**
+     * Reads `--table stations|observations --format csv|parquet [--join]
+     * [--from <time>] [--to <time>] [--bbox <box>] [--provider <name>]
+     * [--station <ids>] --output <path>`.
+     */
+    pub fn from_args(args: &[String]) -> Result<(TableExport, PathBuf), Error> {
+        let values = parse_args(args)?;
+        let required = |name: &str| values.get(name).map(String::as_str).ok_or_else(|| invalid_input(format!("Missing --{}", name)));
+        let table = required("table")?;
+        let table = Table::from_name(table).ok_or_else(|| invalid_input(format!("Unknown table: {}, use stations or observations", table)))?;
+        let format = required("format")?;
+        let format = TableFormat::from_name(format).ok_or_else(|| invalid_input(format!("Unknown format: {}, use csv or parquet", format)))?;
+        let output = PathBuf::from(required("output")?);
+        Ok((TableExport::from_params(table, format, &values)?, output))
+    }
+
+    fn is_filtered(&self) -> bool {
+        self.stations.bbox.is_some() || self.stations.provider.is_some() || self.station_ids.is_some()
+    }
+
+    fn columns(&self) -> Vec<Column> {
+        match (self.table, self.join) {
+            (Table::Stations, _) => STATION_COLUMNS.to_vec(),
+            (Table::Observations, false) => OBSERVATION_COLUMNS.to_vec(),
+            (Table::Observations, true) => OBSERVATION_COLUMNS.iter().chain(JOINED_STATION_COLUMNS).copied().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColumnType {
+    Text,
+    Time,
+    Float,
+    OptionalFloat,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Column {
+    name: &'static str,
+    column_type: ColumnType,
+}
+
+const fn column(name: &'static str, column_type: ColumnType) -> Column {
+    Column { name, column_type }
+}
+
+const STATION_COLUMNS: &[Column] = &[
+    column("id", ColumnType::Text),
+    column("name", ColumnType::Text),
+    column("lat", ColumnType::Float),
+    column("lon", ColumnType::Float),
+    column("altitude", ColumnType::OptionalFloat),
+    column("provider", ColumnType::Text),
+];
+
+const OBSERVATION_COLUMNS: &[Column] = &[
+    column("station_id", ColumnType::Text),
+    column("observation_time", ColumnType::Time),
+    column("air_temperature", ColumnType::Float),
+    column("rel_humidity", ColumnType::Float),
+];
+
+/// Current metadata of the station of a joined observation.
+const JOINED_STATION_COLUMNS: &[Column] = &[
+    column("station_name", ColumnType::Text),
+    column("lat", ColumnType::Float),
+    column("lon", ColumnType::Float),
+    column("altitude", ColumnType::OptionalFloat),
+    column("provider", ColumnType::Text),
+];
+
+enum Cell<'a> {
+    Text(&'a str),
+    Time(DateTime<Utc>),
+    Float(f32),
+    OptionalFloat(Option<f32>),
+}
+
+fn station_cells(station: &Station) -> [Cell<'_>; 6] {
+    [
+        Cell::Text(&station.id),
+        Cell::Text(&station.name),
+        Cell::Float(station.lat),
+        Cell::Float(station.lon),
+        Cell::OptionalFloat(station.altitude),
+        Cell::Text(&station.provider),
+    ]
+}
+
+fn observation_cells(observation: &Observation) -> [Cell<'_>; 4] {
+    [
+        Cell::Text(&observation.station_id),
+        Cell::Time(observation.observation_time),
+        Cell::Float(observation.aerial_temperature),
+        Cell::Float(observation.relative_humidity),
+    ]
+}
+
+trait RowWriter {
+    fn write_row(&mut self, row: &[Cell]) -> Result<(), Error>;
+    fn finish(self) -> Result<(), Error>;
+}
+
+struct CsvRowWriter<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+fn csv_error(err: csv::Error) -> Error {
+    Error::other(format!("CSV writing failed: {}", err))
+}
+
+impl<W: Write> CsvRowWriter<W> {
+    fn new(writer: W, columns: &[Column]) -> Result<CsvRowWriter<W>, Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(columns.iter().map(|c| c.name)).map_err(csv_error)?;
+        Ok(CsvRowWriter { writer })
+    }
+}
+
+impl<W: Write> RowWriter for CsvRowWriter<W> {
+    fn write_row(&mut self, row: &[Cell]) -> Result<(), Error> {
+        let fields = row.iter().map(|cell| match cell {
+            Cell::Text(value) => value.to_string(),
+            Cell::Time(value) => format_observation_time(value),
+            Cell::Float(value) => value.to_string(),
+            Cell::OptionalFloat(value) => value.map(|v| v.to_string()).unwrap_or_default(),
+        });
+        self.writer.write_record(fields).map_err(csv_error)
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+}
+
+/// Values of a column in the current row group.
+enum ColumnBuffer {
+    Text(Vec<ByteArray>),
+    Time(Vec<i64>),
+    /// Values and, for optional columns, definition levels (1 if present).
+    Float(Vec<f32>, Option<Vec<i16>>),
+}
+
+/**
+ * Writes rows as Parquet, buffering up to `ROW_GROUP_ROWS` rows per column
+ * before writing them out as a SNAPPY compressed row group.
+ */
+struct ParquetRowWriter<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    buffers: Vec<ColumnBuffer>,
+    rows: usize,
+}
+
+fn parquet_error(err: parquet::errors::ParquetError) -> Error {
+    Error::other(format!("Parquet writing failed: {}", err))
+}
+
+fn parquet_schema(columns: &[Column]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|c| match c.column_type {
+            ColumnType::Text => format!("REQUIRED BYTE_ARRAY {} (UTF8);", c.name),
+            ColumnType::Time => format!("REQUIRED INT64 {} (TIMESTAMP(MILLIS,true));", c.name),
+            ColumnType::Float => format!("REQUIRED FLOAT {};", c.name),
+            ColumnType::OptionalFloat => format!("OPTIONAL FLOAT {};", c.name),
+        })
+        .collect();
+    format!("message schema {{ {} }}", fields.join(" "))
+}
+
+impl<W: Write + Send> ParquetRowWriter<W> {
+    fn new(writer: W, columns: &[Column]) -> Result<ParquetRowWriter<W>, Error> {
+        let schema = parse_message_type(&parquet_schema(columns)).map_err(parquet_error)?;
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = SerializedFileWriter::new(writer, Arc::new(schema), Arc::new(properties)).map_err(parquet_error)?;
+        let buffers = columns
+            .iter()
+            .map(|c| match c.column_type {
+                ColumnType::Text => ColumnBuffer::Text(Vec::new()),
+                ColumnType::Time => ColumnBuffer::Time(Vec::new()),
+                ColumnType::Float => ColumnBuffer::Float(Vec::new(), None),
+                ColumnType::OptionalFloat => ColumnBuffer::Float(Vec::new(), Some(Vec::new())),
+            })
+            .collect();
+        Ok(ParquetRowWriter { writer, buffers, rows: 0 })
+    }
+
+    fn write_row_group(&mut self) -> Result<(), Error> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let mut row_group = self.writer.next_row_group().map_err(parquet_error)?;
+        for buffer in &mut self.buffers {
+            let mut column = row_group.next_column().map_err(parquet_error)?.ok_or_else(|| Error::other("Parquet writing failed: missing column"))?;
+            match buffer {
+                ColumnBuffer::Text(values) => column.typed::<ByteArrayType>().write_batch(values, None, None),
+                ColumnBuffer::Time(values) => column.typed::<Int64Type>().write_batch(values, None, None),
+                ColumnBuffer::Float(values, levels) => column.typed::<FloatType>().write_batch(values, levels.as_deref(), None),
+            }
+            .map_err(parquet_error)?;
+            column.close().map_err(parquet_error)?;
+            match buffer {
+                ColumnBuffer::Text(values) => values.clear(),
+                ColumnBuffer::Time(values) => values.clear(),
+                ColumnBuffer::Float(values, levels) => {
+                    values.clear();
+                    levels.iter_mut().for_each(Vec::clear);
+                }
+            }
+        }
+        row_group.close().map_err(parquet_error)?;
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> RowWriter for ParquetRowWriter<W> {
+    fn write_row(&mut self, row: &[Cell]) -> Result<(), Error> {
+        for (buffer, cell) in self.buffers.iter_mut().zip(row) {
+            match (buffer, cell) {
+                (ColumnBuffer::Text(values), Cell::Text(value)) => values.push(ByteArray::from(*value)),
+                (ColumnBuffer::Time(values), Cell::Time(value)) => values.push(value.timestamp_millis()),
+                (ColumnBuffer::Float(values, None), Cell::Float(value)) => values.push(*value),
+                (ColumnBuffer::Float(values, Some(levels)), Cell::OptionalFloat(value)) => {
+                    levels.push(value.is_some() as i16);
+                    values.extend(value);
+                }
+                _ => return Err(Error::other("Parquet writing failed: row does not match the schema")),
+            }
+        }
+        self.rows += 1;
+        if self.rows == ROW_GROUP_ROWS {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        self.write_row_group()?;
+        self.writer.close().map_err(parquet_error)?;
+        Ok(())
+    }
+}
+
+/**
+ * Writes the table to `writer` as rows are read from the store, returning
+ * the number of rows written. Observations are written oldest first; joined
+ * observations carry the current name, position and provider of their
+ * station.
+ */
+pub fn write_table<W: Write + Send>(writer: W, store: &dyn Store, export: &TableExport) -> Result<usize, Error> {
+    let columns = export.columns();
+    match export.format {
+        TableFormat::Csv => write_rows(CsvRowWriter::new(writer, &columns)?, store, export),
+        TableFormat::Parquet => write_rows(ParquetRowWriter::new(writer, &columns)?, store, export),
+    }
+}
+
+/// Writes the table to a file, returning the number of rows written.
+pub fn export_table(store: &dyn Store, export: &TableExport, output: &Path) -> Result<usize, Error> {
+    let file = File::create(output).map_err(|err| Error::other(format!("Creating {} failed: {}", output.display(), err)))?;
+    let mut writer = BufWriter::new(file);
+    let rows = write_table(&mut writer, store, export)?;
+    writer.flush()?;
+    Ok(rows)
+}
+
+fn write_rows(mut rows: impl RowWriter, store: &dyn Store, export: &TableExport) -> Result<usize, Error> {
+    let mut stations: Vec<Station> = store.get_stations_with_latest_observation(&export.stations)?.into_iter().map(|(s, _)| s).collect();
+    if let Some(ids) = &export.station_ids {
+        stations.retain(|s| ids.contains(&s.id));
+    }
+    let mut count = 0;
+    match export.table {
+        Table::Stations => {
+            for station in &stations {
+                rows.write_row(&station_cells(station))?;
+                count += 1;
+            }
+        }
+        Table::Observations => {
+            let filter = ObservationFilter {
+                station_ids: export.is_filtered().then(|| stations.iter().map(|s| s.id.clone()).collect()),
+                from: export.from,
+                to: export.to,
+            };
+            let stations: HashMap<&str, &Station> = stations.iter().map(|s| (s.id.as_str(), s)).collect();
+            store.for_each_observation(&filter, &mut |observation| {
+                if !export.join {
+                    rows.write_row(&observation_cells(&observation))?;
+                } else if let Some(station) = stations.get(observation.station_id.as_str()) {
+                    let [_, name, lat, lon, altitude, provider] = station_cells(station);
+                    let [station_id, time, temperature, humidity] = observation_cells(&observation);
+                    rows.write_row(&[station_id, time, temperature, humidity, name, lat, lon, altitude, provider])?;
+                } else {
+                    return Ok(());
+                }
+                count += 1;
+                Ok(())
+            })?;
+        }
+    }
+    rows.finish()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::Field,
+    };
+
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{testing::{observation, station}, MeteoData},
+    };
+
+    fn store() -> MemoryStore {
+        let store = MemoryStore::default();
+        let data = MeteoData {
+            stations: vec![Station { altitude: Some(650.0), ..station("a", 40.0, -3.5) }, station("b", 41.4, 2.2)],
+            observations: vec![
+                observation("a", "2023-08-10T10:00:00Z", 30.5),
+                observation("b", "2023-08-10T10:00:00Z", 28.0),
+                observation("a", "2023-08-10T11:00:00Z", 32.0),
+            ],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &data).unwrap();
+        store
+    }
+
+    fn parquet_reader(content: Vec<u8>, name: &str) -> SerializedFileReader<File> {
+        let path = std::env::temp_dir().join(format!("wheatr-{}-{}.parquet", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+        reader
+    }
+
+    fn export(table: Table, format: TableFormat, params: &[(&str, &str)]) -> TableExport {
+        let params = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        TableExport::from_params(table, format, &params).unwrap()
+    }
+
+    #[test]
+    fn csv_observations_filtered_and_joined() {
+        let store = store();
+        let mut csv = Vec::new();
+        let params = [("bbox", "-4,39,-3,41"), ("from", "2023-08-10T10:30:00Z"), ("join", "true")];
+        let rows = write_table(&mut csv, &store, &export(Table::Observations, TableFormat::Csv, &params)).unwrap();
+
+        assert_eq!(rows, 1);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "station_id,observation_time,air_temperature,rel_humidity,station_name,lat,lon,altitude,provider\n\
+             a,2023-08-10T11:00:00Z,32,40,a,40,-3.5,650,aemet\n"
+        );
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let store = store();
+        let mut parquet = Vec::new();
+        let rows = write_table(&mut parquet, &store, &export(Table::Observations, TableFormat::Parquet, &[])).unwrap();
+        assert_eq!(rows, 3);
+
+        let reader = parquet_reader(parquet, "observations");
+        let read: Vec<(String, i64, f32)> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                let fields: Vec<Field> = row.unwrap().get_column_iter().map(|(_, f)| f.clone()).collect();
+                match &fields[..] {
+                    [Field::Str(id), Field::TimestampMillis(time), Field::Float(t), Field::Float(_)] => (id.clone(), *time, *t),
+                    other => panic!("unexpected row {:?}", other),
+                }
+            })
+            .collect();
+        let time = parse_observation_time("2023-08-10T10:00:00Z").unwrap().timestamp_millis();
+        assert_eq!(read, vec![("a".to_string(), time, 30.5), ("b".to_string(), time, 28.0), ("a".to_string(), time + 3_600_000, 32.0)]);
+
+        let mut stations = Vec::new();
+        write_table(&mut stations, &store, &export(Table::Stations, TableFormat::Parquet, &[("station", "b")])).unwrap();
+        let reader = parquet_reader(stations, "stations");
+        let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        assert_eq!(row.get_column_iter().find(|(name, _)| *name == "altitude").map(|(_, f)| f.clone()), Some(Field::Null));
+    }
+}
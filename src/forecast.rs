@@ -0,0 +1,108 @@
+use std::io::Error;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::{
+    boundaries::MunicipalityBoundaries,
+    calculators::location_data_calculations::calculate_heat_index,
+    connectors::store::Store,
+    met::{Forecast, Location, Municipality, WheatrForecastResponseData, WheatrForecastStep},
+};
+
+/// Farthest town of a municipality whose forecast is given for a location outside its boundary.
+const MAX_MUNICIPALITY_DISTANCE_KM: f32 = 20.0;
+
+/**
+ * Hourly forecast at `loc` from the current hour on, with the heat index of
+ * every hour. It is the forecast of the municipality containing the
+ * location, or else of the closest municipality with forecasts if its town
+ * is not farther than `MAX_MUNICIPALITY_DISTANCE_KM`; `None` without any.
+ */
+pub fn get_forecast(
+    store: &dyn Store,
+    boundaries: &MunicipalityBoundaries,
+    loc: Location,
+    now: DateTime<Utc>,
+) -> Result<Option<WheatrForecastResponseData>, Error> {
+    let current_hour = now.duration_trunc(Duration::hours(1)).map_err(Error::other)?;
+    let mut candidates: Vec<Municipality> = vec![];
+    if let Some(place) = boundaries.locate(&loc) {
+        candidates.extend(store.get_municipality(&place.code)?);
+    }
+    let within_reach = |m: &Municipality| Location { lat: m.lat, lon: m.lon }.distance_km(&loc) <= MAX_MUNICIPALITY_DISTANCE_KM;
+    candidates.extend(store.get_closest_municipality(&loc)?.filter(within_reach));
+    for municipality in candidates {
+        let forecasts = store.get_forecasts(&municipality.id, current_hour)?;
+        if let Some(elaborated_at) = forecasts.iter().map(|f| f.elaborated_at).max() {
+            return Ok(Some(forecast_response(loc, municipality, elaborated_at, &forecasts)));
+        }
+    }
+    Ok(None)
+}
+
+fn forecast_response(loc: Location, municipality: Municipality, elaborated_at: DateTime<Utc>, forecasts: &[Forecast]) -> WheatrForecastResponseData {
+    let local_timezone = loc.timezone();
+    let steps = forecasts
+        .iter()
+        .map(|f| WheatrForecastStep {
+            time: f.forecast_time,
+            local_time: f.forecast_time.with_timezone(&local_timezone).to_rfc3339(),
+            air_temperature: f.aerial_temperature,
+            rel_humidity: f.relative_humidity,
+            hi: calculate_heat_index(f.aerial_temperature, f.relative_humidity),
+        })
+        .collect();
+
+    WheatrForecastResponseData {
+        local_lat: loc.lat,
+        local_lon: loc.lon,
+        local_timezone: local_timezone.name().to_string(),
+        municipality,
+        elaborated_at,
+        steps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connectors::memory_store::MemoryStore, met::parse_observation_time};
+
+    #[test]
+    fn forecast_of_the_containing_or_closest_municipality() {
+        let store = MemoryStore::default();
+        let municipality = |id: &str, lat: f32, lon: f32| Municipality { id: id.to_string(), name: id.to_string(), lat, lon, ..Default::default() };
+        // the town of Torremolinos is placed closer to the Málaga location than the town of Málaga
+        store
+            .upsert_municipalities(&[municipality("29067", 36.75, -4.38), municipality("29901", 36.71, -4.43), municipality("28079", 40.42, -3.70)])
+            .unwrap();
+        let forecasts: Vec<Forecast> = ["28079", "29067", "29901"]
+            .iter()
+            .flat_map(|id| {
+                ["2024-07-15T11:00:00Z", "2024-07-15T12:00:00Z", "2024-07-15T13:00:00Z"].map(|time| Forecast {
+                    municipality_id: id.to_string(),
+                    forecast_time: parse_observation_time(time).unwrap(),
+                    aerial_temperature: 32.0,
+                    relative_humidity: 60.0,
+                    elaborated_at: parse_observation_time("2024-07-15T07:00:00Z").unwrap(),
+                })
+            })
+            .collect();
+        store.upsert_forecasts(&forecasts).unwrap();
+        let boundaries = MunicipalityBoundaries::from_geojson(include_str!("../fixtures/boundaries/municipalities.geojson"), "NATCODE", "NAMEUNIT").unwrap();
+
+        let now = parse_observation_time("2024-07-15T12:30:00Z").unwrap();
+        let malaga = || Location { lat: 36.72, lon: -4.42 };
+        let forecast = get_forecast(&store, &boundaries, malaga(), now).unwrap().unwrap();
+        assert_eq!(forecast.municipality.id, "29067");
+        assert_eq!(forecast.steps.len(), 2);
+        assert_eq!(forecast.steps[0].local_time, "2024-07-15T14:00:00+02:00");
+        assert_eq!(forecast.steps[0].hi, calculate_heat_index(32.0, 60.0));
+        // without boundaries, the closest town
+        assert_eq!(get_forecast(&store, &MunicipalityBoundaries::empty(), malaga(), now).unwrap().unwrap().municipality.id, "29901");
+
+        // no municipality within reach
+        assert!(get_forecast(&store, &boundaries, Location { lat: 37.5, lon: -4.42 }, now).unwrap().is_none());
+        assert!(get_forecast(&MemoryStore::default(), &boundaries, malaga(), now).unwrap().is_none());
+    }
+}
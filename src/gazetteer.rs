@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    io::Error,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::{
+    boundaries::MunicipalityBoundaries,
+    connectors::store::{StationFilter, Store},
+    met::WheatrSearchResult,
+};
+
+const MUNICIPALITY: &str = "municipality";
+const STATION: &str = "station";
+
+/**
+ * Name in lower case without accents and punctuation, so `Alcalá de
+ * Henares`, `ALCALA DE HENARES` and `alcala-de-henares` are alike.
+ */
+pub fn normalize(name: &str) -> String {
+    let folded: String = name
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    folded.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Most edits for a fuzzy match, none for short queries, which would match too much.
+fn max_distance(query: &str) -> usize {
+    match query.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/**
+ * Rank of a match of a normalized query, lower is better: the whole name,
+ * a prefix of it, a prefix of one of its words, or else a fuzzy match of
+ * the name or its beginning, ranked by the number of edits.
+ */
+fn match_rank(query: &str, name: &str) -> Option<usize> {
+    if name == query {
+        return Some(0);
+    }
+    if name.starts_with(query) {
+        return Some(1);
+    }
+    if name.split(' ').any(|word| word.starts_with(query)) {
+        return Some(2);
+    }
+    let beginning: String = name.chars().take(query.chars().count()).collect();
+    let distance = strsim::levenshtein(query, name).min(strsim::levenshtein(query, &beginning));
+    match distance {
+        d if d > 0 && d <= max_distance(query) => Some(2 + d),
+        _ => None,
+    }
+}
+
+/**
+ * Names of municipalities and stations with their positions, to find
+ * places by name. Municipalities come from the municipal boundaries and
+ * the municipalities of the forecasts, stations from the stations table.
+ */
+#[derive(Default)]
+pub struct Gazetteer {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    id: String,
+    result: WheatrSearchResult,
+}
+
+impl Gazetteer {
+    /**
+     * Gathers the entries of the current stations and municipalities.
+     * Municipalities with forecasts are placed at their town rather than
+     * inside their boundary.
+     */
+    pub fn load(store: &dyn Store, boundaries: &MunicipalityBoundaries) -> Result<Gazetteer, Error> {
+        let mut municipalities: HashMap<String, WheatrSearchResult> = HashMap::new();
+        for (place, (lon, lat)) in boundaries.places() {
+            let result = WheatrSearchResult {
+                kind: MUNICIPALITY.to_string(),
+                id: place.code.clone(),
+                name: place.municipality.clone(),
+                province: Some(place.province.clone()),
+                lat,
+                lon,
+            };
+            municipalities.insert(place.code.clone(), result);
+        }
+        for municipality in store.get_municipalities()? {
+            let result = municipalities.entry(municipality.id.clone()).or_insert_with(|| WheatrSearchResult {
+                kind: MUNICIPALITY.to_string(),
+                id: municipality.id.clone(),
+                name: municipality.name.clone(),
+                province: municipality.province.clone(),
+                lat: municipality.lat,
+                lon: municipality.lon,
+            });
+            (result.lat, result.lon) = (municipality.lat, municipality.lon);
+        }
+        let stations = store.get_stations_with_latest_observation(&StationFilter::default())?;
+        let entries = municipalities
+            .into_values()
+            .chain(stations.into_iter().map(|(station, _)| WheatrSearchResult {
+                kind: STATION.to_string(),
+                id: station.id,
+                name: station.name,
+                province: None,
+                lat: station.lat,
+                lon: station.lon,
+            }))
+            .map(|result| Entry { name: normalize(&result.name), id: normalize(&result.id), result })
+            .collect();
+        Ok(Gazetteer { entries })
+    }
+
+    /**
+     * Best matches of `query` by name, or by INE code or station id,
+     * municipalities first among equally good matches.
+     */
+    pub fn search(&self, query: &str, limit: usize) -> Vec<WheatrSearchResult> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+        let mut matches: Vec<(usize, &WheatrSearchResult)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let rank = if entry.id == query { Some(0) } else { match_rank(&query, &entry.name) };
+                rank.map(|rank| (rank, &entry.result))
+            })
+            .collect();
+        matches.sort_by(|(rank_a, a), (rank_b, b)| {
+            (rank_a, a.kind != MUNICIPALITY, a.name.len(), &a.name, &a.id).cmp(&(rank_b, b.kind != MUNICIPALITY, b.name.len(), &b.name, &b.id))
+        });
+        matches.into_iter().take(limit).map(|(_, result)| result.clone()).collect()
+    }
+}
+
+/**
+ * The gazetteer shared by the API handlers, reloaded by the ingestion jobs
+ * when stations or municipalities may have changed.
+ */
+pub struct GazetteerCache {
+    gazetteer: RwLock<Arc<Gazetteer>>,
+    boundaries: Arc<MunicipalityBoundaries>,
+}
+
+impl GazetteerCache {
+    /// Empty until the first refresh.
+    pub fn new(boundaries: Arc<MunicipalityBoundaries>) -> GazetteerCache {
+        GazetteerCache { gazetteer: RwLock::new(Arc::new(Gazetteer::default())), boundaries }
+    }
+
+    pub fn get(&self) -> Arc<Gazetteer> {
+        self.gazetteer.read().unwrap().clone()
+    }
+
+    pub fn refresh(&self, store: &dyn Store) -> Result<Arc<Gazetteer>, Error> {
+        let gazetteer = Arc::new(Gazetteer::load(store, &self.boundaries)?);
+        *self.gazetteer.write().unwrap() = gazetteer.clone();
+        Ok(gazetteer)
+    }
+}
+
+/**
+ * Reloads the gazetteer, logging the outcome like the other jobs.
+ */
+pub fn update_gazetteer(store: &dyn Store, cache: &GazetteerCache) {
+    let start = Instant::now();
+    match cache.refresh(store) {
+        Ok(gazetteer) => println!("Gazetteer loaded in {:?}: {} places", start.elapsed(), gazetteer.entries.len()),
+        Err(e) => println!("Gazetteer loading failed. {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        connectors::memory_store::MemoryStore,
+        met::{Location, Municipality, Station},
+    };
+
+    #[test]
+    fn finds_places_by_prefix_and_fuzzy_names() {
+        let store = MemoryStore::default();
+        let station = |id: &str, name: &str, lat: f32, lon: f32| Station { id: id.to_string(), name: name.to_string(), lat, lon, altitude: None, provider: "aemet".to_string() };
+        store
+            .upsert_stations(&[station("6155A", "MÁLAGA AEROPUERTO", 36.67, -4.48), station("3195", "MADRID RETIRO", 40.41, -3.68)], Utc::now())
+            .unwrap();
+        let town = Municipality { id: "29067".to_string(), name: "Málaga".to_string(), province: Some("Málaga".to_string()), lat: 36.72, lon: -4.42, altitude: None };
+        store.upsert_municipalities(&[town]).unwrap();
+        let boundaries = MunicipalityBoundaries::from_geojson(include_str!("../fixtures/boundaries/municipalities.geojson"), "NATCODE", "NAMEUNIT").unwrap();
+        let boundaries = Arc::new(boundaries);
+        let cache = GazetteerCache::new(boundaries.clone());
+        assert!(cache.get().search("malaga", 10).is_empty());
+        let gazetteer = cache.refresh(&store).unwrap();
+
+        let results = gazetteer.search("malaga", 10);
+        let ids: Vec<&str> = results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["29067", "6155A"]);
+        // placed at the town rather than inside the boundary
+        assert_eq!((results[0].lat, results[0].lon), (36.72, -4.42));
+        assert_eq!(gazetteer.search("TORREMOLÍNOS", 10)[0].id, "29901");
+        assert_eq!(gazetteer.search("torremo", 10)[0].province.as_deref(), Some("Málaga"));
+        assert_eq!(gazetteer.search("retiro", 10)[0].id, "3195");
+        assert_eq!(gazetteer.search("Torremolino", 10)[0].id, "29901");
+        assert_eq!(gazetteer.search("Tormeolinos", 10)[0].id, "29901");
+        assert_eq!(gazetteer.search("29067", 10)[0].name, "Málaga");
+        assert!(gazetteer.search("sevilla", 10).is_empty());
+        assert_eq!(gazetteer.search("ma", 1).len(), 1);
+
+        // the interior point of a boundary is inside it
+        let torremolinos = gazetteer.search("torremolinos", 1).remove(0);
+        let place = boundaries.locate(&Location { lat: torremolinos.lat, lon: torremolinos.lon }).unwrap();
+        assert_eq!(place.code, "29901");
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    fs,
+    io::Error,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::get_env_var_or,
+    connectors::store::Store,
+    met::{BoundingBox, Location, WheatrApiResponseData},
+    snapshot::ObservationSnapshot,
+};
+
+const ENV_GRID_RESOLUTION: &str = "WHEATR_GRID_RESOLUTION";
+const ENV_GRID_CACHE_PATH: &str = "WHEATR_GRID_CACHE_PATH";
+const DEFAULT_GRID_RESOLUTION: f32 = 0.05;
+const DEFAULT_GRID_CACHE_PATH: &str = ".grid.json";
+
+/**
+ * Parts of Spain covered by the gridded field. They are gridded separately,
+ * so the sea between them does not blow up the grid size. The box of the
+ * peninsula takes in the Balearic Islands and Ceuta, whose nodes are left
+ * to their own regions listed after it.
+ */
+pub const SPAIN_REGIONS: &[(&str, BoundingBox)] = &[
+    ("peninsula", BoundingBox { min_lat: 35.9, min_lon: -9.4, max_lat: 43.9, max_lon: 3.4 }),
+    ("balearics", BoundingBox { min_lat: 38.6, min_lon: 1.1, max_lat: 40.1, max_lon: 4.4 }),
+    ("canaries", BoundingBox { min_lat: 27.6, min_lon: -18.2, max_lat: 29.5, max_lon: -13.3 }),
+    ("ceuta", BoundingBox { min_lat: 35.86, min_lon: -5.39, max_lat: 35.92, max_lon: -5.27 }),
+    ("melilla", BoundingBox { min_lat: 35.26, min_lon: -2.97, max_lat: 35.32, max_lon: -2.91 }),
+];
+
+/**
+ * JSON has no NaN, nodes without value are stored as `null`.
+ */
+mod nan_as_null {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[f32], serializer: S) -> Result<S::Ok, S::Error> {
+        let values: Vec<Option<f32>> = values.iter().map(|v| Some(*v).filter(|v| v.is_finite())).collect();
+        values.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+        let values: Vec<Option<f32>> = Vec::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|v| v.unwrap_or(f32::NAN)).collect())
+    }
+}
+
+/**
+ * Field values on the nodes of a regular lat/lon grid over a region,
+ * stored row by row from the south-west corner.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FieldGrid {
+    pub region: String,
+    pub bbox: BoundingBox,
+    pub rows: usize,
+    pub cols: usize,
+    #[serde(with = "nan_as_null")]
+    pub temperature: Vec<f32>,
+    #[serde(with = "nan_as_null")]
+    pub humidity: Vec<f32>,
+    #[serde(with = "nan_as_null")]
+    pub heat_index: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GriddedField {
+    pub resolution: f32,
+    pub observation_time: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    pub grids: Vec<FieldGrid>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FieldVariable {
+    Temperature,
+    Humidity,
+    HeatIndex,
+}
+
+impl FieldVariable {
+    /// Variable by its API name, as used in the tile and contour URLs.
+    pub fn from_name(name: &str) -> Option<FieldVariable> {
+        match name {
+            "temperature" | "t" => Some(FieldVariable::Temperature),
+            "humidity" | "rh" => Some(FieldVariable::Humidity),
+            "hi" | "heat_index" => Some(FieldVariable::HeatIndex),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldVariable::Temperature => "temperature",
+            FieldVariable::Humidity => "humidity",
+            FieldVariable::HeatIndex => "hi",
+        }
+    }
+}
+
+/**
+ * Bounding box of the nodes of a region `resolution` degrees apart from its
+ * south-west corner, covering the region, with the number of rows and
+ * columns.
+ */
+pub fn grid_shape(bbox: &BoundingBox, resolution: f32) -> (BoundingBox, usize, usize) {
+    let rows = ((bbox.max_lat - bbox.min_lat) / resolution).ceil() as usize + 1;
+    let cols = ((bbox.max_lon - bbox.min_lon) / resolution).ceil() as usize + 1;
+    let grid_bbox = BoundingBox {
+        max_lat: bbox.min_lat + (rows - 1) as f32 * resolution,
+        max_lon: bbox.min_lon + (cols - 1) as f32 * resolution,
+        ..*bbox
+    };
+    (grid_bbox, rows, cols)
+}
+
+/**
+ * Whether a node of `region` lies inside the box of a region listed after
+ * it, so it is gridded by that region only.
+ */
+fn is_in_later_region(region: &str, loc: &Location) -> bool {
+    SPAIN_REGIONS
+        .iter()
+        .skip_while(|(name, _)| *name != region)
+        .skip(1)
+        .any(|(_, b)| b.min_lat < loc.lat && loc.lat < b.max_lat && b.min_lon < loc.lon && loc.lon < b.max_lon)
+}
+
+/**
+ * Interpolates the snapshot onto the nodes of a region, `resolution`
+ * degrees apart from its south-west corner. Nodes of later regions in
+ * `SPAIN_REGIONS` are left without value.
+ */
+pub fn generate_grid(snapshot: &ObservationSnapshot, region: &str, bbox: &BoundingBox, resolution: f32) -> FieldGrid {
+    let (grid_bbox, rows, cols) = grid_shape(bbox, resolution);
+    let mut grid = FieldGrid {
+        region: region.to_string(),
+        bbox: grid_bbox,
+        rows,
+        cols,
+        temperature: Vec::with_capacity(rows * cols),
+        humidity: Vec::with_capacity(rows * cols),
+        heat_index: Vec::with_capacity(rows * cols),
+    };
+    for row in 0..rows {
+        for col in 0..cols {
+            let loc = Location {
+                lat: bbox.min_lat + row as f32 * resolution,
+                lon: bbox.min_lon + col as f32 * resolution,
+            };
+            let values = if is_in_later_region(region, &loc) { None } else { snapshot.get_local_values(&loc) };
+            let (temperature, humidity, heat_index) = values.unwrap_or((f32::NAN, f32::NAN, f32::NAN));
+            grid.temperature.push(temperature);
+            grid.humidity.push(humidity);
+            grid.heat_index.push(heat_index);
+        }
+    }
+    grid
+}
+
+impl FieldGrid {
+    pub fn values(&self, variable: FieldVariable) -> &[f32] {
+        match variable {
+            FieldVariable::Temperature => &self.temperature,
+            FieldVariable::Humidity => &self.humidity,
+            FieldVariable::HeatIndex => &self.heat_index,
+        }
+    }
+
+    /**
+     * Bilinear interpolation of a variable at the location, `None` outside
+     * the grid or next to nodes without value.
+     */
+    pub fn lookup(&self, loc: &Location, variable: FieldVariable) -> Option<f32> {
+        let b = &self.bbox;
+        if !(b.min_lat..=b.max_lat).contains(&loc.lat) || !(b.min_lon..=b.max_lon).contains(&loc.lon) {
+            return None;
+        }
+        let y = (loc.lat - b.min_lat) / (b.max_lat - b.min_lat) * (self.rows - 1) as f32;
+        let x = (loc.lon - b.min_lon) / (b.max_lon - b.min_lon) * (self.cols - 1) as f32;
+        let row = (y.floor() as usize).min(self.rows - 2);
+        let col = (x.floor() as usize).min(self.cols - 2);
+        let (dy, dx) = (y - row as f32, x - col as f32);
+        let values = self.values(variable);
+        let at = |r: usize, c: usize| values[r * self.cols + c];
+        let value = at(row, col) * (1.0 - dx) * (1.0 - dy)
+            + at(row, col + 1) * dx * (1.0 - dy)
+            + at(row + 1, col) * (1.0 - dx) * dy
+            + at(row + 1, col + 1) * dx * dy;
+        Some(value).filter(|v| v.is_finite())
+    }
+}
+
+impl GriddedField {
+    /**
+     * Interpolates the snapshot onto the grids of all regions of Spain at
+     * `resolution` degrees, using the same calculation as `/api/hi`.
+     */
+    pub fn generate(snapshot: &ObservationSnapshot, resolution: f32, now: DateTime<Utc>) -> GriddedField {
+        GriddedField {
+            resolution,
+            observation_time: snapshot.get_observation_time().unwrap_or_default(),
+            generated_at: now,
+            grids: SPAIN_REGIONS
+                .iter()
+                .map(|(region, bbox)| generate_grid(snapshot, region, bbox, resolution))
+                .collect(),
+        }
+    }
+
+    pub fn lookup(&self, loc: &Location, variable: FieldVariable) -> Option<f32> {
+        self.grids.iter().find_map(|grid| grid.lookup(loc, variable))
+    }
+
+    /**
+     * API response from the grid. It has no used stations, as the values
+     * are interpolated from the grid nodes.
+     */
+    pub fn get_local_data(&self, loc: &Location) -> Option<WheatrApiResponseData> {
+        let local_timezone = loc.timezone();
+        Some(WheatrApiResponseData {
+            used_stations: vec![],
+            local_lat: loc.lat,
+            local_lon: loc.lon,
+            local_air_temperature: self.lookup(loc, FieldVariable::Temperature)?,
+            local_rel_humidity: self.lookup(loc, FieldVariable::Humidity)?,
+            local_hi: self.lookup(loc, FieldVariable::HeatIndex)?,
+            observation_time: self.observation_time,
+            observation_local_time: self.observation_time.with_timezone(&local_timezone).to_rfc3339(),
+            local_timezone: local_timezone.name().to_string(),
+            warnings: vec![],
+            place: None,
+        })
+    }
+}
+
+/**
+ * The latest gridded field, shared by the API handlers and regenerated by
+ * the ingestion job. It is persisted to disk, so it is available right after
+ * a restart.
+ */
+pub struct FieldCache {
+    field: RwLock<Option<Arc<GriddedField>>>,
+    path: PathBuf,
+    resolution: f32,
+}
+
+impl FieldCache {
+    pub fn new(path: impl Into<PathBuf>, resolution: f32) -> FieldCache {
+        FieldCache { field: RwLock::new(None), path: path.into(), resolution }
+    }
+
+    pub fn from_env() -> FieldCache {
+        let resolution = get_env_var_or(ENV_GRID_RESOLUTION, DEFAULT_GRID_RESOLUTION);
+        let resolution = if resolution > 0.0 { resolution } else { DEFAULT_GRID_RESOLUTION };
+        FieldCache::new(get_env_var_or(ENV_GRID_CACHE_PATH, DEFAULT_GRID_CACHE_PATH.to_string()), resolution)
+    }
+
+    pub fn resolution(&self) -> f32 {
+        self.resolution
+    }
+
+    pub fn get(&self) -> Option<Arc<GriddedField>> {
+        self.field.read().unwrap().clone()
+    }
+
+    /**
+     * Loads the field saved by a previous run, unless it was generated at
+     * another resolution.
+     */
+    pub fn load(&self) -> Result<(), Error> {
+        let content = fs::read(&self.path)?;
+        let field: GriddedField = serde_json::from_slice(&content).map_err(Error::other)?;
+        if field.resolution != self.resolution {
+            return Err(Error::other(format!("Cached grid resolution is {}, not {}", field.resolution, self.resolution)));
+        }
+        *self.field.write().unwrap() = Some(Arc::new(field));
+        Ok(())
+    }
+
+    /// Regenerates the field from the latest observations and saves it.
+    pub fn refresh(&self, store: &dyn Store) -> Result<Arc<GriddedField>, Error> {
+        let snapshot = ObservationSnapshot::load(store)?;
+        if snapshot.get_observation_time().is_none() {
+            return Err(Error::other("No observations to grid"));
+        }
+        let field = Arc::new(GriddedField::generate(&snapshot, self.resolution, Utc::now()));
+        *self.field.write().unwrap() = Some(field.clone());
+        let content = serde_json::to_vec(field.as_ref()).map_err(Error::other)?;
+        fs::write(&self.path, content).map_err(|err| Error::other(format!("Saving grid failed: {}", err)))?;
+        Ok(field)
+    }
+}
+
+/**
+ * Regenerates the gridded field, logging the outcome like the other jobs.
+ */
+pub fn update_field(store: &dyn Store, cache: &FieldCache) {
+    println!("Grid generation started");
+    let start = Instant::now();
+    match cache.refresh(store) {
+        Ok(field) => println!(
+            "Grid generation finished in {:?}: {} nodes",
+            start.elapsed(),
+            field.grids.iter().map(|g| g.rows * g.cols).sum::<usize>()
+        ),
+        Err(e) => println!("Grid generation failed. {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{testing::{observation, station}, MeteoData},
+    };
+
+    #[test]
+    fn bilinear_lookup_on_generated_grid() {
+        let store = MemoryStore::default();
+        // temperature grows by 1 degree per degree of longitude everywhere
+        let meteo_data = MeteoData {
+            stations: vec![station("A", 36.0, -5.0), station("B", 37.0, -5.0), station("C", 36.0, -4.0)],
+            observations: vec![observation("A", "2023-08-10T10:00:00", 20.0), observation("B", "2023-08-10T10:00:00", 20.0), observation("C", "2023-08-10T10:00:00", 21.0)],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+        let path = std::env::temp_dir().join(format!("wheatr-grid-{}.json", std::process::id()));
+        let cache = FieldCache::new(&path, 0.5);
+
+        let field = cache.refresh(&store).unwrap();
+        assert_eq!(field.grids.len(), SPAIN_REGIONS.len());
+        let temperature = field.lookup(&Location { lat: 37.3, lon: -4.25 }, FieldVariable::Temperature).unwrap();
+        assert!((temperature - 20.75).abs() < 0.001);
+        assert!(field.lookup(&Location { lat: 50.0, lon: 0.0 }, FieldVariable::Temperature).is_none());
+
+        let reloaded = FieldCache::new(&path, 0.5);
+        reloaded.load().unwrap();
+        assert_eq!(reloaded.get().unwrap().observation_time, field.observation_time);
+        assert!(FieldCache::new(&path, 0.1).load().is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn islands_are_gridded_once() {
+        let store = MemoryStore::default();
+        let meteo_data = MeteoData {
+            stations: vec![station("A", 39.5, 2.7), station("B", 39.9, 3.1), station("C", 39.0, 1.4)],
+            observations: vec![observation("A", "2023-08-10T10:00:00", 30.0), observation("B", "2023-08-10T10:00:00", 30.0), observation("C", "2023-08-10T10:00:00", 30.0)],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+        let field = GriddedField::generate(&ObservationSnapshot::load(&store).unwrap(), 0.5, Utc::now());
+
+        let palma = Location { lat: 39.57, lon: 2.65 };
+        let grid = |region: &str| field.grids.iter().find(|grid| grid.region == region).unwrap();
+        assert!(grid("peninsula").lookup(&palma, FieldVariable::Temperature).is_none());
+        assert!(grid("balearics").lookup(&palma, FieldVariable::Temperature).is_some());
+        assert!(field.lookup(&palma, FieldVariable::Temperature).is_some());
+        // the peninsula still has values north of the islands
+        assert!(grid("peninsula").lookup(&Location { lat: 41.4, lon: 2.2 }, FieldVariable::Temperature).is_some());
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{Error, ErrorKind},
+};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::{
+    calculators::location_data_calculations::calculate_local_values,
+    connectors::store::{ProviderFilter, Store},
+    met::{Location, Observation, Station, WheatrHistoryResponseData, WheatrHistoryStep},
+};
+
+/// Longest time range served by a single history request.
+pub const MAX_HISTORY_DAYS: i64 = 31;
+/// Observations older than this at a step are not used for it.
+pub const MAX_OBSERVATION_AGE_MINUTES: i64 = 60;
+
+/**
+ * Latest observation of every station recorded in the allowed age before
+ * `at`, in the order of the stations. `None` if any of them is missing.
+ */
+fn find_observations_at(
+    stations: &[Station; 3],
+    observations_by_station: &HashMap<&str, Vec<&Observation>>,
+    at: DateTime<Utc>,
+) -> Option<[Observation; 3]> {
+    let oldest = at - Duration::minutes(MAX_OBSERVATION_AGE_MINUTES);
+    let mut found: [Observation; 3] = Default::default();
+    for (i, station) in stations.iter().enumerate() {
+        let observations = observations_by_station.get(station.id.as_str())?;
+        let after_at = observations.partition_point(|o| o.observation_time <= at);
+        let observation = observations[..after_at].last().filter(|o| o.observation_time >= oldest)?;
+        found[i] = (*observation).clone();
+    }
+    Some(found)
+}
+
+/**
+ * Recomputes the local data at `loc` for every whole hour in `[from, to]`
+ * from the stored observations, using the stations of the providers closest
+ * to the location at each hour. The station periods of the range are loaded
+ * once and the closest stations of every hour are resolved from them.
+ */
+pub fn get_history(
+    store: &dyn Store,
+    loc: Location,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    providers: &ProviderFilter,
+) -> Result<WheatrHistoryResponseData, Error> {
+    if from > to {
+        return Err(Error::new(ErrorKind::InvalidData, "Bad Request: from is later than to"));
+    }
+    if to - from > Duration::days(MAX_HISTORY_DAYS) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Bad Request: time range is longer than {} days", MAX_HISTORY_DAYS)));
+    }
+
+    let first_hour = from.duration_trunc(Duration::hours(1)).map_err(Error::other)?;
+    let first_hour = if first_hour < from { first_hour + Duration::hours(1) } else { first_hour };
+    let periods = store.get_station_periods(first_hour, to, providers)?;
+    let mut steps_stations: Vec<(DateTime<Utc>, [Station; 3])> = vec![];
+    let mut hour = first_hour;
+    while hour <= to {
+        let stations = loc
+            .closest_stations(periods.iter().filter(|p| p.is_valid_at(hour)).map(|p| &p.station))
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Not Found: less than three stations are known"))?;
+        steps_stations.push((hour, stations.map(Station::clone)));
+        hour += Duration::hours(1);
+    }
+
+    let station_ids: Vec<String> = steps_stations
+        .iter()
+        .flat_map(|(_, stations)| stations.iter().map(|s| s.id.clone()))
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect();
+    let observations = store.get_observations_between(&station_ids, first_hour - Duration::minutes(MAX_OBSERVATION_AGE_MINUTES), to)?;
+    let mut observations_by_station: HashMap<&str, Vec<&Observation>> = HashMap::new();
+    for observation in &observations {
+        observations_by_station.entry(observation.station_id.as_str()).or_default().push(observation);
+    }
+
+    let local_timezone = loc.timezone();
+    let steps = steps_stations
+        .iter()
+        .filter_map(|(time, stations)| {
+            let step_observations = find_observations_at(stations, &observations_by_station, *time)?;
+            let (local_air_temperature, local_rel_humidity, local_hi) = calculate_local_values(&loc, stations, &step_observations)?;
+            Some(WheatrHistoryStep {
+                time: *time,
+                local_time: time.with_timezone(&local_timezone).to_rfc3339(),
+                local_air_temperature,
+                local_rel_humidity,
+                local_hi,
+                used_station_ids: stations.iter().map(|s| s.id.clone()).collect(),
+            })
+        })
+        .collect();
+
+    Ok(WheatrHistoryResponseData {
+        local_lat: loc.lat,
+        local_lon: loc.lon,
+        local_timezone: local_timezone.name().to_string(),
+        from,
+        to,
+        steps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{parse_observation_time, testing::{observation, station}, MeteoData},
+    };
+
+    #[test]
+    fn hourly_steps_with_complete_observations() {
+        let store = MemoryStore::default();
+        let mut observations = vec![];
+        for (hour, temperature) in [("09", 20.0), ("10", 30.0), ("11", 25.0)] {
+            for id in ["6155A", "6156X", "6172O"] {
+                let time = format!("2023-08-10T{}:00:00", hour);
+                if !(id == "6172O" && hour == "11") {
+                    observations.push(observation(id, &time, temperature));
+                }
+            }
+        }
+        let meteo_data = MeteoData {
+            stations: vec![
+                station("6155A", 36.66612, -4.482307),
+                station("6156X", 36.717785, -4.48167),
+                station("6172O", 36.716663, -4.41972),
+            ],
+            observations,
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+
+        let from = parse_observation_time("2023-08-10T08:30:00").unwrap();
+        let to = parse_observation_time("2023-08-10T12:00:00").unwrap();
+        let history = get_history(&store, Location { lat: 36.69528, lon: -4.45386 }, from, to, &ProviderFilter::default()).unwrap();
+
+        // 6172O is missing at 11:00, its 10:00 observation is used then but is too old at 12:00
+        let times: Vec<String> = history.steps.iter().map(|s| s.local_time.clone()).collect();
+        assert_eq!(
+            times,
+            vec!["2023-08-10T11:00:00+02:00", "2023-08-10T12:00:00+02:00", "2023-08-10T13:00:00+02:00"]
+        );
+        assert!((history.steps[1].local_air_temperature - 30.0).abs() < 0.001);
+        assert_eq!(history.steps[0].used_station_ids.len(), 3);
+        assert!(get_history(&store, Location { lat: 36.69528, lon: -4.45386 }, to, from, &ProviderFilter::default()).is_err());
+    }
+}
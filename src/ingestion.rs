@@ -0,0 +1,204 @@
+use std::{collections::HashSet, io::Error, time::Instant};
+
+use chrono::{Duration, DurationRound, Utc};
+
+use crate::{
+    connectors::{
+        db_writer::write_to_database,
+        downloader::{get_http_status, take_last_http_status},
+        store::Store,
+    },
+    met::{ForecastData, IngestionRun, MeteoData, Warning},
+};
+
+/**
+ * Loads the data of a provider and writes it by `write_data`, which counts
+ * what was parsed and written into the run. The run is recorded in the
+ * store whatever its outcome is, so it can be checked whether the data is
+ * current, with the HTTP status of the last response of the download.
+ */
+fn record_run<T>(
+    store: &dyn Store,
+    provider: &str,
+    load_data: &dyn Fn() -> Result<T, Error>,
+    write_data: &dyn Fn(&T, &mut IngestionRun) -> Result<(), Error>,
+) -> IngestionRun {
+    let mut run = IngestionRun { provider: provider.to_string(), started_at: Utc::now(), ..Default::default() };
+
+    println!("Meteo data downloading started ({})", provider);
+    let start = Instant::now();
+    take_last_http_status();
+    let loaded = load_data();
+    run.http_status = take_last_http_status();
+    match loaded {
+        Ok(data) => {
+            println!("Meteo data downloading finished in {:?}", start.elapsed());
+            println!("Meteo data persisting started");
+            let start = Instant::now();
+            match write_data(&data, &mut run) {
+                Ok(()) => {
+                    println!("Meteo data persisting finished in {:?}", start.elapsed());
+                    run.succeeded = true;
+                }
+                Err(e) => {
+                    println!("Meteo data persisting failed. {}", e);
+                    run.error = Some(format!("Persisting failed: {}", e));
+                }
+            }
+        }
+        Err(e) => {
+            println!("Meteo data downloading failed. {}", e);
+            run.http_status = get_http_status(&e).or(run.http_status);
+            run.error = Some(format!("Downloading failed: {}", e));
+        }
+    }
+    run.finished_at = Utc::now();
+
+    if let Err(e) = store.insert_ingestion_run(&run) {
+        println!("Recording ingestion run failed. {}", e);
+    }
+    run
+}
+
+/**
+ * Observations loaded for ingestion. `written` is called once they are
+ * written to the store, to release what they were loaded from.
+ */
+pub trait LoadedData {
+    fn meteo_data(&self) -> &MeteoData;
+
+    fn written(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl LoadedData for MeteoData {
+    fn meteo_data(&self) -> &MeteoData {
+        self
+    }
+}
+
+/// Loads the observations of a provider and writes them to the store.
+pub fn run_ingestion<T: LoadedData>(store: &dyn Store, provider: &str, load_data: &dyn Fn() -> Result<T, Error>) -> IngestionRun {
+    record_run(store, provider, load_data, &|data, run| {
+        let meteo_data = data.meteo_data();
+        run.parsed_stations = meteo_data.stations.iter().map(|s| &s.id).collect::<HashSet<_>>().len();
+        run.parsed_observations = meteo_data.observations.len();
+        run.skipped_observations = meteo_data.skipped_observations;
+        let inserted = write_to_database(store, meteo_data)?;
+        run.inserted_observations = inserted;
+        run.duplicated_observations = run.parsed_observations - inserted;
+        data.written()
+    })
+}
+
+/**
+ * Loads forecasts and writes them to the store, replacing the ones of an
+ * earlier elaboration and deleting the ones of past hours. Municipalities
+ * are counted as stations and forecast hours as observations.
+ */
+pub fn run_forecast_ingestion(store: &dyn Store, provider: &str, load_data: &dyn Fn() -> Result<ForecastData, Error>) -> IngestionRun {
+    record_run(store, provider, load_data, &|forecast_data, run| {
+        run.parsed_stations = forecast_data.municipalities.iter().map(|m| &m.id).collect::<HashSet<_>>().len();
+        run.parsed_observations = forecast_data.forecasts.len();
+        run.skipped_observations = forecast_data.skipped_forecasts;
+        store.upsert_municipalities(&forecast_data.municipalities)?;
+        let written = store.upsert_forecasts(&forecast_data.forecasts)?;
+        run.inserted_observations = written;
+        run.duplicated_observations = run.parsed_observations - written;
+        let current_hour = run.started_at.duration_trunc(Duration::hours(1)).map_err(Error::other)?;
+        store.delete_forecasts_before(current_hour)?;
+        Ok(())
+    })
+}
+
+/**
+ * Loads the active warnings and replaces the stored ones not expired yet by
+ * them. Warnings are counted as observations.
+ */
+pub fn run_warning_ingestion(store: &dyn Store, provider: &str, load_data: &dyn Fn() -> Result<Vec<Warning>, Error>) -> IngestionRun {
+    record_run(store, provider, load_data, &|warnings, run| {
+        run.parsed_observations = warnings.len();
+        let stored = store.replace_warnings(warnings, run.started_at)?;
+        run.inserted_observations = stored;
+        run.duplicated_observations = run.parsed_observations - stored;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        connectors::{
+            downloader::{download_content, HttpStatusError},
+            memory_store::MemoryStore,
+        },
+        met::{parse_observation_time, Observation, Station},
+    };
+
+    fn meteo_data(times: &[&str]) -> MeteoData {
+        MeteoData {
+            // a station listed twice is parsed once
+            stations: vec![Station { id: "6155A".to_string(), ..Default::default() }, Station { id: "6155A".to_string(), ..Default::default() }],
+            observations: times
+                .iter()
+                .map(|time| Observation {
+                    station_id: "6155A".to_string(),
+                    observation_time: parse_observation_time(time).unwrap(),
+                    aerial_temperature: 30.0,
+                    relative_humidity: 40.0,
+                })
+                .collect(),
+            skipped_observations: 1,
+        }
+    }
+
+    #[test]
+    fn record_successful_and_failed_runs() {
+        let store = MemoryStore::default();
+        run_ingestion(&store, "aemet", &|| Ok(meteo_data(&["2023-08-10T10:00:00"])));
+        let run = run_ingestion(&store, "aemet", &|| Ok(meteo_data(&["2023-08-10T10:00:00", "2023-08-10T11:00:00"])));
+        assert!(run.succeeded);
+        assert_eq!(
+            (run.parsed_stations, run.parsed_observations, run.inserted_observations),
+            (1, 2, 1)
+        );
+        assert_eq!((run.duplicated_observations, run.skipped_observations), (1, 1));
+
+        run_ingestion::<MeteoData>(&store, "aemet", &|| Err(Error::other(HttpStatusError(429))));
+        let runs = store.get_ingestion_runs(2).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(!runs[0].succeeded);
+        assert_eq!(runs[0].http_status, Some(429));
+        assert_eq!(runs[1], run);
+    }
+
+    #[test]
+    fn record_the_status_of_successful_downloads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/data.json", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]").unwrap();
+        });
+
+        let store = MemoryStore::default();
+        let run = run_ingestion(&store, "aemet", &|| {
+            download_content(&url, "")?;
+            Ok(meteo_data(&["2023-08-10T10:00:00"]))
+        });
+        assert!(run.succeeded);
+        assert_eq!(run.http_status, Some(200));
+        // files read without a download have no status
+        assert_eq!(run_ingestion(&store, "metar", &|| Ok(meteo_data(&[]))).http_status, None);
+    }
+}
@@ -1,34 +1,116 @@
-use clokwerk::{Scheduler, TimeUnits};
-use std::{io::Error, thread, time::{Instant, Duration}, str::FromStr};
+use chrono::{DateTime, Utc};
+use clokwerk::{Interval, Job, Scheduler, TimeUnits};
+use retention::RetentionPolicy;
+use ingestion::LoadedData;
+use std::{collections::HashMap, env, io::Error, path::PathBuf, sync::Arc, thread, time::{Instant, Duration}, str::FromStr};
 use tide::{prelude::*, Request, Response, http::Mime};
 
-use connectors::sqlite_connector::get_closest_stations_from_db;
-
-use crate::{met::{Location, WheatrApiResponseData}, connectors::sqlite_connector::get_latest_observations};
+use crate::{met::{parse_observation_time, BoundingBox, Location, WheatrApiResponseData}, connectors::{aemet_connector, aemet_forecast_connector, aemet_warnings_connector, bufr_connector, euskalmet_connector, metar_connector, meteocat_connector, meteogalicia_connector, sqlite_connector::SqliteStore, store::{ProviderFilter, StationFilter, Store}}, grid::{FieldCache, FieldVariable}, territory::Territory, tiles::TileCache, boundaries::MunicipalityBoundaries, gazetteer::GazetteerCache, contours::ContourGeometry};
 
+mod batch;
+mod boundaries;
+mod bufr;
 mod calculators;
+mod config;
 mod connectors;
+mod contours;
+mod export;
+mod forecast;
+mod gazetteer;
+mod grid;
+mod history;
+mod ingestion;
 mod met;
+mod retention;
+mod snapshot;
+mod stations;
+mod territory;
+mod tiles;
+mod warnings;
+
+const ENV_DB_PATH: &str = "WHEATR_DB_PATH";
+const ENV_DB_URL: &str = "WHEATR_DB_URL";
+const DEFAULT_DB_PATH: &str = ".met.sqlite";
+const DEFAULT_INGESTION_RUNS_LIMIT: usize = 20;
+const MAX_INGESTION_RUNS_LIMIT: usize = 1000;
+const DEFAULT_RECENT_HOURS: i64 = 24;
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+const MAX_SEARCH_LIMIT: usize = 50;
+
+#[derive(Clone)]
+struct AppState {
+    store: Arc<dyn Store>,
+    fields: Arc<FieldCache>,
+    territory: Arc<Territory>,
+    tiles: Arc<TileCache>,
+    boundaries: Arc<MunicipalityBoundaries>,
+    gazetteer: Arc<GazetteerCache>,
+}
+
+#[cfg(feature = "postgres")]
+fn open_postgres_store(db_url: &str) -> Result<Arc<dyn Store>, Error> {
+    Ok(Arc::new(connectors::postgres_connector::PostgresStore::open(db_url)?))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn open_postgres_store(_db_url: &str) -> Result<Arc<dyn Store>, Error> {
+    Err(Error::other("PostgreSQL support is not compiled in, build with the postgres feature"))
+}
+
+/**
+ * Opens the configured store: PostgreSQL if `WHEATR_DB_URL` is set,
+ * otherwise SQLite at `WHEATR_DB_PATH` (defaults to `.met.sqlite`).
+ */
+fn open_store() -> Result<Arc<dyn Store>, Error> {
+    if let Ok(db_url) = env::var(ENV_DB_URL) {
+        return open_postgres_store(&db_url);
+    }
+    let db_path = env::var(ENV_DB_PATH).unwrap_or_else(|_| DEFAULT_DB_PATH.to_string());
+    Ok(Arc::new(SqliteStore::open(&db_path)?))
+}
+
+/**
+ * Runs the ingestion of a provider and regenerates the gridded field from
+ * its data, whose tiles replace the ones of the previous field. The
+ * gazetteer is reloaded with the stations.
+ */
+fn update_meteo_db<T: LoadedData>(state: &AppState, provider: &str, load_data: &dyn Fn() -> Result<T, Error>) {
+    let run = ingestion::run_ingestion(state.store.as_ref(), provider, load_data);
+    if run.succeeded {
+        grid::update_field(state.store.as_ref(), &state.fields);
+        gazetteer::update_gazetteer(state.store.as_ref(), &state.gazetteer);
+    }
+}
+
+/// Ingests from a provider now and then at every interval.
+fn schedule_ingestion<T: LoadedData + 'static>(scheduler: &mut Scheduler<Utc>, state: &AppState, provider: &'static str, load_data: fn() -> Result<T, Error>, interval: Interval) {
+    update_meteo_db(state, provider, &load_data);
+    let ingestion_state = state.clone();
+    scheduler.every(interval).run(move || update_meteo_db(&ingestion_state, provider, &load_data));
+}
 
-fn update_meteo_db() {
-    println!("Meteo data downloading started");
+fn apply_retention(store: &dyn Store, policy: &RetentionPolicy) {
+    println!("Data retention started");
     let start = Instant::now();
-    let meteo_data = match connectors::aemet_connector::load_data() {
-        Ok(md) => md,
-        Err(e) => { println!("Meteo data downloading failed. {}", e); return; }
-    };
-    println!("Meteo data downloading finished in {:?}", start.elapsed());
+    match retention::apply_retention(store, policy, Utc::now()) {
+        Ok(report) => println!(
+            "Data retention finished in {:?}: {} observations rolled up, {} daily summaries and {} warnings deleted",
+            start.elapsed(), report.rolled_up_observations, report.deleted_daily_summaries, report.deleted_warnings
+        ),
+        Err(e) => println!("Data retention failed. {}", e),
+    }
+}
 
-    println!("Meteo data persisting started");
+fn compact_db(store: &dyn Store) {
+    println!("Database compaction started");
     let start = Instant::now();
-    match connectors::db_writer::write_to_database(&meteo_data) {
-        Ok(_) => (),
-        Err(e) => { println!("Meteo data persisting failed. {}", e); return; }
-    };
-    println!("Meteo data persisting finished in {:?}", start.elapsed());
+    match store.compact() {
+        Ok(_) => println!("Database compaction finished in {:?}", start.elapsed()),
+        Err(e) => println!("Database compaction failed. {}", e),
+    }
 }
 
-fn read_query_params(req: Request<()>) -> Result<Location, Error> {
+fn read_query_params(req: &Request<AppState>) -> Result<Location, Error> {
     let mut query_pairs = req.url().query_pairs();
     if query_pairs.count() < 2 {
         return Err(Error::new(std::io::ErrorKind::InvalidData, "Bad Request: missing query params"));
@@ -58,73 +140,724 @@ fn read_query_params(req: Request<()>) -> Result<Location, Error> {
     Ok(loc)
 }
 
-fn get_local_data(loc: Location) -> Result<WheatrApiResponseData, Error> {
+fn read_limit_param(req: &Request<AppState>, default: usize, max: usize) -> Result<usize, Error> {
+    match req.url().query_pairs().find(|item| { item.0 == "limit" }) {
+        None => Ok(default),
+        Some(p) => match usize::from_str(&p.1) {
+            Ok(limit) => Ok(limit.min(max)),
+            Err(_e) => Err(Error::new(std::io::ErrorKind::InvalidData, "Bad Request: limit is not a number")),
+        },
+    }
+}
 
-    let start = Instant::now();
+fn read_param(req: &Request<AppState>, name: &str) -> Option<String> {
+    req.url().query_pairs().find(|item| { item.0 == name }).map(|p| p.1.to_string())
+}
+
+/**
+ * Location of the `place` param, the best match of the gazetteer, or else
+ * of the `lat` and `lon` params.
+ */
+fn read_location(req: &Request<AppState>) -> Result<Location, Error> {
+    let place = match read_param(req, "place") {
+        Some(p) => p,
+        None => return read_query_params(req),
+    };
+    match req.state().gazetteer.get().search(&place, 1).first() {
+        Some(result) => {
+            println!("Request: {} at {}, {}", place, result.lat, result.lon);
+            Ok(Location { lat: result.lat, lon: result.lon })
+        }
+        None => Err(Error::new(std::io::ErrorKind::NotFound, format!("Not Found: no place matches {}", place))),
+    }
+}
 
-    let closest_stations = get_closest_stations_from_db(&loc)?;
-    let latest_observations = get_latest_observations(&closest_stations)?;
-    let located_temperature_values = calculators::location_data_calculations::get_located_values(&closest_stations, &latest_observations, &|o| { o.aerial_temperature});
-    let located_humidity_values = calculators::location_data_calculations::get_located_values(&closest_stations, &latest_observations, &|o| { o.relative_humidity});
-    let local_temperature_data = calculators::location_data_calculations::calculate_local_data(&loc,&located_temperature_values);
-    let local_humidity_data = calculators::location_data_calculations::calculate_local_data(&loc,&located_humidity_values);
-    let local_hi = calculators::location_data_calculations::calculate_heat_index(local_temperature_data, local_humidity_data);
+/// Status of a request failing on its params.
+fn param_error_status(e: &Error) -> u16 {
+    match e.kind() {
+        std::io::ErrorKind::InvalidData => 400,
+        std::io::ErrorKind::NotFound => 404,
+        _ => 500,
+    }
+}
+
+/**
+ * Reads the `bbox` (`min_lon,min_lat,max_lon,max_lat`, as in GeoJSON) and
+ * `provider` params.
+ */
+fn read_station_filter(req: &Request<AppState>) -> Result<StationFilter, Error> {
+    let bbox = match read_param(req, "bbox") {
+        None => None,
+        Some(bbox) => Some(
+            BoundingBox::parse(&bbox)
+                .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "Bad Request: bbox must be min_lon,min_lat,max_lon,max_lat"))?,
+        ),
+    };
+    Ok(StationFilter { bbox, provider: read_param(req, "provider") })
+}
+
+/**
+ * Reads the comma separated `providers` to interpolate from (default: all)
+ * and `exclude_providers` params.
+ */
+fn read_provider_filter(req: &Request<AppState>) -> ProviderFilter {
+    let list = |value: String| -> Vec<String> { value.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect() };
+    ProviderFilter {
+        include: read_param(req, "providers").map(list),
+        exclude: read_param(req, "exclude_providers").map(list).unwrap_or_default(),
+    }
+}
+
+/**
+ * Reads the contour `variable` (default: hi), comma separated `levels`
+ * (default: by variable) and `geometry` (`lines` or `polygons`, default:
+ * lines) params.
+ */
+fn read_contour_params(req: &Request<AppState>) -> Result<(FieldVariable, Vec<f32>, ContourGeometry), Error> {
+    let variable = match read_param(req, "variable") {
+        None => FieldVariable::HeatIndex,
+        Some(name) => FieldVariable::from_name(&name)
+            .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "Bad Request: variable must be temperature, humidity or hi"))?,
+    };
+    let mut levels = match read_param(req, "levels") {
+        None => contours::default_levels(variable),
+        Some(levels) => levels
+            .split(',')
+            .map(|l| f32::from_str(l.trim()).ok().filter(|l| l.is_finite()))
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "Bad Request: levels must be numbers separated by commas"))?,
+    };
+    if levels.len() > contours::MAX_CONTOUR_LEVELS {
+        return Err(Error::new(std::io::ErrorKind::InvalidData, format!("Bad Request: more than {} levels", contours::MAX_CONTOUR_LEVELS)));
+    }
+    levels.sort_by(f32::total_cmp);
+    levels.dedup();
+    let geometry = match read_param(req, "geometry") {
+        None => ContourGeometry::Lines,
+        Some(name) => ContourGeometry::from_name(&name)
+            .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "Bad Request: geometry must be lines or polygons"))?,
+    };
+    Ok((variable, levels, geometry))
+}
+
+fn read_time_param(req: &Request<AppState>, name: &str) -> Result<Option<DateTime<Utc>>, Error> {
+    match req.url().query_pairs().find(|item| { item.0 == name }) {
+        None => Ok(None),
+        Some(p) => match parse_observation_time(&p.1) {
+            Ok(t) => Ok(Some(t)),
+            Err(_e) => Err(Error::new(std::io::ErrorKind::InvalidData, format!("Bad Request: {} is not an ISO-8601 time", name))),
+        },
+    }
+}
+
+/**
+ * Interpolates data at `loc` from the latest observations, or from the
+ * latest ones recorded in the hour before `at` using the station positions
+ * valid at that time. Fails as not found if a station has no such
+ * observation.
+ */
+fn get_local_data(store: &dyn Store, loc: Location, at: Option<DateTime<Utc>>, providers: &ProviderFilter) -> Result<WheatrApiResponseData, Error> {
+
+    let start = Instant::now();
 
-    let api_response = WheatrApiResponseData {
-        used_stations: closest_stations.to_vec(),
-        local_air_temperature: local_temperature_data,
-        local_hi: local_hi,
-        local_lat: loc.lat,
-        local_lon: loc.lon,
-        local_rel_humidity: local_humidity_data,
+    let no_observations = || Error::new(std::io::ErrorKind::NotFound, "Not Found: the closest stations have no observations");
+    let (closest_stations, latest_observations) = match at {
+        None => {
+            let closest_stations = store.get_closest_stations(&loc, providers)?;
+            let latest_observations = store.get_latest_observations(&closest_stations)?;
+            (closest_stations, latest_observations)
+        }
+        Some(at) => {
+            let closest_stations = store.get_closest_stations_at(&loc, at, providers)?;
+            let latest_observations = store.get_latest_observations_at(&closest_stations, at)?;
+            let oldest = at - chrono::Duration::minutes(history::MAX_OBSERVATION_AGE_MINUTES);
+            if latest_observations.iter().any(|o| o.observation_time < oldest) {
+                return Err(no_observations());
+            }
+            (closest_stations, latest_observations)
+        }
     };
+    let api_response = calculators::location_data_calculations::calculate_response_data(&loc, &closest_stations, &latest_observations)
+        .ok_or_else(no_observations)?;
 
     println!("Data: {}", api_response);
     println!("Time elapsed to serve request is: {:?}", start.elapsed());
     Ok(api_response)
 }
 
+fn print_pending_migrations(store: &dyn Store) {
+    match store.get_pending_migrations() {
+        Ok(pending) if pending.is_empty() => println!("Database schema is up to date"),
+        Ok(pending) => {
+            println!("Pending migrations:");
+            pending.iter().for_each(|m| println!("  {:>4} {}", m.version, m.description));
+        }
+        Err(e) => println!("Checking migrations failed. {}", e),
+    }
+}
+
+fn export_fields(store: &dyn Store, args: &[String]) {
+    let options = match export::fields::ExportOptions::from_args(args, FieldCache::from_env().resolution()) {
+        Ok(o) => o,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage: export --format geotiff|netcdf --from <time> [--to <time>] [--region <name>] [--resolution <degrees>] --output <path>");
+            return;
+        }
+    };
+    println!("Export started");
+    let start = Instant::now();
+    match export::fields::export_fields(store, &options) {
+        Ok(paths) => {
+            println!("Export finished in {:?}", start.elapsed());
+            paths.iter().for_each(|p| println!("  {}", p.display()));
+        }
+        Err(e) => println!("Export failed. {}", e),
+    }
+}
+
+fn import_csv(store: &dyn Store, args: &[String]) {
+    let (config, path) = match connectors::csv_importer::CsvImportConfig::from_args(args) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage: import-csv --source <name> --file <path> [--delimiter <char>|tab] [--columns <field>=<header>,...] [--temperature-unit c|f|k] [--humidity-unit percent|fraction] [--timezone <name>] [--time-format <format>]");
+            return;
+        }
+    };
+    if let Err(e) = store.migrate() {
+        println!("Migrating database failed. {}", e);
+        return;
+    }
+    let run = ingestion::run_ingestion(store, &config.source, &|| connectors::csv_importer::load_file(&path, &config));
+    if run.succeeded {
+        println!(
+            "Imported {} stations and {} observations ({} duplicated, {} skipped)",
+            run.parsed_stations, run.inserted_observations, run.duplicated_observations, run.skipped_observations
+        );
+    }
+}
+
+fn import_synop(store: &dyn Store, args: &[String]) {
+    let (path, reference) = match connectors::synop_decoder::from_args(args) {
+        Ok(a) => a,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage: import-synop --file <path> [--reference <time>]");
+            return;
+        }
+    };
+    if let Err(e) = store.migrate() {
+        println!("Migrating database failed. {}", e);
+        return;
+    }
+    let run = ingestion::run_ingestion(store, connectors::synop_decoder::PROVIDER, &|| connectors::synop_decoder::load_file(&path, reference));
+    if run.succeeded {
+        println!(
+            "Imported {} stations and {} observations ({} duplicated, {} skipped)",
+            run.parsed_stations, run.inserted_observations, run.duplicated_observations, run.skipped_observations
+        );
+    }
+}
+
+fn import_bufr(store: &dyn Store, args: &[String]) {
+    let path = match config::parse_args(args).map(|mut values| values.remove("file")) {
+        Ok(Some(path)) => PathBuf::from(path),
+        _ => {
+            println!("Usage: import-bufr --file <path>");
+            return;
+        }
+    };
+    if let Err(e) = store.migrate() {
+        println!("Migrating database failed. {}", e);
+        return;
+    }
+    let run = ingestion::run_ingestion(store, bufr_connector::PROVIDER, &|| bufr_connector::load_file(&path));
+    if run.succeeded {
+        println!(
+            "Imported {} stations and {} observations ({} duplicated, {} skipped)",
+            run.parsed_stations, run.inserted_observations, run.duplicated_observations, run.skipped_observations
+        );
+    }
+}
+
+fn export_table(store: &dyn Store, args: &[String]) {
+    let (table_export, output) = match export::tables::TableExport::from_args(args) {
+        Ok(o) => o,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage: export-table --table stations|observations --format csv|parquet [--join] [--from <time>] [--to <time>] [--bbox <min_lon,min_lat,max_lon,max_lat>] [--provider <name>] [--station <id,...>] --output <path>");
+            return;
+        }
+    };
+    println!("Export started");
+    let start = Instant::now();
+    match export::tables::export_table(store, &table_export, &output) {
+        Ok(rows) => println!("Exported {} rows to {} in {:?}", rows, output.display(), start.elapsed()),
+        Err(e) => println!("Export failed. {}", e),
+    }
+}
+
 #[async_std::main]
 async fn main() -> tide::Result<()> {
 
-    thread::spawn(|| {
-        update_meteo_db();
-        let mut scheduler = Scheduler::new();
-        scheduler.every(1.hours()).run(update_meteo_db);
+    let store = open_store()?;
+
+    let args: Vec<String> = env::args().collect();
+    if let Some(command) = args.get(1) {
+        match command.as_str() {
+            "migrations" => print_pending_migrations(store.as_ref()),
+            "export" => export_fields(store.as_ref(), &args[2..]),
+            "export-table" => export_table(store.as_ref(), &args[2..]),
+            "import-csv" => import_csv(store.as_ref(), &args[2..]),
+            "import-synop" => import_synop(store.as_ref(), &args[2..]),
+            "import-bufr" => import_bufr(store.as_ref(), &args[2..]),
+            _ => println!("Unknown command: {}. Available commands: migrations, export, export-table, import-csv, import-synop, import-bufr", command),
+        }
+        return Ok(());
+    }
+
+    let applied_migrations = store.migrate()?;
+    if !applied_migrations.is_empty() {
+        println!("Applied database migrations: {:?}", applied_migrations);
+    }
+
+    let fields = FieldCache::from_env();
+    if let Err(e) = fields.load() {
+        println!("Cached grid is not loaded. {}", e);
+    }
+    let territory = Arc::new(Territory::from_env());
+    let tiles = Arc::new(TileCache::from_env(territory.clone()));
+    let boundaries = Arc::new(MunicipalityBoundaries::from_env());
+    let gazetteer = Arc::new(GazetteerCache::new(boundaries.clone()));
+    gazetteer::update_gazetteer(store.as_ref(), &gazetteer);
+    let state = AppState { store, fields: Arc::new(fields), territory, tiles, boundaries, gazetteer };
+
+    let job_state = state.clone();
+    let retention_policy = RetentionPolicy::from_env();
+    thread::spawn(move || {
+        update_meteo_db(&job_state, aemet_connector::PROVIDER, &aemet_connector::load_data);
+        let mut scheduler = Scheduler::with_tz(Utc);
+        let ingestion_state = job_state.clone();
+        scheduler.every(1.hours()).run(move || update_meteo_db(&ingestion_state, aemet_connector::PROVIDER, &aemet_connector::load_data));
+        // airports report every 30 minutes
+        if metar_connector::is_configured() {
+            schedule_ingestion(&mut scheduler, &job_state, metar_connector::PROVIDER, metar_connector::load_data, 30.minutes());
+        }
+        if bufr_connector::is_configured() {
+            schedule_ingestion(&mut scheduler, &job_state, bufr_connector::PROVIDER, bufr_connector::load_data, 10.minutes());
+        }
+        // regional networks, which publish at least every 30 minutes
+        if meteocat_connector::is_configured() {
+            schedule_ingestion(&mut scheduler, &job_state, meteocat_connector::PROVIDER, meteocat_connector::load_data, 30.minutes());
+        }
+        if euskalmet_connector::is_configured() {
+            schedule_ingestion(&mut scheduler, &job_state, euskalmet_connector::PROVIDER, euskalmet_connector::load_data, 30.minutes());
+        }
+        if meteogalicia_connector::is_configured() {
+            schedule_ingestion(&mut scheduler, &job_state, meteogalicia_connector::PROVIDER, meteogalicia_connector::load_data, 30.minutes());
+        }
+        // AEMET updates municipal forecasts a few times a day
+        if aemet_forecast_connector::is_configured() {
+            let forecast_state = job_state.clone();
+            let update_forecasts = move || {
+                let run = ingestion::run_forecast_ingestion(forecast_state.store.as_ref(), aemet_forecast_connector::PROVIDER, &aemet_forecast_connector::load_data);
+                if run.succeeded {
+                    gazetteer::update_gazetteer(forecast_state.store.as_ref(), &forecast_state.gazetteer);
+                }
+            };
+            update_forecasts();
+            scheduler.every(6.hours()).run(update_forecasts);
+        }
+        // warnings are reissued as they are updated, at least daily
+        if aemet_warnings_connector::is_configured() {
+            let warning_store = job_state.store.clone();
+            let update_warnings = move || {
+                ingestion::run_warning_ingestion(warning_store.as_ref(), aemet_warnings_connector::PROVIDER, &aemet_warnings_connector::load_data);
+            };
+            update_warnings();
+            scheduler.every(30.minutes()).run(update_warnings);
+        }
+        if retention_policy.compaction_interval_days > 0 {
+            let job_store = job_state.store.clone();
+            scheduler.every(retention_policy.compaction_interval_days.days()).run(move || compact_db(job_store.as_ref()));
+        }
+        let job_store = job_state.store;
+        match scheduler.every(1.day()).try_at(&retention_policy.run_at) {
+            Ok(job) => { job.run(move || apply_retention(job_store.as_ref(), &retention_policy)); },
+            Err(e) => println!("Data retention is disabled, invalid run time {}: {}", retention_policy.run_at, e),
+        };
         loop {
             scheduler.run_pending();
             thread::sleep(Duration::from_secs(1));
         }
     });
 
-    let mut app = tide::new();
+    let mut app = tide::with_state(state);
     app.with(tide::log::LogMiddleware::new());
 
     app.at("/").serve_dir("public")?;
-    app.at("/api/hi").get(|request: Request<()>| async move {
-        let loc = match read_query_params(request) {
-            Ok(l) => l,
+    app.at("/api/hi").get(|request: Request<AppState>| async move {
+        let params = read_location(&request).and_then(|loc| Ok((loc, read_time_param(&request, "at")?)));
+        let (loc, at) = match params {
+            Ok(p) => p,
             Err(e) => {
-                let mut response = Response::new(400);
+                let mut response = Response::new(param_error_status(&e));
                 response.set_error(e);
                 return Ok(response)
             }
         };
-        match get_local_data(loc) {
-            Ok(local_data) => {
+        let providers = read_provider_filter(&request);
+        let warnings = warnings::get_warnings_at(request.state().store.as_ref(), &loc, at.unwrap_or_else(Utc::now)).unwrap_or_else(|e| {
+            println!("Loading warnings failed. {}", e);
+            vec![]
+        });
+        let place = request.state().boundaries.locate(&loc).cloned();
+        // the gridded field is interpolated from the stations of all providers
+        let fast_mode = at.is_none() && providers.is_empty() && read_param(&request, "mode").as_deref() == Some("fast");
+        let fast_data = match request.state().fields.get() {
+            Some(field) if fast_mode => field.get_local_data(&loc),
+            _ => None,
+        };
+        let local_data = match fast_data {
+            Some(data) => Ok(data),
+            None => get_local_data(request.state().store.as_ref(), loc, at, &providers),
+        };
+        match local_data {
+            Ok(mut local_data) => {
+                local_data.warnings = warnings;
+                local_data.place = place;
                 let mut response = Response::new(200);
                 // response.append_header("Access-Control-Allow-Origin", "*");
                 response.set_content_type(Mime::from_str("application/json;charset=utf-8").unwrap());
                 response.set_body(json!(local_data));
+                Ok(response)
+            },
+            Err(e) => {
+                let mut response = Response::new(param_error_status(&e));
+                response.set_error(e);
+                Ok(response)
+            }
+        }
+    });
+    app.at("/api/hi/batch").post(|mut request: Request<AppState>| async move {
+        let body: serde_json::Value = match request.body_json().await {
+            Ok(b) => b,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(Error::new(std::io::ErrorKind::InvalidData, format!("Bad Request: invalid JSON body: {}", e)));
+                return Ok(response)
+            }
+        };
+        let start = Instant::now();
+        match batch::get_batch_local_data(request.state().store.as_ref(), &request.state().boundaries, &body) {
+            Ok(items) => {
+                println!("Time elapsed to serve {} points is: {:?}", items.len(), start.elapsed());
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/json;charset=utf-8").unwrap());
+                response.set_body(json!(items));
+                Ok(response)
+            },
+            Err(e) => {
+                let status = if e.kind() == std::io::ErrorKind::InvalidData { 400 } else { 500 };
+                let mut response = Response::new(status);
+                response.set_error(e);
+                Ok(response)
+            }
+        }
+    });
+    app.at("/api/history").get(|request: Request<AppState>| async move {
+        let params = read_query_params(&request).and_then(|loc| {
+            let from = read_time_param(&request, "from")?
+                .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "Bad Request: from param is missing"))?;
+            let to = read_time_param(&request, "to")?.unwrap_or_else(Utc::now);
+            Ok((loc, from, to))
+        });
+        let (loc, from, to) = match params {
+            Ok(p) => p,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(e);
+                return Ok(response)
+            }
+        };
+        match history::get_history(request.state().store.as_ref(), loc, from, to, &read_provider_filter(&request)) {
+            Ok(history) => {
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/json;charset=utf-8").unwrap());
+                response.set_body(json!(history));
+                Ok(response)
+            },
+            Err(e) => {
+                let mut response = Response::new(param_error_status(&e));
+                response.set_error(e);
+                Ok(response)
+            }
+        }
+    });
+    app.at("/api/forecast").get(|request: Request<AppState>| async move {
+        let loc = match read_query_params(&request) {
+            Ok(l) => l,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(e);
+                return Ok(response)
+            }
+        };
+        match forecast::get_forecast(request.state().store.as_ref(), &request.state().boundaries, loc, Utc::now()) {
+            Ok(Some(forecast)) => {
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/json;charset=utf-8").unwrap());
+                response.set_body(json!(forecast));
+                Ok(response)
+            },
+            Ok(None) => Ok(Response::new(404)),
+            Err(e) => {
+                let mut response = Response::new(500);
+                response.set_error(e);
+                Ok(response)
+            }
+        }
+    });
+    app.at("/api/search").get(|request: Request<AppState>| async move {
+        let params = match read_param(&request, "q") {
+            Some(q) if !q.trim().is_empty() => read_limit_param(&request, DEFAULT_SEARCH_LIMIT, MAX_SEARCH_LIMIT).map(|limit| (q, limit)),
+            _ => Err(Error::new(std::io::ErrorKind::InvalidData, "Bad Request: q param is missing")),
+        };
+        let (query, limit) = match params {
+            Ok(p) => p,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(e);
+                return Ok(response)
+            }
+        };
+        let mut response = Response::new(200);
+        response.set_content_type(Mime::from_str("application/json;charset=utf-8").unwrap());
+        response.set_body(json!(request.state().gazetteer.get().search(&query, limit)));
+        Ok(response)
+    });
+    app.at("/api/stations").get(|request: Request<AppState>| async move {
+        let filter = match read_station_filter(&request) {
+            Ok(f) => f,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(e);
+                return Ok(response)
+            }
+        };
+        match stations::get_stations_geojson(request.state().store.as_ref(), &filter, Utc::now()) {
+            Ok(collection) => {
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/geo+json;charset=utf-8").unwrap());
+                response.set_body(collection);
+                Ok(response)
+            },
+            Err(e) => {
+                let mut response = Response::new(500);
+                response.set_error(e);
+                Ok(response)
+            }
+        }
+    });
+    app.at("/api/stations/:id").get(|request: Request<AppState>| async move {
+        let hours = match read_param(&request, "hours").map(|h| i64::from_str(&h)) {
+            None => DEFAULT_RECENT_HOURS,
+            Some(Ok(h)) if h > 0 => h,
+            Some(_) => {
+                let mut response = Response::new(400);
+                response.set_error(Error::new(std::io::ErrorKind::InvalidData, "Bad Request: hours is not a positive number"));
                 return Ok(response)
+            }
+        };
+        match stations::get_station_geojson(request.state().store.as_ref(), request.param("id")?, hours, Utc::now()) {
+            Ok(Some(feature)) => {
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/geo+json;charset=utf-8").unwrap());
+                response.set_body(feature);
+                Ok(response)
             },
+            Ok(None) => Ok(Response::new(404)),
             Err(e) => {
                 let mut response = Response::new(500);
                 response.set_error(e);
+                Ok(response)
+            }
+        }
+    });
+    app.at("/api/contours").get(|request: Request<AppState>| async move {
+        let (variable, levels, geometry) = match read_contour_params(&request) {
+            Ok(p) => p,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(e);
+                return Ok(response)
+            }
+        };
+        match request.state().fields.get() {
+            Some(field) => {
+                let start = Instant::now();
+                let contours = contours::get_contours(&field, &request.state().territory, variable, &levels, geometry);
+                println!("Time elapsed to contour {} levels is: {:?}", levels.len(), start.elapsed());
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/geo+json;charset=utf-8").unwrap());
+                response.set_body(contours);
+                Ok(response)
+            },
+            None => {
+                let mut response = Response::new(503);
+                response.set_error(Error::other("Gridded field is not generated yet"));
+                Ok(response)
+            }
+        }
+    });
+    app.at("/tiles/:variable/:z/:x/:y").get(|request: Request<AppState>| async move {
+        let variable = FieldVariable::from_name(request.param("variable")?);
+        let z = u8::from_str(request.param("z")?);
+        let x = u32::from_str(request.param("x")?);
+        let y = request.param("y")?.strip_suffix(".png").map(u32::from_str);
+        let (variable, z, x, y) = match (variable, z, x, y) {
+            (Some(variable), Ok(z), Ok(x), Some(Ok(y))) => (variable, z, x, y),
+            _ => return Ok(Response::new(404)),
+        };
+        let field = match request.state().fields.get() {
+            Some(f) => f,
+            None => {
+                let mut response = Response::new(503);
+                response.set_error(Error::other("Gridded field is not generated yet"));
+                return Ok(response)
+            }
+        };
+        match request.state().tiles.get_tile(&field, variable, z, x, y) {
+            Ok(tile) => {
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("image/png").unwrap());
+                response.set_body(tile.as_slice());
+                Ok(response)
+            },
+            Err(e) => {
+                let status = if e.kind() == std::io::ErrorKind::InvalidData { 400 } else { 500 };
+                let mut response = Response::new(status);
+                response.set_error(e);
+                Ok(response)
+            }
+        }
+    });
+    app.at("/api/export/:file").get(|request: Request<AppState>| async move {
+        let (table, format) = match request.param("file")?.split_once('.') {
+            Some((table, format)) => (export::tables::Table::from_name(table), export::tables::TableFormat::from_name(format)),
+            None => (None, None),
+        };
+        let (table, format) = match (table, format) {
+            (Some(table), Some(format)) => (table, format),
+            _ => return Ok(Response::new(404)),
+        };
+        let params: HashMap<String, String> = request.url().query_pairs().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let table_export = match export::tables::TableExport::from_params(table, format, &params) {
+            Ok(e) => e,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(Error::new(std::io::ErrorKind::InvalidData, format!("Bad Request: {}", e)));
+                return Ok(response)
+            }
+        };
+        let store = request.state().store.clone();
+        let mut response = Response::new(200);
+        response.set_content_type(Mime::from_str(format.content_type()).unwrap());
+        response.insert_header("Content-Disposition", format!("attachment; filename=\"{}\"", request.param("file")?));
+        response.set_body(export::stream::stream_body(move |writer| {
+            export::tables::write_table(writer, store.as_ref(), &table_export).map(|_| ())
+        }));
+        Ok(response)
+    });
+    app.at("/api/ingestion-runs").get(|request: Request<AppState>| async move {
+        let limit = match read_limit_param(&request, DEFAULT_INGESTION_RUNS_LIMIT, MAX_INGESTION_RUNS_LIMIT) {
+            Ok(l) => l,
+            Err(e) => {
+                let mut response = Response::new(400);
+                response.set_error(e);
                 return Ok(response)
             }
+        };
+        match request.state().store.get_ingestion_runs(limit) {
+            Ok(runs) => {
+                let mut response = Response::new(200);
+                response.set_content_type(Mime::from_str("application/json;charset=utf-8").unwrap());
+                response.set_body(json!(runs));
+                Ok(response)
+            },
+            Err(e) => {
+                let mut response = Response::new(500);
+                response.set_error(e);
+                Ok(response)
+            }
         }
     });
     app.listen("127.0.0.1:8088").await?;
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connectors::{db_writer::write_to_database, memory_store::MemoryStore}, met::{testing::{observation, station}, MeteoData}};
+
+    #[test]
+    fn local_data_from_latest_observations() {
+        let store = MemoryStore::default();
+        let meteo_data = MeteoData {
+            stations: vec![
+                station("6155A", 36.66612, -4.482307),
+                station("6156X", 36.717785, -4.48167),
+                station("6172O", 36.716663, -4.41972),
+                station("5402", 37.844166, -4.846111),
+            ],
+            observations: vec![
+                observation("6155A", "2023-08-10T09:00:00", 20.0),
+                observation("6155A", "2023-08-10T10:00:00", 30.0),
+                observation("6156X", "2023-08-10T10:00:00", 30.0),
+                observation("6172O", "2023-08-10T10:00:00", 30.0),
+                observation("5402", "2023-08-10T10:00:00", 40.0),
+            ],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+
+        let local_data = get_local_data(&store, Location { lat: 36.69528, lon: -4.45386 }, None, &ProviderFilter::default()).unwrap();
+
+        let mut used_stations: Vec<String> = local_data.used_stations.iter().map(|s| s.id.clone()).collect();
+        used_stations.sort();
+        assert_eq!(used_stations, vec!["6155A", "6156X", "6172O"]);
+        assert!((local_data.local_air_temperature - 30.0).abs() < 0.001);
+        assert_eq!(local_data.observation_local_time, "2023-08-10T12:00:00+02:00");
+    }
+
+    #[test]
+    fn local_data_needs_recent_observations_at_a_time() {
+        let store = MemoryStore::default();
+        let stations = vec![
+            station("6155A", 36.66612, -4.482307),
+            station("6156X", 36.717785, -4.48167),
+            station("6172O", 36.716663, -4.41972),
+        ];
+        store.upsert_stations(&stations, parse_observation_time("2020-01-01T00:00:00").unwrap()).unwrap();
+        store
+            .insert_observations(&[
+                observation("6155A", "2023-08-10T10:00:00", 30.0),
+                observation("6156X", "2023-08-10T10:00:00", 30.0),
+                observation("6172O", "2023-08-10T09:30:00", 30.0),
+            ])
+            .unwrap();
+        let error_at = |time: &str| {
+            let at = parse_observation_time(time).unwrap();
+            get_local_data(&store, Location { lat: 36.69528, lon: -4.45386 }, Some(at), &ProviderFilter::default()).err().map(|e| e.kind())
+        };
+
+        assert_eq!(error_at("2023-08-10T10:15:00"), None);
+        // before the first observation
+        assert_eq!(error_at("2020-06-01T00:00:00"), Some(std::io::ErrorKind::NotFound));
+        // a station last reported more than an hour before
+        assert_eq!(error_at("2023-08-10T10:45:00"), Some(std::io::ErrorKind::NotFound));
+        // before the stations are known
+        assert_eq!(error_at("2019-01-01T00:00:00"), Some(std::io::ErrorKind::NotFound));
+    }
+}
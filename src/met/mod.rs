@@ -1,7 +1,51 @@
 use std::fmt::Display;
 
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, ParseResult, SecondsFormat, Utc};
+use chrono_tz::{Atlantic::Canary, Europe::Madrid, Tz};
 use serde::{Deserialize, Serialize};
 
+#[cfg(test)]
+pub mod testing;
+
+// Rough bounding box of the Canary Islands, the only part of Spain outside Europe/Madrid
+const CANARY_LAT_RANGE: (f32, f32) = (27.0, 29.8);
+const CANARY_LON_RANGE: (f32, f32) = (-18.5, -13.0);
+const EARTH_RADIUS_KM: f32 = 6371.0;
+
+/**
+ * Parses an observation timestamp into UTC. AEMET sends `fint` without
+ * any offset (e.g. `2023-08-10T10:00:00`), which is UTC by definition,
+ * while explicit offsets (`Z`, `+00:00`) are also accepted.
+ */
+pub fn parse_observation_time(value: &str) -> ParseResult<DateTime<Utc>> {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(dt) => Ok(dt.with_timezone(&Utc)),
+        Err(_) => NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").map(|dt| dt.and_utc()),
+    }
+}
+
+/**
+ * Formats a timestamp the way it is stored in the database: ISO-8601 in UTC
+ * with second precision, so lexicographic and chronological order match.
+ */
+pub fn format_observation_time(value: &DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/**
+ * Time of a report giving only the day of month, hour and minute, as METAR
+ * and SYNOP do: the latest such time not later than an hour after
+ * `reference`.
+ */
+pub fn resolve_report_time(day: u32, hour: u32, minute: u32, reference: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let this_month = NaiveDate::from_ymd_opt(reference.year(), reference.month(), 1)?;
+    [this_month, this_month - Months::new(1), this_month - Months::new(2)]
+        .iter()
+        .filter_map(|month| month.with_day(day)?.and_hms_opt(hour, minute, 0))
+        .map(|time| time.and_utc())
+        .find(|time| *time <= reference + Duration::hours(1))
+}
+
 pub trait ToSqlParams {
     fn to_sql_params(&self) -> (String, String, String, String);
 }
@@ -12,6 +56,23 @@ pub struct Station {
     pub name: String,
     pub lat: f32,
     pub lon: f32,
+    pub altitude: Option<f32>,
+    /// Data provider the station belongs to, e.g. `aemet`.
+    pub provider: String,
+}
+impl Station {
+    /// Whether `other` describes the same station with a different name, position or altitude.
+    pub fn differs_from(&self, other: &Station) -> bool {
+        const EPSILON: f32 = 1e-5;
+        let altitude_differs = match (self.altitude, other.altitude) {
+            (Some(a), Some(b)) => (a - b).abs() > EPSILON,
+            (a, b) => a.is_some() != b.is_some(),
+        };
+        self.name != other.name
+            || (self.lat - other.lat).abs() > EPSILON
+            || (self.lon - other.lon).abs() > EPSILON
+            || altitude_differs
+    }
 }
 impl Display for Station {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -22,21 +83,22 @@ impl Display for Station {
         )
     }
 }
-impl ToSqlParams for Station {
-    fn to_sql_params(&self) -> (String, String, String, String) {
-        (
-            self.id.clone(),
-            self.name.clone(),
-            self.lat.to_string(),
-            self.lon.to_string(),
-        )
+/// A state of a station with its validity period, `valid_to` being `None` while still valid.
+#[derive(Clone, Debug)]
+pub struct StationPeriod {
+    pub station: Station,
+    pub valid_from: DateTime<Utc>,
+    pub valid_to: Option<DateTime<Utc>>,
+}
+impl StationPeriod {
+    pub fn is_valid_at(&self, at: DateTime<Utc>) -> bool {
+        self.valid_from <= at && self.valid_to.is_none_or(|to| to > at)
     }
 }
-
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Observation {
     pub station_id: String,
-    pub observation_time: String,
+    pub observation_time: DateTime<Utc>,
     pub aerial_temperature: f32,
     pub relative_humidity: f32,
 }
@@ -54,16 +116,128 @@ impl ToSqlParams for Observation {
     fn to_sql_params(&self) -> (String, String, String, String) {
         (
             self.station_id.clone(),
-            self.observation_time.clone(),
+            format_observation_time(&self.observation_time),
             self.aerial_temperature.to_string(),
             self.relative_humidity.to_string(),
         )
     }
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Aggregate {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+/**
+ * Daily roll-up of the observations of a station, kept after the raw
+ * observations are removed by the retention policy. Days are UTC days.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DailySummary {
+    pub station_id: String,
+    pub day: NaiveDate,
+    pub samples: u32,
+    pub aerial_temperature: Aggregate,
+    pub relative_humidity: Aggregate,
+    pub heat_index: Aggregate,
+}
 pub struct MeteoData {
     pub stations: Vec<Station>,
     pub observations: Vec<Observation>,
+    /// Entries dropped while parsing, because of missing values or invalid times.
+    pub skipped_observations: usize,
+}
+
+/**
+ * Record of a single ingestion run of a provider. Observations parsed but
+ * not inserted were already stored by a previous run.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct IngestionRun {
+    pub provider: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub succeeded: bool,
+    /// HTTP status of the last download of the provider, whether the run succeeded or failed. `None` without a response, e.g. for file imports.
+    pub http_status: Option<u16>,
+    pub error: Option<String>,
+    pub parsed_stations: usize,
+    pub parsed_observations: usize,
+    pub inserted_observations: usize,
+    pub duplicated_observations: usize,
+    pub skipped_observations: usize,
+}
+
+/// Municipality forecasts are given for, identified by its INE code (`29067`).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Municipality {
+    pub id: String,
+    pub name: String,
+    pub province: Option<String>,
+    pub lat: f32,
+    pub lon: f32,
+    pub altitude: Option<f32>,
+}
+
+/// Hourly forecast of a municipality, from the elaboration at `elaborated_at`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Forecast {
+    pub municipality_id: String,
+    pub forecast_time: DateTime<Utc>,
+    pub aerial_temperature: f32,
+    pub relative_humidity: f32,
+    pub elaborated_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct ForecastData {
+    pub municipalities: Vec<Municipality>,
+    pub forecasts: Vec<Forecast>,
+    /// Hours dropped while parsing, because of missing values or invalid times.
+    pub skipped_forecasts: usize,
+}
+
+/**
+ * Heat or cold warning of an area, from a CAP alert. One alert covering
+ * several areas gives a warning per area.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Warning {
+    pub id: String,
+    /// `heat` or `cold`.
+    pub kind: String,
+    pub event: String,
+    /// Level of the warning, e.g. `amarillo`, `naranja` or `rojo`.
+    pub level: String,
+    pub severity: String,
+    pub headline: String,
+    pub area: String,
+    pub onset: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    /// Outlines of the area as (lon, lat) points, an area may have several.
+    #[serde(skip)]
+    pub polygons: Vec<Vec<(f32, f32)>>,
+}
+impl Warning {
+    /// Reads stored polygons, also the single outline warnings were stored with before.
+    pub fn polygons_from_json(value: &str) -> Vec<Vec<(f32, f32)>> {
+        serde_json::from_str(value)
+            .or_else(|_| serde_json::from_str(value).map(|polygon| vec![polygon]))
+            .unwrap_or_default()
+    }
+}
+
+/// Municipality containing a location, with its province and autonomous community.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Place {
+    /// INE code of the municipality, e.g. `29067`.
+    pub code: String,
+    pub municipality: String,
+    pub province: String,
+    pub autonomous_community: String,
+    /// Name to show, e.g. `Málaga (29067)`.
+    pub label: String,
 }
 
 pub struct Location {
@@ -71,6 +245,73 @@ pub struct Location {
     pub lon: f32,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct BoundingBox {
+    pub min_lat: f32,
+    pub min_lon: f32,
+    pub max_lat: f32,
+    pub max_lon: f32,
+}
+impl BoundingBox {
+    /// Parses `min_lon,min_lat,max_lon,max_lat`, the GeoJSON order.
+    pub fn parse(value: &str) -> Option<BoundingBox> {
+        let values: Vec<f32> = value.split(',').filter_map(|v| v.trim().parse().ok()).collect();
+        match values[..] {
+            [min_lon, min_lat, max_lon, max_lat] => Some(BoundingBox { min_lat, min_lon, max_lat, max_lon }),
+            _ => None,
+        }
+    }
+}
+
+impl Location {
+    /// Great-circle distance to `other`, in kilometres.
+    pub fn distance_km(&self, other: &Location) -> f32 {
+        let (lat_a, lat_b) = (self.lat.to_radians(), other.lat.to_radians());
+        let half_dlat = (lat_b - lat_a) / 2.0;
+        let half_dlon = (other.lon - self.lon).to_radians() / 2.0;
+        let a = half_dlat.sin().powi(2) + lat_a.cos() * lat_b.cos() * half_dlon.sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
+    /// Square of the length of a degree of longitude in degrees of latitude, at this latitude.
+    pub fn lon_scale(&self) -> f32 {
+        self.lat.to_radians().cos().powi(2)
+    }
+
+    /**
+     * Squared distance to the position `lat`, `lon` in degrees of latitude,
+     * shortening longitudes by the latitude of this location. It ranks nearby
+     * positions like the great-circle distance and is the one the stores
+     * order closest stations and municipalities by.
+     */
+    pub fn ranking_distance(&self, lat: f32, lon: f32) -> f32 {
+        (lat - self.lat).powi(2) + (lon - self.lon).powi(2) * self.lon_scale()
+    }
+
+    /**
+     * The three stations closest to this location by `ranking_distance`,
+     * skipping stations on the position of a closer one. `None` if there are
+     * less than three positions.
+     */
+    pub fn closest_stations<'a>(&self, stations: impl IntoIterator<Item = &'a Station>) -> Option<[&'a Station; 3]> {
+        let mut stations: Vec<(f32, &Station)> = stations.into_iter().map(|s| (self.ranking_distance(s.lat, s.lon), s)).collect();
+        stations.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.id.cmp(&b.1.id)));
+        stations.dedup_by(|a, b| a.1.lat == b.1.lat && a.1.lon == b.1.lon);
+        let closest: Vec<&Station> = stations.into_iter().take(3).map(|(_, s)| s).collect();
+        closest.try_into().ok()
+    }
+
+    pub fn timezone(&self) -> Tz {
+        if (CANARY_LAT_RANGE.0..=CANARY_LAT_RANGE.1).contains(&self.lat)
+            && (CANARY_LON_RANGE.0..=CANARY_LON_RANGE.1).contains(&self.lon)
+        {
+            Canary
+        } else {
+            Madrid
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct WheatrApiResponseData {
     pub used_stations: Vec<Station>,
@@ -79,9 +320,106 @@ pub struct WheatrApiResponseData {
     pub local_air_temperature: f32,
     pub local_rel_humidity: f32,
     pub local_hi: f32,
+    pub observation_time: DateTime<Utc>,
+    pub observation_local_time: String,
+    pub local_timezone: String,
+    /// Active heat and cold warnings of areas containing the location.
+    pub warnings: Vec<Warning>,
+    /// Municipality of the location, if municipality boundaries are loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place: Option<Place>,
 }
 impl Display for WheatrApiResponseData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Weather data on {}, {} place:\n temp/hum {}/{} with HI {}\n\n by the following stations\n{:?}", self.local_lat, self.local_lon, self.local_air_temperature, self.local_rel_humidity, self.local_hi, self.used_stations)
+        write!(f, "Weather data on {}, {} place at {} ({}):\n temp/hum {}/{} with HI {}\n\n by the following stations\n{:?}", self.local_lat, self.local_lon, self.observation_local_time, self.local_timezone, self.local_air_temperature, self.local_rel_humidity, self.local_hi, self.used_stations)
+    }
+}
+
+/**
+ * Result of a point of a batch request, either its data or the reason
+ * why it could not be calculated.
+ */
+#[derive(Serialize)]
+pub struct WheatrBatchItem {
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<WheatrApiResponseData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WheatrHistoryStep {
+    pub time: DateTime<Utc>,
+    pub local_time: String,
+    pub local_air_temperature: f32,
+    pub local_rel_humidity: f32,
+    pub local_hi: f32,
+    pub used_station_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct WheatrHistoryResponseData {
+    pub local_lat: f32,
+    pub local_lon: f32,
+    pub local_timezone: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Hourly steps, hours without recent enough observations of all three stations are left out.
+    pub steps: Vec<WheatrHistoryStep>,
+}
+
+#[derive(Serialize)]
+pub struct WheatrForecastStep {
+    pub time: DateTime<Utc>,
+    pub local_time: String,
+    pub air_temperature: f32,
+    pub rel_humidity: f32,
+    pub hi: f32,
+}
+
+#[derive(Serialize)]
+pub struct WheatrForecastResponseData {
+    pub local_lat: f32,
+    pub local_lon: f32,
+    pub local_timezone: String,
+    /// Municipality the forecast is given for.
+    pub municipality: Municipality,
+    pub elaborated_at: DateTime<Utc>,
+    /// Hourly steps from the current hour on.
+    pub steps: Vec<WheatrForecastStep>,
+}
+
+/// Municipality or station matching a search, whose position can be asked for by `/api/hi`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WheatrSearchResult {
+    /// `municipality` or `station`.
+    pub kind: String,
+    /// INE code of a municipality, id of a station.
+    pub id: String,
+    pub name: String,
+    pub province: Option<String>,
+    pub lat: f32,
+    pub lon: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_aemet_observation_time() {
+        let expected = "2023-08-10T10:00:00Z";
+        let parsed = parse_observation_time("2023-08-10T10:00:00").unwrap();
+        assert_eq!(format_observation_time(&parsed), expected);
+        let parsed = parse_observation_time("2023-08-10T12:00:00+02:00").unwrap();
+        assert_eq!(format_observation_time(&parsed), expected);
+        assert!(parse_observation_time("10/08/2023 10:00").is_err());
+    }
+
+    #[test]
+    fn timezone_by_location() {
+        assert_eq!(Location { lat: 36.72, lon: -4.42 }.timezone(), Madrid);
+        assert_eq!(Location { lat: 28.12, lon: -15.43 }.timezone(), Canary);
     }
 }
@@ -0,0 +1,16 @@
+use super::{parse_observation_time, Observation, Station};
+
+/// AEMET station named by its id, without altitude.
+pub fn station(id: &str, lat: f32, lon: f32) -> Station {
+    Station { id: id.to_string(), name: id.to_string(), lat, lon, altitude: None, provider: "aemet".to_string() }
+}
+
+/// Observation at `time` (ISO-8601) with a humidity of 40 %.
+pub fn observation(station_id: &str, time: &str, aerial_temperature: f32) -> Observation {
+    Observation {
+        station_id: station_id.to_string(),
+        observation_time: parse_observation_time(time).unwrap(),
+        aerial_temperature,
+        relative_humidity: 40.0,
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::Error;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{config::get_env_var_or, connectors::store::Store};
+
+const ENV_RAW_RETENTION_DAYS: &str = "WHEATR_RAW_RETENTION_DAYS";
+const ENV_DAILY_RETENTION_DAYS: &str = "WHEATR_DAILY_RETENTION_DAYS";
+const ENV_RETENTION_AT: &str = "WHEATR_RETENTION_AT";
+const ENV_COMPACTION_INTERVAL_DAYS: &str = "WHEATR_COMPACTION_INTERVAL_DAYS";
+
+pub struct RetentionPolicy {
+    /// Days to keep hourly observations for, older ones are rolled up into daily summaries.
+    pub raw_retention_days: u32,
+    /// Days to keep daily summaries for.
+    pub daily_retention_days: u32,
+    /// Time of day (UTC, `HH:MM`) when the retention job runs.
+    pub run_at: String,
+    /// Days between two compactions of the database, 0 disables compaction.
+    pub compaction_interval_days: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> RetentionPolicy {
+        RetentionPolicy {
+            raw_retention_days: 30,
+            daily_retention_days: 10 * 365,
+            run_at: "03:30".to_string(),
+            compaction_interval_days: 7,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn from_env() -> RetentionPolicy {
+        let default = RetentionPolicy::default();
+        RetentionPolicy {
+            raw_retention_days: get_env_var_or(ENV_RAW_RETENTION_DAYS, default.raw_retention_days),
+            daily_retention_days: get_env_var_or(ENV_DAILY_RETENTION_DAYS, default.daily_retention_days),
+            run_at: get_env_var_or(ENV_RETENTION_AT, default.run_at),
+            compaction_interval_days: get_env_var_or(ENV_COMPACTION_INTERVAL_DAYS, default.compaction_interval_days),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RetentionReport {
+    pub rolled_up_observations: usize,
+    pub deleted_daily_summaries: usize,
+    pub deleted_warnings: usize,
+}
+
+/**
+ * Rolls observations older than the raw retention period up into daily
+ * summaries, deleting them, then deletes the expired summaries and the
+ * warnings expired before the observations kept. The cutoff
+ * is aligned to the start of a UTC day, so only whole days are summarized;
+ * observations of such a day arriving later are merged into its existing
+ * summary.
+ */
+pub fn apply_retention(store: &dyn Store, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<RetentionReport, Error> {
+    let raw_cutoff_day = (now - Duration::days(policy.raw_retention_days as i64)).date_naive();
+    let raw_cutoff = raw_cutoff_day.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let daily_cutoff = (now - Duration::days(policy.daily_retention_days as i64)).date_naive();
+
+    let rolled_up_observations = store.roll_up_observations_before(raw_cutoff)?;
+    let deleted_daily_summaries = store.delete_daily_summaries_before(daily_cutoff)?;
+    let deleted_warnings = store.delete_warnings_before(raw_cutoff)?;
+
+    Ok(RetentionReport { rolled_up_observations, deleted_daily_summaries, deleted_warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{memory_store::MemoryStore, store::ObservationFilter},
+        met::{parse_observation_time, testing::observation, Station, Warning},
+    };
+
+    #[test]
+    fn summarize_and_delete_expired_observations() {
+        let store = MemoryStore::default();
+        let policy = RetentionPolicy { raw_retention_days: 2, daily_retention_days: 5, ..Default::default() };
+        store
+            .upsert_stations(&[Station { id: "6155A".to_string(), ..Default::default() }], Utc::now())
+            .unwrap();
+        store
+            .insert_observations(&[
+                observation("6155A", "2023-08-01T10:00:00", 30.0),
+                observation("6155A", "2023-08-07T10:00:00", 29.0),
+                observation("6155A", "2023-08-07T23:00:00", 21.0),
+                observation("6155A", "2023-08-08T01:00:00", 20.0),
+                observation("6155A", "2023-08-10T09:00:00", 28.0),
+            ])
+            .unwrap();
+
+        let warning = |id: &str, expires: &str| Warning {
+            id: id.to_string(),
+            onset: parse_observation_time("2023-08-01T10:00:00").unwrap(),
+            expires: parse_observation_time(expires).unwrap(),
+            ..Default::default()
+        };
+        let now = parse_observation_time("2023-08-10T10:00:00").unwrap();
+        store.replace_warnings(&[warning("expired", "2023-08-07T18:00:00"), warning("kept", "2023-08-08T18:00:00")], now).unwrap();
+        let report = apply_retention(&store, &policy, now).unwrap();
+
+        assert_eq!(report, RetentionReport { rolled_up_observations: 3, deleted_daily_summaries: 1, deleted_warnings: 1 });
+        assert_eq!(store.get_warnings_at(parse_observation_time("2023-08-08T10:00:00").unwrap()).unwrap().len(), 1);
+        let summaries = store.get_daily_summaries();
+        let days: Vec<String> = summaries.iter().map(|s| s.day.to_string()).collect();
+        assert_eq!(days, vec!["2023-08-07"]);
+        assert_eq!((summaries[0].samples, summaries[0].aerial_temperature.mean), (2, 25.0));
+        let mut remaining = 0;
+        store
+            .for_each_observation(&ObservationFilter::default(), &mut |_| {
+                remaining += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(remaining, 2);
+
+        // a second run finds nothing left to roll up
+        let report = apply_retention(&store, &policy, now).unwrap();
+        assert_eq!(report, RetentionReport::default());
+        assert_eq!(store.get_daily_summaries(), summaries);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    calculators::location_data_calculations::{calculate_local_values, calculate_response_data},
+    connectors::store::{StationFilter, Store},
+    history::MAX_OBSERVATION_AGE_MINUTES,
+    met::{Location, Observation, Station, WheatrApiResponseData},
+};
+
+/**
+ * The latest observation of every station, loaded once to answer many
+ * location queries without going back to the store.
+ */
+pub struct ObservationSnapshot {
+    stations: Vec<(Station, Observation)>,
+}
+
+impl ObservationSnapshot {
+    /**
+     * The latest observation of every station, leaving out those older than
+     * the hour before the newest one, like `load_at` does for past hours.
+     * The newest observation rather than now is the reference, as AEMET
+     * publishes observations late.
+     */
+    pub fn load(store: &dyn Store) -> Result<ObservationSnapshot, Error> {
+        let mut stations: Vec<(Station, Observation)> = store
+            .get_stations_with_latest_observation(&StationFilter::default())?
+            .into_iter()
+            .filter_map(|(station, latest)| latest.map(|observation| (station, observation)))
+            .collect();
+        if let Some(newest) = stations.iter().map(|(_, observation)| observation.observation_time).max() {
+            let oldest = newest - Duration::minutes(MAX_OBSERVATION_AGE_MINUTES);
+            stations.retain(|(_, observation)| observation.observation_time >= oldest);
+        }
+        Ok(ObservationSnapshot { stations })
+    }
+
+    /**
+     * The latest observation of each of the stations recorded in the hour
+     * before `at`, to grid past hours. Stations are taken at the given
+     * positions.
+     */
+    pub fn load_at(store: &dyn Store, stations: &[Station], at: DateTime<Utc>) -> Result<ObservationSnapshot, Error> {
+        let station_ids: Vec<String> = stations.iter().map(|s| s.id.clone()).collect();
+        let mut latest: HashMap<String, Observation> = HashMap::new();
+        // oldest first, so later observations replace earlier ones
+        for observation in store.get_observations_between(&station_ids, at - Duration::minutes(MAX_OBSERVATION_AGE_MINUTES), at)? {
+            latest.insert(observation.station_id.clone(), observation);
+        }
+        let stations = stations
+            .iter()
+            .filter_map(|station| latest.remove(&station.id).map(|observation| (station.clone(), observation)))
+            .collect();
+        Ok(ObservationSnapshot { stations })
+    }
+
+    /**
+     * The three stations closest to the location having an observation,
+     * ranked like the stores rank them for `/api/hi`. Stations on the same
+     * position as a closer one are skipped, as they cannot span a plane with
+     * it.
+     */
+    fn get_closest(&self, loc: &Location) -> Option<[&(Station, Observation); 3]> {
+        let distance = |s: &Station| loc.ranking_distance(s.lat, s.lon);
+        let mut closest: Vec<(f32, &(Station, Observation))> = Vec::with_capacity(4);
+        for entry in &self.stations {
+            let d = distance(&entry.0);
+            if closest.len() == 3 && d >= closest[2].0 {
+                continue;
+            }
+            if closest.iter().any(|(_, c)| c.0.lat == entry.0.lat && c.0.lon == entry.0.lon) {
+                continue;
+            }
+            let position = closest.partition_point(|(c, _)| *c <= d);
+            closest.insert(position, (d, entry));
+            closest.truncate(3);
+        }
+        let closest: Vec<&(Station, Observation)> = closest.into_iter().map(|(_, entry)| entry).collect();
+        closest.try_into().ok()
+    }
+
+    /// Interpolated temperature, humidity and heat index at the location.
+    pub fn get_local_values(&self, loc: &Location) -> Option<(f32, f32, f32)> {
+        let closest = self.get_closest(loc)?;
+        let stations = closest.map(|(station, _)| station.clone());
+        let observations = closest.map(|(_, observation)| observation.clone());
+        calculate_local_values(loc, &stations, &observations)
+    }
+
+    /// Time of the latest observation in the snapshot.
+    pub fn get_observation_time(&self) -> Option<DateTime<Utc>> {
+        self.stations.iter().map(|(_, observation)| observation.observation_time).max()
+    }
+
+    pub fn get_local_data(&self, loc: &Location) -> Result<WheatrApiResponseData, Error> {
+        let no_observations = || Error::new(ErrorKind::NotFound, "Not Found: less than three stations have observations");
+        let closest = self.get_closest(loc).ok_or_else(no_observations)?;
+        let stations = closest.map(|(station, _)| station.clone());
+        let observations = closest.map(|(_, observation)| observation.clone());
+        calculate_response_data(loc, &stations, &observations).ok_or_else(no_observations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{parse_observation_time, testing::{observation, station}, MeteoData},
+    };
+
+    #[test]
+    fn stale_observations_are_left_out() {
+        let store = MemoryStore::default();
+        let meteo_data = MeteoData {
+            stations: vec![
+                station("6155A", 36.66612, -4.482307),
+                station("6156X", 36.717785, -4.48167),
+                station("6172O", 36.716663, -4.41972),
+                station("6069X", 36.6975, -4.4595),
+            ],
+            observations: vec![
+                observation("6155A", "2023-08-10T10:00:00", 30.0),
+                observation("6156X", "2023-08-10T09:30:00", 30.0),
+                observation("6172O", "2023-08-10T09:00:00", 30.0),
+                // the closest station, but stopped reporting
+                observation("6069X", "2023-08-10T07:00:00", 30.0),
+            ],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+
+        let snapshot = ObservationSnapshot::load(&store).unwrap();
+        let data = snapshot.get_local_data(&Location { lat: 36.69528, lon: -4.45386 }).unwrap();
+        assert!(data.used_stations.iter().all(|s| s.id != "6069X"));
+        assert_eq!(snapshot.get_observation_time(), Some(parse_observation_time("2023-08-10T10:00:00").unwrap()));
+    }
+}
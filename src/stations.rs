@@ -0,0 +1,126 @@
+use std::io::Error;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::{
+    calculators::location_data_calculations::calculate_heat_index,
+    connectors::store::{StationFilter, Store},
+    met::{Observation, Station},
+};
+
+/// Longest period of recent observations returned for a station.
+pub const MAX_RECENT_HOURS: i64 = 31 * 24;
+
+fn observation_properties(observation: &Observation) -> Value {
+    json!({
+        "observation_time": observation.observation_time,
+        "air_temperature": observation.aerial_temperature,
+        "rel_humidity": observation.relative_humidity,
+        "hi": calculate_heat_index(observation.aerial_temperature, observation.relative_humidity),
+    })
+}
+
+/**
+ * GeoJSON point feature of a station, its metadata merged with `extra`
+ * properties.
+ */
+fn station_feature(station: &Station, extra: Value) -> Value {
+    let mut properties = json!({
+        "id": station.id,
+        "name": station.name,
+        "provider": station.provider,
+        "altitude": station.altitude,
+    });
+    if let (Some(properties), Value::Object(extra)) = (properties.as_object_mut(), extra) {
+        properties.extend(extra);
+    }
+    json!({
+        "type": "Feature",
+        "id": station.id,
+        "geometry": { "type": "Point", "coordinates": [station.lon, station.lat] },
+        "properties": properties,
+    })
+}
+
+/**
+ * All stations matching the filter as a GeoJSON FeatureCollection, with
+ * their latest observation and its age in minutes at `now`.
+ */
+pub fn get_stations_geojson(store: &dyn Store, filter: &StationFilter, now: DateTime<Utc>) -> Result<Value, Error> {
+    let features: Vec<Value> = store
+        .get_stations_with_latest_observation(filter)?
+        .iter()
+        .map(|(station, latest)| {
+            station_feature(
+                station,
+                json!({
+                    "latest_observation": latest.as_ref().map(observation_properties),
+                    "age_minutes": latest.as_ref().map(|o| (now - o.observation_time).num_minutes()),
+                }),
+            )
+        })
+        .collect();
+    Ok(json!({ "type": "FeatureCollection", "features": features }))
+}
+
+/**
+ * A station as a GeoJSON Feature with its observations of the last `hours`
+ * hours before `now`, oldest first. `None` if the station is unknown.
+ */
+pub fn get_station_geojson(store: &dyn Store, id: &str, hours: i64, now: DateTime<Utc>) -> Result<Option<Value>, Error> {
+    let station = match store.get_station(id)? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let since = now - Duration::hours(hours.min(MAX_RECENT_HOURS));
+    let observations: Vec<Value> = store
+        .get_observations_between(std::slice::from_ref(&station.id), since, now)?
+        .iter()
+        .map(observation_properties)
+        .collect();
+    Ok(Some(station_feature(&station, json!({ "observations": observations }))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{parse_observation_time, testing::{observation, station}, BoundingBox, MeteoData},
+    };
+
+    #[test]
+    fn stations_as_geojson() {
+        let store = MemoryStore::default();
+        let meteo_data = MeteoData {
+            stations: vec![
+                station("6155A", 36.66612, -4.482307),
+                station("5402", 37.844166, -4.846111),
+                Station { provider: "private".to_string(), ..station("X1", 36.7, -4.4) },
+            ],
+            observations: vec![observation("6155A", "2023-08-10T09:00:00", 30.0), observation("6155A", "2023-08-10T10:00:00", 30.0)],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+        let now = parse_observation_time("2023-08-10T10:30:00").unwrap();
+
+        let filter = StationFilter {
+            bbox: Some(BoundingBox { min_lat: 36.0, min_lon: -5.0, max_lat: 37.0, max_lon: -4.0 }),
+            provider: Some("aemet".to_string()),
+        };
+        let collection = get_stations_geojson(&store, &filter, now).unwrap();
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0]["geometry"]["coordinates"], json!([-4.482307f32, 36.66612f32]));
+        assert_eq!(features[0]["properties"]["age_minutes"], 30);
+        assert_eq!(features[0]["properties"]["latest_observation"]["air_temperature"], 30.0);
+
+        let all = get_stations_geojson(&store, &StationFilter::default(), now).unwrap();
+        assert_eq!(all["features"][2]["properties"]["latest_observation"], Value::Null);
+
+        let detail = get_station_geojson(&store, "6155A", 1, now).unwrap().unwrap();
+        assert_eq!(detail["properties"]["observations"].as_array().unwrap().len(), 1);
+        assert!(get_station_geojson(&store, "unknown", 1, now).unwrap().is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use std::{fs, io::Error};
+
+use serde_json::Value;
+
+use crate::{
+    config::get_env_var_or,
+    met::{BoundingBox, Location},
+};
+
+const ENV_TERRITORY_PATH: &str = "WHEATR_TERRITORY_PATH";
+
+/// Simplified outline of Spain, used when no territory file is configured.
+const SPAIN_GEOJSON: &str = include_str!("../data/spain.geojson");
+
+/// Ray casting test of a ring of (lon, lat) points, closed or not.
+pub fn ring_contains(ring: &[(f32, f32)], loc: &Location) -> bool {
+    let mut previous = match ring.last() {
+        Some(p) => *p,
+        None => return false,
+    };
+    let mut inside = false;
+    for &point in ring {
+        let ((x1, y1), (x2, y2)) = (previous, point);
+        if (y1 > loc.lat) != (y2 > loc.lat) && loc.lon < x1 + (loc.lat - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+        previous = point;
+    }
+    inside
+}
+
+/// A polygon as rings of (lon, lat) points, the first one being its outline.
+pub struct Polygon {
+    pub bbox: BoundingBox,
+    rings: Vec<Vec<(f32, f32)>>,
+}
+
+impl Polygon {
+    pub fn new(rings: Vec<Vec<(f32, f32)>>) -> Polygon {
+        let mut bbox = BoundingBox { min_lat: f32::MAX, min_lon: f32::MAX, max_lat: f32::MIN, max_lon: f32::MIN };
+        for &(lon, lat) in rings.iter().flatten() {
+            bbox.min_lat = bbox.min_lat.min(lat);
+            bbox.min_lon = bbox.min_lon.min(lon);
+            bbox.max_lat = bbox.max_lat.max(lat);
+            bbox.max_lon = bbox.max_lon.max(lon);
+        }
+        Polygon { bbox, rings }
+    }
+
+    /// Even-odd rule over all rings, so holes are outside.
+    pub fn contains(&self, loc: &Location) -> bool {
+        let b = &self.bbox;
+        if loc.lat < b.min_lat || loc.lat > b.max_lat || loc.lon < b.min_lon || loc.lon > b.max_lon {
+            return false;
+        }
+        self.rings.iter().filter(|ring| ring_contains(ring, loc)).count() % 2 == 1
+    }
+
+    /// A (lon, lat) point inside the polygon, the middle of its widest span at mid latitude.
+    pub fn interior_point(&self) -> Option<(f32, f32)> {
+        let lat = (self.bbox.min_lat + self.bbox.max_lat) / 2.0;
+        let mut crossings = vec![];
+        for ring in &self.rings {
+            let mut previous = match ring.last() {
+                Some(p) => *p,
+                None => continue,
+            };
+            for &point in ring {
+                let ((x1, y1), (x2, y2)) = (previous, point);
+                if (y1 > lat) != (y2 > lat) {
+                    crossings.push(x1 + (lat - y1) / (y2 - y1) * (x2 - x1));
+                }
+                previous = point;
+            }
+        }
+        crossings.sort_by(f32::total_cmp);
+        crossings
+            .chunks_exact(2)
+            .max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+            .map(|span| ((span[0] + span[1]) / 2.0, lat))
+    }
+}
+
+/**
+ * Land area of Spain, to tell which points of the interpolated field are
+ * on Spanish territory.
+ */
+pub struct Territory {
+    polygons: Vec<Polygon>,
+}
+
+fn read_ring(ring: &Value) -> Option<Vec<(f32, f32)>> {
+    ring.as_array()?
+        .iter()
+        .map(|point| Some((point[0].as_f64()? as f32, point[1].as_f64()? as f32)))
+        .collect()
+}
+
+fn read_polygon(rings: &Value) -> Option<Polygon> {
+    let rings: Option<Vec<_>> = rings.as_array()?.iter().map(read_ring).collect();
+    Some(Polygon::new(rings?))
+}
+
+/// Adds the polygons of a Polygon or MultiPolygon geometry, other geometries are ignored.
+pub fn read_geometry(geometry: &Value, polygons: &mut Vec<Polygon>) -> Option<()> {
+    match geometry["type"].as_str()? {
+        "Polygon" => polygons.push(read_polygon(&geometry["coordinates"])?),
+        "MultiPolygon" => {
+            for rings in geometry["coordinates"].as_array()? {
+                polygons.push(read_polygon(rings)?);
+            }
+        }
+        _ => (),
+    }
+    Some(())
+}
+
+impl Territory {
+    /**
+     * Reads the Polygon and MultiPolygon geometries of a GeoJSON
+     * FeatureCollection, Feature or bare geometry.
+     */
+    pub fn from_geojson(content: &str) -> Result<Territory, Error> {
+        let geojson: Value = serde_json::from_str(content).map_err(Error::other)?;
+        let geometries: Vec<&Value> = match geojson["type"].as_str() {
+            Some("FeatureCollection") => geojson["features"]
+                .as_array()
+                .map(|features| features.iter().map(|f| &f["geometry"]).collect())
+                .unwrap_or_default(),
+            Some("Feature") => vec![&geojson["geometry"]],
+            _ => vec![&geojson],
+        };
+        let mut polygons = Vec::new();
+        for geometry in geometries {
+            read_geometry(geometry, &mut polygons).ok_or_else(|| Error::other("Territory loading failed: invalid geometry"))?;
+        }
+        if polygons.is_empty() {
+            return Err(Error::other("Territory loading failed: no polygons"));
+        }
+        Ok(Territory { polygons })
+    }
+
+    /**
+     * Loads the GeoJSON file at `WHEATR_TERRITORY_PATH`, or the bundled
+     * simplified outline of Spain if it is not set or cannot be read.
+     */
+    pub fn from_env() -> Territory {
+        let path: String = get_env_var_or(ENV_TERRITORY_PATH, String::new());
+        if !path.is_empty() {
+            match fs::read_to_string(&path).and_then(|content| Territory::from_geojson(&content)) {
+                Ok(territory) => return territory,
+                Err(e) => println!("Territory file {} is not loaded. {}", path, e),
+            }
+        }
+        Territory::from_geojson(SPAIN_GEOJSON).expect("bundled territory is valid")
+    }
+
+    pub fn contains(&self, loc: &Location) -> bool {
+        self.polygons.iter().any(|polygon| polygon.contains(loc))
+    }
+
+    /// Whether any polygon's bounding box overlaps `bbox`.
+    pub fn intersects(&self, bbox: &BoundingBox) -> bool {
+        self.polygons.iter().any(|p| {
+            p.bbox.min_lat <= bbox.max_lat
+                && p.bbox.max_lat >= bbox.min_lat
+                && p.bbox.min_lon <= bbox.max_lon
+                && p.bbox.max_lon >= bbox.min_lon
+        })
+    }
+}
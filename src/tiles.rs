@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    io::{Error, ErrorKind},
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::get_env_var_or,
+    grid::{FieldVariable, GriddedField},
+    met::{BoundingBox, Location},
+    territory::Territory,
+};
+
+pub const TILE_SIZE: u32 = 256;
+pub const MAX_ZOOM: u8 = 18;
+/// The cache is emptied when it grows beyond this many tiles.
+const MAX_CACHED_TILES: usize = 4096;
+
+const ENV_RAMP_TEMPERATURE: &str = "WHEATR_TILE_RAMP_TEMPERATURE";
+const ENV_RAMP_HUMIDITY: &str = "WHEATR_TILE_RAMP_HUMIDITY";
+const ENV_RAMP_HI: &str = "WHEATR_TILE_RAMP_HI";
+const DEFAULT_RAMP_TEMPERATURE: &str = "-10:#313695,0:#4575b4,10:#abd9e9,20:#fee090,30:#f46d43,40:#a50026";
+const DEFAULT_RAMP_HUMIDITY: &str = "0:#8c510a,25:#d8b365,50:#f6e8c3,75:#5ab4ac,100:#01665e";
+/// Heat index categories: caution, extreme caution, danger, extreme danger.
+const DEFAULT_RAMP_HI: &str = "26:#ffffcc,27:#ffeda0,32:#feb24c,41:#f03b20,54:#bd0026";
+
+/**
+ * Colours at increasing values, linearly blended in between and clamped to
+ * the first and last one outside.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColourRamp {
+    stops: Vec<(f32, [u8; 4])>,
+}
+
+fn parse_colour(colour: &str) -> Option<[u8; 4]> {
+    let hex = colour.trim().strip_prefix('#')?;
+    if !hex.is_ascii() || (hex.len() != 6 && hex.len() != 8) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Some([channel(0)?, channel(2)?, channel(4)?, alpha])
+}
+
+impl ColourRamp {
+    /**
+     * Parses `value:#rrggbb` stops separated by commas, optionally with an
+     * alpha channel as `#rrggbbaa`.
+     */
+    pub fn parse(ramp: &str) -> Result<ColourRamp, Error> {
+        let invalid = |stop: &str| Error::new(ErrorKind::InvalidData, format!("Invalid colour ramp stop: {}", stop));
+        let mut stops = Vec::new();
+        for stop in ramp.split(',') {
+            let (value, colour) = stop.split_once(':').ok_or_else(|| invalid(stop))?;
+            let value: f32 = value.trim().parse().map_err(|_| invalid(stop))?;
+            stops.push((value, parse_colour(colour).ok_or_else(|| invalid(stop))?));
+        }
+        if stops.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(Error::new(ErrorKind::InvalidData, "Colour ramp values must be increasing"));
+        }
+        Ok(ColourRamp { stops })
+    }
+
+    pub fn colour_at(&self, value: f32) -> [u8; 4] {
+        let upper = self.stops.partition_point(|(v, _)| *v <= value);
+        if upper == 0 {
+            return self.stops[0].1;
+        }
+        if upper == self.stops.len() {
+            return self.stops[upper - 1].1;
+        }
+        let ((v1, c1), (v2, c2)) = (self.stops[upper - 1], self.stops[upper]);
+        let ratio = (value - v1) / (v2 - v1);
+        let mut colour = [0; 4];
+        for i in 0..4 {
+            colour[i] = (c1[i] as f32 + (c2[i] as f32 - c1[i] as f32) * ratio).round() as u8;
+        }
+        colour
+    }
+}
+
+fn ramp_from_env(key: &str, default: &str) -> ColourRamp {
+    let ramp: String = get_env_var_or(key, default.to_string());
+    ColourRamp::parse(&ramp).unwrap_or_else(|e| {
+        println!("{} is not used. {}", key, e);
+        ColourRamp::parse(default).expect("default colour ramp is valid")
+    })
+}
+
+/// Longitude at a horizontal position of the Web Mercator world at zoom `z`, in tiles.
+fn tile_lon(x: f64, z: u8) -> f64 {
+    x / (1u64 << z) as f64 * 360.0 - 180.0
+}
+
+/// Latitude at a vertical position of the Web Mercator world at zoom `z`, in tiles.
+fn tile_lat(y: f64, z: u8) -> f64 {
+    (PI * (1.0 - 2.0 * y / (1u64 << z) as f64)).sinh().atan().to_degrees()
+}
+
+type TileKey = (FieldVariable, u8, u32, u32);
+
+/// Tiles rendered from the field generated at `generated_at`.
+#[derive(Default)]
+struct CachedTiles {
+    generated_at: Option<DateTime<Utc>>,
+    tiles: HashMap<TileKey, Arc<Vec<u8>>>,
+}
+
+/**
+ * Renders the gridded field as Web Mercator PNG tiles, transparent outside
+ * Spanish territory, and keeps the tiles rendered from the latest field.
+ */
+pub struct TileCache {
+    territory: Arc<Territory>,
+    ramps: HashMap<FieldVariable, ColourRamp>,
+    cached: RwLock<CachedTiles>,
+}
+
+impl TileCache {
+    pub fn new(territory: Arc<Territory>, ramps: HashMap<FieldVariable, ColourRamp>) -> TileCache {
+        TileCache { territory, ramps, cached: RwLock::new(CachedTiles::default()) }
+    }
+
+    pub fn from_env(territory: Arc<Territory>) -> TileCache {
+        let ramps = HashMap::from([
+            (FieldVariable::Temperature, ramp_from_env(ENV_RAMP_TEMPERATURE, DEFAULT_RAMP_TEMPERATURE)),
+            (FieldVariable::Humidity, ramp_from_env(ENV_RAMP_HUMIDITY, DEFAULT_RAMP_HUMIDITY)),
+            (FieldVariable::HeatIndex, ramp_from_env(ENV_RAMP_HI, DEFAULT_RAMP_HI)),
+        ]);
+        TileCache::new(territory, ramps)
+    }
+
+    /**
+     * The PNG tile at `z/x/y`, rendered from the field unless it is cached.
+     * The tiles of an older field are dropped by the first tile of a newer
+     * one, and a tile of an older field rendered meanwhile is not cached.
+     */
+    pub fn get_tile(&self, field: &GriddedField, variable: FieldVariable, z: u8, x: u32, y: u32) -> Result<Arc<Vec<u8>>, Error> {
+        if z > MAX_ZOOM || x as u64 >= 1u64 << z || y as u64 >= 1u64 << z {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Bad Request: tile {}/{}/{} does not exist", z, x, y)));
+        }
+        let key = (variable, z, x, y);
+        {
+            let cached = self.cached.read().unwrap();
+            if cached.generated_at == Some(field.generated_at) {
+                if let Some(tile) = cached.tiles.get(&key) {
+                    return Ok(tile.clone());
+                }
+            }
+        }
+        let tile = Arc::new(self.render(field, variable, z, x, y)?);
+        let mut cached = self.cached.write().unwrap();
+        if cached.generated_at.is_some_and(|generated_at| generated_at > field.generated_at) {
+            return Ok(tile);
+        }
+        if cached.generated_at != Some(field.generated_at) || cached.tiles.len() >= MAX_CACHED_TILES {
+            cached.tiles.clear();
+            cached.generated_at = Some(field.generated_at);
+        }
+        cached.tiles.insert(key, tile.clone());
+        Ok(tile)
+    }
+
+    fn render(&self, field: &GriddedField, variable: FieldVariable, z: u8, x: u32, y: u32) -> Result<Vec<u8>, Error> {
+        let size = TILE_SIZE as usize;
+        let mut pixels = vec![0u8; size * size * 4];
+        let bbox = BoundingBox {
+            min_lat: tile_lat(y as f64 + 1.0, z) as f32,
+            min_lon: tile_lon(x as f64, z) as f32,
+            max_lat: tile_lat(y as f64, z) as f32,
+            max_lon: tile_lon(x as f64 + 1.0, z) as f32,
+        };
+        if let (true, Some(ramp)) = (self.territory.intersects(&bbox), self.ramps.get(&variable)) {
+            let pixel_size = 1.0 / TILE_SIZE as f64;
+            for row in 0..size {
+                let lat = tile_lat(y as f64 + (row as f64 + 0.5) * pixel_size, z) as f32;
+                for col in 0..size {
+                    let loc = Location { lat, lon: tile_lon(x as f64 + (col as f64 + 0.5) * pixel_size, z) as f32 };
+                    if !self.territory.contains(&loc) {
+                        continue;
+                    }
+                    if let Some(value) = field.lookup(&loc, variable) {
+                        let offset = (row * size + col) * 4;
+                        pixels[offset..offset + 4].copy_from_slice(&ramp.colour_at(value));
+                    }
+                }
+            }
+        }
+        encode_png(&pixels)
+    }
+}
+
+fn encode_png(pixels: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut content = Vec::new();
+    let mut encoder = png::Encoder::new(&mut content, TILE_SIZE, TILE_SIZE);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|err| Error::other(format!("Tile encoding failed: {}", err)))?;
+    writer.write_image_data(pixels).map_err(|err| Error::other(format!("Tile encoding failed: {}", err)))?;
+    writer.finish().map_err(|err| Error::other(format!("Tile encoding failed: {}", err)))?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{db_writer::write_to_database, memory_store::MemoryStore},
+        met::{parse_observation_time, testing::{observation, station}, MeteoData},
+        snapshot::ObservationSnapshot,
+    };
+
+    fn pixel(png: &[u8], col: usize, row: usize) -> [u8; 4] {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        let offset = (row * TILE_SIZE as usize + col) * 4;
+        pixels[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn ramp_colours() {
+        let ramp = ColourRamp::parse("0:#000000, 10:#ff000080").unwrap();
+        assert_eq!(ramp.colour_at(-5.0), [0, 0, 0, 255]);
+        assert_eq!(ramp.colour_at(5.0), [128, 0, 0, 192]);
+        assert_eq!(ramp.colour_at(20.0), [255, 0, 0, 128]);
+        assert!(ColourRamp::parse("10:#000000,0:#ffffff").is_err());
+        assert!(ColourRamp::parse("10:red").is_err());
+    }
+
+    #[test]
+    fn tiles_are_transparent_outside_spain() {
+        let store = MemoryStore::default();
+        let meteo_data = MeteoData {
+            stations: vec![station("A", 42.0, -6.0), station("B", 36.0, -6.0), station("C", 39.0, 2.0)],
+            observations: vec![observation("A", "2023-08-10T10:00:00", 30.0), observation("B", "2023-08-10T10:00:00", 30.0), observation("C", "2023-08-10T10:00:00", 30.0)],
+            skipped_observations: 0,
+        };
+        write_to_database(&store, &meteo_data).unwrap();
+        let snapshot = ObservationSnapshot::load(&store).unwrap();
+        let field = GriddedField::generate(&snapshot, 0.5, parse_observation_time("2023-08-10T10:05:00").unwrap());
+        let ramp = ColourRamp::parse("20:#0000ff,40:#ff0000").unwrap();
+        let tiles = TileCache::new(
+            Arc::new(Territory::from_env()),
+            HashMap::from([(FieldVariable::Temperature, ramp.clone())]),
+        );
+
+        // z6 tile 31/24 spans -5.6..0 E and 36.6..41.0 N: Madrid and the sea off Cartagena
+        let tile = tiles.get_tile(&field, FieldVariable::Temperature, 6, 31, 24).unwrap();
+        let madrid = (((-3.7 / 360.0 + 0.5) * 64.0 - 31.0) * 256.0) as usize;
+        assert_eq!(pixel(&tile, madrid, 37), ramp.colour_at(30.0));
+        let sea = (((-0.5 / 360.0 + 0.5) * 64.0 - 31.0) * 256.0) as usize;
+        assert_eq!(pixel(&tile, sea, 240), [0, 0, 0, 0]);
+        assert!(Arc::ptr_eq(&tile, &tiles.get_tile(&field, FieldVariable::Temperature, 6, 31, 24).unwrap()));
+
+        // a newer field replaces the cached tiles, which a render of the older one does not bring back
+        let newer_field = GriddedField { generated_at: field.generated_at + chrono::Duration::hours(1), ..field.clone() };
+        let newer_tile = tiles.get_tile(&newer_field, FieldVariable::Temperature, 6, 31, 24).unwrap();
+        assert!(!Arc::ptr_eq(&tile, &newer_tile));
+        let older_tile = tiles.get_tile(&field, FieldVariable::Temperature, 6, 31, 25).unwrap();
+        assert!(!Arc::ptr_eq(&older_tile, &tiles.get_tile(&field, FieldVariable::Temperature, 6, 31, 25).unwrap()));
+        assert!(Arc::ptr_eq(&newer_tile, &tiles.get_tile(&newer_field, FieldVariable::Temperature, 6, 31, 24).unwrap()));
+        assert!(tiles.get_tile(&field, FieldVariable::Temperature, 2, 4, 0).is_err());
+    }
+}
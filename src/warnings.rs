@@ -0,0 +1,51 @@
+use std::io::Error;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    connectors::store::Store,
+    met::{Location, Warning},
+    territory::ring_contains,
+};
+
+/// Warnings in force at `at` of the areas containing `loc`.
+pub fn get_warnings_at(store: &dyn Store, loc: &Location, at: DateTime<Utc>) -> Result<Vec<Warning>, Error> {
+    let warnings = store.get_warnings_at(at)?;
+    Ok(warnings.into_iter().filter(|w| w.polygons.iter().any(|p| ring_contains(p, loc))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connectors::memory_store::MemoryStore, met::parse_observation_time};
+
+    #[test]
+    fn warnings_of_areas_containing_the_location() {
+        let store = MemoryStore::default();
+        let warning = |id: &str, onset: &str, polygon: Vec<(f32, f32)>| Warning {
+            id: id.to_string(),
+            kind: "heat".to_string(),
+            onset: parse_observation_time(onset).unwrap(),
+            expires: parse_observation_time("2024-07-15T18:59:59Z").unwrap(),
+            polygons: vec![polygon],
+            ..Default::default()
+        };
+        let malaga = vec![(-4.9, 36.9), (-4.2, 36.9), (-4.2, 36.5), (-4.9, 36.5)];
+        let cordoba = vec![(-5.2, 38.0), (-4.4, 38.0), (-4.4, 37.5), (-5.2, 37.5)];
+        let at = parse_observation_time("2024-07-15T12:00:00Z").unwrap();
+        store
+            .replace_warnings(
+                &[
+                    warning("malaga", "2024-07-15T10:00:00Z", malaga.clone()),
+                    warning("malaga-later", "2024-07-15T16:00:00Z", malaga),
+                    warning("cordoba", "2024-07-15T10:00:00Z", cordoba),
+                ],
+                at,
+            )
+            .unwrap();
+
+        let warnings = get_warnings_at(&store, &Location { lat: 36.72, lon: -4.42 }, at).unwrap();
+        assert_eq!(warnings.iter().map(|w| w.id.as_str()).collect::<Vec<_>>(), vec!["malaga"]);
+        assert!(get_warnings_at(&store, &Location { lat: 40.42, lon: -3.70 }, at).unwrap().is_empty());
+    }
+}